@@ -0,0 +1,48 @@
+extern crate glsl_to_spirv;
+
+use glsl_to_spirv::ShaderType;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+const SHADER_DIR: &str = "src/render/shaders";
+
+fn main() {
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    println!("cargo:rerun-if-changed={}", SHADER_DIR);
+
+    for entry in fs::read_dir(SHADER_DIR).expect("could not read the shader directory") {
+        let path = entry.unwrap().path();
+        println!("cargo:rerun-if-changed={}", path.display());
+        let ty = match path.extension().and_then(|e| e.to_str()) {
+            Some("vert") => ShaderType::Vertex,
+            Some("frag") => ShaderType::Fragment,
+            _ => continue,
+        };
+        let src = preprocess(&path);
+        let mut spirv = glsl_to_spirv::compile(&src, ty)
+            .unwrap_or_else(|err| panic!("could not compile {}:\n{}", path.display(), err));
+        let mut bytes = Vec::new();
+        spirv.read_to_end(&mut bytes).unwrap();
+        let name = format!("{}.spv", path.file_name().unwrap().to_str().unwrap());
+        fs::write(Path::new(&out_dir).join(name), bytes).unwrap();
+    }
+}
+
+/// Resolves the `#include "file"` directives, relative to the shader directory.
+fn preprocess(path: &Path) -> String {
+    let src = fs::read_to_string(path)
+        .unwrap_or_else(|err| panic!("could not read {}: {}", path.display(), err));
+    let mut res = String::new();
+    for line in src.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("#include") {
+            let file = trimmed["#include".len()..].trim().trim_matches('"');
+            res.push_str(&preprocess(&Path::new(SHADER_DIR).join(file)));
+        } else {
+            res.push_str(line);
+            res.push('\n');
+        }
+    }
+    res
+}
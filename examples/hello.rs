@@ -1,8 +1,14 @@
 extern crate ows;
 extern crate winit;
 
-use ows::geom::IRect;
+use ows::geom::{FCorners, FMargins, FPoint, FRect, IRect, Margins};
+use ows::image::Image;
+use ows::paint::{Gradient, GradientStop};
+use ows::path::{FillRule, Path};
 use ows::render;
+use ows::text::{FontCollection, FontDescriptor, Paragraph, ParagraphStyle};
+use std::f32::consts::TAU;
+use std::time::{Duration, Instant};
 
 
 fn main() {
@@ -14,11 +20,68 @@ fn main() {
         .build(&events_loop)
         .unwrap();
 
-    let render_thread = render::Thread::new(Some(&window));
+    let text = "Hello, ows! Text wraps and shalom עולם, 你好.";
+    let mut fonts = FontCollection::system();
+    let chain = fonts.fallback_chain(&FontDescriptor::new("sans-serif"), text);
+
+    let cache_dir = std::env::temp_dir().join("ows-hello");
+    let render_thread = render::Thread::with_cache_dir(Some(&window), cache_dir);
+    render_thread.set_antialiasing(window.id(), render::Antialiasing::Msaa(4));
+
+    // a 4x4 checkerboard, with a frame of one texel for the nine-patch
+    let texels = (0..16)
+        .flat_map(|i| match (i / 4 + i % 4) % 2 {
+            0 => [40, 40, 60, 255],
+            _ => [240, 240, 240, 255],
+        })
+        .collect();
+    let checker = render_thread.add_image(Image::from_rgba(4, 4, texels));
+    // flattened once, and drawn by every frame
+    let badge = render_thread.add_path(Path::rounded_rect(
+        FRect::new(40f32, 260f32, 200f32, 64f32),
+        FCorners::uniform(32f32),
+    ));
+
+    // an image given on the command line is decoded in the background,
+    // the loader wakes the events loop up to redraw it
+    let proxy = events_loop.create_proxy();
+    let loader = render_thread.loader(1, move |loaded| {
+        if let Some(err) = &loaded.error {
+            println!("could not load {:?}: {}", loaded.source, err);
+        }
+        let _ = proxy.wakeup();
+    });
+    let photo = std::env::args()
+        .nth(1)
+        .map(|path| loader.load_image(std::path::PathBuf::from(path)));
+
+    // after a device or surface loss, the events loop redraws, and adds the window again
+    // if its surface was lost
+    let (lost_tx, lost_rx) = std::sync::mpsc::channel();
+    let proxy = events_loop.create_proxy();
+    render_thread.on_lost(move |lost| {
+        let _ = lost_tx.send(lost);
+        let _ = proxy.wakeup();
+    });
+
+    // with OWS_RECORD set to a file, the frames are recorded there for the replay example
+    let mut recorder = std::env::var_os("OWS_RECORD").map(|path| {
+        let file = std::fs::File::create(path).expect("could not create the recording");
+        render::Recorder::new(std::io::BufWriter::new(file), &[window.id()])
+            .expect("could not write the recording")
+    });
+
+    // the spinner keeps turning between the frames sent by the events loop
+    let spin_start = Instant::now();
 
     // spawn the render thread
     events_loop.run_forever(|event| {
         println!("received event: {:?}", event);
+        for lost in lost_rx.try_iter() {
+            if let render::Lost::Surface(_) = lost {
+                render_thread.add_window(&window);
+            }
+        }
 
         let size: (u32, u32) = window
             .get_inner_size()
@@ -26,11 +89,99 @@ fn main() {
             .unwrap()
             .into();
 
-        render_thread.frame(render::Frame::new(
+        let mut frame = render::Frame::new(
             window.id(),
             IRect::new(0, 0, size.0 as _, size.1 as _),
             Some([0.8f32, 0.5f32, 0.6f32, 1f32]),
-        ));
+        );
+        frame.fill_rect(
+            FRect::new(40f32, 40f32, 200f32, 120f32),
+            Gradient::linear(
+                FPoint::new(40f32, 0f32),
+                FPoint::new(240f32, 0f32),
+                vec![
+                    GradientStop::new(0f32, [0.2f32, 0.3f32, 0.9f32, 1f32]),
+                    GradientStop::new(1f32, [0.9f32, 0.9f32, 0.3f32, 1f32]),
+                ],
+            ),
+        );
+
+        let card = FRect::new(300f32, 60f32, 240f32, 160f32);
+        let radii = FCorners::uniform(12f32);
+        // the card fades as a whole
+        frame.push_layer(render::Layer::new(0.8f32));
+        frame.box_shadow(render::BoxShadow {
+            rect: card,
+            radii,
+            offset: FPoint::new(0f32, 6f32),
+            blur: 16f32,
+            spread: 0f32,
+            color: [0f32, 0f32, 0f32, 0.5f32],
+            inset: false,
+        });
+        frame.rounded_rect(card, radii, [1f32, 1f32, 1f32, 1f32]);
+        let grey = [0.6f32, 0.6f32, 0.6f32, 1f32];
+        frame.border(render::Border {
+            rect: card,
+            radii,
+            widths: FMargins::new(1f32, 1f32, 1f32, 4f32),
+            colors: Margins::new(grey, grey, grey, [0.2f32, 0.3f32, 0.9f32, 1f32]),
+        });
+        frame.pop_layer();
+
+        frame.fill_shared(&badge, FillRule::NonZero, [0.2f32, 0.6f32, 0.4f32, 1f32]);
+        frame.image(
+            render::ImageDraw::new(checker.clone(), FRect::new(300f32, 260f32, 64f32, 64f32))
+                .with_filter(render::Filter::Nearest),
+        );
+        frame.image(
+            render::ImageDraw::new(checker.clone(), FRect::new(380f32, 260f32, 160f32, 64f32))
+                .with_filter(render::Filter::Nearest)
+                .with_nine_patch(FMargins::new(1f32, 1f32, 1f32, 1f32)),
+        );
+        if let Some(photo) = &photo {
+            frame.image(render::ImageDraw::new(
+                photo.clone(),
+                FRect::new(580f32, 60f32, 160f32, 120f32),
+            ));
+        }
+
+        if !chain.is_empty() {
+            let mut style = ParagraphStyle::new(chain[0].clone(), 24f32);
+            style.fallback = chain[1..].to_vec();
+            style.max_width = Some(200f32);
+            let para = Paragraph::new(text, style);
+            for run in para.glyph_runs(FPoint::new(40f32, 280f32), [0.1f32, 0.1f32, 0.2f32, 1f32]) {
+                frame.glyph_run(run);
+            }
+        }
+
+        // a spinner, turned by the render thread
+        let from = render::TransformValue::identity();
+        let to = render::TransformValue::rotation(TAU);
+        let turn = render::Property::Transform {
+            origin: FPoint::new(580f32, 60f32),
+            keyframes: vec![
+                render::Keyframe::new(Duration::from_secs(0), from),
+                render::Keyframe::new(Duration::from_secs(1), to),
+            ],
+        };
+        let spin = render::Animation::new(turn, spin_start).endless();
+        frame.push_layer(render::Layer::new(1f32).with_animation(spin));
+        let bar = FRect::new(574f32, 30f32, 12f32, 24f32);
+        frame.rounded_rect(bar, FCorners::uniform(6f32), [0.2f32, 0.4f32, 0.9f32, 1f32]);
+        frame.pop_layer();
+
+        // a frosted glass panel over the gradient and the card
+        let panel = FRect::new(180f32, 120f32, 180f32, 100f32);
+        frame.push_clip(render::Clip::RoundedRect { rect: panel, radii });
+        frame.backdrop_blur(panel, 24f32);
+        frame.fill_rect(panel, [1f32, 1f32, 1f32, 0.3f32]);
+        frame.pop_clip();
+        if let Some(recorder) = &mut recorder {
+            recorder.record(&frame).expect("could not record the frame");
+        }
+        render_thread.frame(frame);
 
         match event {
             winit::Event::WindowEvent { event: winit::WindowEvent::CloseRequested, .. } => {
@@ -40,5 +191,8 @@ fn main() {
         }
     });
 
+    if let Some(mut recorder) = recorder {
+        recorder.flush().expect("could not write the recording");
+    }
     render_thread.stop();
 }
@@ -0,0 +1,76 @@
+extern crate ows;
+extern crate winit;
+
+use ows::render;
+use std::fs::File;
+use std::io::BufReader;
+use std::time::Instant;
+
+// Replays a recording written by `render::Recorder`, at the pace it was recorded.
+// The windows of the recording are resized to the viewports of their frames.
+// The last frames stay on screen until a window is closed.
+fn main() {
+    let path = std::env::args().nth(1).expect("usage: replay <recording>");
+    let file = File::open(&path).expect("could not open the recording");
+    let mut replay =
+        render::Replay::new(BufReader::new(file)).expect("could not read the recording");
+
+    let mut events_loop = winit::EventsLoop::new();
+    let windows: Vec<winit::Window> = (0..replay.windows())
+        .map(|i| {
+            winit::WindowBuilder::new()
+                .with_title(format!("{} - window {}", path, i))
+                .build(&events_loop)
+                .unwrap()
+        })
+        .collect();
+    let render_thread = render::Thread::new(&windows);
+
+    let mut sizes = vec![None; windows.len()];
+    let mut open = true;
+    let start = Instant::now();
+    while open {
+        events_loop.poll_events(|event| {
+            if let winit::Event::WindowEvent {
+                event: winit::WindowEvent::CloseRequested,
+                ..
+            } = event
+            {
+                open = false;
+            }
+        });
+        let recorded = match replay.next(&render_thread) {
+            Ok(Some(recorded)) => recorded,
+            Ok(None) => break,
+            Err(err) => {
+                println!("could not replay {}: {}", path, err);
+                break;
+            }
+        };
+        let due = start + recorded.time;
+        let now = Instant::now();
+        if due > now {
+            std::thread::sleep(due - now);
+        }
+
+        let window = &windows[recorded.window];
+        let size = (recorded.viewport.w, recorded.viewport.h);
+        if sizes[recorded.window] != Some(size) {
+            sizes[recorded.window] = Some(size);
+            let physical = winit::dpi::PhysicalSize::new(f64::from(size.0), f64::from(size.1));
+            window.set_inner_size(physical.to_logical(window.get_hidpi_factor()));
+        }
+        render_thread.frame(recorded.into_frame(window.id()));
+    }
+
+    if open {
+        events_loop.run_forever(|event| match event {
+            winit::Event::WindowEvent { event: winit::WindowEvent::CloseRequested, .. } => {
+                winit::ControlFlow::Break
+            }
+            _ => winit::ControlFlow::Continue,
+        });
+    }
+
+    render_thread.stop();
+}
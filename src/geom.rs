@@ -1,3 +1,5 @@
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
 pub type FPoint = Point<f32>;
 pub type IPoint = Point<i32>;
 
@@ -10,6 +12,8 @@ pub type IRect = Rect<i32>;
 pub type FMargins = Margins<f32>;
 pub type IMargins = Margins<i32>;
 
+pub type FCorners = Corners<f32>;
+
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub struct Point<T: Copy> {
     pub x: T,
@@ -22,6 +26,111 @@ impl<T: Copy> Point<T> {
     }
 }
 
+impl Point<f32> {
+    pub fn dot(self, rhs: FPoint) -> f32 {
+        self.x * rhs.x + self.y * rhs.y
+    }
+    /// z component of the 3D cross product
+    pub fn cross(self, rhs: FPoint) -> f32 {
+        self.x * rhs.y - self.y * rhs.x
+    }
+    pub fn length(self) -> f32 {
+        self.dot(self).sqrt()
+    }
+    pub fn distance(self, other: FPoint) -> f32 {
+        (other - self).length()
+    }
+    /// Unit vector with the same direction, or zero if the vector is null.
+    pub fn normalize(self) -> FPoint {
+        let len = self.length();
+        if len > 0f32 {
+            self / len
+        } else {
+            self
+        }
+    }
+    /// The vector rotated by 90 degrees.
+    pub fn perp(self) -> FPoint {
+        Point {
+            x: -self.y,
+            y: self.x,
+        }
+    }
+    pub fn lerp(self, other: FPoint, t: f32) -> FPoint {
+        self + (other - self) * t
+    }
+}
+
+impl<T> Add for Point<T>
+where
+    T: Add<Output = T> + Copy,
+{
+    type Output = Point<T>;
+
+    fn add(self, rhs: Point<T>) -> Point<T> {
+        Point {
+            x: self.x + rhs.x,
+            y: self.y + rhs.y,
+        }
+    }
+}
+
+impl<T> Sub for Point<T>
+where
+    T: Sub<Output = T> + Copy,
+{
+    type Output = Point<T>;
+
+    fn sub(self, rhs: Point<T>) -> Point<T> {
+        Point {
+            x: self.x - rhs.x,
+            y: self.y - rhs.y,
+        }
+    }
+}
+
+impl<T> Mul<T> for Point<T>
+where
+    T: Mul<Output = T> + Copy,
+{
+    type Output = Point<T>;
+
+    fn mul(self, rhs: T) -> Point<T> {
+        Point {
+            x: self.x * rhs,
+            y: self.y * rhs,
+        }
+    }
+}
+
+impl<T> Div<T> for Point<T>
+where
+    T: Div<Output = T> + Copy,
+{
+    type Output = Point<T>;
+
+    fn div(self, rhs: T) -> Point<T> {
+        Point {
+            x: self.x / rhs,
+            y: self.y / rhs,
+        }
+    }
+}
+
+impl<T> Neg for Point<T>
+where
+    T: Neg<Output = T> + Copy,
+{
+    type Output = Point<T>;
+
+    fn neg(self) -> Point<T> {
+        Point {
+            x: -self.x,
+            y: -self.y,
+        }
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub struct Size<T: Copy> {
     pub w: T,
@@ -108,3 +217,175 @@ impl<T: Copy> Margins<T> {
         }
     }
 }
+
+/// Per-corner values, such as the radii of a rounded rectangle.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Corners<T: Copy> {
+    pub tl: T,
+    pub tr: T,
+    pub br: T,
+    pub bl: T,
+}
+
+impl<T: Copy> Corners<T> {
+    pub fn new(tl: T, tr: T, br: T, bl: T) -> Corners<T> {
+        Corners { tl, tr, br, bl }
+    }
+
+    pub fn uniform(v: T) -> Corners<T> {
+        Corners {
+            tl: v,
+            tr: v,
+            br: v,
+            bl: v,
+        }
+    }
+}
+
+impl Corners<f32> {
+    /// Scales the radii down so that adjacent corners do not overlap
+    /// in a rectangle of the given size.
+    pub fn constrained(&self, w: f32, h: f32) -> FCorners {
+        let mut f = 1f32;
+        for &(sum, len) in &[
+            (self.tl + self.tr, w),
+            (self.bl + self.br, w),
+            (self.tl + self.bl, h),
+            (self.tr + self.br, h),
+        ] {
+            if sum > len {
+                f = f.min(len / sum);
+            }
+        }
+        Corners {
+            tl: (self.tl * f).max(0f32),
+            tr: (self.tr * f).max(0f32),
+            br: (self.br * f).max(0f32),
+            bl: (self.bl * f).max(0f32),
+        }
+    }
+}
+
+/// 2D affine transform.
+/// A point (x, y) is mapped to (m11*x + m21*y + m31, m12*x + m22*y + m32).
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Transform {
+    pub m11: f32,
+    pub m12: f32,
+    pub m21: f32,
+    pub m22: f32,
+    pub m31: f32,
+    pub m32: f32,
+}
+
+impl Transform {
+    pub fn new(m11: f32, m12: f32, m21: f32, m22: f32, m31: f32, m32: f32) -> Transform {
+        Transform {
+            m11,
+            m12,
+            m21,
+            m22,
+            m31,
+            m32,
+        }
+    }
+
+    pub fn identity() -> Transform {
+        Transform::new(1f32, 0f32, 0f32, 1f32, 0f32, 0f32)
+    }
+
+    pub fn translation(x: f32, y: f32) -> Transform {
+        Transform::new(1f32, 0f32, 0f32, 1f32, x, y)
+    }
+
+    pub fn scale(sx: f32, sy: f32) -> Transform {
+        Transform::new(sx, 0f32, 0f32, sy, 0f32, 0f32)
+    }
+
+    /// Rotation of `angle` radians, clockwise in a y-down coordinate system.
+    pub fn rotation(angle: f32) -> Transform {
+        let (sin, cos) = angle.sin_cos();
+        Transform::new(cos, sin, -sin, cos, 0f32, 0f32)
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Transform::identity()
+    }
+
+    /// Whether axis-aligned rectangles stay axis-aligned.
+    pub fn is_axis_aligned(&self) -> bool {
+        (self.m12 == 0f32 && self.m21 == 0f32) || (self.m11 == 0f32 && self.m22 == 0f32)
+    }
+
+    /// Scale factor of areas, as a length ratio.
+    pub fn scale_factor(&self) -> f32 {
+        (self.m11 * self.m22 - self.m21 * self.m12).abs().sqrt()
+    }
+
+    /// The transform that applies `self` and then `other`.
+    pub fn then(&self, other: &Transform) -> Transform {
+        Transform {
+            m11: other.m11 * self.m11 + other.m21 * self.m12,
+            m12: other.m12 * self.m11 + other.m22 * self.m12,
+            m21: other.m11 * self.m21 + other.m21 * self.m22,
+            m22: other.m12 * self.m21 + other.m22 * self.m22,
+            m31: other.m11 * self.m31 + other.m21 * self.m32 + other.m31,
+            m32: other.m12 * self.m31 + other.m22 * self.m32 + other.m32,
+        }
+    }
+
+    pub fn inverse(&self) -> Option<Transform> {
+        let det = self.m11 * self.m22 - self.m21 * self.m12;
+        if det == 0f32 || !det.is_finite() {
+            return None;
+        }
+        Some(Transform {
+            m11: self.m22 / det,
+            m12: -self.m12 / det,
+            m21: -self.m21 / det,
+            m22: self.m11 / det,
+            m31: (self.m21 * self.m32 - self.m22 * self.m31) / det,
+            m32: (self.m12 * self.m31 - self.m11 * self.m32) / det,
+        })
+    }
+
+    pub fn apply(&self, p: FPoint) -> FPoint {
+        FPoint::new(
+            self.m11 * p.x + self.m21 * p.y + self.m31,
+            self.m12 * p.x + self.m22 * p.y + self.m32,
+        )
+    }
+
+    /// Bounding box of the transformed rectangle.
+    pub fn apply_rect(&self, r: FRect) -> FRect {
+        let pts = [
+            self.apply(FPoint::new(r.x, r.y)),
+            self.apply(FPoint::new(r.x + r.w, r.y)),
+            self.apply(FPoint::new(r.x + r.w, r.y + r.h)),
+            self.apply(FPoint::new(r.x, r.y + r.h)),
+        ];
+        let (mut x0, mut y0) = (pts[0].x, pts[0].y);
+        let (mut x1, mut y1) = (x0, y0);
+        for p in &pts[1..] {
+            x0 = x0.min(p.x);
+            y0 = y0.min(p.y);
+            x1 = x1.max(p.x);
+            y1 = y1.max(p.y);
+        }
+        FRect::new(x0, y0, x1 - x0, y1 - y0)
+    }
+
+    /// Applies the transform without the translation.
+    pub fn apply_vector(&self, v: FPoint) -> FPoint {
+        FPoint::new(
+            self.m11 * v.x + self.m21 * v.y,
+            self.m12 * v.x + self.m22 * v.y,
+        )
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform::identity()
+    }
+}
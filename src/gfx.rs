@@ -2,16 +2,29 @@ use gfx_back as back;
 use gfx_hal as hal;
 
 pub type Backend = back::Backend;
+pub type Buffer = <Backend as hal::Backend>::Buffer;
 pub type CommandBuffer = hal::command::CommandBuffer<Backend, hal::Graphics, hal::command::OneShot>;
 pub type CommandPool = hal::CommandPool<Backend, hal::Graphics>;
 pub type CommandQueue = hal::CommandQueue<Backend, hal::Graphics>;
+pub type DescriptorPool = <Backend as hal::Backend>::DescriptorPool;
+pub type DescriptorSet = <Backend as hal::Backend>::DescriptorSet;
+pub type DescriptorSetLayout = <Backend as hal::Backend>::DescriptorSetLayout;
 pub type Device = <Backend as hal::Backend>::Device;
 pub type Fence = <Backend as hal::Backend>::Fence;
+pub type Framebuffer = <Backend as hal::Backend>::Framebuffer;
+pub type GraphicsPipeline = <Backend as hal::Backend>::GraphicsPipeline;
 pub type Image = <Backend as hal::Backend>::Image;
+pub type ImageView = <Backend as hal::Backend>::ImageView;
 pub type Instance = back::Instance;
+pub type Memory = <Backend as hal::Backend>::Memory;
 pub type PhysicalDevice = <Backend as hal::Backend>::PhysicalDevice;
+pub type PipelineCache = <Backend as hal::Backend>::PipelineCache;
+pub type PipelineLayout = <Backend as hal::Backend>::PipelineLayout;
 pub type QueueFamily = <Backend as hal::Backend>::QueueFamily;
 pub type QueueGroup = hal::QueueGroup<Backend, hal::Graphics>;
+pub type RenderPass = <Backend as hal::Backend>::RenderPass;
+pub type Sampler = <Backend as hal::Backend>::Sampler;
 pub type Semaphore = <Backend as hal::Backend>::Semaphore;
+pub type ShaderModule = <Backend as hal::Backend>::ShaderModule;
 pub type Surface = <Backend as hal::Backend>::Surface;
 pub type Swapchain = <Backend as hal::Backend>::Swapchain;
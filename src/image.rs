@@ -0,0 +1,227 @@
+use std::io;
+
+/// A bitmap image in premultiplied RGBA, with four bytes per pixel.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Image {
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+impl Image {
+    /// An image from straight (non-premultiplied) RGBA pixels, row by row.
+    pub fn from_rgba(width: u32, height: u32, rgba: Vec<u8>) -> Image {
+        let mut image = Image::from_premultiplied(width, height, rgba);
+        for p in image.rgba.chunks_mut(4) {
+            let a = p[3] as u32;
+            for c in &mut p[..3] {
+                *c = ((*c as u32 * a + 127) / 255) as u8;
+            }
+        }
+        image
+    }
+
+    /// An image from premultiplied RGBA pixels, row by row.
+    pub fn from_premultiplied(width: u32, height: u32, rgba: Vec<u8>) -> Image {
+        let len = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|pixels| pixels.checked_mul(4));
+        assert_eq!(Some(rgba.len()), len, "image data does not match its size");
+        Image {
+            width,
+            height,
+            rgba,
+        }
+    }
+
+    /// Decodes a PNG or a JPEG file, recognized by its signature.
+    pub fn decode(bytes: &[u8]) -> io::Result<Image> {
+        let invalid = |err: String| io::Error::new(io::ErrorKind::InvalidData, err);
+        if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            decode_png(bytes).map_err(|err| invalid(err.to_string()))
+        } else if bytes.starts_with(&[0xff, 0xd8]) {
+            decode_jpeg(bytes).map_err(|err| invalid(err.to_string()))
+        } else {
+            Err(invalid("unknown image format".to_string()))
+        }
+    }
+
+    pub fn from_file<P: AsRef<std::path::Path>>(path: P) -> io::Result<Image> {
+        Image::decode(&std::fs::read(path)?)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn rgba(&self) -> &[u8] {
+        &self.rgba
+    }
+
+    pub fn into_rgba(self) -> Vec<u8> {
+        self.rgba
+    }
+
+    /// Encodes the image to PNG, with straight alpha.
+    pub fn encode_png(&self) -> io::Result<Vec<u8>> {
+        let rgba: Vec<u8> = self
+            .rgba
+            .chunks(4)
+            .flat_map(|p| {
+                let a = p[3] as u32;
+                let c = |c: u8| match a {
+                    0 => 0,
+                    _ => ((c as u32 * 255 + a / 2) / a).min(255) as u8,
+                };
+                [c(p[0]), c(p[1]), c(p[2]), p[3]]
+            })
+            .collect();
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&rgba)?;
+        writer.finish()?;
+        Ok(bytes)
+    }
+
+    /// Number of levels of a full mipmap chain, down to one pixel.
+    pub fn mip_levels(&self) -> u32 {
+        32 - self.width.max(self.height).max(1).leading_zeros()
+    }
+
+    /// The next mipmap level, half the size and averaging 2x2 pixels.
+    /// The last column or row of an odd size is averaged with itself.
+    pub fn downsampled(&self) -> Image {
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+        for y in 0..height {
+            let (y0, y1) = (
+                (2 * y).min(self.height - 1),
+                (2 * y + 1).min(self.height - 1),
+            );
+            for x in 0..width {
+                let (x0, x1) = ((2 * x).min(self.width - 1), (2 * x + 1).min(self.width - 1));
+                let texel = |x: u32, y: u32, c: usize| {
+                    self.rgba[(y as usize * self.width as usize + x as usize) * 4 + c] as u32
+                };
+                rgba.extend((0..4).map(|c| {
+                    ((texel(x0, y0, c)
+                        + texel(x1, y0, c)
+                        + texel(x0, y1, c)
+                        + texel(x1, y1, c)
+                        + 2)
+                        / 4) as u8
+                }));
+            }
+        }
+        Image {
+            width,
+            height,
+            rgba,
+        }
+    }
+}
+
+fn decode_png(bytes: &[u8]) -> Result<Image, png::DecodingError> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0u8; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    let texels = &buf[..info.buffer_size()];
+    let rgba: Vec<u8> = match info.color_type {
+        png::ColorType::Rgba => texels.to_vec(),
+        png::ColorType::Rgb => texels
+            .chunks(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => texels
+            .chunks(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        // palettes are expanded by the transformations
+        png::ColorType::Grayscale | png::ColorType::Indexed => {
+            texels.iter().flat_map(|&g| [g, g, g, 255]).collect()
+        }
+    };
+    Ok(Image::from_rgba(info.width, info.height, rgba))
+}
+
+fn decode_jpeg(bytes: &[u8]) -> Result<Image, jpeg_decoder::Error> {
+    let mut decoder = jpeg_decoder::Decoder::new(bytes);
+    let pixels = decoder.decode()?;
+    let info = decoder.info().expect("jpeg info read by decode");
+    let rgba: Vec<u8> = match info.pixel_format {
+        jpeg_decoder::PixelFormat::RGB24 => pixels
+            .chunks(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        jpeg_decoder::PixelFormat::L8 => pixels.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        // big endian 16 bits luminance
+        jpeg_decoder::PixelFormat::L16 => pixels
+            .chunks(2)
+            .flat_map(|p| [p[0], p[0], p[0], 255])
+            .collect(),
+        // Adobe inverted CMYK
+        jpeg_decoder::PixelFormat::CMYK32 => pixels
+            .chunks(4)
+            .flat_map(|p| {
+                let k = p[3] as u32;
+                let c = |v: u8| ((v as u32 * k + 127) / 255) as u8;
+                [c(p[0]), c(p[1]), c(p[2]), 255]
+            })
+            .collect(),
+    };
+    Ok(Image::from_premultiplied(
+        info.width as u32,
+        info.height as u32,
+        rgba,
+    ))
+}
+
+#[test]
+fn decode_and_mipmaps() {
+    let mut png = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut png, 3, 2);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        #[rustfmt::skip]
+        writer.write_image_data(&[
+            255, 0, 0, 255,  255, 0, 0, 127,  0, 0, 255, 255,
+            255, 0, 0, 255,  255, 0, 0, 127,  0, 0, 255, 255,
+        ]).unwrap();
+    }
+    let image = Image::decode(&png).unwrap();
+    assert_eq!((3, 2), (image.width(), image.height()));
+    assert_eq!(&[127, 0, 0, 127], &image.rgba()[4..8]);
+    assert_eq!(2, image.mip_levels());
+
+    let level = image.downsampled();
+    assert_eq!((1, 1), (level.width(), level.height()));
+    assert_eq!(&[191, 0, 0, 191], level.rgba());
+
+    let err = Image::decode(b"GIF89a").unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+}
+
+#[test]
+fn encode_png() {
+    let image = Image::from_rgba(2, 1, vec![255, 0, 0, 255, 0, 255, 0, 127]);
+    let decoded = Image::decode(&image.encode_png().unwrap()).unwrap();
+    assert_eq!(image, decoded);
+}
+
+#[test]
+#[should_panic(expected = "image data does not match its size")]
+fn image_size_overflow() {
+    // 65536 * 65536 * 4 wraps to 0 in 32 bits
+    Image::from_premultiplied(65536, 65536, Vec::new());
+}
@@ -4,4 +4,8 @@ extern crate winit;
 
 pub mod geom;
 pub mod gfx;
+pub mod image;
+pub mod paint;
+pub mod path;
 pub mod render;
+pub mod text;
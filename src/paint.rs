@@ -0,0 +1,514 @@
+use crate::geom::{FPoint, Transform};
+use crate::render::GradientHandle;
+use std::f32::consts::PI;
+
+/// RGBA color with straight (not pre-multiplied) alpha.
+pub type Color = [f32; 4];
+
+/// Number of texels of a baked gradient.
+pub const RAMP_SIZE: usize = 256;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Paint {
+    Solid(Color),
+    Gradient(Gradient),
+    /// A gradient created with `render::Thread::add_gradient`.
+    SharedGradient(GradientHandle),
+}
+
+impl Paint {
+    /// Color of the paint at `p`, in the user space of the paint.
+    pub fn color_at(&self, p: FPoint) -> Color {
+        match self {
+            Paint::Solid(c) => *c,
+            Paint::Gradient(g) => g.color_at(p),
+            Paint::SharedGradient(g) => g.gradient().color_at(p),
+        }
+    }
+}
+
+impl From<Color> for Paint {
+    fn from(color: Color) -> Paint {
+        Paint::Solid(color)
+    }
+}
+
+impl From<Gradient> for Paint {
+    fn from(gradient: Gradient) -> Paint {
+        Paint::Gradient(gradient)
+    }
+}
+
+impl<'a> From<&'a GradientHandle> for Paint {
+    fn from(gradient: &'a GradientHandle) -> Paint {
+        Paint::SharedGradient(gradient.clone())
+    }
+}
+
+/// How a source is combined with the destination.
+/// The Porter-Duff operators, followed by the separable
+/// and the non-separable blend modes of the W3C compositing specification.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub enum BlendMode {
+    Clear,
+    Src,
+    Dst,
+    #[default]
+    SrcOver,
+    DstOver,
+    SrcIn,
+    DstIn,
+    SrcOut,
+    DstOut,
+    SrcAtop,
+    DstAtop,
+    Xor,
+    Plus,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    ColorDodge,
+    ColorBurn,
+    HardLight,
+    SoftLight,
+    Difference,
+    Exclusion,
+    Hue,
+    Saturation,
+    Color,
+    Luminosity,
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 28] = [
+        BlendMode::Clear,
+        BlendMode::Src,
+        BlendMode::Dst,
+        BlendMode::SrcOver,
+        BlendMode::DstOver,
+        BlendMode::SrcIn,
+        BlendMode::DstIn,
+        BlendMode::SrcOut,
+        BlendMode::DstOut,
+        BlendMode::SrcAtop,
+        BlendMode::DstAtop,
+        BlendMode::Xor,
+        BlendMode::Plus,
+        BlendMode::Multiply,
+        BlendMode::Screen,
+        BlendMode::Overlay,
+        BlendMode::Darken,
+        BlendMode::Lighten,
+        BlendMode::ColorDodge,
+        BlendMode::ColorBurn,
+        BlendMode::HardLight,
+        BlendMode::SoftLight,
+        BlendMode::Difference,
+        BlendMode::Exclusion,
+        BlendMode::Hue,
+        BlendMode::Saturation,
+        BlendMode::Color,
+        BlendMode::Luminosity,
+    ];
+
+    /// Factors of the source and of the destination, for the modes that are
+    /// a weighted sum of them.
+    pub fn porter_duff_factors(self, src_alpha: f32, dst_alpha: f32) -> Option<(f32, f32)> {
+        let (sa, da) = (src_alpha, dst_alpha);
+        Some(match self {
+            BlendMode::Clear => (0f32, 0f32),
+            BlendMode::Src => (1f32, 0f32),
+            BlendMode::Dst => (0f32, 1f32),
+            BlendMode::SrcOver => (1f32, 1f32 - sa),
+            BlendMode::DstOver => (1f32 - da, 1f32),
+            BlendMode::SrcIn => (da, 0f32),
+            BlendMode::DstIn => (0f32, sa),
+            BlendMode::SrcOut => (1f32 - da, 0f32),
+            BlendMode::DstOut => (0f32, 1f32 - sa),
+            BlendMode::SrcAtop => (da, 1f32 - sa),
+            BlendMode::DstAtop => (1f32 - da, sa),
+            BlendMode::Xor => (1f32 - da, 1f32 - sa),
+            BlendMode::Plus => (1f32, 1f32),
+            _ => return None,
+        })
+    }
+
+    /// Whether the mode can be done by fixed-function blending.
+    pub fn is_fixed_function(self) -> bool {
+        self == BlendMode::Screen || self.porter_duff_factors(0f32, 0f32).is_some()
+    }
+
+    /// Whether a transparent source leaves the destination unchanged,
+    /// so that drawing only changes the pixels it covers.
+    pub fn is_bounded(self) -> bool {
+        match self.porter_duff_factors(0f32, 1f32) {
+            Some((_, dst_factor)) => dst_factor == 1f32,
+            None => true,
+        }
+    }
+
+    /// Blends pre-multiplied colors.
+    pub fn blend(self, src: Color, dst: Color) -> Color {
+        let (sa, da) = (src[3], dst[3]);
+        if let Some((fs, fd)) = self.porter_duff_factors(sa, da) {
+            let mut c = [0f32; 4];
+            for k in 0..4 {
+                c[k] = (src[k] * fs + dst[k] * fd).min(1f32);
+            }
+            return c;
+        }
+        let cs = unpremultiply(src);
+        let cb = unpremultiply(dst);
+        let (cs, cb) = ([cs[0], cs[1], cs[2]], [cb[0], cb[1], cb[2]]);
+        let b = match self {
+            BlendMode::Hue => set_lum(set_sat(cs, sat(cb)), lum(cb)),
+            BlendMode::Saturation => set_lum(set_sat(cb, sat(cs)), lum(cb)),
+            BlendMode::Color => set_lum(cs, lum(cb)),
+            BlendMode::Luminosity => set_lum(cb, lum(cs)),
+            _ => {
+                let mut b = [0f32; 3];
+                for k in 0..3 {
+                    b[k] = self.blend_channel(cs[k], cb[k]);
+                }
+                b
+            }
+        };
+        let mut c = [0f32; 4];
+        for k in 0..3 {
+            c[k] = src[k] * (1f32 - da) + dst[k] * (1f32 - sa) + sa * da * b[k];
+        }
+        c[3] = sa + da - sa * da;
+        c
+    }
+
+    /// The separable blend function, on straight colors.
+    fn blend_channel(self, cs: f32, cb: f32) -> f32 {
+        match self {
+            BlendMode::Multiply => cs * cb,
+            BlendMode::Screen => cs + cb - cs * cb,
+            BlendMode::Overlay => BlendMode::HardLight.blend_channel(cb, cs),
+            BlendMode::Darken => cs.min(cb),
+            BlendMode::Lighten => cs.max(cb),
+            BlendMode::ColorDodge => {
+                if cb == 0f32 {
+                    0f32
+                } else if cs >= 1f32 {
+                    1f32
+                } else {
+                    (cb / (1f32 - cs)).min(1f32)
+                }
+            }
+            BlendMode::ColorBurn => {
+                if cb >= 1f32 {
+                    1f32
+                } else if cs == 0f32 {
+                    0f32
+                } else {
+                    1f32 - ((1f32 - cb) / cs).min(1f32)
+                }
+            }
+            BlendMode::HardLight => {
+                if cs <= 0.5f32 {
+                    cb * 2f32 * cs
+                } else {
+                    BlendMode::Screen.blend_channel(cb, 2f32 * cs - 1f32)
+                }
+            }
+            BlendMode::SoftLight => {
+                if cs <= 0.5f32 {
+                    cb - (1f32 - 2f32 * cs) * cb * (1f32 - cb)
+                } else {
+                    let d = if cb <= 0.25f32 {
+                        ((16f32 * cb - 12f32) * cb + 4f32) * cb
+                    } else {
+                        cb.sqrt()
+                    };
+                    cb + (2f32 * cs - 1f32) * (d - cb)
+                }
+            }
+            BlendMode::Difference => (cs - cb).abs(),
+            BlendMode::Exclusion => cs + cb - 2f32 * cs * cb,
+            _ => cs,
+        }
+    }
+}
+
+fn lum(c: [f32; 3]) -> f32 {
+    0.3f32 * c[0] + 0.59f32 * c[1] + 0.11f32 * c[2]
+}
+
+fn set_lum(c: [f32; 3], l: f32) -> [f32; 3] {
+    let d = l - lum(c);
+    let c = [c[0] + d, c[1] + d, c[2] + d];
+    // clip the color in gamut, keeping its luminosity
+    let l = lum(c);
+    let n = c[0].min(c[1]).min(c[2]);
+    let x = c[0].max(c[1]).max(c[2]);
+    let mut res = c;
+    for v in res.iter_mut() {
+        if n < 0f32 {
+            *v = l + (*v - l) * l / (l - n);
+        }
+        if x > 1f32 {
+            *v = l + (*v - l) * (1f32 - l) / (x - l);
+        }
+    }
+    res
+}
+
+fn sat(c: [f32; 3]) -> f32 {
+    c[0].max(c[1]).max(c[2]) - c[0].min(c[1]).min(c[2])
+}
+
+fn set_sat(c: [f32; 3], s: f32) -> [f32; 3] {
+    let n = c[0].min(c[1]).min(c[2]);
+    let x = c[0].max(c[1]).max(c[2]);
+    if x > n {
+        [
+            (c[0] - n) * s / (x - n),
+            (c[1] - n) * s / (x - n),
+            (c[2] - n) * s / (x - n),
+        ]
+    } else {
+        [0f32; 3]
+    }
+}
+
+/// Defines how a gradient is painted outside of its [0, 1] offset range.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ExtendMode {
+    Pad,
+    Repeat,
+    Reflect,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct GradientStop {
+    pub offset: f32,
+    pub color: Color,
+}
+
+impl GradientStop {
+    pub fn new(offset: f32, color: Color) -> GradientStop {
+        GradientStop { offset, color }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum GradientKind {
+    Linear {
+        start: FPoint,
+        end: FPoint,
+    },
+    Radial {
+        center: FPoint,
+        radius: f32,
+    },
+    /// Angles are in radians, clockwise from the x axis in a y-down coordinate system.
+    Sweep {
+        center: FPoint,
+        start_angle: f32,
+        end_angle: f32,
+    },
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Gradient {
+    pub kind: GradientKind,
+    /// Stops sorted by increasing offset.
+    pub stops: Vec<GradientStop>,
+    pub extend: ExtendMode,
+    /// Transform from the gradient space to the user space.
+    pub transform: Transform,
+}
+
+impl Gradient {
+    pub fn new(kind: GradientKind, stops: Vec<GradientStop>) -> Gradient {
+        Gradient {
+            kind,
+            stops,
+            extend: ExtendMode::Pad,
+            transform: Transform::identity(),
+        }
+    }
+
+    pub fn linear(start: FPoint, end: FPoint, stops: Vec<GradientStop>) -> Gradient {
+        Gradient::new(GradientKind::Linear { start, end }, stops)
+    }
+
+    pub fn radial(center: FPoint, radius: f32, stops: Vec<GradientStop>) -> Gradient {
+        Gradient::new(GradientKind::Radial { center, radius }, stops)
+    }
+
+    pub fn sweep(
+        center: FPoint,
+        start_angle: f32,
+        end_angle: f32,
+        stops: Vec<GradientStop>,
+    ) -> Gradient {
+        Gradient::new(
+            GradientKind::Sweep {
+                center,
+                start_angle,
+                end_angle,
+            },
+            stops,
+        )
+    }
+
+    pub fn with_extend(mut self, extend: ExtendMode) -> Gradient {
+        self.extend = extend;
+        self
+    }
+
+    pub fn with_transform(mut self, transform: Transform) -> Gradient {
+        self.transform = transform;
+        self
+    }
+
+    /// Gradient offset at `p` (in gradient space), before the extend mode is applied.
+    pub fn offset_at(&self, p: FPoint) -> f32 {
+        match self.kind {
+            GradientKind::Linear { start, end } => {
+                let d = end - start;
+                (p - start).dot(d) / d.dot(d)
+            }
+            GradientKind::Radial { center, radius } => (p - center).length() / radius,
+            GradientKind::Sweep {
+                center,
+                start_angle,
+                end_angle,
+            } => {
+                let d = p - center;
+                let a = (d.y.atan2(d.x) - start_angle).rem_euclid(2f32 * PI);
+                a / (end_angle - start_angle)
+            }
+        }
+    }
+
+    /// Maps an offset to [0, 1] according to the extend mode.
+    pub fn extend_offset(&self, t: f32) -> f32 {
+        match self.extend {
+            ExtendMode::Pad => t.clamp(0f32, 1f32),
+            ExtendMode::Repeat => t - t.floor(),
+            ExtendMode::Reflect => 1f32 - (t.rem_euclid(2f32) - 1f32).abs(),
+        }
+    }
+
+    /// Color at offset `t` in [0, 1].
+    /// Colors are interpolated with pre-multiplied alpha.
+    pub fn color_at_offset(&self, t: f32) -> Color {
+        let stops = &self.stops;
+        if stops.is_empty() {
+            return [0f32; 4];
+        }
+        let idx = stops.iter().position(|s| s.offset > t);
+        let c = match idx {
+            Some(0) => premultiply(stops[0].color),
+            None => premultiply(stops[stops.len() - 1].color),
+            Some(i) => {
+                let (s0, s1) = (&stops[i - 1], &stops[i]);
+                let f = (t - s0.offset) / (s1.offset - s0.offset);
+                let (c0, c1) = (premultiply(s0.color), premultiply(s1.color));
+                let mut c = [0f32; 4];
+                for k in 0..4 {
+                    c[k] = c0[k] + (c1[k] - c0[k]) * f;
+                }
+                c
+            }
+        };
+        unpremultiply(c)
+    }
+
+    /// Color at `p` in the user space of the gradient.
+    pub fn color_at(&self, p: FPoint) -> Color {
+        let p = match self.transform.inverse() {
+            Some(inv) => inv.apply(p),
+            None => return [0f32; 4],
+        };
+        self.color_at_offset(self.extend_offset(self.offset_at(p)))
+    }
+
+    /// Bakes the color stops in `RAMP_SIZE` pre-multiplied RGBA texels.
+    pub fn ramp(&self) -> Vec<[u8; 4]> {
+        (0..RAMP_SIZE)
+            .map(|i| {
+                let c = premultiply(self.color_at_offset(i as f32 / (RAMP_SIZE - 1) as f32));
+                let mut texel = [0u8; 4];
+                for k in 0..4 {
+                    texel[k] = (c[k].clamp(0f32, 1f32) * 255f32).round() as u8;
+                }
+                texel
+            })
+            .collect()
+    }
+}
+
+pub fn premultiply(c: Color) -> Color {
+    [c[0] * c[3], c[1] * c[3], c[2] * c[3], c[3]]
+}
+
+pub fn unpremultiply(c: Color) -> Color {
+    if c[3] > 0f32 {
+        [c[0] / c[3], c[1] / c[3], c[2] / c[3], c[3]]
+    } else {
+        [0f32; 4]
+    }
+}
+
+#[test]
+fn gradient_eval() {
+    let black = [0f32, 0f32, 0f32, 1f32];
+    let white = [1f32, 1f32, 1f32, 1f32];
+    let stops = vec![GradientStop::new(0f32, black), GradientStop::new(1f32, white)];
+
+    let lin = Gradient::linear(FPoint::new(0f32, 0f32), FPoint::new(10f32, 0f32), stops.clone());
+    assert_eq!(black, lin.color_at(FPoint::new(-5f32, 3f32)));
+    assert_eq!([0.5f32, 0.5f32, 0.5f32, 1f32], lin.color_at(FPoint::new(5f32, 3f32)));
+    assert_eq!(white, lin.color_at(FPoint::new(15f32, 3f32)));
+
+    let rep = lin.clone().with_extend(ExtendMode::Repeat);
+    assert_eq!(black, rep.color_at(FPoint::new(10f32, 0f32)));
+    let refl = lin.clone().with_extend(ExtendMode::Reflect);
+    assert_eq!(white, refl.color_at(FPoint::new(10f32, 0f32)));
+    assert_eq!([0.5f32, 0.5f32, 0.5f32, 1f32], refl.color_at(FPoint::new(15f32, 0f32)));
+
+    let moved = lin.with_transform(Transform::translation(10f32, 0f32));
+    assert_eq!([0.5f32, 0.5f32, 0.5f32, 1f32], moved.color_at(FPoint::new(15f32, 0f32)));
+
+    let rad = Gradient::radial(FPoint::new(0f32, 0f32), 10f32, stops);
+    assert_eq!(white, rad.color_at(FPoint::new(0f32, 10f32)));
+    let ramp = rad.ramp();
+    assert_eq!([0, 0, 0, 255], ramp[0]);
+    assert_eq!([255, 255, 255, 255], ramp[RAMP_SIZE - 1]);
+}
+
+#[test]
+fn blend_modes() {
+    let red = [1f32, 0f32, 0f32, 1f32];
+    let half_blue = [0f32, 0f32, 0.5f32, 0.5f32];
+    let grey = [0.5f32, 0.5f32, 0.5f32, 1f32];
+
+    assert_eq!([0.5f32, 0f32, 0.5f32, 1f32], BlendMode::SrcOver.blend(half_blue, red));
+    assert_eq!([0f32, 0f32, 0.5f32, 0.5f32], BlendMode::SrcIn.blend(half_blue, red));
+    assert_eq!([0f32; 4], BlendMode::SrcOut.blend(half_blue, red));
+    assert_eq!([0.5f32, 0f32, 0f32, 0.5f32], BlendMode::DstIn.blend(half_blue, red));
+
+    assert_eq!([0.5f32, 0f32, 0f32, 1f32], BlendMode::Multiply.blend(grey, red));
+    assert_eq!([1f32, 0.5f32, 0.5f32, 1f32], BlendMode::Screen.blend(grey, red));
+    assert_eq!([0.5f32, 0.5f32, 0.5f32, 1f32], BlendMode::Difference.blend(grey, red));
+    // blending with a transparent destination gives the source
+    for &mode in &BlendMode::ALL[13..] {
+        assert_eq!(half_blue, mode.blend(half_blue, [0f32; 4]), "{:?}", mode);
+    }
+    for &mode in &BlendMode::ALL {
+        let unchanged = mode.blend([0f32; 4], half_blue) == half_blue;
+        assert_eq!(mode.is_bounded(), unchanged, "{:?}", mode);
+    }
+    // the luminosity of the grey on the hue and saturation of the red
+    let c = BlendMode::Luminosity.blend(grey, red);
+    assert!((lum([c[0], c[1], c[2]]) - 0.5f32).abs() < 1e-5f32);
+    assert!(c[0] > c[1] && c[1] == c[2]);
+}
@@ -0,0 +1,275 @@
+use super::hit::{edge_winding, inside};
+use super::{FillRule, Path, Polyline, DEFAULT_TOLERANCE};
+use crate::geom::FPoint;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum BoolOp {
+    Union,
+    Intersection,
+    /// The area of the first path that is not in the second.
+    Difference,
+    Xor,
+}
+
+impl BoolOp {
+    fn apply(self, a: bool, b: bool) -> bool {
+        match self {
+            BoolOp::Union => a || b,
+            BoolOp::Intersection => a && b,
+            BoolOp::Difference => a && !b,
+            BoolOp::Xor => a != b,
+        }
+    }
+}
+
+/// Points are snapped to a grid of this resolution (in path units)
+/// so that vertices shared by several edges compare equal.
+const GRID: f32 = 256f32;
+/// Distance of the sample points to the edges when classifying them.
+const SAMPLE_OFFSET: f32 = 1f32 / 2048f32;
+
+impl Path {
+    /// Combines the areas filled by two paths with `fill_rule`.
+    ///
+    /// The result is made of non-overlapping closed polygons, oriented such as
+    /// the filled area is on the left of each edge. It can be filled with either rule.
+    pub fn boolean(&self, other: &Path, op: BoolOp, fill_rule: FillRule) -> Path {
+        let ea = edges(&self.flatten(DEFAULT_TOLERANCE));
+        let eb = edges(&other.flatten(DEFAULT_TOLERANCE));
+
+        let mut all: Vec<Edge> = ea.iter().chain(eb.iter()).cloned().collect();
+        split_edges(&mut all);
+
+        // keep the edges that separate the inside and the outside of the result
+        let in_result = |p: FPoint| {
+            let wa: i32 = ea.iter().map(|e| edge_winding(e.a, e.b, p)).sum();
+            let wb: i32 = eb.iter().map(|e| edge_winding(e.a, e.b, p)).sum();
+            op.apply(inside(wa, fill_rule), inside(wb, fill_rule))
+        };
+        let mut seen = HashSet::new();
+        let mut kept = Vec::new();
+        for e in all {
+            let key = if key(e.a) < key(e.b) {
+                (key(e.a), key(e.b))
+            } else {
+                (key(e.b), key(e.a))
+            };
+            if !seen.insert(key) {
+                continue;
+            }
+            let mid = e.a.lerp(e.b, 0.5f32);
+            let off = (e.b - e.a).normalize().perp() * SAMPLE_OFFSET;
+            let left = in_result(mid + off);
+            let right = in_result(mid - off);
+            if left && !right {
+                kept.push(e);
+            } else if right && !left {
+                kept.push(Edge { a: e.b, b: e.a });
+            }
+        }
+
+        chain(&kept)
+    }
+
+    pub fn union(&self, other: &Path) -> Path {
+        self.boolean(other, BoolOp::Union, FillRule::NonZero)
+    }
+
+    pub fn intersection(&self, other: &Path) -> Path {
+        self.boolean(other, BoolOp::Intersection, FillRule::NonZero)
+    }
+
+    pub fn difference(&self, other: &Path) -> Path {
+        self.boolean(other, BoolOp::Difference, FillRule::NonZero)
+    }
+
+    pub fn xor(&self, other: &Path) -> Path {
+        self.boolean(other, BoolOp::Xor, FillRule::NonZero)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Edge {
+    a: FPoint,
+    b: FPoint,
+}
+
+fn snap(p: FPoint) -> FPoint {
+    FPoint::new((p.x * GRID).round() / GRID, (p.y * GRID).round() / GRID)
+}
+
+fn key(p: FPoint) -> (i64, i64) {
+    ((p.x * GRID).round() as i64, (p.y * GRID).round() as i64)
+}
+
+/// Edges of the polylines, closed as for filling.
+fn edges(lines: &[Polyline]) -> Vec<Edge> {
+    let mut edges = Vec::new();
+    for line in lines {
+        let n = line.points.len();
+        for i in 0..n {
+            let a = snap(line.points[i]);
+            let b = snap(line.points[(i + 1) % n]);
+            if a != b {
+                edges.push(Edge { a, b });
+            }
+        }
+    }
+    edges
+}
+
+/// Splits the edges at all their mutual intersections.
+fn split_edges(edges: &mut Vec<Edge>) {
+    let mut splits: Vec<Vec<(f32, FPoint)>> = vec![Vec::new(); edges.len()];
+    for i in 0..edges.len() {
+        for j in i + 1..edges.len() {
+            intersect(edges, i, j, &mut splits);
+        }
+    }
+    let mut res = Vec::with_capacity(edges.len());
+    for (e, mut s) in edges.iter().zip(splits) {
+        s.sort_by(|l, r| l.0.partial_cmp(&r.0).unwrap());
+        let mut a = e.a;
+        for (_, p) in s.into_iter().chain(Some((1f32, e.b))) {
+            if key(p) != key(a) {
+                res.push(Edge { a, b: p });
+                a = p;
+            }
+        }
+    }
+    *edges = res;
+}
+
+fn intersect(edges: &[Edge], i: usize, j: usize, splits: &mut [Vec<(f32, FPoint)>]) {
+    let (e1, e2) = (edges[i], edges[j]);
+    let min1 = FPoint::new(e1.a.x.min(e1.b.x), e1.a.y.min(e1.b.y));
+    let max1 = FPoint::new(e1.a.x.max(e1.b.x), e1.a.y.max(e1.b.y));
+    let min2 = FPoint::new(e2.a.x.min(e2.b.x), e2.a.y.min(e2.b.y));
+    let max2 = FPoint::new(e2.a.x.max(e2.b.x), e2.a.y.max(e2.b.y));
+    if min1.x > max2.x || min2.x > max1.x || min1.y > max2.y || min2.y > max1.y {
+        return;
+    }
+
+    let d1 = e1.b - e1.a;
+    let d2 = e2.b - e2.a;
+    let denom = d1.cross(d2);
+    let eps = 1e-6f32;
+
+    if denom.abs() <= eps * d1.length() * d2.length() {
+        // parallel: split at the end points lying on the other edge if collinear
+        if (e2.a - e1.a).cross(d1).abs() > eps * d1.length() * 16f32 {
+            return;
+        }
+        for &p in &[e2.a, e2.b] {
+            let t = (p - e1.a).dot(d1) / d1.dot(d1);
+            if t > 0f32 && t < 1f32 {
+                splits[i].push((t, p));
+            }
+        }
+        for &p in &[e1.a, e1.b] {
+            let t = (p - e2.a).dot(d2) / d2.dot(d2);
+            if t > 0f32 && t < 1f32 {
+                splits[j].push((t, p));
+            }
+        }
+        return;
+    }
+
+    let t = (e2.a - e1.a).cross(d2) / denom;
+    let u = (e2.a - e1.a).cross(d1) / denom;
+    if t < -eps || t > 1f32 + eps || u < -eps || u > 1f32 + eps {
+        return;
+    }
+    // prefer existing end points to keep shared vertices exact
+    let p = if u <= eps {
+        e2.a
+    } else if u >= 1f32 - eps {
+        e2.b
+    } else if t <= eps {
+        e1.a
+    } else if t >= 1f32 - eps {
+        e1.b
+    } else {
+        snap(e1.a.lerp(e1.b, t))
+    };
+    if key(p) != key(e1.a) && key(p) != key(e1.b) {
+        splits[i].push((t, p));
+    }
+    if key(p) != key(e2.a) && key(p) != key(e2.b) {
+        splits[j].push((u, p));
+    }
+}
+
+/// Links the edges into closed contours.
+fn chain(edges: &[Edge]) -> Path {
+    let mut outgoing: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    for (i, e) in edges.iter().enumerate() {
+        outgoing.entry(key(e.a)).or_default().push(i);
+    }
+    let mut used = vec![false; edges.len()];
+    let mut b = Path::builder();
+
+    for first in 0..edges.len() {
+        if used[first] {
+            continue;
+        }
+        let mut contour = Vec::new();
+        let mut cur = first;
+        loop {
+            used[cur] = true;
+            contour.push(edges[cur].a);
+            let end = key(edges[cur].b);
+            if end == key(edges[first].a) {
+                break;
+            }
+            let next = outgoing
+                .get(&end)
+                .and_then(|out| out.iter().cloned().find(|&e| !used[e]));
+            match next {
+                Some(next) => cur = next,
+                None => break,
+            }
+        }
+        if contour.len() > 2 {
+            b.move_to(contour[0]);
+            for &p in &contour[1..] {
+                b.line_to(p);
+            }
+            b.close();
+        }
+    }
+    b.build()
+}
+
+#[test]
+fn boolean_ops() {
+    use crate::geom::FRect;
+
+    let a = Path::rect(FRect::new(0f32, 0f32, 10f32, 10f32));
+    let b = Path::rect(FRect::new(5f32, 5f32, 10f32, 10f32));
+    let pa = FPoint::new(2f32, 2f32);
+    let pab = FPoint::new(7f32, 7f32);
+    let pb = FPoint::new(12f32, 12f32);
+    let out = FPoint::new(12f32, 2f32);
+
+    let check = |p: &Path, expected: [bool; 4]| {
+        for &rule in &[FillRule::NonZero, FillRule::EvenOdd] {
+            assert_eq!(expected[0], p.contains(pa, rule));
+            assert_eq!(expected[1], p.contains(pab, rule));
+            assert_eq!(expected[2], p.contains(pb, rule));
+            assert_eq!(expected[3], p.contains(out, rule));
+        }
+    };
+    check(&a.union(&b), [true, true, true, false]);
+    check(&a.intersection(&b), [false, true, false, false]);
+    check(&a.difference(&b), [true, false, false, false]);
+    check(&a.xor(&b), [true, false, true, false]);
+
+    // shared edges
+    let c = Path::rect(FRect::new(10f32, 0f32, 10f32, 10f32));
+    let u = a.union(&c);
+    assert!(u.contains(FPoint::new(10f32, 5f32), FillRule::NonZero));
+    assert_eq!(Some(FRect::new(0f32, 0f32, 20f32, 10f32)), u.bounds());
+    assert!(a.intersection(&c).is_empty());
+}
@@ -0,0 +1,123 @@
+use super::{FillRule, Path, Polyline, StrokeStyle, DEFAULT_TOLERANCE};
+use crate::geom::FPoint;
+
+impl Path {
+    /// Winding number of the path around `p`.
+    /// Open sub-paths are implicitly closed, as they are when filled.
+    pub fn winding(&self, p: FPoint) -> i32 {
+        winding(&self.flatten(DEFAULT_TOLERANCE), p)
+    }
+
+    pub fn contains(&self, p: FPoint, fill_rule: FillRule) -> bool {
+        inside(self.winding(p), fill_rule)
+    }
+
+    /// Distance from `p` to the nearest point of the path outline.
+    /// Returns infinity for an empty path.
+    pub fn distance(&self, p: FPoint) -> f32 {
+        let mut dist = f32::INFINITY;
+        for line in self.flatten(DEFAULT_TOLERANCE) {
+            let n = line.points.len();
+            if n == 1 {
+                dist = dist.min(p.distance(line.points[0]));
+            }
+            let seg_count = if line.closed { n } else { n.saturating_sub(1) };
+            for i in 0..seg_count {
+                let (a, b) = (line.points[i], line.points[(i + 1) % n]);
+                dist = dist.min(segment_distance(p, a, b));
+            }
+        }
+        dist
+    }
+
+    /// Distance from `p` to the area covered by the stroke of this path.
+    /// Returns zero if `p` is within the stroke.
+    pub fn stroke_distance(&self, p: FPoint, style: &StrokeStyle) -> f32 {
+        let outline = self.stroke(style, DEFAULT_TOLERANCE);
+        if outline.contains(p, FillRule::NonZero) {
+            0f32
+        } else {
+            outline.distance(p)
+        }
+    }
+
+    pub fn stroke_contains(&self, p: FPoint, style: &StrokeStyle) -> bool {
+        self.stroke(style, DEFAULT_TOLERANCE)
+            .contains(p, FillRule::NonZero)
+    }
+}
+
+pub(crate) fn inside(winding: i32, fill_rule: FillRule) -> bool {
+    match fill_rule {
+        FillRule::NonZero => winding != 0,
+        FillRule::EvenOdd => winding % 2 != 0,
+    }
+}
+
+pub(crate) fn winding(lines: &[Polyline], p: FPoint) -> i32 {
+    let mut w = 0;
+    for line in lines {
+        let n = line.points.len();
+        for i in 0..n {
+            let (a, b) = (line.points[i], line.points[(i + 1) % n]);
+            w += edge_winding(a, b, p);
+        }
+    }
+    w
+}
+
+/// Contribution of the edge `a -> b` to the winding number around `p`.
+pub(crate) fn edge_winding(a: FPoint, b: FPoint, p: FPoint) -> i32 {
+    let side = (b - a).cross(p - a);
+    if a.y <= p.y {
+        if b.y > p.y && side > 0f32 {
+            return 1;
+        }
+    } else if b.y <= p.y && side < 0f32 {
+        return -1;
+    }
+    0
+}
+
+fn segment_distance(p: FPoint, a: FPoint, b: FPoint) -> f32 {
+    let ab = b - a;
+    let len2 = ab.dot(ab);
+    if len2 <= 0f32 {
+        return p.distance(a);
+    }
+    let t = ((p - a).dot(ab) / len2).clamp(0f32, 1f32);
+    p.distance(a + ab * t)
+}
+
+#[test]
+fn contains() {
+    use crate::geom::FRect;
+
+    let mut b = Path::builder();
+    b.move_to(FPoint::new(0f32, 0f32));
+    b.line_to(FPoint::new(10f32, 0f32));
+    b.line_to(FPoint::new(10f32, 10f32));
+    b.line_to(FPoint::new(0f32, 10f32));
+    b.close();
+    // inner square with same orientation
+    b.move_to(FPoint::new(3f32, 3f32));
+    b.line_to(FPoint::new(7f32, 3f32));
+    b.line_to(FPoint::new(7f32, 7f32));
+    b.line_to(FPoint::new(3f32, 7f32));
+    b.close();
+    let path = b.build();
+
+    assert!(path.contains(FPoint::new(1f32, 1f32), FillRule::NonZero));
+    assert!(path.contains(FPoint::new(5f32, 5f32), FillRule::NonZero));
+    assert!(path.contains(FPoint::new(1f32, 1f32), FillRule::EvenOdd));
+    assert!(!path.contains(FPoint::new(5f32, 5f32), FillRule::EvenOdd));
+    assert!(!path.contains(FPoint::new(11f32, 5f32), FillRule::NonZero));
+
+    let rect = Path::rect(FRect::new(0f32, 0f32, 10f32, 10f32));
+    assert_eq!(2f32, rect.distance(FPoint::new(12f32, 5f32)));
+    assert_eq!(1f32, rect.distance(FPoint::new(5f32, 1f32)));
+
+    let style = StrokeStyle::new(2f32);
+    assert_eq!(0f32, rect.stroke_distance(FPoint::new(10.5f32, 5f32), &style));
+    assert_eq!(1f32, rect.stroke_distance(FPoint::new(12f32, 5f32), &style));
+}
@@ -0,0 +1,349 @@
+use crate::geom::{FCorners, FPoint, FRect, Transform};
+
+mod boolean;
+mod hit;
+mod stroke;
+
+pub use self::boolean::BoolOp;
+pub use self::stroke::{LineCap, LineJoin, StrokeStyle};
+
+/// Default flattening tolerance, in path units.
+pub const DEFAULT_TOLERANCE: f32 = 0.1;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum FillRule {
+    NonZero,
+    EvenOdd,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Segment {
+    MoveTo(FPoint),
+    LineTo(FPoint),
+    QuadTo(FPoint, FPoint),
+    CubicTo(FPoint, FPoint, FPoint),
+    Close,
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Path {
+    segs: Vec<Segment>,
+}
+
+/// A flattened sub-path.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Polyline {
+    pub points: Vec<FPoint>,
+    pub closed: bool,
+}
+
+impl Path {
+    pub fn builder() -> PathBuilder {
+        PathBuilder {
+            segs: Vec::new(),
+            start: None,
+            current: None,
+        }
+    }
+
+    pub fn rect(rect: FRect) -> Path {
+        let mut b = Path::builder();
+        b.move_to(FPoint::new(rect.x, rect.y));
+        b.line_to(FPoint::new(rect.x + rect.w, rect.y));
+        b.line_to(FPoint::new(rect.x + rect.w, rect.y + rect.h));
+        b.line_to(FPoint::new(rect.x, rect.y + rect.h));
+        b.close();
+        b.build()
+    }
+
+    pub fn rounded_rect(rect: FRect, radii: FCorners) -> Path {
+        // control point distance of the cubic approximation of a quarter circle
+        const KAPPA: f32 = 0.552_284_8;
+        let r = radii.constrained(rect.w, rect.h);
+        let (x0, y0) = (rect.x, rect.y);
+        let (x1, y1) = (rect.x + rect.w, rect.y + rect.h);
+        let mut b = Path::builder();
+        b.move_to(FPoint::new(x0 + r.tl, y0));
+        b.line_to(FPoint::new(x1 - r.tr, y0));
+        if r.tr > 0f32 {
+            b.cubic_to(
+                FPoint::new(x1 - r.tr * (1f32 - KAPPA), y0),
+                FPoint::new(x1, y0 + r.tr * (1f32 - KAPPA)),
+                FPoint::new(x1, y0 + r.tr),
+            );
+        }
+        b.line_to(FPoint::new(x1, y1 - r.br));
+        if r.br > 0f32 {
+            b.cubic_to(
+                FPoint::new(x1, y1 - r.br * (1f32 - KAPPA)),
+                FPoint::new(x1 - r.br * (1f32 - KAPPA), y1),
+                FPoint::new(x1 - r.br, y1),
+            );
+        }
+        b.line_to(FPoint::new(x0 + r.bl, y1));
+        if r.bl > 0f32 {
+            b.cubic_to(
+                FPoint::new(x0 + r.bl * (1f32 - KAPPA), y1),
+                FPoint::new(x0, y1 - r.bl * (1f32 - KAPPA)),
+                FPoint::new(x0, y1 - r.bl),
+            );
+        }
+        b.line_to(FPoint::new(x0, y0 + r.tl));
+        if r.tl > 0f32 {
+            b.cubic_to(
+                FPoint::new(x0, y0 + r.tl * (1f32 - KAPPA)),
+                FPoint::new(x0 + r.tl * (1f32 - KAPPA), y0),
+                FPoint::new(x0 + r.tl, y0),
+            );
+        }
+        b.close();
+        b.build()
+    }
+
+    /// The segments as they are, without the implicit moves added by the builder.
+    pub(crate) fn from_segments(segs: Vec<Segment>) -> Path {
+        Path { segs }
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segs
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segs.is_empty()
+    }
+
+    /// Bounding box of all the points of the path, control points included.
+    pub fn bounds(&self) -> Option<FRect> {
+        let mut min = FPoint::new(f32::INFINITY, f32::INFINITY);
+        let mut max = FPoint::new(f32::NEG_INFINITY, f32::NEG_INFINITY);
+        let mut add = |p: FPoint| {
+            min = FPoint::new(min.x.min(p.x), min.y.min(p.y));
+            max = FPoint::new(max.x.max(p.x), max.y.max(p.y));
+        };
+        for seg in &self.segs {
+            match *seg {
+                Segment::MoveTo(p) | Segment::LineTo(p) => add(p),
+                Segment::QuadTo(c, p) => {
+                    add(c);
+                    add(p);
+                }
+                Segment::CubicTo(c1, c2, p) => {
+                    add(c1);
+                    add(c2);
+                    add(p);
+                }
+                Segment::Close => {}
+            }
+        }
+        if min.x <= max.x {
+            Some(FRect::new(min.x, min.y, max.x - min.x, max.y - min.y))
+        } else {
+            None
+        }
+    }
+
+    pub fn transformed(&self, transform: &Transform) -> Path {
+        let t = |p: FPoint| transform.apply(p);
+        Path {
+            segs: self
+                .segs
+                .iter()
+                .map(|s| match *s {
+                    Segment::MoveTo(p) => Segment::MoveTo(t(p)),
+                    Segment::LineTo(p) => Segment::LineTo(t(p)),
+                    Segment::QuadTo(c, p) => Segment::QuadTo(t(c), t(p)),
+                    Segment::CubicTo(c1, c2, p) => Segment::CubicTo(t(c1), t(c2), t(p)),
+                    Segment::Close => Segment::Close,
+                })
+                .collect(),
+        }
+    }
+
+    /// Approximates the curves with line segments that do not deviate more
+    /// than `tolerance` from the actual curves.
+    /// Consecutive duplicated points are removed.
+    pub fn flatten(&self, tolerance: f32) -> Vec<Polyline> {
+        let mut flat = Flattener {
+            lines: Vec::new(),
+            points: Vec::new(),
+            start: FPoint::new(0f32, 0f32),
+        };
+        for seg in &self.segs {
+            match *seg {
+                Segment::MoveTo(p) => {
+                    flat.finish(false);
+                    flat.start = p;
+                    flat.push(p);
+                }
+                Segment::LineTo(p) => {
+                    flat.begin();
+                    flat.push(p);
+                }
+                Segment::QuadTo(c, p) => {
+                    let p0 = flat.begin();
+                    let dd = (p0 - c * 2f32 + p).length();
+                    let n = segment_count(0.25f32 * dd, tolerance);
+                    for i in 1..=n {
+                        let t = i as f32 / n as f32;
+                        let mt = 1f32 - t;
+                        flat.push(p0 * (mt * mt) + c * (2f32 * mt * t) + p * (t * t));
+                    }
+                }
+                Segment::CubicTo(c1, c2, p) => {
+                    let p0 = flat.begin();
+                    let dd = f32::max(
+                        (p0 - c1 * 2f32 + c2).length(),
+                        (c1 - c2 * 2f32 + p).length(),
+                    );
+                    let n = segment_count(0.75f32 * dd, tolerance);
+                    for i in 1..=n {
+                        let t = i as f32 / n as f32;
+                        let mt = 1f32 - t;
+                        flat.push(
+                            p0 * (mt * mt * mt)
+                                + c1 * (3f32 * mt * mt * t)
+                                + c2 * (3f32 * mt * t * t)
+                                + p * (t * t * t),
+                        );
+                    }
+                }
+                Segment::Close => {
+                    flat.finish(true);
+                }
+            }
+        }
+        flat.finish(false);
+        flat.lines
+    }
+}
+
+/// Number of line segments needed to flatten a curve (Wang's formula).
+/// `dd` is the scaled maximum second difference of the control points.
+fn segment_count(dd: f32, tolerance: f32) -> usize {
+    let n = (dd / tolerance.max(1e-4f32)).sqrt().ceil();
+    if n.is_finite() {
+        (n as usize).clamp(1, 512)
+    } else {
+        1
+    }
+}
+
+struct Flattener {
+    lines: Vec<Polyline>,
+    points: Vec<FPoint>,
+    start: FPoint,
+}
+
+impl Flattener {
+    /// Ensures a sub-path is started and returns its current point.
+    fn begin(&mut self) -> FPoint {
+        if self.points.is_empty() {
+            self.points.push(self.start);
+        }
+        *self.points.last().unwrap()
+    }
+
+    fn push(&mut self, p: FPoint) {
+        if self.points.last() != Some(&p) {
+            self.points.push(p);
+        }
+    }
+
+    fn finish(&mut self, closed: bool) {
+        if self.points.is_empty() {
+            return;
+        }
+        let mut points = std::mem::take(&mut self.points);
+        if closed && points.len() > 1 && points.first() == points.last() {
+            points.pop();
+        }
+        self.lines.push(Polyline { points, closed });
+    }
+}
+
+pub struct PathBuilder {
+    segs: Vec<Segment>,
+    start: Option<FPoint>,
+    current: Option<FPoint>,
+}
+
+impl PathBuilder {
+    pub fn move_to(&mut self, p: FPoint) -> &mut PathBuilder {
+        self.segs.push(Segment::MoveTo(p));
+        self.start = Some(p);
+        self.current = Some(p);
+        self
+    }
+
+    pub fn line_to(&mut self, p: FPoint) -> &mut PathBuilder {
+        if self.ensure_current(p) {
+            self.segs.push(Segment::LineTo(p));
+            self.current = Some(p);
+        }
+        self
+    }
+
+    pub fn quad_to(&mut self, c: FPoint, p: FPoint) -> &mut PathBuilder {
+        self.ensure_current(c);
+        self.segs.push(Segment::QuadTo(c, p));
+        self.current = Some(p);
+        self
+    }
+
+    pub fn cubic_to(&mut self, c1: FPoint, c2: FPoint, p: FPoint) -> &mut PathBuilder {
+        self.ensure_current(c1);
+        self.segs.push(Segment::CubicTo(c1, c2, p));
+        self.current = Some(p);
+        self
+    }
+
+    pub fn close(&mut self) -> &mut PathBuilder {
+        if self.current.is_some() {
+            self.segs.push(Segment::Close);
+            self.current = self.start;
+        }
+        self
+    }
+
+    pub fn current(&self) -> Option<FPoint> {
+        self.current
+    }
+
+    pub fn build(&mut self) -> Path {
+        self.start = None;
+        self.current = None;
+        Path {
+            segs: std::mem::take(&mut self.segs),
+        }
+    }
+
+    /// Starts a sub-path at `p` if there is no current point.
+    /// Returns whether there was a current point.
+    fn ensure_current(&mut self, p: FPoint) -> bool {
+        if self.current.is_none() {
+            self.move_to(p);
+            false
+        } else {
+            true
+        }
+    }
+}
+
+#[test]
+fn rounded_rect() {
+    use crate::geom::FCorners;
+
+    let rect = FRect::new(0f32, 0f32, 20f32, 10f32);
+    // radii too large for the height are scaled down to 5
+    let p = Path::rounded_rect(rect, FCorners::uniform(8f32));
+    assert_eq!(Some(rect), p.bounds());
+    assert!(!p.contains(FPoint::new(0.5f32, 0.5f32), FillRule::NonZero));
+    assert!(!p.contains(FPoint::new(19.5f32, 9.5f32), FillRule::NonZero));
+    assert!(p.contains(FPoint::new(5f32, 0.6f32), FillRule::NonZero));
+    assert!(p.contains(FPoint::new(10f32, 5f32), FillRule::NonZero));
+
+    let p = Path::rounded_rect(rect, FCorners::new(0f32, 0f32, 5f32, 0f32));
+    assert!(p.contains(FPoint::new(0.5f32, 0.5f32), FillRule::NonZero));
+    assert!(!p.contains(FPoint::new(19.5f32, 9.5f32), FillRule::NonZero));
+}
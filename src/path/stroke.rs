@@ -0,0 +1,379 @@
+use super::{Path, PathBuilder, Polyline};
+use crate::geom::FPoint;
+use std::f32::consts::PI;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum LineJoin {
+    Miter,
+    Round,
+    Bevel,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum LineCap {
+    Butt,
+    Round,
+    Square,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct StrokeStyle {
+    pub width: f32,
+    pub join: LineJoin,
+    pub cap: LineCap,
+    /// Maximum ratio between the miter length and the stroke width
+    /// before a miter join is turned into a bevel.
+    pub miter_limit: f32,
+    /// Alternating lengths of dashes and gaps. An empty array means a solid line.
+    /// An array of odd length is repeated to yield an even number of entries.
+    pub dashes: Vec<f32>,
+    pub dash_offset: f32,
+}
+
+impl StrokeStyle {
+    pub fn new(width: f32) -> StrokeStyle {
+        StrokeStyle {
+            width,
+            join: LineJoin::Miter,
+            cap: LineCap::Butt,
+            miter_limit: 4f32,
+            dashes: Vec::new(),
+            dash_offset: 0f32,
+        }
+    }
+
+    pub fn with_join(mut self, join: LineJoin) -> StrokeStyle {
+        self.join = join;
+        self
+    }
+
+    pub fn with_cap(mut self, cap: LineCap) -> StrokeStyle {
+        self.cap = cap;
+        self
+    }
+
+    pub fn with_miter_limit(mut self, miter_limit: f32) -> StrokeStyle {
+        self.miter_limit = miter_limit;
+        self
+    }
+
+    pub fn with_dashes(mut self, dashes: Vec<f32>, offset: f32) -> StrokeStyle {
+        self.dashes = dashes;
+        self.dash_offset = offset;
+        self
+    }
+}
+
+impl Default for StrokeStyle {
+    fn default() -> StrokeStyle {
+        StrokeStyle::new(1f32)
+    }
+}
+
+impl Path {
+    /// Computes the outline of the stroke of this path.
+    ///
+    /// The outline is made of closed polygons that overlap each other.
+    /// They are all oriented the same way and must be filled with the `NonZero` rule.
+    pub fn stroke(&self, style: &StrokeStyle, tolerance: f32) -> Path {
+        let mut stroker = Stroker {
+            style,
+            hw: style.width / 2f32,
+            tolerance: tolerance.max(1e-4f32),
+            builder: Path::builder(),
+        };
+        if stroker.hw <= 0f32 || stroker.hw.is_nan() {
+            return Path::default();
+        }
+        let lines = self.flatten(tolerance);
+        let lines = match dash_pattern(&style.dashes) {
+            Some(pattern) => dash(&lines, &pattern, style.dash_offset),
+            None => lines,
+        };
+        for line in &lines {
+            stroker.polyline(line);
+        }
+        stroker.builder.build()
+    }
+}
+
+struct Stroker<'a> {
+    style: &'a StrokeStyle,
+    hw: f32,
+    tolerance: f32,
+    builder: PathBuilder,
+}
+
+impl<'a> Stroker<'a> {
+    fn polyline(&mut self, line: &Polyline) {
+        let pts = &line.points;
+        let n = pts.len();
+        if n == 0 {
+            return;
+        }
+        if n == 1 {
+            self.dot(pts[0]);
+            return;
+        }
+        let closed = line.closed && n > 2;
+        let seg_count = if closed { n } else { n - 1 };
+        for i in 0..seg_count {
+            let (p0, p1) = (pts[i], pts[(i + 1) % n]);
+            let nrm = (p1 - p0).normalize().perp() * self.hw;
+            self.polygon(&[p0 + nrm, p1 + nrm, p1 - nrm, p0 - nrm]);
+        }
+        let joins = if closed { 0..n } else { 1..n - 1 };
+        for i in joins {
+            let prev = pts[(i + n - 1) % n];
+            self.join(prev, pts[i], pts[(i + 1) % n]);
+        }
+        if !closed {
+            self.cap(pts[0], (pts[0] - pts[1]).normalize());
+            self.cap(pts[n - 1], (pts[n - 1] - pts[n - 2]).normalize());
+        }
+    }
+
+    fn join(&mut self, prev: FPoint, p: FPoint, next: FPoint) {
+        let d0 = (p - prev).normalize();
+        let d1 = (next - p).normalize();
+        let cross = d0.cross(d1);
+        let dot = d0.dot(d1);
+        if cross.abs() < 1e-6f32 && dot > 0f32 {
+            return;
+        }
+        // the outer side of the turn is the one opposite to the turn direction
+        let side = if cross > 0f32 { -self.hw } else { self.hw };
+        let o0 = d0.perp() * side;
+        let o1 = d1.perp() * side;
+        match self.style.join {
+            LineJoin::Bevel => self.polygon(&[p, p + o0, p + o1]),
+            LineJoin::Miter => {
+                // sin of the half angle between the two segments
+                let sin_half = ((1f32 + dot) / 2f32).max(0f32).sqrt();
+                let ratio = 1f32 / sin_half;
+                if sin_half > 0f32 && ratio <= self.style.miter_limit {
+                    let miter = (o0 + o1).normalize() * (self.hw * ratio);
+                    self.polygon(&[p, p + o0, p + miter, p + o1]);
+                } else {
+                    self.polygon(&[p, p + o0, p + o1]);
+                }
+            }
+            LineJoin::Round => {
+                let a0 = o0.y.atan2(o0.x);
+                let mut sweep = o1.y.atan2(o1.x) - a0;
+                if sweep > PI {
+                    sweep -= 2f32 * PI;
+                } else if sweep < -PI {
+                    sweep += 2f32 * PI;
+                }
+                let mut pts = vec![p];
+                self.arc(&mut pts, p, a0, sweep);
+                self.polygon(&pts);
+            }
+        }
+    }
+
+    /// Adds a cap at `p`, `dir` being the unit vector pointing out of the line.
+    fn cap(&mut self, p: FPoint, dir: FPoint) {
+        let nrm = dir.perp() * self.hw;
+        match self.style.cap {
+            LineCap::Butt => {}
+            LineCap::Square => {
+                let ext = dir * self.hw;
+                self.polygon(&[p + nrm, p + nrm + ext, p - nrm + ext, p - nrm]);
+            }
+            LineCap::Round => {
+                let mut pts = Vec::new();
+                self.arc(&mut pts, p, nrm.y.atan2(nrm.x), -PI);
+                self.polygon(&pts);
+            }
+        }
+    }
+
+    /// Stroke of a zero-length sub-path.
+    fn dot(&mut self, p: FPoint) {
+        let hw = self.hw;
+        match self.style.cap {
+            LineCap::Butt => {}
+            LineCap::Square => self.polygon(&[
+                p + FPoint::new(-hw, -hw),
+                p + FPoint::new(hw, -hw),
+                p + FPoint::new(hw, hw),
+                p + FPoint::new(-hw, hw),
+            ]),
+            LineCap::Round => {
+                let mut pts = Vec::new();
+                self.arc(&mut pts, p, 0f32, 2f32 * PI);
+                pts.pop();
+                self.polygon(&pts);
+            }
+        }
+    }
+
+    /// Pushes the points of an arc of radius `hw` centered on `c`,
+    /// end points included.
+    fn arc(&self, pts: &mut Vec<FPoint>, c: FPoint, start: f32, sweep: f32) {
+        let max_step = if self.tolerance < self.hw {
+            2f32 * (1f32 - self.tolerance / self.hw).acos()
+        } else {
+            PI / 2f32
+        };
+        let n = (sweep.abs() / max_step.max(0.01f32)).ceil().max(1f32) as usize;
+        for i in 0..=n {
+            let a = start + sweep * i as f32 / n as f32;
+            pts.push(c + FPoint::new(a.cos(), a.sin()) * self.hw);
+        }
+    }
+
+    /// Adds a closed polygon with a positive signed area.
+    fn polygon(&mut self, pts: &[FPoint]) {
+        let mut area = 0f32;
+        for i in 0..pts.len() {
+            area += pts[i].cross(pts[(i + 1) % pts.len()]);
+        }
+        if area.abs() < 1e-9f32 {
+            return;
+        }
+        let pts: Vec<FPoint> = if area > 0f32 {
+            pts.to_vec()
+        } else {
+            pts.iter().rev().cloned().collect()
+        };
+        self.builder.move_to(pts[0]);
+        for &p in &pts[1..] {
+            self.builder.line_to(p);
+        }
+        self.builder.close();
+    }
+}
+
+/// Validates the dash array and makes it of even length.
+fn dash_pattern(dashes: &[f32]) -> Option<Vec<f32>> {
+    if dashes.is_empty() || dashes.iter().any(|&d| d < 0f32 || !d.is_finite()) {
+        return None;
+    }
+    if dashes.iter().sum::<f32>() <= 0f32 {
+        return None;
+    }
+    let mut pattern = dashes.to_vec();
+    if pattern.len() % 2 == 1 {
+        pattern.extend_from_slice(dashes);
+    }
+    Some(pattern)
+}
+
+/// Splits the polylines into the "on" parts of the dash pattern.
+/// The pattern restarts at each sub-path.
+fn dash(lines: &[Polyline], pattern: &[f32], offset: f32) -> Vec<Polyline> {
+    let total: f32 = pattern.iter().sum();
+    let mut res = Vec::new();
+
+    for line in lines {
+        // locate the offset in the pattern
+        let mut idx = 0;
+        let mut left = offset % total;
+        if left < 0f32 {
+            left += total;
+        }
+        while left >= pattern[idx] {
+            left -= pattern[idx];
+            idx = (idx + 1) % pattern.len();
+        }
+        left = pattern[idx] - left;
+
+        let pts = &line.points;
+        let n = pts.len();
+        let seg_count = if line.closed { n } else { n.saturating_sub(1) };
+
+        let first = res.len();
+        let on_at_start = idx % 2 == 0;
+        let mut cur: Vec<FPoint> = if on_at_start && n > 0 {
+            vec![pts[0]]
+        } else {
+            Vec::new()
+        };
+
+        for i in 0..seg_count {
+            let (p0, p1) = (pts[i], pts[(i + 1) % n]);
+            let len = p0.distance(p1);
+            let mut pos = 0f32;
+            while len - pos > left {
+                pos += left;
+                let p = p0.lerp(p1, pos / len);
+                if idx % 2 == 0 {
+                    cur.push(p);
+                    res.push(Polyline {
+                        points: std::mem::take(&mut cur),
+                        closed: false,
+                    });
+                } else {
+                    cur.push(p);
+                }
+                idx = (idx + 1) % pattern.len();
+                left = pattern[idx];
+            }
+            left -= len - pos;
+            if idx % 2 == 0 {
+                cur.push(p1);
+            }
+        }
+
+        if !cur.is_empty() {
+            if line.closed && res.len() == first {
+                // the whole sub-path is in a single dash
+                res.push(line.clone());
+            } else if line.closed && on_at_start {
+                // the last dash continues into the first one
+                let head = std::mem::take(&mut res[first].points);
+                cur.extend(head.into_iter().skip(1));
+                res[first].points = cur;
+            } else {
+                res.push(Polyline {
+                    points: cur,
+                    closed: false,
+                });
+            }
+        }
+    }
+
+    for line in &mut res {
+        line.points.dedup();
+    }
+    res
+}
+
+#[test]
+fn stroke_caps() {
+    use crate::geom::FRect;
+
+    let mut b = Path::builder();
+    b.move_to(FPoint::new(0f32, 0f32));
+    b.line_to(FPoint::new(10f32, 0f32));
+    let line = b.build();
+
+    let butt = line.stroke(&StrokeStyle::new(2f32), 0.1f32);
+    assert_eq!(Some(FRect::new(0f32, -1f32, 10f32, 2f32)), butt.bounds());
+
+    let square = line.stroke(&StrokeStyle::new(2f32).with_cap(LineCap::Square), 0.1f32);
+    assert_eq!(Some(FRect::new(-1f32, -1f32, 12f32, 2f32)), square.bounds());
+}
+
+#[test]
+fn stroke_dashes() {
+    let mut b = Path::builder();
+    b.move_to(FPoint::new(0f32, 0f32));
+    b.line_to(FPoint::new(10f32, 0f32));
+    let line = b.build();
+
+    let style = StrokeStyle::new(2f32).with_dashes(vec![2f32, 3f32], 0f32);
+    let dashed = line.stroke(&style, 0.1f32);
+    let contours = dashed
+        .segments()
+        .iter()
+        .filter(|s| matches!(s, super::Segment::MoveTo(_)))
+        .count();
+    assert_eq!(2, contours);
+    let bounds = dashed.bounds().unwrap();
+    assert_eq!(0f32, bounds.x);
+    assert_eq!(7f32, bounds.x + bounds.w);
+}
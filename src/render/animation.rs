@@ -0,0 +1,376 @@
+use super::frame::Cmd;
+use crate::geom::{FPoint, Transform};
+use std::time::{Duration, Instant};
+
+/// How the progress between two keyframes maps to the progress of the value.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Easing {
+    Linear,
+    /// A cubic Bézier curve from (0, 0) to (1, 1) with the control points (x1, y1)
+    /// and (x2, y2), like the CSS `cubic-bezier` function.
+    /// x1 and x2 are clamped to [0, 1].
+    CubicBezier(f32, f32, f32, f32),
+    /// Jumps at the end of each of the steps, like the CSS `steps` function.
+    Steps(u32),
+}
+
+impl Easing {
+    pub const EASE: Easing = Easing::CubicBezier(0.25, 0.1, 0.25, 1.0);
+    pub const EASE_IN: Easing = Easing::CubicBezier(0.42, 0.0, 1.0, 1.0);
+    pub const EASE_OUT: Easing = Easing::CubicBezier(0.0, 0.0, 0.58, 1.0);
+    pub const EASE_IN_OUT: Easing = Easing::CubicBezier(0.42, 0.0, 0.58, 1.0);
+
+    /// The progress of the value at the progress `t` of the time, from 0 to 1.
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0f32, 1f32);
+        match *self {
+            Easing::Linear => t,
+            Easing::CubicBezier(x1, y1, x2, y2) => {
+                cubic_bezier(x1.clamp(0f32, 1f32), y1, x2.clamp(0f32, 1f32), y2, t)
+            }
+            Easing::Steps(steps) => {
+                let steps = steps.max(1) as f32;
+                (t * steps).floor() / steps
+            }
+        }
+    }
+}
+
+/// The y of the curve at `x`, which is monotonic in x as x1 and x2 are in [0, 1].
+fn cubic_bezier(x1: f32, y1: f32, x2: f32, y2: f32, x: f32) -> f32 {
+    // the coordinates are polynomials of the curve parameter
+    let eval = |p1: f32, p2: f32, s: f32| {
+        let c = 3f32 * p1;
+        let b = 3f32 * (p2 - p1) - c;
+        let a = 1f32 - c - b;
+        ((a * s + b) * s + c) * s
+    };
+    let (mut lo, mut hi) = (0f32, 1f32);
+    for _ in 0..24 {
+        let mid = (lo + hi) / 2f32;
+        if eval(x1, x2, mid) < x {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    eval(y1, y2, (lo + hi) / 2f32)
+}
+
+/// A value taken at a time of an iteration of an animation.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Keyframe<T> {
+    /// From the start of the iteration.
+    pub time: Duration,
+    pub value: T,
+    /// How the value goes from this keyframe to the next one.
+    pub easing: Easing,
+}
+
+impl<T> Keyframe<T> {
+    pub fn new(time: Duration, value: T) -> Keyframe<T> {
+        Keyframe {
+            time,
+            value,
+            easing: Easing::Linear,
+        }
+    }
+
+    pub fn with_easing(mut self, easing: Easing) -> Keyframe<T> {
+        self.easing = easing;
+        self
+    }
+}
+
+/// A transform interpolated by components, so that rotations can exceed a turn.
+/// It scales, then rotates, then translates, about the origin of the animation.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct TransformValue {
+    pub translate: FPoint,
+    /// Radians, clockwise like `Transform::rotation`.
+    pub rotate: f32,
+    pub scale: FPoint,
+}
+
+impl TransformValue {
+    pub fn identity() -> TransformValue {
+        TransformValue {
+            translate: FPoint::new(0f32, 0f32),
+            rotate: 0f32,
+            scale: FPoint::new(1f32, 1f32),
+        }
+    }
+
+    pub fn translation(x: f32, y: f32) -> TransformValue {
+        TransformValue {
+            translate: FPoint::new(x, y),
+            ..TransformValue::identity()
+        }
+    }
+
+    pub fn rotation(angle: f32) -> TransformValue {
+        TransformValue {
+            rotate: angle,
+            ..TransformValue::identity()
+        }
+    }
+
+    pub fn scale(sx: f32, sy: f32) -> TransformValue {
+        TransformValue {
+            scale: FPoint::new(sx, sy),
+            ..TransformValue::identity()
+        }
+    }
+
+    fn transform(&self, origin: FPoint) -> Transform {
+        let (t, o) = (self.translate, origin);
+        Transform::translation(-o.x, -o.y)
+            .then(&Transform::scale(self.scale.x, self.scale.y))
+            .then(&Transform::rotation(self.rotate))
+            .then(&Transform::translation(o.x + t.x, o.y + t.y))
+    }
+}
+
+/// An animated property of a layer.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Property {
+    /// Replaces the opacity of the layer.
+    Opacity(Vec<Keyframe<f32>>),
+    /// Transforms the content of the layer, but not its mask,
+    /// about `origin` in the user space of the layer.
+    Transform {
+        origin: FPoint,
+        keyframes: Vec<Keyframe<TransformValue>>,
+    },
+    /// Translates the content of the layer, but not its mask, by the opposite of the offset.
+    /// It applies before the transform.
+    ScrollOffset(Vec<Keyframe<FPoint>>),
+}
+
+/// A property animated by the render thread, each time it renders, until the frame
+/// is replaced by another one of the same window.
+/// An iteration lasts until the time of the last keyframe.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Animation {
+    pub property: Property,
+    /// Start of the first iteration. The value of the first keyframe is used before it.
+    pub start: Instant,
+    /// Number of iterations, endless if None.
+    /// The value of the last keyframe is kept after the last one.
+    pub iterations: Option<u32>,
+}
+
+impl Animation {
+    /// An animation of one iteration.
+    pub fn new(property: Property, start: Instant) -> Animation {
+        Animation {
+            property,
+            start,
+            iterations: Some(1),
+        }
+    }
+
+    pub fn with_iterations(mut self, iterations: u32) -> Animation {
+        self.iterations = Some(iterations);
+        self
+    }
+
+    pub fn endless(mut self) -> Animation {
+        self.iterations = None;
+        self
+    }
+
+    /// Duration of an iteration.
+    pub fn duration(&self) -> Duration {
+        let last = match &self.property {
+            Property::Opacity(keyframes) => keyframes.last().map(|k| k.time),
+            Property::Transform { keyframes, .. } => keyframes.last().map(|k| k.time),
+            Property::ScrollOffset(keyframes) => keyframes.last().map(|k| k.time),
+        };
+        last.unwrap_or_default()
+    }
+
+    /// Whether the value may change after `now`.
+    pub fn is_running(&self, now: Instant) -> bool {
+        let duration = self.duration();
+        if duration == Duration::default() {
+            return false;
+        }
+        match self.iterations {
+            None => true,
+            Some(n) => now < self.start + duration * n,
+        }
+    }
+
+    /// Time in the current iteration.
+    fn iteration_time(&self, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.start);
+        let duration = self.duration();
+        match self.iterations {
+            _ if duration == Duration::default() => elapsed,
+            Some(n) if elapsed >= duration * n => duration,
+            _ => Duration::from_nanos((elapsed.as_nanos() % duration.as_nanos()) as u64),
+        }
+    }
+}
+
+trait Interpolate: Copy {
+    fn lerp(a: Self, b: Self, t: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn lerp(a: f32, b: f32, t: f32) -> f32 {
+        a + (b - a) * t
+    }
+}
+
+impl Interpolate for FPoint {
+    fn lerp(a: FPoint, b: FPoint, t: f32) -> FPoint {
+        FPoint::new(f32::lerp(a.x, b.x, t), f32::lerp(a.y, b.y, t))
+    }
+}
+
+impl Interpolate for TransformValue {
+    fn lerp(a: TransformValue, b: TransformValue, t: f32) -> TransformValue {
+        TransformValue {
+            translate: FPoint::lerp(a.translate, b.translate, t),
+            rotate: f32::lerp(a.rotate, b.rotate, t),
+            scale: FPoint::lerp(a.scale, b.scale, t),
+        }
+    }
+}
+
+/// The value at `time` in an iteration. The keyframes are sorted by time.
+fn sample<T: Interpolate>(keyframes: &[Keyframe<T>], time: Duration) -> Option<T> {
+    let first = keyframes.first()?;
+    if time <= first.time {
+        return Some(first.value);
+    }
+    for pair in keyframes.windows(2) {
+        let (a, b) = (&pair[0], &pair[1]);
+        if time < b.time {
+            let span = b.time.saturating_sub(a.time).as_secs_f32();
+            let t = if span > 0f32 {
+                time.saturating_sub(a.time).as_secs_f32() / span
+            } else {
+                1f32
+            };
+            return Some(T::lerp(a.value, b.value, a.easing.apply(t)));
+        }
+    }
+    keyframes.last().map(|k| k.value)
+}
+
+/// The animated opacity of a layer, and the animated transform of its content, at `now`.
+pub(crate) fn layer_values(
+    animations: &[Animation],
+    now: Instant,
+) -> (Option<f32>, Option<Transform>) {
+    let (mut opacity, mut scroll, mut transform) = (None, None, None);
+    for anim in animations {
+        let time = anim.iteration_time(now);
+        match &anim.property {
+            Property::Opacity(keyframes) => {
+                opacity = sample(keyframes, time).or(opacity);
+            }
+            Property::Transform { origin, keyframes } => {
+                let value = sample(keyframes, time);
+                transform = value.map(|v| v.transform(*origin)).or(transform);
+            }
+            Property::ScrollOffset(keyframes) => {
+                let offset = sample(keyframes, time);
+                scroll = offset
+                    .map(|o| Transform::translation(-o.x, -o.y))
+                    .or(scroll);
+            }
+        }
+    }
+    let transform = match (scroll, transform) {
+        (Some(scroll), Some(transform)) => Some(scroll.then(&transform)),
+        (scroll, transform) => scroll.or(transform),
+    };
+    (opacity, transform)
+}
+
+/// Whether an animation of the display list may change after `now`.
+pub(crate) fn running(cmds: &[Cmd], now: Instant) -> bool {
+    cmds.iter().any(|cmd| match cmd {
+        Cmd::PushLayer(layer) => {
+            layer.animations.iter().any(|a| a.is_running(now))
+                || layer.mask.as_ref().is_some_and(|mask| running(mask, now))
+        }
+        _ => false,
+    })
+}
+
+#[test]
+fn keyframes() {
+    let ms = Duration::from_millis;
+    assert_eq!(0.5, Easing::Linear.apply(0.5));
+    assert!((Easing::EASE_IN_OUT.apply(0.5) - 0.5).abs() < 1e-4);
+    assert!(Easing::EASE_IN.apply(0.5) < 0.5 && Easing::EASE_OUT.apply(0.5) > 0.5);
+    assert_eq!(0.25, Easing::Steps(4).apply(0.3));
+    assert_eq!(1.0, Easing::Steps(4).apply(1.0));
+
+    let keyframes = vec![
+        Keyframe::new(ms(100), 0f32),
+        Keyframe::new(ms(200), 1f32),
+        Keyframe::new(ms(400), 0f32),
+    ];
+    assert_eq!(Some(0.0), sample(&keyframes, ms(0)));
+    assert_eq!(Some(0.5), sample(&keyframes, ms(150)));
+    assert_eq!(Some(0.5), sample(&keyframes, ms(300)));
+    assert_eq!(Some(0.0), sample(&keyframes, ms(500)));
+
+    let start = Instant::now();
+    let anim = Animation::new(Property::Opacity(keyframes), start).with_iterations(2);
+    assert_eq!(ms(150), anim.iteration_time(start + ms(550)));
+    assert_eq!(ms(400), anim.iteration_time(start + ms(900)));
+    assert!(anim.is_running(start + ms(700)));
+    assert!(!anim.is_running(start + ms(800)));
+    assert!(anim.clone().endless().is_running(start + ms(800)));
+}
+
+#[test]
+fn layer_animations() {
+    use super::frame::Layer;
+    let (ms, start) = (Duration::from_millis, Instant::now());
+    let fade = |from: f32, to: f32| {
+        let keyframes = vec![Keyframe::new(ms(0), from), Keyframe::new(ms(100), to)];
+        Animation::new(Property::Opacity(keyframes), start)
+    };
+    // the last animation of a property wins, unless it has no keyframes
+    let now = start + ms(25);
+    let animations = vec![fade(0f32, 1f32), fade(1f32, 0f32)];
+    assert_eq!((Some(0.75), None), layer_values(&animations, now));
+    let empty = Animation::new(Property::Opacity(vec![]), start);
+    let animations = vec![fade(0f32, 1f32), empty];
+    assert_eq!((Some(0.25), None), layer_values(&animations, now));
+
+    // the content is scrolled, then transformed
+    let scale = vec![Keyframe::new(ms(0), TransformValue::scale(2f32, 2f32))];
+    let scroll = vec![Keyframe::new(ms(0), FPoint::new(10f32, 0f32))];
+    let animations = vec![
+        Animation::new(
+            Property::Transform {
+                origin: FPoint::new(0f32, 0f32),
+                keyframes: scale,
+            },
+            start,
+        ),
+        Animation::new(Property::ScrollOffset(scroll), start),
+    ];
+    let (opacity, transform) = layer_values(&animations, start);
+    assert_eq!(None, opacity);
+    let p = transform.unwrap().apply(FPoint::new(10f32, 5f32));
+    assert_eq!(FPoint::new(0f32, 10f32), p);
+
+    // the animations of the layers and of their masks keep the frame running
+    let layer = |animation: Animation| Cmd::PushLayer(Layer::new(1f32).with_animation(animation));
+    let now = start + ms(50);
+    assert!(!running(&[Cmd::PopLayer], now));
+    assert!(running(&[layer(fade(0f32, 1f32))], now));
+    assert!(!running(&[layer(fade(0f32, 1f32))], start + ms(100)));
+    let masked = Layer::new(1f32).with_mask(vec![layer(fade(0f32, 1f32).endless())]);
+    assert!(running(&[Cmd::PushLayer(masked)], start + ms(1000)));
+}
@@ -0,0 +1,51 @@
+/// How the edges of the paths and of the shapes are anti-aliased in a window.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum Antialiasing {
+    /// Edges are aliased.
+    None,
+    /// Multisampling with 2, 4 or 8 samples per pixel, resolved into the window.
+    Msaa(u8),
+    /// Coverage computed at the edges by the shaders.
+    #[default]
+    Analytic,
+}
+
+impl Antialiasing {
+    /// Samples per pixel of the render targets.
+    pub fn samples(self) -> u8 {
+        match self {
+            Antialiasing::Msaa(samples) => samples,
+            _ => 1,
+        }
+    }
+
+    /// Returns the closest setting supported by the device.
+    /// `color_counts` and `stencil_counts` are the masks of the sample counts
+    /// supported by the attachments, as in the device limits.
+    /// Multisampling falls back to fewer samples, then to analytic coverage.
+    pub(crate) fn supported(self, color_counts: u8, stencil_counts: u8) -> Antialiasing {
+        let samples = match self {
+            Antialiasing::Msaa(samples) => samples,
+            aa => return aa,
+        };
+        let counts = color_counts & stencil_counts;
+        [8u8, 4, 2]
+            .iter()
+            .cloned()
+            .find(|&s| s <= samples && counts & s != 0)
+            .map_or(Antialiasing::Analytic, Antialiasing::Msaa)
+    }
+}
+
+#[test]
+fn supported_samples() {
+    use self::Antialiasing::*;
+
+    let (color, stencil) = (0b1111, 0b0111);
+    assert_eq!(Msaa(4), Msaa(4).supported(color, stencil));
+    assert_eq!(Msaa(4), Msaa(8).supported(color, stencil));
+    assert_eq!(Msaa(2), Msaa(3).supported(color, stencil));
+    assert_eq!(Analytic, Msaa(1).supported(color, stencil));
+    assert_eq!(Analytic, Msaa(4).supported(0b0001, stencil));
+    assert_eq!(None, None.supported(0b0001, 0b0001));
+}
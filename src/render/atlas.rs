@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Heights of the shelves are multiples of this, so that they can be reused
+/// by entries of similar heights.
+const SHELF_STEP: u32 = 8;
+
+/// Texels left empty at the right and at the bottom of the entries,
+/// so that the filtering does not read the neighbours.
+const PADDING: u32 = 1;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) struct AtlasRect {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+/// Packs rectangles in a 2D texture, in horizontal shelves.
+/// When full, the shelves that were not used in the current frame
+/// are evicted, least recently used first.
+pub(crate) struct Atlas<K, V> {
+    size: (u32, u32),
+    /// Sorted by `y`, they cover the texture height from 0 to `bottom`.
+    shelves: Vec<Shelf>,
+    bottom: u32,
+    entries: HashMap<K, Entry<V>>,
+    frame: u64,
+}
+
+struct Shelf {
+    y: u32,
+    height: u32,
+    /// Start of the free part of the shelf.
+    x: u32,
+    last_used: u64,
+}
+
+struct Entry<V> {
+    rect: AtlasRect,
+    shelf_y: u32,
+    value: V,
+}
+
+impl<K: Hash + Eq, V> Atlas<K, V> {
+    pub fn new(size: (u32, u32)) -> Atlas<K, V> {
+        Atlas {
+            size,
+            shelves: Vec::new(),
+            bottom: 0,
+            entries: HashMap::new(),
+            frame: 1,
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    /// Starts a frame, the entries used from now on are kept until the next frame.
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// Looks an entry up and marks it as used in the frame.
+    pub fn get(&mut self, key: &K) -> Option<(AtlasRect, &V)> {
+        let entry = self.entries.get(key)?;
+        let frame = self.frame;
+        let i = self.shelf(entry.shelf_y);
+        self.shelves[i].last_used = frame;
+        Some((entry.rect, &entry.value))
+    }
+
+    /// Allocates a `w`×`h` rectangle for `key`, evicting unused shelves if needed.
+    /// None if it does not fit, even after eviction.
+    pub fn insert(&mut self, key: K, w: u32, h: u32, value: V) -> Option<AtlasRect> {
+        let (pw, ph) = (w + PADDING, h + PADDING);
+        if pw > self.size.0 || ph > self.size.1 {
+            return None;
+        }
+        let height = ph.div_ceil(SHELF_STEP) * SHELF_STEP;
+        let i = loop {
+            if let Some(i) = self.allocate(pw, height) {
+                break i;
+            }
+            if !self.evict() {
+                return None;
+            }
+        };
+        let frame = self.frame;
+        let shelf = &mut self.shelves[i];
+        let rect = AtlasRect {
+            x: shelf.x,
+            y: shelf.y,
+            w,
+            h,
+        };
+        shelf.x += pw;
+        shelf.last_used = frame;
+        let shelf_y = shelf.y;
+        self.entries.insert(
+            key,
+            Entry {
+                rect,
+                shelf_y,
+                value,
+            },
+        );
+        Some(rect)
+    }
+
+    fn shelf(&self, y: u32) -> usize {
+        self.shelves
+            .binary_search_by_key(&y, |s| s.y)
+            .expect("atlas entry without shelf")
+    }
+
+    /// Finds room in a shelf for a `w` wide entry, returns the index of the shelf.
+    fn allocate(&mut self, w: u32, height: u32) -> Option<usize> {
+        let width = self.size.0;
+        // the shelf wasting the least height, not more than half of it
+        let best = self
+            .shelves
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.height >= height && s.x + w <= width)
+            .filter(|(_, s)| s.x == 0 || s.height <= height + height / 2)
+            .min_by_key(|(_, s)| s.height)
+            .map(|(i, _)| i);
+        if let Some(i) = best {
+            let shelf = &self.shelves[i];
+            if shelf.x == 0 && shelf.height > height {
+                // splits the empty shelf
+                let rest = Shelf {
+                    y: shelf.y + height,
+                    height: shelf.height - height,
+                    x: 0,
+                    last_used: 0,
+                };
+                self.shelves[i].height = height;
+                self.shelves.insert(i + 1, rest);
+            }
+            return Some(i);
+        }
+        if self.bottom + height <= self.size.1 {
+            self.shelves.push(Shelf {
+                y: self.bottom,
+                height,
+                x: 0,
+                last_used: 0,
+            });
+            self.bottom += height;
+            return Some(self.shelves.len() - 1);
+        }
+        None
+    }
+
+    /// Empties the least recently used shelf that has entries and that was not
+    /// used in this frame. Returns false if there is none.
+    fn evict(&mut self) -> bool {
+        let frame = self.frame;
+        let lru = self
+            .shelves
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.x > 0 && s.last_used < frame)
+            .min_by_key(|(_, s)| s.last_used)
+            .map(|(i, _)| i);
+        let mut i = match lru {
+            Some(i) => i,
+            None => return false,
+        };
+        let y = self.shelves[i].y;
+        self.entries.retain(|_, e| e.shelf_y != y);
+        self.shelves[i].x = 0;
+
+        // merges the empty neighbours
+        if i + 1 < self.shelves.len() && self.shelves[i + 1].x == 0 {
+            let next = self.shelves.remove(i + 1);
+            self.shelves[i].height += next.height;
+        }
+        if i > 0 && self.shelves[i - 1].x == 0 {
+            let shelf = self.shelves.remove(i);
+            i -= 1;
+            self.shelves[i].height += shelf.height;
+        }
+        if i + 1 == self.shelves.len() {
+            let shelf = self.shelves.pop().unwrap();
+            self.bottom = shelf.y;
+        }
+        true
+    }
+}
+
+#[test]
+fn atlas_eviction() {
+    let rect = |x, y, w, h| AtlasRect { x, y, w, h };
+    let mut atlas = Atlas::new((64, 32));
+    let a = atlas.insert('a', 40, 10, ()).unwrap();
+    let b = atlas.insert('b', 20, 10, ()).unwrap();
+    assert_eq!(rect(0, 0, 40, 10), a);
+    assert_eq!(rect(41, 0, 20, 10), b);
+    // a second shelf
+    let c = atlas.insert('c', 30, 5, ()).unwrap();
+    assert_eq!(rect(0, 16, 30, 5), c);
+    // full, and everything is used in this frame
+    assert_eq!(None, atlas.insert('d', 30, 10, ()));
+
+    atlas.begin_frame();
+    assert!(atlas.get(&'c').is_some());
+    // the first shelf is evicted, the second one is kept
+    let d = atlas.insert('d', 30, 10, ()).unwrap();
+    assert_eq!(rect(0, 0, 30, 10), d);
+    assert!(atlas.get(&'a').is_none());
+    assert!(atlas.get(&'b').is_none());
+    assert!(atlas.get(&'c').is_some());
+
+    // too large for the texture
+    assert_eq!(None, atlas.insert('e', 64, 8, ()));
+}
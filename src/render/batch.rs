@@ -0,0 +1,324 @@
+use super::draw::{DrawData, Op, Vertex};
+use std::mem;
+use std::ops::Range;
+
+/// Size of the cells of the grid that finds the overlapping primitives, in pixels.
+const CELL_SIZE: f32 = 64f32;
+
+/// Number of draws an operation may move before, to bound the batching time.
+const MAX_LOOKBACK: usize = 64;
+
+/// How the primitives of a frame were batched.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct BatchStats {
+    /// Shapes, glyph runs and images of the display list.
+    pub primitives: usize,
+    /// Instanced draws the primitives were merged into.
+    pub batches: usize,
+    /// Draws of the frame, instanced or not.
+    pub draw_calls: usize,
+    /// Pipelines bound to record the frame.
+    pub pipeline_binds: usize,
+}
+
+/// A draw of a batched pass.
+struct Entry {
+    op: Op,
+    /// Instances merged in the draw, in painting order.
+    sources: Vec<Range<u32>>,
+}
+
+/// Merges the instanced operations of the passes into as few draws as possible.
+/// An operation only moves before the draws it does not overlap to join a compatible one,
+/// so that the frame looks as if drawn in the display list order.
+/// The instances are reordered so that each draw has a single range.
+pub(crate) fn batch(data: &mut DrawData) {
+    let mut shapes = Vec::with_capacity(data.instances.len());
+    let mut glyphs = Vec::with_capacity(data.glyphs.len());
+    let mut images = Vec::with_capacity(data.images.len());
+    for p in 0..data.passes.len() {
+        let ops = mem::take(&mut data.passes[p].ops);
+        let entries = batch_pass(data, ops);
+        let mut ops = Vec::with_capacity(entries.len());
+        for Entry { mut op, sources } in entries {
+            match &mut op {
+                Op::Shapes { instances, .. } => {
+                    *instances = gather(&data.instances, &mut shapes, &sources)
+                }
+                Op::Glyphs { instances, .. } => {
+                    *instances = gather(&data.glyphs, &mut glyphs, &sources)
+                }
+                Op::Images { instances, .. } => {
+                    *instances = gather(&data.images, &mut images, &sources)
+                }
+                _ => {}
+            }
+            ops.push(op);
+        }
+        data.passes[p].ops = ops;
+    }
+    data.instances = shapes;
+    data.glyphs = glyphs;
+    data.images = images;
+
+    let ops = data.passes.iter().flat_map(|p| &p.ops);
+    data.stats.batches = ops.clone().filter(|op| instances(op).is_some()).count();
+    data.stats.draw_calls = ops
+        .filter(|op| !matches!(op, Op::Scissor(_) | Op::Backdrop))
+        .count();
+}
+
+fn batch_pass(data: &DrawData, ops: Vec<Op>) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = Vec::with_capacity(ops.len());
+    let mut grid = Grid::new(data.viewport[2], data.viewport[3]);
+    // the operations never move before a state change
+    let mut run_start = 0;
+    for op in ops {
+        let rects = match item_rects(data, &op) {
+            Some(rects) => rects,
+            None => {
+                grid.clear();
+                entries.push(Entry::new(op));
+                run_start = entries.len();
+                continue;
+            }
+        };
+        // the operation must be drawn after the ones it overlaps
+        let first = rects
+            .iter()
+            .filter_map(|r| grid.last_overlap(r))
+            .fold(run_start, usize::max)
+            .max(entries.len().saturating_sub(MAX_LOOKBACK));
+        let target = (first..entries.len())
+            .rev()
+            .find(|&i| compatible(&entries[i].op, &op));
+        let idx = match target {
+            Some(i) => {
+                entries[i].sources.extend(instances(&op));
+                i
+            }
+            None => {
+                entries.push(Entry::new(op));
+                entries.len() - 1
+            }
+        };
+        for r in &rects {
+            grid.insert(*r, idx);
+        }
+    }
+    entries
+}
+
+impl Entry {
+    fn new(op: Op) -> Entry {
+        let sources = instances(&op).into_iter().collect();
+        Entry { op, sources }
+    }
+}
+
+fn instances(op: &Op) -> Option<Range<u32>> {
+    match op {
+        Op::Shapes { instances, .. }
+        | Op::Glyphs { instances, .. }
+        | Op::Images { instances, .. } => Some(instances.clone()),
+        _ => None,
+    }
+}
+
+/// Whether two operations can be drawn at once.
+fn compatible(a: &Op, b: &Op) -> bool {
+    match (a, b) {
+        (
+            Op::Shapes {
+                paint: pa,
+                blend_mode: ba,
+                ..
+            },
+            Op::Shapes {
+                paint: pb,
+                blend_mode: bb,
+                ..
+            },
+        ) => pa == pb && ba == bb,
+        (
+            Op::Glyphs {
+                paint: pa,
+                blend_mode: ba,
+                ..
+            },
+            Op::Glyphs {
+                paint: pb,
+                blend_mode: bb,
+                ..
+            },
+        ) => pa == pb && ba == bb,
+        (
+            Op::Images {
+                image: ia,
+                filter: fa,
+                opacity: oa,
+                blend_mode: ba,
+                ..
+            },
+            Op::Images {
+                image: ib,
+                filter: fb,
+                opacity: ob,
+                blend_mode: bb,
+                ..
+            },
+        ) => (ia, fa, oa, ba) == (ib, fb, ob, bb),
+        _ => false,
+    }
+}
+
+/// Bounds of what an operation draws, in viewport coordinates.
+/// None for the operations that change the state of the pass.
+pub(crate) fn item_rects(data: &DrawData, op: &Op) -> Option<Vec<[f32; 4]>> {
+    let range = |r: &Range<u32>| r.start as usize..r.end as usize;
+    match op {
+        Op::Shapes { instances, .. } => Some(
+            data.instances[range(instances)]
+                .iter()
+                .map(|i| transformed(i.bounds, i.mat0, i.mat1))
+                .collect(),
+        ),
+        Op::Glyphs { instances, .. } => Some(
+            data.glyphs[range(instances)]
+                .iter()
+                .map(|g| g.rect)
+                // glyphs that are not drawn
+                .filter(|r| r[0] < r[2])
+                .collect(),
+        ),
+        Op::Images { instances, .. } => Some(
+            data.images[range(instances)]
+                .iter()
+                .map(|i| transformed(i.rect, i.mat0, i.mat1))
+                .collect(),
+        ),
+        Op::Effect { consts, .. } => Some(vec![transformed(consts.rect, consts.mat0, consts.mat1)]),
+        Op::Stencil { vertices, .. } | Op::Cover { vertices, .. } | Op::Fringe { vertices, .. } => {
+            Some(vec![vertex_bounds(&data.vertices[range(vertices)])])
+        }
+        _ => None,
+    }
+}
+
+/// Bounding box of vertices, in viewport coordinates.
+pub(crate) fn vertex_bounds(vertices: &[Vertex]) -> [f32; 4] {
+    let mut r = [
+        f32::INFINITY,
+        f32::INFINITY,
+        f32::NEG_INFINITY,
+        f32::NEG_INFINITY,
+    ];
+    for v in vertices {
+        r = [
+            r[0].min(v.pos[0]),
+            r[1].min(v.pos[1]),
+            r[2].max(v.pos[0]),
+            r[3].max(v.pos[1]),
+        ];
+    }
+    r
+}
+
+/// Bounding box of a rectangle transformed by the rows `mat0` and `mat1`.
+fn transformed(r: [f32; 4], mat0: [f32; 4], mat1: [f32; 4]) -> [f32; 4] {
+    let corners = [(r[0], r[1]), (r[2], r[1]), (r[0], r[3]), (r[2], r[3])];
+    let mut b = [
+        f32::INFINITY,
+        f32::INFINITY,
+        f32::NEG_INFINITY,
+        f32::NEG_INFINITY,
+    ];
+    for &(x, y) in &corners {
+        let px = mat0[0] * x + mat0[1] * y + mat0[2];
+        let py = mat1[0] * x + mat1[1] * y + mat1[2];
+        b = [b[0].min(px), b[1].min(py), b[2].max(px), b[3].max(py)];
+    }
+    b
+}
+
+fn gather<T: Copy>(src: &[T], dst: &mut Vec<T>, sources: &[Range<u32>]) -> Range<u32> {
+    let start = dst.len() as u32;
+    for r in sources {
+        dst.extend_from_slice(&src[r.start as usize..r.end as usize]);
+    }
+    start..dst.len() as u32
+}
+
+/// The rectangles drawn since the last state change, with the index of their draw,
+/// bucketed by the cells of the viewport they cover.
+struct Grid {
+    cols: usize,
+    rows: usize,
+    cells: Vec<Vec<([f32; 4], usize)>>,
+    /// Cells that are not empty.
+    used: Vec<usize>,
+}
+
+impl Grid {
+    fn new(width: f32, height: f32) -> Grid {
+        let cols = (width / CELL_SIZE).ceil().max(1f32) as usize;
+        let rows = (height / CELL_SIZE).ceil().max(1f32) as usize;
+        Grid {
+            cols,
+            rows,
+            cells: vec![Vec::new(); cols * rows],
+            used: Vec::new(),
+        }
+    }
+
+    /// Cells covered by `r`, the ones on the border also cover the outside of the viewport.
+    fn cells(&self, r: &[f32; 4]) -> impl Iterator<Item = usize> {
+        let cell = |v: f32, n: usize| ((v / CELL_SIZE).floor().max(0f32) as usize).min(n - 1);
+        let (x0, x1) = (cell(r[0], self.cols), cell(r[2], self.cols));
+        let (y0, y1) = (cell(r[1], self.rows), cell(r[3], self.rows));
+        let cols = self.cols;
+        (y0..=y1).flat_map(move |y| (x0..=x1).map(move |x| y * cols + x))
+    }
+
+    /// Index of the last draw that overlaps `r`.
+    fn last_overlap(&self, r: &[f32; 4]) -> Option<usize> {
+        self.cells(r)
+            .flat_map(|c| &self.cells[c])
+            .filter(|(o, _)| r[0] < o[2] && o[0] < r[2] && r[1] < o[3] && o[1] < r[3])
+            .map(|&(_, idx)| idx)
+            .max()
+    }
+
+    fn insert(&mut self, r: [f32; 4], idx: usize) {
+        let cells: Vec<_> = self.cells(&r).collect();
+        for c in cells {
+            if self.cells[c].is_empty() {
+                self.used.push(c);
+            }
+            self.cells[c].push((r, idx));
+        }
+    }
+
+    fn clear(&mut self) {
+        for c in self.used.drain(..) {
+            self.cells[c].clear();
+        }
+    }
+}
+
+#[test]
+fn grid_overlaps() {
+    let mut grid = Grid::new(200f32, 100f32);
+    grid.insert([10f32, 10f32, 150f32, 20f32], 0);
+    grid.insert([10f32, 20f32, 150f32, 30f32], 1);
+    // touching edges do not overlap
+    assert_eq!(Some(0), grid.last_overlap(&[0f32, 0f32, 20f32, 20f32]));
+    assert_eq!(Some(1), grid.last_overlap(&[100f32, 15f32, 300f32, 25f32]));
+    assert_eq!(None, grid.last_overlap(&[0f32, 30f32, 200f32, 100f32]));
+    // outside of the viewport
+    grid.insert([-50f32, 90f32, -10f32, 200f32], 2);
+    assert_eq!(Some(2), grid.last_overlap(&[-20f32, 150f32, 0f32, 160f32]));
+
+    grid.clear();
+    assert_eq!(None, grid.last_overlap(&[0f32, 0f32, 200f32, 100f32]));
+}
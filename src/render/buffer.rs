@@ -0,0 +1,156 @@
+use super::memory::{Allocator, Memory, Tiling};
+use crate::gfx;
+use gfx_hal::{buffer, memory, Device};
+use std::mem;
+
+/// Alignment of the allocations of a `LinearBuffer`,
+/// enough for the vertex inputs and the copy offsets.
+const LINEAR_ALIGN: u64 = 16;
+
+/// A buffer in host visible and coherent memory.
+pub(crate) struct Buffer {
+    pub buffer: gfx::Buffer,
+    memory: Memory,
+    pub size: u64,
+}
+
+impl Buffer {
+    /// A buffer written by the host, and read by the device.
+    pub fn new(dev: &gfx::Device, alloc: &Allocator, size: u64, usage: buffer::Usage) -> Buffer {
+        // resizable BAR or unified memory
+        let preferred = memory::Properties::DEVICE_LOCAL;
+        Buffer::allocate(dev, alloc, size, usage, preferred)
+    }
+
+    /// A buffer written by the device, and read by the host.
+    /// Cached memory is read much faster than the write-combined memory of `new`.
+    pub fn readback(dev: &gfx::Device, alloc: &Allocator, size: u64) -> Buffer {
+        let preferred = memory::Properties::CPU_CACHED;
+        Buffer::allocate(dev, alloc, size, buffer::Usage::TRANSFER_DST, preferred)
+    }
+
+    fn allocate(
+        dev: &gfx::Device,
+        alloc: &Allocator,
+        size: u64,
+        usage: buffer::Usage,
+        preferred: memory::Properties,
+    ) -> Buffer {
+        unsafe {
+            let mut buffer = dev
+                .create_buffer(size, usage)
+                .expect("could not create a buffer");
+            let req = dev.get_buffer_requirements(&buffer);
+            let memory = alloc.allocate(
+                dev,
+                &req,
+                memory::Properties::CPU_VISIBLE | memory::Properties::COHERENT,
+                preferred,
+                Tiling::Linear,
+            );
+            memory.bind_buffer(dev, &mut buffer);
+            Buffer {
+                buffer,
+                memory,
+                size,
+            }
+        }
+    }
+
+    /// Makes sure that `buf` holds a buffer of at least `size` bytes.
+    /// The previous buffer must not be in use by the device anymore.
+    pub fn ensure(
+        buf: &mut Option<Buffer>,
+        dev: &gfx::Device,
+        alloc: &Allocator,
+        size: u64,
+        usage: buffer::Usage,
+    ) {
+        if buf.as_ref().map_or(false, |b| b.size >= size) {
+            return;
+        }
+        if let Some(old) = buf.take() {
+            old.destroy(dev);
+        }
+        *buf = Some(Buffer::new(dev, alloc, size.next_power_of_two(), usage));
+    }
+
+    pub fn write<T: Copy>(&self, offset: u64, data: &[T]) {
+        if !data.is_empty() {
+            assert!(offset + (data.len() * mem::size_of::<T>()) as u64 <= self.size);
+            self.memory.write(offset, data);
+        }
+    }
+
+    /// The whole content, once the device is done writing it.
+    pub fn read(&self) -> Vec<u8> {
+        let mut data = vec![0u8; self.size as usize];
+        self.memory.read(0, &mut data);
+        data
+    }
+
+    pub fn destroy(self, dev: &gfx::Device) {
+        unsafe {
+            dev.destroy_buffer(self.buffer);
+        }
+        self.memory.free();
+    }
+}
+
+/// The transient data of a frame, allocated one after the other in a buffer.
+/// The buffer grows when a frame needs more than its size.
+pub(crate) struct LinearBuffer {
+    buffer: Option<Buffer>,
+    usage: buffer::Usage,
+    head: u64,
+}
+
+impl LinearBuffer {
+    pub fn new(usage: buffer::Usage) -> LinearBuffer {
+        LinearBuffer {
+            buffer: None,
+            usage,
+            head: 0,
+        }
+    }
+
+    /// Starts a frame that allocates slices of `sizes` bytes.
+    /// The device must be done with the previous frame.
+    pub fn reset(&mut self, dev: &gfx::Device, alloc: &Allocator, sizes: &[u64]) {
+        let size = sizes
+            .iter()
+            .map(|s| s.div_ceil(LINEAR_ALIGN) * LINEAR_ALIGN)
+            .sum();
+        if size > 0 {
+            Buffer::ensure(&mut self.buffer, dev, alloc, size, self.usage);
+        }
+        self.head = 0;
+    }
+
+    /// Writes `data` after the previous slices, and returns its offset.
+    /// None if `data` is empty.
+    pub fn push<T: Copy>(&mut self, data: &[T]) -> Option<u64> {
+        if data.is_empty() {
+            return None;
+        }
+        let buf = self
+            .buffer
+            .as_ref()
+            .expect("linear buffer pushed before reset");
+        let offset = self.head;
+        buf.write(offset, data);
+        let len = (data.len() * mem::size_of::<T>()) as u64;
+        self.head += len.div_ceil(LINEAR_ALIGN) * LINEAR_ALIGN;
+        Some(offset)
+    }
+
+    pub fn buffer(&self) -> Option<&gfx::Buffer> {
+        self.buffer.as_ref().map(|b| &b.buffer)
+    }
+
+    pub fn destroy(self, dev: &gfx::Device) {
+        if let Some(buf) = self.buffer {
+            buf.destroy(dev);
+        }
+    }
+}
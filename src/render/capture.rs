@@ -0,0 +1,159 @@
+use super::buffer::Buffer;
+use super::memory::Allocator;
+use super::texture::COLOR_RANGE;
+use crate::gfx;
+use crate::image::Image;
+use gfx_hal::format::{Aspects, Format};
+use gfx_hal::{buffer, command, image, memory, pso, Device};
+
+/// The content of a window, as presented.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Capture {
+    /// Premultiplied like the frames are composited. `Image::encode_png` encodes it.
+    pub image: Image,
+    /// Format of the swapchain the pixels were converted from.
+    pub format: Format,
+}
+
+/// Whether the swapchain images of `format` can be converted to RGBA.
+pub(crate) fn is_supported(format: Format) -> bool {
+    is_bgra(format).is_some()
+}
+
+/// Whether the red and blue channels are swapped, None if not supported.
+fn is_bgra(format: Format) -> Option<bool> {
+    match format {
+        Format::Rgba8Unorm | Format::Rgba8Srgb => Some(false),
+        Format::Bgra8Unorm | Format::Bgra8Srgb => Some(true),
+        _ => None,
+    }
+}
+
+fn to_rgba(format: Format, mut pixels: Vec<u8>) -> Vec<u8> {
+    if is_bgra(format) == Some(true) {
+        for p in pixels.chunks_mut(4) {
+            p.swap(0, 2);
+        }
+    }
+    pixels
+}
+
+/// A copy of a swapchain image to a host visible buffer.
+pub(crate) struct Readback {
+    buffer: Buffer,
+    size: (u32, u32),
+    format: Format,
+}
+
+impl Readback {
+    /// Records the copy of `image`, after the render pass that left it in the `Present` layout.
+    /// The swapchain must have the `TRANSFER_SRC` usage, and a supported format.
+    pub unsafe fn record(
+        dev: &gfx::Device,
+        alloc: &Allocator,
+        cmd: &mut gfx::CommandBuffer,
+        image: &gfx::Image,
+        format: Format,
+        size: (u32, u32),
+    ) -> Readback {
+        let len = u64::from(size.0) * u64::from(size.1) * 4;
+        let buffer = Buffer::readback(dev, alloc, len);
+        let to_transfer = memory::Barrier::Image {
+            states: (
+                image::Access::COLOR_ATTACHMENT_WRITE,
+                image::Layout::Present,
+            )
+                ..(
+                    image::Access::TRANSFER_READ,
+                    image::Layout::TransferSrcOptimal,
+                ),
+            target: image,
+            families: None,
+            range: COLOR_RANGE,
+        };
+        cmd.pipeline_barrier(
+            pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT..pso::PipelineStage::TRANSFER,
+            memory::Dependencies::empty(),
+            &[to_transfer],
+        );
+        cmd.copy_image_to_buffer(
+            image,
+            image::Layout::TransferSrcOptimal,
+            &buffer.buffer,
+            &[command::BufferImageCopy {
+                buffer_offset: 0,
+                buffer_width: size.0,
+                buffer_height: size.1,
+                image_layers: image::SubresourceLayers {
+                    aspects: Aspects::COLOR,
+                    level: 0,
+                    layers: 0..1,
+                },
+                image_offset: image::Offset { x: 0, y: 0, z: 0 },
+                image_extent: image::Extent {
+                    width: size.0,
+                    height: size.1,
+                    depth: 1,
+                },
+            }],
+        );
+        let barriers = [
+            memory::Barrier::Image {
+                states: (
+                    image::Access::TRANSFER_READ,
+                    image::Layout::TransferSrcOptimal,
+                )..(image::Access::empty(), image::Layout::Present),
+                target: image,
+                families: None,
+                range: COLOR_RANGE,
+            },
+            memory::Barrier::AllBuffers(buffer::Access::TRANSFER_WRITE..buffer::Access::HOST_READ),
+        ];
+        cmd.pipeline_barrier(
+            pso::PipelineStage::TRANSFER
+                ..pso::PipelineStage::BOTTOM_OF_PIPE | pso::PipelineStage::HOST,
+            memory::Dependencies::empty(),
+            &barriers,
+        );
+        Readback {
+            buffer,
+            size,
+            format,
+        }
+    }
+
+    /// Whether the frame submitted with `fence` is complete, or the device was lost meanwhile.
+    pub fn is_done(&self, dev: &gfx::Device, fence: &gfx::Fence) -> bool {
+        unsafe { dev.get_fence_status(fence) }.unwrap_or(true)
+    }
+
+    /// Converts the pixels of the completed frame, without waiting for it.
+    /// None if it is not complete, or if the device was lost meanwhile.
+    pub fn finish(self, dev: &gfx::Device, fence: &gfx::Fence) -> Option<Capture> {
+        let done = unsafe { dev.get_fence_status(fence) };
+        let capture = match done {
+            Ok(true) => Some(Capture {
+                image: Image::from_premultiplied(
+                    self.size.0,
+                    self.size.1,
+                    to_rgba(self.format, self.buffer.read()),
+                ),
+                format: self.format,
+            }),
+            _ => None,
+        };
+        self.buffer.destroy(dev);
+        capture
+    }
+}
+
+#[test]
+fn bgra_pixels() {
+    let pixels = vec![1, 2, 3, 4, 5, 6, 7, 8];
+    assert_eq!(pixels, to_rgba(Format::Rgba8Srgb, pixels.clone()));
+    assert_eq!(
+        vec![3, 2, 1, 4, 7, 6, 5, 8],
+        to_rgba(Format::Bgra8Unorm, pixels)
+    );
+    assert!(!is_supported(Format::Rgba16Sfloat));
+}
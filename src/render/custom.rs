@@ -0,0 +1,139 @@
+use super::layer::LayerTarget;
+use super::texture::COLOR_RANGE;
+use crate::geom::FRect;
+use crate::gfx;
+use gfx_hal::{format, image, memory, pso};
+use std::sync::Arc;
+
+/// Records GPU commands drawn by a frame, on the render thread.
+/// It is shared by the frames that draw it, and called once per frame.
+pub type RenderCallback = Arc<dyn Fn(&mut CustomRender) + Send + Sync>;
+
+/// What a `RenderCallback` renders with.
+///
+/// The callback renders to an intermediate target covering the rectangle of the command,
+/// which is then composited over it, in its order in the display list,
+/// with the current clip and blend mode. The following rules apply:
+///
+/// - The callback is called outside of any render pass, and must not leave one open.
+/// - The target is cleared to transparent black, in the `ColorAttachmentOptimal` layout.
+///   It must be left in that layout, with premultiplied colors, and only written by color
+///   attachment outputs: other writes need a barrier to `COLOR_ATTACHMENT_OUTPUT` first.
+///   Only `rect` is composited.
+/// - No state needs to be restored: the pipelines, descriptor sets, vertex buffers,
+///   viewports, scissors and push constants are bound again by the renderer afterwards.
+/// - The command buffer is submitted by the renderer, it must not be finished or reset.
+/// - Resources used by the commands must live until the frame completes. The frames with
+///   the same `image_index` are rendered one after the other, so resources kept per
+///   image index can be reused by the callback.
+pub struct CustomRender<'a> {
+    pub device: &'a gfx::Device,
+    /// Changes when the device is recreated after it was lost. The objects created with
+    /// the previous device cannot be used, nor destroyed with the new one.
+    pub generation: u64,
+    pub cmd: &'a mut gfx::CommandBuffer,
+    pub image: &'a gfx::Image,
+    pub view: &'a gfx::ImageView,
+    pub format: format::Format,
+    /// Size of the target, which may be larger than `rect`.
+    pub size: (u32, u32),
+    /// Bounds of the transformed rectangle in the target, in pixels.
+    /// The transform only moves and clips the rendering, it does not rotate or scale it.
+    pub rect: pso::Rect,
+    /// Position of the target in the window, in pixels: the pixel (x, y) of the target
+    /// covers the pixel (x + origin.0, y + origin.1) of the window.
+    pub origin: (i32, i32),
+    /// Index of the swapchain image the frame renders to.
+    pub image_index: usize,
+    /// Number of swapchain images.
+    pub image_count: usize,
+}
+
+/// Calls `callback` on `target`, which was just cleared by a render pass, and leaves it
+/// in the `ShaderReadOnlyOptimal` layout expected by the composites.
+pub(crate) unsafe fn record(
+    callback: &RenderCallback,
+    (dev, generation): (&gfx::Device, u64),
+    cmd: &mut gfx::CommandBuffer,
+    target: &LayerTarget,
+    (rect, origin): (pso::Rect, (i32, i32)),
+    (image_index, image_count): (usize, usize),
+) {
+    let color = &target.color;
+    let to_attachment = memory::Barrier::Image {
+        states: (
+            image::Access::COLOR_ATTACHMENT_WRITE,
+            image::Layout::ShaderReadOnlyOptimal,
+        )
+            ..(
+                image::Access::COLOR_ATTACHMENT_READ | image::Access::COLOR_ATTACHMENT_WRITE,
+                image::Layout::ColorAttachmentOptimal,
+            ),
+        target: &color.image,
+        families: None,
+        range: COLOR_RANGE,
+    };
+    cmd.pipeline_barrier(
+        pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT..pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+        memory::Dependencies::empty(),
+        &[to_attachment],
+    );
+    callback(&mut CustomRender {
+        device: dev,
+        generation,
+        cmd: &mut *cmd,
+        image: &color.image,
+        view: &color.view,
+        format: color.format,
+        size: (color.width, color.height),
+        rect,
+        origin,
+        image_index,
+        image_count,
+    });
+    let to_shader = memory::Barrier::Image {
+        states: (
+            image::Access::COLOR_ATTACHMENT_WRITE,
+            image::Layout::ColorAttachmentOptimal,
+        )
+            ..(
+                image::Access::SHADER_READ,
+                image::Layout::ShaderReadOnlyOptimal,
+            ),
+        target: &color.image,
+        families: None,
+        range: COLOR_RANGE,
+    };
+    cmd.pipeline_barrier(
+        pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT..pso::PipelineStage::FRAGMENT_SHADER,
+        memory::Dependencies::empty(),
+        &[to_shader],
+    );
+}
+
+/// Bounds of a transformed rectangle in the window, in whole pixels and inside the viewport.
+/// None if nothing of it is visible.
+pub(crate) fn window_rect(viewport: [f32; 4], bounds: FRect) -> Option<[i32; 4]> {
+    let vp = viewport;
+    let x0 = (vp[0] + bounds.x.max(0f32)).floor() as i32;
+    let y0 = (vp[1] + bounds.y.max(0f32)).floor() as i32;
+    let x1 = (vp[0] + (bounds.x + bounds.w).min(vp[2])).ceil() as i32;
+    let y1 = (vp[1] + (bounds.y + bounds.h).min(vp[3])).ceil() as i32;
+    if x1 <= x0 || y1 <= y0 {
+        return None;
+    }
+    Some([x0, y0, x1, y1])
+}
+
+#[test]
+fn window_rects() {
+    let viewport = [10f32, 20f32, 100f32, 50f32];
+    // rounded outwards, and moved by the viewport
+    let rect = FRect::new(0.5, 1.5, 20f32, 10.2);
+    assert_eq!(Some([10, 21, 31, 32]), window_rect(viewport, rect));
+    // clipped by the viewport
+    let rect = FRect::new(-5f32, 40f32, 200f32, 20f32);
+    assert_eq!(Some([10, 60, 110, 70]), window_rect(viewport, rect));
+    let rect = FRect::new(100f32, 0f32, 10f32, 10f32);
+    assert_eq!(None, window_rect(viewport, rect));
+}
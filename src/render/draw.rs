@@ -0,0 +1,2442 @@
+use super::animation;
+use super::antialiasing::Antialiasing;
+use super::batch::{self, BatchStats};
+use super::custom::{self, RenderCallback};
+use super::effect::{EffectHandle, EffectPipelines, EFFECT_UNIFORMS};
+use super::frame::{Border, BoxShadow, Clip, Cmd, EffectDraw, Frame, GlyphRun, ImageDraw, Layer};
+use super::glyph::{GlyphCache, SUBPIXEL_STEPS};
+use super::images::{Filter, ImageCache, ImageHandle};
+use super::layer::{Backdrop, LayerTarget};
+use super::pipeline::{self, Pipelines, PAINT_STAGES};
+use super::resource::{GradientHandle, ResourceId};
+use crate::geom::{FCorners, FMargins, FPoint, FRect, IRect, Transform};
+use crate::gfx;
+use crate::paint::{
+    self, BlendMode, Color, ExtendMode, Gradient, GradientKind, GradientStop, Paint, RAMP_SIZE,
+};
+use crate::path::{FillRule, Path, Polyline, DEFAULT_TOLERANCE};
+use crate::text::{Font, GlyphId};
+use gfx_hal::command::{ClearColor, ClearDepthStencil, ClearValue, RenderPassInlineEncoder};
+use gfx_hal::pso;
+use std::ops::Range;
+use std::time::Instant;
+use std::{fmt, mem, ptr, slice};
+
+/// Number of gradients that can be used in a single frame.
+pub(crate) const RAMP_ROWS: usize = 64;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Vertex {
+    pub pos: [f32; 2],
+    /// Multiplies the paint, less than 1 in the anti-aliasing fringes only.
+    pub coverage: f32,
+}
+
+/// Push constants of the paint shaders.
+/// Must be kept in sync with `shaders/paint.glsl`.
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub(crate) struct PaintConsts {
+    pub viewport: [f32; 4],
+    pub color: [f32; 4],
+    pub mat0: [f32; 4],
+    pub mat1: [f32; 4],
+    pub params: [f32; 4],
+    pub info: [i32; 4],
+}
+
+impl PaintConsts {
+    fn words(&self) -> &[u32] {
+        unsafe {
+            slice::from_raw_parts(
+                self as *const PaintConsts as *const u32,
+                mem::size_of::<PaintConsts>() / 4,
+            )
+        }
+    }
+}
+
+/// Push constants of the effects.
+/// Must be kept in sync with `shaders/effect.vert`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct EffectConsts {
+    pub viewport: [f32; 4],
+    /// Rows of the transform from the user space to viewport coordinates.
+    pub mat0: [f32; 4],
+    pub mat1: [f32; 4],
+    /// x0, y0, x1, y1 in user space.
+    pub rect: [f32; 4],
+    pub uniforms: [f32; EFFECT_UNIFORMS],
+}
+
+impl EffectConsts {
+    fn words(&self) -> &[u32] {
+        unsafe {
+            slice::from_raw_parts(
+                self as *const EffectConsts as *const u32,
+                mem::size_of::<EffectConsts>() / 4,
+            )
+        }
+    }
+}
+
+/// An analytically rendered shape.
+/// Must be kept in sync with `shaders/shape.vert`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct ShapeInstance {
+    /// Rows of the transform from the shape space to viewport coordinates.
+    pub mat0: [f32; 4],
+    pub mat1: [f32; 4],
+    pub bounds: [f32; 4],
+    pub rect: [f32; 4],
+    pub radii: [f32; 4],
+    pub widths: [f32; 4],
+    pub clip_rect: [f32; 4],
+    pub clip_radii: [f32; 4],
+    pub params: [f32; 4],
+    pub colors: [[f32; 4]; 4],
+}
+
+/// A glyph of the atlas drawn at a pixel-aligned position.
+/// Must be kept in sync with `shaders/glyph.vert`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct GlyphInstance {
+    /// x0, y0, x1, y1 in viewport coordinates.
+    pub rect: [f32; 4],
+    /// x0, y0, x1, y1 in atlas texels.
+    pub uv: [f32; 4],
+    /// x: 1 for the color glyphs, 0 for the coverage ones.
+    pub params: [f32; 4],
+}
+
+/// A rectangle of an image drawn in user space.
+/// Must be kept in sync with `shaders/image.vert`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct ImageInstance {
+    /// Rows of the transform from the user space to viewport coordinates.
+    pub mat0: [f32; 4],
+    pub mat1: [f32; 4],
+    /// x0, y0, x1, y1 in user space.
+    pub rect: [f32; 4],
+    /// x0, y0, x1, y1 in image texels.
+    pub uv: [f32; 4],
+}
+
+const SHAPE_FILL: f32 = 0f32;
+const SHAPE_BORDER: f32 = 1f32;
+const SHAPE_SHADOW: f32 = 2f32;
+const SHAPE_INSET_SHADOW: f32 = 3f32;
+/// Width of the anti-aliasing fringe of the paths, in pixels.
+const FRINGE_WIDTH: f32 = 1f32;
+
+/// Drawn instead of the images that are not loaded.
+const IMAGE_PLACEHOLDER: Color = [0.5f32, 0.5f32, 0.5f32, 0.25f32];
+
+/// Largest standard deviation of the blurs, in pixels.
+/// The kernels fit in `MAX_BLUR_TAPS` at the lowest resolution.
+const MAX_BLUR_SIGMA: f32 = 80f32;
+/// Blurs are rendered at a resolution divided by up to this factor.
+const MAX_BLUR_SCALE: u32 = 8;
+/// Texels sampled on each side of a blurred texel, at most.
+/// Must be kept in sync with `MAX_TAPS` in `shaders/blur.frag`.
+const MAX_BLUR_TAPS: u32 = 32;
+/// The resolution is reduced as long as the standard deviation stays above this many texels.
+const MIN_BLUR_TEXELS: f32 = 1.5;
+/// Number of primitives a draw is checked against before it joins the layer
+/// that blends the previous draws, to bound the building time.
+const MAX_BLEND_GROUP: usize = 256;
+
+const PAINT_SOLID: i32 = 0;
+const PAINT_LINEAR: i32 = 1;
+const PAINT_RADIAL: i32 = 2;
+const PAINT_SWEEP: i32 = 3;
+
+/// A draw operation, with its vertices range in `DrawData::vertices`.
+pub(crate) enum Op {
+    /// Writes the winding of a path in the stencil buffer.
+    Stencil {
+        fill_rule: FillRule,
+        vertices: Range<u32>,
+    },
+    /// Paints the outside edges of a path whose winding is in the stencil buffer.
+    Fringe {
+        paint: PaintConsts,
+        blend_mode: BlendMode,
+        vertices: Range<u32>,
+    },
+    /// Paints where the stencil buffer is set, and resets it.
+    Cover {
+        paint: PaintConsts,
+        blend_mode: BlendMode,
+        vertices: Range<u32>,
+    },
+    /// Sets the scissor rectangle: x0, y0, x1, y1 in viewport coordinates.
+    Scissor([i32; 4]),
+    /// Sets the clip bit of the stencil buffer everywhere.
+    ClipReset { vertices: Range<u32> },
+    /// Writes the winding of a clip path where the clip bit is set.
+    ClipStencil {
+        fill_rule: FillRule,
+        vertices: Range<u32>,
+    },
+    /// Keeps the clip bit inside the clip path only, and resets the winding.
+    ClipCover { vertices: Range<u32> },
+    /// Copies the target of the pass to the backdrop read by the next composite.
+    Backdrop,
+    /// Composites the target of a pass, masked by the alpha of another one.
+    Composite {
+        layer: usize,
+        /// Scale of the layer, as set by `Pass::scale`.
+        scale: u32,
+        mask: Option<usize>,
+        opacity: f32,
+        blend_mode: BlendMode,
+        vertices: Range<u32>,
+    },
+    /// Replaces the target with the blur of the target of a pass in one direction.
+    Blur {
+        src: usize,
+        src_scale: u32,
+        direction: [f32; 2],
+        /// Standard deviation, in texels of the target.
+        sigma: f32,
+        /// Part of the viewport where the blur is used.
+        region: [f32; 4],
+        vertices: Range<u32>,
+    },
+    /// Draws instances of `DrawData::instances`.
+    /// Without paint, the instances are filled with their own color.
+    Shapes {
+        paint: Option<PaintConsts>,
+        blend_mode: BlendMode,
+        instances: Range<u32>,
+    },
+    /// Draws instances of `DrawData::glyphs`.
+    Glyphs {
+        paint: PaintConsts,
+        blend_mode: BlendMode,
+        instances: Range<u32>,
+    },
+    /// Draws instances of `DrawData::images` sampling the same image.
+    Images {
+        image: ResourceId,
+        filter: Filter,
+        opacity: f32,
+        blend_mode: BlendMode,
+        instances: Range<u32>,
+    },
+    /// Fills a rectangle with an effect.
+    Effect {
+        effect: ResourceId,
+        blend_mode: BlendMode,
+        consts: EffectConsts,
+        inputs: Vec<EffectInput>,
+    },
+}
+
+/// A texture sampled by an effect.
+pub(crate) enum EffectInput {
+    Image(ResourceId),
+    /// The target of a pass.
+    Layer(usize),
+}
+
+/// The operations rendering to one target.
+pub(crate) struct Pass {
+    pub ops: Vec<Op>,
+    /// The pass renders at the resolution of the window divided by this factor,
+    /// in the top left corner of its target.
+    scale: u32,
+    clear_color: [f32; 4],
+    scissor: [i32; 4],
+    clips: Vec<ClipEntry>,
+    /// Renders the target instead of the operations.
+    custom: Option<CustomPass>,
+    /// What the operations draw: x0, y0, x1, y1 in viewport coordinates.
+    bounds: [f32; 4],
+    /// Whether the target has the size of the window, for the shaders that read it
+    /// or draw to it at window coordinates.
+    window: bool,
+}
+
+/// A callback rendering the target of a pass.
+struct CustomPass {
+    callback: RenderCallback,
+    /// x0, y0, x1, y1 in window coordinates.
+    rect: [i32; 4],
+}
+
+impl Pass {
+    fn needs_backdrop(&self) -> bool {
+        self.ops.iter().any(|op| matches!(op, Op::Backdrop))
+    }
+}
+
+/// The GPU resources used to record the passes of a frame.
+pub(crate) struct Resources<'a> {
+    /// With its generation.
+    pub device: (&'a gfx::Device, u64),
+    pub pipes: &'a Pipelines,
+    /// Area of the window.
+    pub area: pso::Rect,
+    /// Target of the last pass.
+    pub framebuffer: &'a gfx::Framebuffer,
+    pub desc_set: &'a gfx::DescriptorSet,
+    /// Buffers and offsets of the vertex data.
+    /// Must hold `DrawData::vertices` if it is not empty.
+    pub vertices: Option<(&'a gfx::Buffer, u64)>,
+    /// Must hold `DrawData::instances` if it is not empty.
+    pub instances: Option<(&'a gfx::Buffer, u64)>,
+    /// Must hold `DrawData::glyphs` if it is not empty.
+    pub glyphs: Option<(&'a gfx::Buffer, u64)>,
+    /// Must hold `DrawData::images` if it is not empty.
+    pub images: Option<(&'a gfx::Buffer, u64)>,
+    /// The images drawn by the frame, uploaded.
+    pub image_cache: &'a ImageCache,
+    /// The effects drawn by the frame, compiled.
+    pub effects: &'a EffectPipelines,
+    /// Targets of the passes but the last one, by pass index,
+    /// with the sizes given by `DrawData::target_rect`.
+    pub layers: Vec<&'a LayerTarget>,
+    /// Must be set if `DrawData::needs_backdrop`.
+    pub backdrop: Option<&'a Backdrop>,
+    /// Index of the swapchain image of the last pass, and number of images.
+    pub image_index: (usize, usize),
+}
+
+/// The data needed to render a frame, derived from its display list.
+pub(crate) struct DrawData {
+    pub viewport: [f32; 4],
+    /// At which the animations are interpolated.
+    time: Instant,
+    aa: Antialiasing,
+    pub vertices: Vec<Vertex>,
+    pub instances: Vec<ShapeInstance>,
+    pub glyphs: Vec<GlyphInstance>,
+    pub images: Vec<ImageInstance>,
+    /// Baked gradients, `RAMP_SIZE` texels per row.
+    pub ramps: Vec<[u8; 4]>,
+    /// Passes in the order they must be rendered.
+    /// The last one renders to the window, each other one to its own intermediate target.
+    pub passes: Vec<Pass>,
+    pub stats: BatchStats,
+    /// Effects drawn by the frame, with their blend mode, to compile if needed.
+    pub effects: Vec<(EffectHandle, BlendMode)>,
+    /// What the frame does not draw, each once.
+    pub warnings: Vec<Warning>,
+    /// What each row of `ramps` holds.
+    ramp_keys: Vec<RampKey>,
+    /// Glyphs to look up in the atlas once the display list is processed.
+    glyph_runs: Vec<GlyphRequest>,
+    /// Images drawn by the frame, to upload if needed.
+    image_handles: Vec<ImageHandle>,
+    /// Passes being built, the last one receives the operations.
+    stack: Vec<Pass>,
+    layers: Vec<LayerEntry>,
+    transform: Transform,
+    transforms: Vec<Transform>,
+    blend_mode: BlendMode,
+    /// Blend modes to restore when popping the layers.
+    blend_modes: Vec<BlendMode>,
+    /// Transforms and depths of the transform stack to restore when popping the layers
+    /// whose content is animated.
+    layer_transforms: Vec<Option<(Transform, usize)>>,
+    viewport_quad: Option<Range<u32>>,
+}
+
+/// A part of a display list that is not drawn.
+/// Reported to the renderer, which prints each one once.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub(crate) enum Warning {
+    /// More than `RAMP_ROWS` gradients in a frame.
+    TooManyGradients,
+    /// An effect drawn with another number of inputs than it samples.
+    EffectInputs {
+        effect: ResourceId,
+        samples: usize,
+        inputs: usize,
+    },
+    /// The glyphs of the frame do not fit in the atlas.
+    GlyphAtlasFull,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::TooManyGradients => {
+                write!(f, "too many gradients in a frame, max is {}", RAMP_ROWS)
+            }
+            Warning::EffectInputs {
+                samples, inputs, ..
+            } => write!(
+                f,
+                "an effect samples {} inputs, but is drawn with {}",
+                samples, inputs
+            ),
+            Warning::GlyphAtlasFull => write!(f, "glyph atlas is full, some glyphs are not drawn"),
+        }
+    }
+}
+
+/// Identifies a gradient in the ramp texture, to add its ramp once per frame.
+enum RampKey {
+    Stops(Vec<GradientStop>),
+    Shared(ResourceId),
+}
+
+/// Glyphs whose instance rectangles hold their origin until they are looked up.
+struct GlyphRequest {
+    font: Font,
+    size: f32,
+    ids: Vec<GlyphId>,
+    /// Index of the instance of the first glyph.
+    start: usize,
+}
+
+enum LayerEntry {
+    /// A layer that composites like its content, drawn in the parent pass.
+    Inline,
+    Offscreen {
+        mask: Option<usize>,
+        opacity: f32,
+        blend_mode: BlendMode,
+        effect: Option<EffectDraw>,
+        blur: f32,
+    },
+}
+
+enum ClipEntry {
+    /// A clip applied with the scissor, with the scissor to restore.
+    Scissor([i32; 4]),
+    /// A clip applied with the stencil buffer.
+    Stencil {
+        fill_rule: FillRule,
+        vertices: Range<u32>,
+    },
+}
+
+impl DrawData {
+    /// The glyphs of the frame are rasterized in `glyphs` if needed,
+    /// and its images are scheduled for upload in `images`.
+    /// The animations are interpolated at `time`.
+    pub fn new(
+        frame: &Frame,
+        time: Instant,
+        aa: Antialiasing,
+        glyphs: &mut GlyphCache,
+        images: &mut ImageCache,
+    ) -> DrawData {
+        let mut data = DrawData::build(frame.viewport, frame.clear_color, &frame.cmds, time, aa);
+        data.resolve_glyphs(glyphs);
+        if glyphs.overflowed() {
+            data.warn(Warning::GlyphAtlasFull);
+        }
+        data.measure();
+        for image in &data.image_handles {
+            images.request(image);
+        }
+        batch::batch(&mut data);
+        data
+    }
+
+    /// The passes of a display list, before its glyphs and images are looked up.
+    fn build(
+        vp: IRect,
+        clear_color: Option<[f32; 4]>,
+        cmds: &[Cmd],
+        time: Instant,
+        aa: Antialiasing,
+    ) -> DrawData {
+        let mut data = DrawData {
+            viewport: [vp.x as f32, vp.y as f32, vp.w as f32, vp.h as f32],
+            time,
+            aa,
+            vertices: Vec::new(),
+            instances: Vec::new(),
+            glyphs: Vec::new(),
+            images: Vec::new(),
+            ramps: Vec::new(),
+            passes: Vec::new(),
+            stats: BatchStats::default(),
+            effects: Vec::new(),
+            warnings: Vec::new(),
+            ramp_keys: Vec::new(),
+            glyph_runs: Vec::new(),
+            image_handles: Vec::new(),
+            stack: Vec::new(),
+            layers: Vec::new(),
+            transform: Transform::identity(),
+            transforms: Vec::new(),
+            blend_mode: BlendMode::SrcOver,
+            blend_modes: Vec::new(),
+            layer_transforms: Vec::new(),
+            viewport_quad: None,
+        };
+        let clear_color = clear_color.unwrap_or([0f32; 4]);
+        data.begin_pass(clear_color);
+        data.cmds(cmds);
+        while !data.layers.is_empty() {
+            data.pop_layer();
+        }
+        if data.pass().needs_backdrop() {
+            // the window cannot be copied, so its content is rendered to a layer first
+            let layer = data.end_pass();
+            data.begin_pass(clear_color);
+            let vertices = data.viewport_quad();
+            data.pass().ops.push(Op::Composite {
+                layer,
+                scale: 1,
+                mask: None,
+                opacity: 1f32,
+                blend_mode: BlendMode::Src,
+                vertices,
+            });
+        }
+        data.end_pass();
+        data
+    }
+
+    fn warn(&mut self, warning: Warning) {
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+    }
+
+    /// Sets the bounds of the passes, once their glyphs are looked up.
+    fn measure(&mut self) {
+        for pass in 0..self.passes.len() {
+            // the effects sample their inputs and draw at window coordinates
+            let mut inputs = Vec::new();
+            for op in &self.passes[pass].ops {
+                if let Op::Effect { inputs: i, .. } = op {
+                    inputs.push(pass);
+                    inputs.extend(i.iter().filter_map(|input| match input {
+                        EffectInput::Layer(layer) => Some(*layer),
+                        EffectInput::Image(_) => None,
+                    }));
+                }
+            }
+            for p in inputs {
+                self.passes[p].window = true;
+            }
+            self.passes[pass].bounds = self.pass_bounds(pass);
+        }
+    }
+
+    /// Bounds of what a pass draws, in viewport coordinates.
+    /// The passes it composites must be measured.
+    fn pass_bounds(&self, pass: usize) -> [f32; 4] {
+        let p = &self.passes[pass];
+        let viewport = [0f32, 0f32, self.viewport[2], self.viewport[3]];
+        if let Some(custom) = &p.custom {
+            let ([x0, y0, x1, y1], [x, y]) = (custom.rect, [self.viewport[0], self.viewport[1]]);
+            return [x0 as f32 - x, y0 as f32 - y, x1 as f32 - x, y1 as f32 - y];
+        }
+        if p.clear_color != [0f32; 4] {
+            return viewport;
+        }
+        let range = |r: &Range<u32>| &self.vertices[r.start as usize..r.end as usize];
+        let mut scissor = viewport;
+        let mut bounds = [0f32; 4];
+        for op in &p.ops {
+            let rects = match op {
+                Op::Scissor(s) => {
+                    scissor = [s[0] as f32, s[1] as f32, s[2] as f32, s[3] as f32];
+                    continue;
+                }
+                Op::Composite {
+                    layer,
+                    mask,
+                    blend_mode,
+                    vertices,
+                    ..
+                } => {
+                    let mut r = batch::vertex_bounds(range(vertices));
+                    // elsewhere, the transparent layer clears or keeps the target
+                    if blend_mode.is_bounded() {
+                        r = intersect(r, self.passes[*layer].bounds);
+                        if let Some(mask) = mask {
+                            r = intersect(r, self.passes[*mask].bounds);
+                        }
+                    }
+                    vec![r]
+                }
+                Op::Blur {
+                    src, sigma, region, ..
+                } => {
+                    // the kernel spreads the source, and reads around the region
+                    let reach = ((blur_taps(*sigma) + 1) * p.scale) as f32;
+                    let src = self.passes[*src].bounds;
+                    vec![intersect(inflate(src, reach), inflate(*region, reach))]
+                }
+                op => match batch::item_rects(self, op) {
+                    Some(rects) => rects,
+                    None => continue,
+                },
+            };
+            for r in rects {
+                bounds = union(bounds, intersect(r, scissor));
+            }
+        }
+        intersect(bounds, viewport)
+    }
+
+    /// Where the target of a pass is in the window: its origin in pixels of the window,
+    /// and its size in pixels of the target, which are `scale` times larger at reduced resolutions.
+    /// None if it has the size of the window.
+    pub fn target_rect(&self, pass: usize) -> Option<([i32; 2], (u32, u32))> {
+        let p = &self.passes[pass];
+        if p.window || pass == self.passes.len() - 1 {
+            return None;
+        }
+        let (vp, b) = (self.viewport, p.bounds);
+        if is_empty(b) {
+            return Some(([vp[0] as i32, vp[1] as i32], (1, 1)));
+        }
+        // the texels are aligned on the ones of the window at the same resolution
+        let (s, f) = (p.scale as i32, p.scale as f32);
+        let floor = |v: f32| (v / f).floor() as i32;
+        let ceil = |v: f32| (v / f).ceil() as i32;
+        let (x0, y0) = (floor(vp[0] + b[0]), floor(vp[1] + b[1]));
+        let (x1, y1) = (ceil(vp[0] + b[2]), ceil(vp[1] + b[3]));
+        Some(([x0 * s, y0 * s], ((x1 - x0) as u32, (y1 - y0) as u32)))
+    }
+
+    fn origin(&self, pass: usize) -> [i32; 2] {
+        self.target_rect(pass).map_or([0, 0], |(origin, _)| origin)
+    }
+
+    /// Whether some blend mode is done in the shader with a copy of the target.
+    pub fn needs_backdrop(&self) -> bool {
+        self.passes.iter().any(Pass::needs_backdrop)
+    }
+
+    fn cmds(&mut self, cmds: &[Cmd]) {
+        for cmd in cmds {
+            match cmd {
+                Cmd::PushTransform(t) => {
+                    self.transforms.push(self.transform);
+                    self.transform = t.then(&self.transform);
+                }
+                Cmd::PopTransform => {
+                    self.transform = self.transforms.pop().unwrap_or_else(Transform::identity);
+                }
+                Cmd::PushClip(clip) => self.push_clip(clip),
+                Cmd::PopClip => self.pop_clip(),
+                Cmd::BlendMode(mode) => self.blend_mode = *mode,
+                Cmd::Fill {
+                    path,
+                    fill_rule,
+                    paint,
+                } => self.blended(|d| {
+                    let lines = d.flatten(path);
+                    d.fill(&lines, *fill_rule, paint)
+                }),
+                Cmd::FillShared {
+                    path,
+                    fill_rule,
+                    paint,
+                } => self.blended(|d| {
+                    let lines = path.flatten(&d.transform);
+                    d.fill(&lines, *fill_rule, paint)
+                }),
+                Cmd::RoundedRect { rect, radii, paint } => {
+                    self.blended(|d| d.rounded_rect(*rect, *radii, paint))
+                }
+                Cmd::Border(border) => self.blended(|d| d.border(border)),
+                Cmd::BoxShadow(shadow) => self.blended(|d| d.box_shadow(shadow)),
+                Cmd::GlyphRun(run) => self.blended(|d| d.glyph_run(run)),
+                Cmd::Image(draw) => self.blended(|d| d.image(draw)),
+                Cmd::Effect { rect, effect } => self.blended(|d| d.effect(*rect, effect, None)),
+                Cmd::BackdropBlur { rect, radius } => self.backdrop_blur(*rect, *radius),
+                Cmd::Custom { rect, callback } => self.blended(|d| d.custom(*rect, callback)),
+                Cmd::PushLayer(layer) => self.push_layer(layer),
+                Cmd::PopLayer => self.pop_layer(),
+            }
+        }
+    }
+
+    /// Draws with the current blend mode, through a layer if the blending
+    /// cannot be done by the fixed-function pipeline.
+    /// Consecutive draws that do not overlap share the layer.
+    fn blended<F: FnOnce(&mut DrawData)>(&mut self, draw: F) {
+        let mode = self.blend_mode;
+        if mode.is_fixed_function() {
+            draw(self);
+        } else {
+            self.push_layer(&Layer::new(1f32).with_blend_mode(mode));
+            draw(self);
+            self.pop_layer();
+            self.join_blend_group(mode);
+        }
+    }
+
+    /// Moves the content of the layer just composited with `mode` to the layer
+    /// composited before it the same way, if what they draw does not overlap.
+    /// Blending them at once then gives the same result.
+    fn join_blend_group(&mut self, mode: BlendMode) {
+        let (a, b) = match self.passes.len() {
+            n if n >= 2 => (n - 2, n - 1),
+            _ => return,
+        };
+        let ops = &self.stack.last().expect("no current pass").ops;
+        let n = ops.len();
+        let composited = |op: &Op, pass: usize| match op {
+            Op::Composite {
+                layer,
+                scale,
+                mask,
+                opacity,
+                blend_mode,
+                ..
+            } => (*layer, *scale, mask, *opacity, *blend_mode) == (pass, 1, &None, 1f32, mode),
+            _ => false,
+        };
+        if n < 4
+            || !matches!(ops[n - 4], Op::Backdrop)
+            || !composited(&ops[n - 3], a)
+            || !matches!(ops[n - 2], Op::Backdrop)
+            || !composited(&ops[n - 1], b)
+        {
+            return;
+        }
+        // the drawing of the second layer starts without clip
+        let (w, h) = (self.viewport[2] as i32, self.viewport[3] as i32);
+        if !self.passes[a].clips.is_empty() || self.passes[a].scissor != [0, 0, w, h] {
+            return;
+        }
+        let (group, draw) = match (self.content_rects(a), self.content_rects(b)) {
+            (Some(group), Some(draw)) if group.len() < MAX_BLEND_GROUP => (group, draw),
+            _ => return,
+        };
+        let draw = draw.into_iter().fold([0f32; 4], union);
+        if group.iter().any(|&r| !is_empty(intersect(r, draw))) {
+            return;
+        }
+        let joined = self.passes.pop().expect("no layer to join");
+        self.passes[a].ops.extend(joined.ops);
+        self.pass().ops.truncate(n - 2);
+    }
+
+    /// Bounds of the primitives of a pass, if they are known before the glyphs are looked up
+    /// and the passes are measured.
+    fn content_rects(&self, pass: usize) -> Option<Vec<[f32; 4]>> {
+        let mut rects = Vec::new();
+        for op in &self.passes[pass].ops {
+            match op {
+                Op::Glyphs { .. } | Op::Composite { .. } | Op::Blur { .. } => return None,
+                // the clips only make them smaller
+                op => rects.extend(batch::item_rects(self, op).unwrap_or_default()),
+            }
+        }
+        Some(rects)
+    }
+
+    fn begin_pass(&mut self, clear_color: [f32; 4]) {
+        let (w, h) = (self.viewport[2] as i32, self.viewport[3] as i32);
+        self.stack.push(Pass {
+            ops: Vec::new(),
+            scale: 1,
+            clear_color,
+            scissor: [0, 0, w, h],
+            clips: Vec::new(),
+            custom: None,
+            bounds: [0f32; 4],
+            window: false,
+        });
+    }
+
+    /// Returns the index of the finished pass.
+    fn end_pass(&mut self) -> usize {
+        let pass = self.stack.pop().expect("no pass to end");
+        self.passes.push(pass);
+        self.passes.len() - 1
+    }
+
+    fn pass(&mut self) -> &mut Pass {
+        self.stack.last_mut().expect("no current pass")
+    }
+
+    fn push_layer(&mut self, layer: &Layer) {
+        // the content of a layer starts with the default blending
+        let blend_mode = mem::replace(&mut self.blend_mode, BlendMode::SrcOver);
+        self.blend_modes.push(blend_mode);
+        let (opacity, transform) = animation::layer_values(&layer.animations, self.time);
+        let opacity = opacity.unwrap_or(layer.opacity);
+        let blur = if layer.blur > 0f32 { layer.blur } else { 0f32 };
+        if opacity >= 1f32
+            && layer.blend_mode == BlendMode::SrcOver
+            && layer.mask.is_none()
+            && layer.effect.is_none()
+            && blur == 0f32
+        {
+            self.layers.push(LayerEntry::Inline);
+            self.animate_content(transform);
+            return;
+        }
+        let mask = layer.mask.as_ref().map(|cmds| {
+            let (transform, transforms, layers) =
+                (self.transform, self.transforms.len(), self.layers.len());
+            self.begin_pass([0f32; 4]);
+            self.cmds(cmds);
+            while self.layers.len() > layers {
+                self.pop_layer();
+            }
+            self.transform = transform;
+            self.transforms.truncate(transforms);
+            self.blend_mode = BlendMode::SrcOver;
+            self.end_pass()
+        });
+        self.begin_pass([0f32; 4]);
+        self.animate_content(transform);
+        self.layers.push(LayerEntry::Offscreen {
+            mask,
+            opacity: opacity.clamp(0f32, 1f32),
+            blend_mode: layer.blend_mode,
+            effect: layer.effect.clone(),
+            blur,
+        });
+    }
+
+    /// Applies the animated transform of the content of a layer, until it is popped.
+    fn animate_content(&mut self, transform: Option<Transform>) {
+        let saved = transform.map(|t| {
+            let saved = (self.transform, self.transforms.len());
+            self.transform = t.then(&self.transform);
+            saved
+        });
+        self.layer_transforms.push(saved);
+    }
+
+    fn pop_layer(&mut self) {
+        if let Some(blend_mode) = self.blend_modes.pop() {
+            self.blend_mode = blend_mode;
+        }
+        if let Some(Some((transform, depth))) = self.layer_transforms.pop() {
+            self.transform = transform;
+            self.transforms.truncate(depth);
+        }
+        if let Some(LayerEntry::Offscreen {
+            mask,
+            opacity,
+            blend_mode,
+            effect,
+            blur,
+        }) = self.layers.pop()
+        {
+            let mut layer = self.end_pass();
+            if let Some(effect) = effect {
+                layer = self.layer_effect(layer, &effect);
+            }
+            let mut scale = 1;
+            if blur > 0f32 {
+                let viewport = [0f32, 0f32, self.viewport[2], self.viewport[3]];
+                let (blurred, blurred_scale) = self.blur(layer, blur, viewport);
+                layer = blurred;
+                scale = blurred_scale;
+            }
+            let vertices = self.viewport_quad();
+            if !blend_mode.is_fixed_function() {
+                self.pass().ops.push(Op::Backdrop);
+            }
+            self.pass().ops.push(Op::Composite {
+                layer,
+                scale,
+                mask,
+                opacity,
+                blend_mode,
+                vertices,
+            });
+        }
+    }
+
+    /// Renders the target of a pass through an effect in a new pass, and returns its index.
+    fn layer_effect(&mut self, layer: usize, effect: &EffectDraw) -> usize {
+        let transform = mem::take(&mut self.transform);
+        let blend_mode = mem::replace(&mut self.blend_mode, BlendMode::SrcOver);
+        self.begin_pass([0f32; 4]);
+        let rect = FRect::new(0f32, 0f32, self.viewport[2], self.viewport[3]);
+        self.effect(rect, effect, Some(layer));
+        self.transform = transform;
+        self.blend_mode = blend_mode;
+        self.end_pass()
+    }
+
+    /// Blurs the target of a pass with a radius of twice the standard deviation,
+    /// in two new passes rendering `region` at a reduced resolution.
+    /// Returns the last one and its scale.
+    fn blur(&mut self, src: usize, radius: f32, region: [f32; 4]) -> (usize, u32) {
+        let (scale, sigma) = blur_scale(radius);
+        let horizontal = self.blur_pass((src, 1), scale, [1f32, 0f32], sigma, region);
+        let vertical = self.blur_pass((horizontal, scale), scale, [0f32, 1f32], sigma, region);
+        (vertical, scale)
+    }
+
+    fn blur_pass(
+        &mut self,
+        (src, src_scale): (usize, u32),
+        scale: u32,
+        direction: [f32; 2],
+        sigma: f32,
+        region: [f32; 4],
+    ) -> usize {
+        self.begin_pass([0f32; 4]);
+        let vertices = self.viewport_quad();
+        let pass = self.pass();
+        pass.scale = scale;
+        pass.ops.push(Op::Blur {
+            src,
+            src_scale,
+            direction,
+            sigma,
+            region,
+            vertices,
+        });
+        self.end_pass()
+    }
+
+    /// Replaces `rect` by the blur of what is below it, inside the current clip.
+    /// What the current pass rendered so far is moved to a layer so that it can be sampled,
+    /// and the pass continues in a new target with the same clip.
+    /// In a layer, that is only what the layer rendered, not what it is composited on.
+    fn backdrop_blur(&mut self, rect: FRect, radius: f32) {
+        if rect.w <= 0f32 || rect.h <= 0f32 || radius.is_nan() || radius <= 0f32 {
+            return;
+        }
+        let mut below = self.stack.pop().expect("no current pass");
+        let (scissor, clips) = (below.scissor, mem::take(&mut below.clips));
+        self.passes.push(below);
+        let below = self.passes.len() - 1;
+        let region = edges(self.transform.apply_rect(rect));
+        let (blurred, scale) = self.blur(below, radius, region);
+
+        self.begin_pass([0f32; 4]);
+        let vertices = self.viewport_quad();
+        self.pass().ops.push(Op::Composite {
+            layer: below,
+            scale: 1,
+            mask: None,
+            opacity: 1f32,
+            blend_mode: BlendMode::Src,
+            vertices,
+        });
+        self.set_scissor(scissor);
+        for clip in &clips {
+            if let ClipEntry::Stencil {
+                fill_rule,
+                vertices,
+            } = clip
+            {
+                self.apply_stencil_clip(*fill_rule, vertices.clone());
+            }
+        }
+        self.pass().clips = clips;
+        let vertices = self.transformed_rect(rect);
+        self.stats.primitives += 1;
+        self.pass().ops.push(Op::Composite {
+            layer: blurred,
+            scale,
+            mask: None,
+            opacity: 1f32,
+            blend_mode: BlendMode::Src,
+            vertices,
+        });
+    }
+
+    /// Renders a callback in a new pass, composited over `rect`.
+    fn custom(&mut self, rect: FRect, callback: &RenderCallback) {
+        if rect.w <= 0f32 || rect.h <= 0f32 {
+            return;
+        }
+        let bounds = self.transform.apply_rect(rect);
+        let window_rect = match custom::window_rect(self.viewport, bounds) {
+            Some(window_rect) => window_rect,
+            None => return,
+        };
+        self.begin_pass([0f32; 4]);
+        self.pass().custom = Some(CustomPass {
+            callback: callback.clone(),
+            rect: window_rect,
+        });
+        let layer = self.end_pass();
+        let vertices = self.transformed_rect(rect);
+        self.stats.primitives += 1;
+        let blend_mode = self.blend_mode;
+        self.pass().ops.push(Op::Composite {
+            layer,
+            scale: 1,
+            mask: None,
+            opacity: 1f32,
+            blend_mode,
+            vertices,
+        });
+    }
+
+    pub fn ramp_rows(&self) -> u32 {
+        self.ramp_keys.len() as u32
+    }
+
+    /// Fills polylines in viewport coordinates.
+    fn fill(&mut self, lines: &[Polyline], fill_rule: FillRule, paint: &Paint) {
+        let (fan, min, max) = match self.fan(lines) {
+            Some(fan) => fan,
+            None => return,
+        };
+        let fringe = if self.aa == Antialiasing::Analytic {
+            self.fringe(lines)
+        } else {
+            0..0
+        };
+        let start = self.vertices.len() as u32;
+        self.push_quad(min, max);
+        let quad = start..self.vertices.len() as u32;
+        let paint = self.paint_consts(paint);
+        self.pass().ops.push(Op::Stencil {
+            fill_rule,
+            vertices: fan,
+        });
+        let blend_mode = self.blend_mode;
+        if !fringe.is_empty() {
+            self.pass().ops.push(Op::Fringe {
+                paint,
+                blend_mode,
+                vertices: fringe,
+            });
+        }
+        self.pass().ops.push(Op::Cover {
+            paint,
+            blend_mode,
+            vertices: quad,
+        });
+    }
+
+    /// The polylines of the path in viewport coordinates.
+    fn flatten(&self, path: &Path) -> Vec<Polyline> {
+        if self.transform.is_identity() {
+            path.flatten(DEFAULT_TOLERANCE)
+        } else {
+            path.transformed(&self.transform).flatten(DEFAULT_TOLERANCE)
+        }
+    }
+
+    /// Pushes the triangles filling the polylines.
+    /// Returns their range and their bounding box.
+    fn fan(&mut self, lines: &[Polyline]) -> Option<(Range<u32>, FPoint, FPoint)> {
+        let start = self.vertices.len() as u32;
+        let mut min = FPoint::new(f32::INFINITY, f32::INFINITY);
+        let mut max = FPoint::new(f32::NEG_INFINITY, f32::NEG_INFINITY);
+        for line in lines {
+            let pts = &line.points;
+            for p in pts {
+                min = FPoint::new(min.x.min(p.x), min.y.min(p.y));
+                max = FPoint::new(max.x.max(p.x), max.y.max(p.y));
+            }
+            // triangle fan from the first point, the stencil sorts out the winding
+            for i in 1..pts.len().saturating_sub(1) {
+                self.push_vertex(pts[0]);
+                self.push_vertex(pts[i]);
+                self.push_vertex(pts[i + 1]);
+            }
+        }
+        let end = self.vertices.len() as u32;
+        if end == start {
+            None
+        } else {
+            Some((start..end, min, max))
+        }
+    }
+
+    /// Pushes strips on both sides of the closed polylines, with a coverage
+    /// going from 1 on the polylines to 0 at `FRINGE_WIDTH` from them.
+    /// Only the strips outside of the fill are drawn, the others are rejected by the stencil.
+    fn fringe(&mut self, lines: &[Polyline]) -> Range<u32> {
+        let start = self.vertices.len() as u32;
+        for line in lines {
+            let mut pts = line.points.clone();
+            pts.dedup();
+            if pts.len() > 1 && pts.first() == pts.last() {
+                pts.pop();
+            }
+            let n = pts.len();
+            if n < 3 {
+                continue;
+            }
+            let offsets: Vec<FPoint> = (0..n)
+                .map(|i| miter(pts[(i + n - 1) % n], pts[i], pts[(i + 1) % n]) * FRINGE_WIDTH)
+                .collect();
+            for &side in &[1f32, -1f32] {
+                for i in 0..n {
+                    let j = (i + 1) % n;
+                    let (a, b) = (pts[i], pts[j]);
+                    let (oa, ob) = (a + offsets[i] * side, b + offsets[j] * side);
+                    let strip = [
+                        (a, 1f32),
+                        (b, 1f32),
+                        (ob, 0f32),
+                        (a, 1f32),
+                        (ob, 0f32),
+                        (oa, 0f32),
+                    ];
+                    for &(p, coverage) in &strip {
+                        self.vertices.push(Vertex {
+                            pos: [p.x, p.y],
+                            coverage,
+                        });
+                    }
+                }
+            }
+        }
+        start..self.vertices.len() as u32
+    }
+
+    fn push_quad(&mut self, min: FPoint, max: FPoint) {
+        let quad = [
+            min,
+            FPoint::new(max.x, min.y),
+            max,
+            min,
+            max,
+            FPoint::new(min.x, max.y),
+        ];
+        for &p in &quad {
+            self.push_vertex(p);
+        }
+    }
+
+    /// Vertices of two triangles covering `rect` in user space.
+    fn transformed_rect(&mut self, rect: FRect) -> Range<u32> {
+        let start = self.vertices.len() as u32;
+        let (x0, y0, x1, y1) = (rect.x, rect.y, rect.x + rect.w, rect.y + rect.h);
+        for &(x, y) in &[(x0, y0), (x1, y0), (x1, y1), (x0, y0), (x1, y1), (x0, y1)] {
+            let p = self.transform.apply(FPoint::new(x, y));
+            self.push_vertex(p);
+        }
+        start..self.vertices.len() as u32
+    }
+
+    /// Vertices of a quad covering the whole viewport.
+    fn viewport_quad(&mut self) -> Range<u32> {
+        if let Some(quad) = &self.viewport_quad {
+            return quad.clone();
+        }
+        let start = self.vertices.len() as u32;
+        let size = FPoint::new(self.viewport[2], self.viewport[3]);
+        self.push_quad(FPoint::new(0f32, 0f32), size);
+        let quad = start..self.vertices.len() as u32;
+        self.viewport_quad = Some(quad.clone());
+        quad
+    }
+
+    fn push_clip(&mut self, clip: &Clip) {
+        let t = self.transform;
+        let (path, fill_rule) = match clip {
+            Clip::Rect(rect) if t.is_axis_aligned() => {
+                let r = t.apply_rect(*rect);
+                let prev = self.pass().scissor;
+                let s = [
+                    prev[0].max(r.x.round() as i32),
+                    prev[1].max(r.y.round() as i32),
+                    prev[2].min((r.x + r.w).round() as i32),
+                    prev[3].min((r.y + r.h).round() as i32),
+                ];
+                // empty scissors are kept well-formed
+                let s = [s[0], s[1], s[2].max(s[0]), s[3].max(s[1])];
+                self.pass().clips.push(ClipEntry::Scissor(prev));
+                self.set_scissor(s);
+                return;
+            }
+            Clip::Rect(rect) => (Path::rect(*rect), FillRule::NonZero),
+            Clip::RoundedRect { rect, radii } => (Path::rounded_rect(*rect, *radii), FillRule::NonZero),
+            Clip::Path { path, fill_rule } => (path.clone(), *fill_rule),
+        };
+        let lines = self.flatten(&path);
+        let vertices = match self.fan(&lines) {
+            Some((fan, _, _)) => fan,
+            // nothing is visible through an empty path
+            None => 0..0,
+        };
+        self.pass().clips.push(ClipEntry::Stencil {
+            fill_rule,
+            vertices: vertices.clone(),
+        });
+        self.apply_stencil_clip(fill_rule, vertices);
+    }
+
+    fn pop_clip(&mut self) {
+        match self.pass().clips.pop() {
+            Some(ClipEntry::Scissor(prev)) => self.set_scissor(prev),
+            Some(ClipEntry::Stencil { .. }) => {
+                // rebuild the clip bit from the remaining stencil clips
+                let vertices = self.viewport_quad();
+                self.pass().ops.push(Op::ClipReset { vertices });
+                let clips: Vec<_> = self
+                    .pass()
+                    .clips
+                    .iter()
+                    .filter_map(|c| match c {
+                        ClipEntry::Stencil {
+                            fill_rule,
+                            vertices,
+                        } => Some((*fill_rule, vertices.clone())),
+                        ClipEntry::Scissor(_) => None,
+                    })
+                    .collect();
+                for (fill_rule, vertices) in clips {
+                    self.apply_stencil_clip(fill_rule, vertices);
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn apply_stencil_clip(&mut self, fill_rule: FillRule, vertices: Range<u32>) {
+        if !vertices.is_empty() {
+            self.pass().ops.push(Op::ClipStencil {
+                fill_rule,
+                vertices,
+            });
+        }
+        let vertices = self.viewport_quad();
+        self.pass().ops.push(Op::ClipCover { vertices });
+    }
+
+    fn set_scissor(&mut self, scissor: [i32; 4]) {
+        let pass = self.pass();
+        pass.scissor = scissor;
+        pass.ops.push(Op::Scissor(scissor));
+    }
+
+    fn rounded_rect(&mut self, rect: FRect, radii: FCorners, paint: &Paint) {
+        if rect.w <= 0f32 || rect.h <= 0f32 {
+            return;
+        }
+        let pc = self.paint_consts(paint);
+        let (paint, color) = if pc.info[0] == PAINT_SOLID {
+            (None, pc.color)
+        } else {
+            (Some(pc), [0f32; 4])
+        };
+        let aa = match self.aa_margin() {
+            Some(aa) => aa,
+            None => return,
+        };
+        self.push_shape(
+            ShapeInstance {
+                bounds: inflate(edges(rect), aa),
+                rect: edges(rect),
+                radii: corners(radii.constrained(rect.w, rect.h)),
+                params: [SHAPE_FILL, 0f32, 0f32, 0f32],
+                colors: [color; 4],
+                ..ShapeInstance::default()
+            },
+            paint,
+        );
+    }
+
+    fn border(&mut self, b: &Border) {
+        let rect = b.rect;
+        if rect.w <= 0f32 || rect.h <= 0f32 {
+            return;
+        }
+        let aa = match self.aa_margin() {
+            Some(aa) => aa,
+            None => return,
+        };
+        let (w, c) = (&b.widths, &b.colors);
+        self.push_shape(
+            ShapeInstance {
+                bounds: inflate(edges(rect), aa),
+                rect: edges(rect),
+                radii: corners(b.radii.constrained(rect.w, rect.h)),
+                widths: [w.l.max(0f32), w.t.max(0f32), w.r.max(0f32), w.b.max(0f32)],
+                params: [SHAPE_BORDER, 0f32, 0f32, 0f32],
+                colors: [
+                    paint::premultiply(c.l),
+                    paint::premultiply(c.t),
+                    paint::premultiply(c.r),
+                    paint::premultiply(c.b),
+                ],
+                ..ShapeInstance::default()
+            },
+            None,
+        );
+    }
+
+    fn box_shadow(&mut self, s: &BoxShadow) {
+        let rect = s.rect;
+        if rect.w <= 0f32 || rect.h <= 0f32 {
+            return;
+        }
+        let aa = match self.aa_margin() {
+            Some(aa) => aa,
+            None => return,
+        };
+        let sigma = s.blur.max(0f32) / 2f32;
+        // inset shadows are cast by a hole that the spread shrinks
+        let spread = if s.inset { -s.spread } else { s.spread };
+        let shape = FRect::new(
+            rect.x + s.offset.x - spread,
+            rect.y + s.offset.y - spread,
+            (rect.w + 2f32 * spread).max(0f32),
+            (rect.h + 2f32 * spread).max(0f32),
+        );
+        if !s.inset && (shape.w == 0f32 || shape.h == 0f32) {
+            return;
+        }
+        let radii = s.radii.constrained(rect.w, rect.h);
+        let spread_radius = |r: f32| if r > 0f32 { (r + spread).max(0f32) } else { 0f32 };
+        let shape_radii = FCorners::new(
+            spread_radius(radii.tl),
+            spread_radius(radii.tr),
+            spread_radius(radii.br),
+            spread_radius(radii.bl),
+        );
+        let (kind, bounds) = if s.inset {
+            (SHAPE_INSET_SHADOW, inflate(edges(rect), aa))
+        } else {
+            (SHAPE_SHADOW, inflate(edges(shape), 3f32 * sigma + aa))
+        };
+        self.push_shape(
+            ShapeInstance {
+                bounds,
+                rect: edges(shape),
+                radii: corners(shape_radii.constrained(shape.w, shape.h)),
+                clip_rect: edges(rect),
+                clip_radii: corners(radii),
+                params: [kind, sigma, 0f32, 0f32],
+                colors: [paint::premultiply(s.color); 4],
+                ..ShapeInstance::default()
+            },
+            None,
+        );
+    }
+
+    /// Margin in shape space leaving room for one pixel of anti-aliasing.
+    /// None if the current transform is degenerated.
+    fn aa_margin(&self) -> Option<f32> {
+        let scale = self.transform.scale_factor();
+        if scale > 0f32 && scale.is_finite() {
+            Some(1f32 / scale)
+        } else {
+            None
+        }
+    }
+
+    fn push_shape(&mut self, mut instance: ShapeInstance, paint: Option<PaintConsts>) {
+        let t = &self.transform;
+        instance.mat0 = [t.m11, t.m21, t.m31, 0f32];
+        instance.mat1 = [t.m12, t.m22, t.m32, 0f32];
+        if self.aa == Antialiasing::None {
+            instance.params[2] = 1f32;
+        }
+        self.stats.primitives += 1;
+        let idx = self.instances.len() as u32;
+        self.instances.push(instance);
+        let blend_mode = self.blend_mode;
+        if paint.is_none() {
+            // consecutive shapes with their own color are drawn at once
+            if let Some(Op::Shapes {
+                paint: None,
+                blend_mode: mode,
+                instances,
+            }) = self.pass().ops.last_mut()
+            {
+                if *mode == blend_mode {
+                    instances.end = idx + 1;
+                    return;
+                }
+            }
+        }
+        self.pass().ops.push(Op::Shapes {
+            paint,
+            blend_mode,
+            instances: idx..idx + 1,
+        });
+    }
+
+    fn glyph_run(&mut self, run: &GlyphRun) {
+        let scale = self.transform.scale_factor();
+        let size = run.size * scale;
+        if run.glyphs.is_empty() || !(size > 0f32 && size.is_finite()) {
+            return;
+        }
+        self.stats.primitives += 1;
+        let paint = self.paint_consts(&run.paint);
+        let start = self.glyphs.len();
+        for g in &run.glyphs {
+            let origin = self.transform.apply(g.pos);
+            self.glyphs.push(GlyphInstance {
+                rect: [origin.x, origin.y, origin.x, origin.y],
+                uv: [0f32; 4],
+                params: [0f32; 4],
+            });
+        }
+        let end = self.glyphs.len();
+        self.glyph_runs.push(GlyphRequest {
+            font: run.font.clone(),
+            size,
+            ids: run.glyphs.iter().map(|g| g.id).collect(),
+            start,
+        });
+        let blend_mode = self.blend_mode;
+        self.pass().ops.push(Op::Glyphs {
+            paint,
+            blend_mode,
+            instances: start as u32..end as u32,
+        });
+    }
+
+    /// Replaces the origins of the glyph instances by their rectangles
+    /// in the viewport and in the atlas.
+    /// The glyphs that cannot be drawn are left empty.
+    fn resolve_glyphs(&mut self, cache: &mut GlyphCache) {
+        cache.begin_frame();
+        for run in &self.glyph_runs {
+            let instances = &mut self.glyphs[run.start..run.start + run.ids.len()];
+            for (inst, &id) in instances.iter_mut().zip(&run.ids) {
+                // the origin is snapped to a pixel vertically and to a subpixel step horizontally
+                let steps = SUBPIXEL_STEPS as f32;
+                let x = (inst.rect[0] * steps).round() / steps;
+                let px = x.floor();
+                let subpixel = ((x - px) * steps) as u32;
+                let py = inst.rect[1].round();
+                *inst = match cache.glyph(&run.font, id, run.size, subpixel) {
+                    Some(img) => {
+                        let (x0, y0) = (px + img.left as f32, py + img.top as f32);
+                        let (w, h) = (img.rect.w as f32, img.rect.h as f32);
+                        let (u0, v0) = (img.rect.x as f32, img.rect.y as f32);
+                        let color = if img.color { 1f32 } else { 0f32 };
+                        GlyphInstance {
+                            rect: [x0, y0, x0 + w, y0 + h],
+                            uv: [u0, v0, u0 + w, v0 + h],
+                            params: [color, 0f32, 0f32, 0f32],
+                        }
+                    }
+                    None => GlyphInstance::default(),
+                };
+            }
+        }
+    }
+
+    fn image(&mut self, draw: &ImageDraw) {
+        let opacity = draw.opacity.clamp(0f32, 1f32);
+        if draw.rect.w <= 0f32 || draw.rect.h <= 0f32 || opacity == 0f32 || opacity.is_nan() {
+            return;
+        }
+        let image = match draw.image.get() {
+            Some(image) => image,
+            None => {
+                let mut color = IMAGE_PLACEHOLDER;
+                color[3] *= opacity;
+                let lines = self.flatten(&Path::rect(draw.rect));
+                self.fill(&lines, FillRule::NonZero, &Paint::Solid(color));
+                return;
+            }
+        };
+        let (w, h) = (image.width() as f32, image.height() as f32);
+        let src = draw.src.unwrap_or_else(|| FRect::new(0f32, 0f32, w, h));
+        if !(src.w > 0f32 && src.h > 0f32) {
+            return;
+        }
+        let t = &self.transform;
+        let instance = ImageInstance {
+            mat0: [t.m11, t.m21, t.m31, 0f32],
+            mat1: [t.m12, t.m22, t.m32, 0f32],
+            ..ImageInstance::default()
+        };
+        self.stats.primitives += 1;
+        let start = self.images.len();
+        match draw.nine_patch {
+            Some(insets) => {
+                self.images.extend(
+                    nine_patch(draw.rect, src, insets)
+                        .into_iter()
+                        .map(|(rect, uv)| ImageInstance { rect, uv, ..instance }),
+                );
+            }
+            None => self.images.push(ImageInstance {
+                rect: edges(draw.rect),
+                uv: edges(src),
+                ..instance
+            }),
+        }
+        if !self.image_handles.contains(&draw.image) {
+            self.image_handles.push(draw.image.clone());
+        }
+        let (image, filter) = (draw.image.id(), draw.filter);
+        let (start, end) = (start as u32, self.images.len() as u32);
+        let blend_mode = self.blend_mode;
+        // consecutive draws of an image with the same state are drawn at once
+        if let Some(Op::Images {
+            image: i,
+            filter: f,
+            opacity: o,
+            blend_mode: mode,
+            instances,
+        }) = self.pass().ops.last_mut()
+        {
+            if (*i, *f, *o, *mode) == (image, filter, opacity, blend_mode) && instances.end == start {
+                instances.end = end;
+                return;
+            }
+        }
+        self.pass().ops.push(Op::Images {
+            image,
+            filter,
+            opacity,
+            blend_mode,
+            instances: start..end,
+        });
+    }
+
+    /// Fills `rect` with an effect, whose first input is the target of `layer` if any.
+    fn effect(&mut self, rect: FRect, draw: &EffectDraw, layer: Option<usize>) {
+        if rect.w <= 0f32 || rect.h <= 0f32 {
+            return;
+        }
+        let mut inputs: Vec<_> = layer.map(EffectInput::Layer).into_iter().collect();
+        for image in &draw.inputs {
+            if image.get().is_none() {
+                return;
+            }
+            if !self.image_handles.contains(image) {
+                self.image_handles.push(image.clone());
+            }
+            inputs.push(EffectInput::Image(image.id()));
+        }
+        if inputs.len() != draw.effect.inputs() {
+            self.warn(Warning::EffectInputs {
+                effect: draw.effect.id(),
+                samples: draw.effect.inputs(),
+                inputs: inputs.len(),
+            });
+            return;
+        }
+        let blend_mode = self.blend_mode;
+        let key = (draw.effect.clone(), blend_mode);
+        if !self.effects.contains(&key) {
+            self.effects.push(key);
+        }
+        self.stats.primitives += 1;
+        let t = &self.transform;
+        let consts = EffectConsts {
+            viewport: self.viewport,
+            mat0: [t.m11, t.m21, t.m31, 0f32],
+            mat1: [t.m12, t.m22, t.m32, 0f32],
+            rect: edges(rect),
+            uniforms: draw.uniforms,
+        };
+        self.pass().ops.push(Op::Effect {
+            effect: draw.effect.id(),
+            blend_mode,
+            consts,
+            inputs,
+        });
+    }
+
+    fn push_vertex(&mut self, p: FPoint) {
+        self.vertices.push(Vertex {
+            pos: [p.x, p.y],
+            coverage: 1f32,
+        });
+    }
+
+    fn paint_consts(&mut self, paint: &Paint) -> PaintConsts {
+        let mut pc = PaintConsts {
+            viewport: self.viewport,
+            info: [PAINT_SOLID, 0, 0, 0],
+            ..PaintConsts::default()
+        };
+        let (g, shared) = match paint {
+            Paint::Solid(c) => {
+                pc.color = paint::premultiply(*c);
+                return pc;
+            }
+            Paint::Gradient(g) => (g, None),
+            Paint::SharedGradient(h) => (h.gradient(), Some(h)),
+        };
+        let to_device = g.transform.then(&self.transform);
+        let (row, inv) = match (self.ramp_row(g, shared), to_device.inverse()) {
+            (Some(row), Some(inv)) => (row, inv),
+            _ => {
+                // out of ramp rows or degenerated transform
+                let c = g.stops.first().map_or([0f32; 4], |s| s.color);
+                pc.color = paint::premultiply(c);
+                return pc;
+            }
+        };
+        pc.mat0 = [inv.m11, inv.m21, inv.m31, 0f32];
+        pc.mat1 = [inv.m12, inv.m22, inv.m32, 0f32];
+        let (kind, params) = match g.kind {
+            GradientKind::Linear { start, end } => (PAINT_LINEAR, [start.x, start.y, end.x, end.y]),
+            GradientKind::Radial { center, radius } => {
+                (PAINT_RADIAL, [center.x, center.y, radius, 0f32])
+            }
+            GradientKind::Sweep {
+                center,
+                start_angle,
+                end_angle,
+            } => (PAINT_SWEEP, [center.x, center.y, start_angle, end_angle]),
+        };
+        let extend = match g.extend {
+            ExtendMode::Pad => 0,
+            ExtendMode::Repeat => 1,
+            ExtendMode::Reflect => 2,
+        };
+        pc.params = params;
+        pc.info = [kind, extend, row as i32, 0];
+        pc
+    }
+
+    /// Row of the ramp texture holding the gradient stops.
+    /// The ramps of the shared gradients are copied instead of computed.
+    fn ramp_row(&mut self, g: &Gradient, shared: Option<&GradientHandle>) -> Option<usize> {
+        let found = self.ramp_keys.iter().position(|key| match (key, shared) {
+            (RampKey::Shared(id), Some(h)) => *id == h.id(),
+            (RampKey::Stops(stops), None) => *stops == g.stops,
+            _ => false,
+        });
+        if found.is_some() {
+            return found;
+        }
+        if self.ramp_keys.len() == RAMP_ROWS {
+            self.warn(Warning::TooManyGradients);
+            return None;
+        }
+        match shared {
+            Some(h) => {
+                self.ramps.extend_from_slice(h.ramp());
+                self.ramp_keys.push(RampKey::Shared(h.id()));
+            }
+            None => {
+                self.ramps.extend(g.ramp());
+                self.ramp_keys.push(RampKey::Stops(g.stops.clone()));
+            }
+        }
+        debug_assert!(self.ramps.len().is_multiple_of(RAMP_SIZE));
+        Some(self.ramp_keys.len() - 1)
+    }
+
+    /// Records a pass in `cmd`, beginning and ending its render passes.
+    /// The pass is split where the target is copied to the backdrop.
+    /// Returns the number of pipelines bound.
+    pub unsafe fn record(
+        &self,
+        pass: usize,
+        cmd: &mut gfx::CommandBuffer,
+        res: &Resources,
+    ) -> usize {
+        let pipes = res.pipes;
+        let root = pass == self.passes.len() - 1;
+        let p = &self.passes[pass];
+        let clear_values = [
+            ClearValue::Color(ClearColor::Float(p.clear_color)),
+            ClearValue::DepthStencil(ClearDepthStencil(1f32, pipeline::CLIP_BIT)),
+        ];
+        if let Some(custom) = &p.custom {
+            let target = res.layers[pass];
+            // an empty render pass clears the target
+            cmd.begin_render_pass_inline(
+                &pipes.layer_pass,
+                &target.framebuffer,
+                target.area(),
+                &clear_values,
+            );
+            let ([x0, y0, x1, y1], [ox, oy]) = (custom.rect, self.origin(pass));
+            let rect = pso::Rect {
+                x: (x0 - ox) as i16,
+                y: (y0 - oy) as i16,
+                w: (x1 - x0) as i16,
+                h: (y1 - y0) as i16,
+            };
+            let rect = (rect, (ox, oy));
+            custom::record(&custom.callback, res.device, cmd, target, rect, res.image_index);
+            return 0;
+        }
+        let mut scissor = [0, 0, self.viewport[2] as i32, self.viewport[3] as i32];
+        let origin = self.origin(pass);
+        // the whole target is cleared, it may be larger than the pass
+        let area = if root {
+            res.area
+        } else {
+            res.layers[pass].area()
+        };
+        let mut binds = 0;
+        for (i, ops) in p.ops.split(|op| matches!(op, Op::Backdrop)).enumerate() {
+            let (render_pass, framebuffer) = if root {
+                debug_assert_eq!(i, 0, "the window is never copied");
+                (&pipes.render_pass, res.framebuffer)
+            } else if i == 0 {
+                (&pipes.layer_pass, &res.layers[pass].framebuffer)
+            } else {
+                let backdrop = res.backdrop.expect("no backdrop to copy to");
+                backdrop.copy(cmd, res.layers[pass]);
+                (&pipes.layer_resume_pass, &res.layers[pass].framebuffer)
+            };
+            let mut enc =
+                cmd.begin_render_pass_inline(render_pass, framebuffer, area, &clear_values);
+            binds += self.record_ops(ops, (p.scale, origin), &mut enc, res, &mut scissor);
+        }
+        binds
+    }
+
+    /// Records operations in a render pass, and returns the number of pipelines bound.
+    /// `scale` and `origin` are the ones of their pass, see `target_rect`.
+    /// `scissor` is the scissor in effect before them, and is updated.
+    unsafe fn record_ops(
+        &self,
+        ops: &[Op],
+        (scale, origin): (u32, [i32; 2]),
+        enc: &mut RenderPassInlineEncoder<gfx::Backend>,
+        res: &Resources,
+        scissor: &mut [i32; 4],
+    ) -> usize {
+        let pipes = res.pipes;
+        let vp = self.viewport;
+        let f = scale as i32;
+        // in the target, rounded outwards at the reduced resolutions
+        let target_rect = |s: [i32; 4]| {
+            let x0 = (vp[0] as i32 + s[0] - origin[0]).div_euclid(f);
+            let y0 = (vp[1] as i32 + s[1] - origin[1]).div_euclid(f);
+            let x1 = (vp[0] as i32 + s[2] - origin[0] + f - 1).div_euclid(f);
+            let y1 = (vp[1] as i32 + s[3] - origin[1] + f - 1).div_euclid(f);
+            [x0, y0, x1, y1]
+        };
+        let scissor_rect = |s: [i32; 4]| {
+            let [x0, y0, x1, y1] = target_rect(s);
+            let (x0, y0) = (x0.max(0), y0.max(0));
+            pso::Rect {
+                x: x0 as i16,
+                y: y0 as i16,
+                w: (x1 - x0).max(0) as i16,
+                h: (y1 - y0).max(0) as i16,
+            }
+        };
+        let viewport = [0, 0, vp[2] as i32, vp[3] as i32];
+        let [x0, y0, x1, y1] = target_rect(viewport);
+        enc.set_viewports(
+            0,
+            &[pso::Viewport {
+                rect: pso::Rect {
+                    x: x0 as i16,
+                    y: y0 as i16,
+                    w: (x1 - x0) as i16,
+                    h: (y1 - y0) as i16,
+                },
+                depth: 0f32..1f32,
+            }],
+        );
+        let full = scissor_rect(viewport);
+        enc.set_scissors(0, &[scissor_rect(*scissor)]);
+
+        if ops.is_empty() {
+            return 0;
+        }
+        enc.bind_graphics_descriptor_sets(&pipes.layout, 0, Some(res.desc_set), &[]);
+        // the fragment shaders find the viewport coordinates from the ones of the target
+        let (ox, oy) = (origin[0] as f32, origin[1] as f32);
+        let viewport_consts = PaintConsts {
+            viewport: [vp[0] - ox, vp[1] - oy, vp[2], vp[3]],
+            ..PaintConsts::default()
+        };
+        let paint_consts = |paint: &PaintConsts| PaintConsts {
+            viewport: viewport_consts.viewport,
+            ..*paint
+        };
+        // all the pipelines read binding 0
+        let mut bound = None;
+        let mut bind = |enc: &mut RenderPassInlineEncoder<gfx::Backend>, input: Input| {
+            if bound != Some(input) {
+                let buf = match input {
+                    Input::Vertices => res.vertices,
+                    Input::Shapes => res.instances,
+                    Input::Glyphs => res.glyphs,
+                    Input::Images => res.images,
+                };
+                enc.bind_vertex_buffers(0, Some(buf.expect("vertex data not uploaded")));
+                bound = Some(input);
+            }
+        };
+        let mut binds = 0;
+        let mut pipeline: *const gfx::GraphicsPipeline = ptr::null();
+        let mut use_pipeline =
+            |enc: &mut RenderPassInlineEncoder<gfx::Backend>, pipe: &gfx::GraphicsPipeline| {
+                if !ptr::eq(pipeline, pipe) {
+                    enc.bind_graphics_pipeline(pipe);
+                    pipeline = pipe;
+                    binds += 1;
+                }
+            };
+        for op in ops {
+            match op {
+                Op::Stencil {
+                    fill_rule,
+                    vertices,
+                } => {
+                    bind(enc, Input::Vertices);
+                    let pipe = match fill_rule {
+                        FillRule::NonZero => &pipes.stencil_nonzero,
+                        FillRule::EvenOdd => &pipes.stencil_evenodd,
+                    };
+                    use_pipeline(enc, pipe);
+                    enc.push_graphics_constants(
+                        &pipes.layout,
+                        PAINT_STAGES,
+                        0,
+                        viewport_consts.words(),
+                    );
+                    enc.draw(vertices.clone(), 0..1);
+                }
+                Op::Cover {
+                    paint,
+                    blend_mode,
+                    vertices,
+                } => {
+                    bind(enc, Input::Vertices);
+                    use_pipeline(enc, pipes.cover(*blend_mode));
+                    let consts = paint_consts(paint);
+                    enc.push_graphics_constants(&pipes.layout, PAINT_STAGES, 0, consts.words());
+                    enc.draw(vertices.clone(), 0..1);
+                }
+                Op::Fringe {
+                    paint,
+                    blend_mode,
+                    vertices,
+                } => {
+                    bind(enc, Input::Vertices);
+                    use_pipeline(enc, pipes.fringe(*blend_mode));
+                    let consts = paint_consts(paint);
+                    enc.push_graphics_constants(&pipes.layout, PAINT_STAGES, 0, consts.words());
+                    enc.draw(vertices.clone(), 0..1);
+                }
+                Op::Scissor(s) => {
+                    *scissor = *s;
+                    enc.set_scissors(0, &[scissor_rect(*s)]);
+                }
+                Op::ClipReset { vertices } | Op::ClipCover { vertices } => {
+                    bind(enc, Input::Vertices);
+                    let pipe = match op {
+                        Op::ClipReset { .. } => &pipes.clip_reset,
+                        _ => &pipes.clip_cover,
+                    };
+                    use_pipeline(enc, pipe);
+                    enc.push_graphics_constants(
+                        &pipes.layout,
+                        PAINT_STAGES,
+                        0,
+                        viewport_consts.words(),
+                    );
+                    // the clip bit must stay valid outside of the scissor
+                    enc.set_scissors(0, &[full]);
+                    enc.draw(vertices.clone(), 0..1);
+                    enc.set_scissors(0, &[scissor_rect(*scissor)]);
+                }
+                Op::ClipStencil {
+                    fill_rule,
+                    vertices,
+                } => {
+                    bind(enc, Input::Vertices);
+                    let pipe = match fill_rule {
+                        FillRule::NonZero => &pipes.clip_nonzero,
+                        FillRule::EvenOdd => &pipes.clip_evenodd,
+                    };
+                    use_pipeline(enc, pipe);
+                    enc.push_graphics_constants(
+                        &pipes.layout,
+                        PAINT_STAGES,
+                        0,
+                        viewport_consts.words(),
+                    );
+                    enc.set_scissors(0, &[full]);
+                    enc.draw(vertices.clone(), 0..1);
+                    enc.set_scissors(0, &[scissor_rect(*scissor)]);
+                }
+                Op::Backdrop => unreachable!("passes are split at the backdrop copies"),
+                Op::Composite {
+                    layer,
+                    scale: layer_scale,
+                    mask,
+                    opacity,
+                    blend_mode,
+                    vertices,
+                } => {
+                    bind(enc, Input::Vertices);
+                    let layer_set = &res.layers[*layer].desc_set;
+                    let mask_set = &res.layers[mask.unwrap_or(*layer)].desc_set;
+                    // the backdrop is only read by the blend modes done in the shader
+                    let (pipe, backdrop_set, shader_mode) = if blend_mode.is_fixed_function() {
+                        (pipes.composite(*blend_mode), layer_set, -1)
+                    } else {
+                        let backdrop = res.backdrop.expect("no backdrop to blend with");
+                        (&pipes.composite_backdrop, &backdrop.desc_set, *blend_mode as i32)
+                    };
+                    use_pipeline(enc, pipe);
+                    let sets = vec![layer_set, mask_set, backdrop_set];
+                    enc.bind_graphics_descriptor_sets(&pipes.composite_layout, 1, sets, &[]);
+                    let has_mask = if mask.is_some() { 1f32 } else { 0f32 };
+                    // offsets from the target to the layer and to the mask
+                    let offset = |pass: usize| {
+                        let o = self.origin(pass);
+                        [(origin[0] - o[0]) as f32, (origin[1] - o[1]) as f32]
+                    };
+                    let (layer_offset, mask_offset) =
+                        (offset(*layer), mask.map_or([0f32; 2], offset));
+                    let consts = PaintConsts {
+                        color: [
+                            layer_offset[0],
+                            layer_offset[1],
+                            mask_offset[0],
+                            mask_offset[1],
+                        ],
+                        params: [*opacity, has_mask, 0f32, 0f32],
+                        info: [shader_mode, *layer_scale as i32, 0, 0],
+                        ..viewport_consts
+                    };
+                    enc.push_graphics_constants(
+                        &pipes.composite_layout,
+                        PAINT_STAGES,
+                        0,
+                        consts.words(),
+                    );
+                    // the layer is transparent outside of its target
+                    let clip = match self.target_rect(*layer) {
+                        Some(([x, y], (w, h))) if blend_mode.is_bounded() => {
+                            let (x, y) = (x - vp[0] as i32, y - vp[1] as i32);
+                            let s = *scissor;
+                            let f = *layer_scale as i32;
+                            let (x1, y1) = (x + w as i32 * f, y + h as i32 * f);
+                            Some([s[0].max(x), s[1].max(y), s[2].min(x1), s[3].min(y1)])
+                        }
+                        _ => None,
+                    };
+                    if let Some(clip) = clip {
+                        enc.set_scissors(0, &[scissor_rect(clip)]);
+                    }
+                    enc.draw(vertices.clone(), 0..1);
+                    if clip.is_some() {
+                        enc.set_scissors(0, &[scissor_rect(*scissor)]);
+                    }
+                }
+                Op::Blur {
+                    src,
+                    src_scale,
+                    direction,
+                    sigma,
+                    vertices,
+                    ..
+                } => {
+                    bind(enc, Input::Vertices);
+                    use_pipeline(enc, &pipes.blur);
+                    let set = Some(&res.layers[*src].desc_set);
+                    enc.bind_graphics_descriptor_sets(&pipes.composite_layout, 1, set, &[]);
+                    let o = self.origin(*src);
+                    let offset = [(origin[0] - o[0]) as f32, (origin[1] - o[1]) as f32];
+                    let consts = PaintConsts {
+                        color: [offset[0], offset[1], 0f32, 0f32],
+                        params: [direction[0], direction[1], *sigma, 0f32],
+                        info: [scale as i32, *src_scale as i32, 0, 0],
+                        ..viewport_consts
+                    };
+                    enc.push_graphics_constants(
+                        &pipes.composite_layout,
+                        PAINT_STAGES,
+                        0,
+                        consts.words(),
+                    );
+                    enc.draw(vertices.clone(), 0..1);
+                }
+                Op::Shapes {
+                    paint,
+                    blend_mode,
+                    instances,
+                } => {
+                    bind(enc, Input::Shapes);
+                    use_pipeline(enc, pipes.shape(*blend_mode));
+                    let consts = paint.as_ref().map_or(viewport_consts, paint_consts);
+                    enc.push_graphics_constants(&pipes.layout, PAINT_STAGES, 0, consts.words());
+                    enc.draw(0..6, instances.clone());
+                }
+                Op::Glyphs {
+                    paint,
+                    blend_mode,
+                    instances,
+                } => {
+                    bind(enc, Input::Glyphs);
+                    use_pipeline(enc, pipes.glyph(*blend_mode));
+                    let consts = paint_consts(paint);
+                    enc.push_graphics_constants(&pipes.layout, PAINT_STAGES, 0, consts.words());
+                    enc.draw(0..6, instances.clone());
+                }
+                Op::Images {
+                    image,
+                    filter,
+                    opacity,
+                    blend_mode,
+                    instances,
+                } => {
+                    let set = match res.image_cache.desc_set(*image, *filter) {
+                        Some(set) => set,
+                        None => continue,
+                    };
+                    bind(enc, Input::Images);
+                    use_pipeline(enc, pipes.image(*blend_mode));
+                    enc.bind_graphics_descriptor_sets(&pipes.composite_layout, 1, Some(set), &[]);
+                    let consts = PaintConsts {
+                        params: [*opacity, 0f32, 0f32, 0f32],
+                        ..viewport_consts
+                    };
+                    enc.push_graphics_constants(
+                        &pipes.composite_layout,
+                        PAINT_STAGES,
+                        0,
+                        consts.words(),
+                    );
+                    enc.draw(0..6, instances.clone());
+                }
+                Op::Effect {
+                    effect,
+                    blend_mode,
+                    consts,
+                    inputs,
+                } => {
+                    let pipe = match res.effects.pipeline(*effect, *blend_mode) {
+                        Some(pipe) => pipe,
+                        None => continue,
+                    };
+                    let sets: Option<Vec<_>> = inputs
+                        .iter()
+                        .map(|input| match input {
+                            EffectInput::Image(id) => res.image_cache.desc_set(*id, Filter::Linear),
+                            EffectInput::Layer(layer) => Some(&res.layers[*layer].desc_set),
+                        })
+                        .collect();
+                    let sets = match sets {
+                        Some(sets) => sets,
+                        None => continue,
+                    };
+                    use_pipeline(enc, pipe);
+                    let rebind = !sets.is_empty();
+                    if rebind {
+                        enc.bind_graphics_descriptor_sets(&pipes.effect_layout, 0, sets, &[]);
+                    }
+                    enc.push_graphics_constants(
+                        &pipes.effect_layout,
+                        PAINT_STAGES,
+                        0,
+                        consts.words(),
+                    );
+                    // the vertices are generated by the vertex shader
+                    enc.draw(0..6, 0..1);
+                    if rebind {
+                        // the inputs replaced the paint set of the other pipelines
+                        let paint_set = Some(res.desc_set);
+                        enc.bind_graphics_descriptor_sets(&pipes.layout, 0, paint_set, &[]);
+                    }
+                }
+            }
+        }
+        binds
+    }
+}
+
+/// The vertex buffers read by the pipelines.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Input {
+    Vertices,
+    Shapes,
+    Glyphs,
+    Images,
+}
+
+/// Edges of a rectangle: x0, y0, x1, y1.
+fn edges(r: FRect) -> [f32; 4] {
+    [r.x, r.y, r.x + r.w, r.y + r.h]
+}
+
+fn intersect(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    [
+        a[0].max(b[0]),
+        a[1].max(b[1]),
+        a[2].min(b[2]),
+        a[3].min(b[3]),
+    ]
+}
+
+/// Bounds of two rectangles, ignoring the empty ones.
+fn union(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    if is_empty(a) {
+        b
+    } else if is_empty(b) {
+        a
+    } else {
+        [
+            a[0].min(b[0]),
+            a[1].min(b[1]),
+            a[2].max(b[2]),
+            a[3].max(b[3]),
+        ]
+    }
+}
+
+fn is_empty(r: [f32; 4]) -> bool {
+    !(r[0] < r[2] && r[1] < r[3])
+}
+
+/// The scale of the targets of a blur, and its standard deviation in their texels.
+/// The resolution is reduced while the standard deviation stays above `MIN_BLUR_TEXELS`.
+fn blur_scale(radius: f32) -> (u32, f32) {
+    let sigma = (radius / 2f32).min(MAX_BLUR_SIGMA);
+    let mut scale = 1;
+    while scale < MAX_BLUR_SCALE && sigma / (2 * scale) as f32 >= MIN_BLUR_TEXELS {
+        scale *= 2;
+    }
+    (scale, sigma / scale as f32)
+}
+
+/// Texels sampled on each side of a blurred texel, like `shaders/blur.frag`.
+fn blur_taps(sigma: f32) -> u32 {
+    ((3f32 * sigma).ceil() as u32).min(MAX_BLUR_TAPS)
+}
+
+/// Weights of the samples of a blurred texel, from the last one on the left.
+#[cfg(test)]
+fn blur_weights(sigma: f32) -> Vec<f32> {
+    let taps = blur_taps(sigma) as i32;
+    let weights: Vec<f32> = (-taps..=taps)
+        .map(|i| (-0.5 * (i * i) as f32 / (sigma * sigma)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    weights.iter().map(|w| w / total).collect()
+}
+
+fn inflate(e: [f32; 4], d: f32) -> [f32; 4] {
+    [e[0] - d, e[1] - d, e[2] + d, e[3] + d]
+}
+
+fn corners(c: FCorners) -> [f32; 4] {
+    [c.tl, c.tr, c.br, c.bl]
+}
+
+/// Splits `rect` and `src` in 3x3 patches, returning their edges row by row.
+/// The corners keep the size of the insets of `src`, unless `rect` is too small for them.
+fn nine_patch(rect: FRect, src: FRect, insets: FMargins) -> Vec<([f32; 4], [f32; 4])> {
+    // unlike clamp, does not panic on an empty or NaN source
+    let fit = |inset: f32, max: f32| inset.max(0f32).min(max.max(0f32));
+    let (l, t) = (fit(insets.l, src.w), fit(insets.t, src.h));
+    let (r, b) = (fit(insets.r, src.w - l), fit(insets.b, src.h - t));
+    let sx = if l + r > rect.w { rect.w / (l + r) } else { 1f32 };
+    let sy = if t + b > rect.h { rect.h / (t + b) } else { 1f32 };
+    let (x1, y1) = (rect.x + rect.w, rect.y + rect.h);
+    let xs = [rect.x, rect.x + l * sx, x1 - r * sx, x1];
+    let ys = [rect.y, rect.y + t * sy, y1 - b * sy, y1];
+    let (u1, v1) = (src.x + src.w, src.y + src.h);
+    let us = [src.x, src.x + l, u1 - r, u1];
+    let vs = [src.y, src.y + t, v1 - b, v1];
+    (0..9)
+        .map(|i| {
+            let (col, row) = (i % 3, i / 3);
+            (
+                [xs[col], ys[row], xs[col + 1], ys[row + 1]],
+                [us[col], vs[row], us[col + 1], vs[row + 1]],
+            )
+        })
+        .collect()
+}
+
+/// Offset of the outline at `p` for a unit width, on the side of the normal of `prev` to `next`.
+/// The offset is mitered, and limited at sharp angles.
+fn miter(prev: FPoint, p: FPoint, next: FPoint) -> FPoint {
+    let n0 = (p - prev).normalize().perp();
+    let n1 = (next - p).normalize().perp();
+    let m = n0 + n1;
+    let len = m.length();
+    if len < 1e-3 {
+        // the outline turns back
+        return n1;
+    }
+    let m = m * (1f32 / len);
+    m * (1f32 / m.dot(n1).max(0.25))
+}
+
+#[cfg(test)]
+fn build(cmds: Vec<Cmd>) -> DrawData {
+    let (viewport, aa) = (IRect::new(0, 0, 200, 100), Antialiasing::Analytic);
+    let mut data = DrawData::build(viewport, None, &cmds, Instant::now(), aa);
+    data.measure();
+    data
+}
+
+/// The scissor and the number of stencil clips after each clip operation of a pass.
+#[cfg(test)]
+fn clip_states(pass: &Pass) -> Vec<([i32; 4], usize)> {
+    let (mut scissor, mut depth) = ([0, 0, 200, 100], 0);
+    let mut states = Vec::new();
+    for op in &pass.ops {
+        match op {
+            Op::Scissor(s) => scissor = *s,
+            Op::ClipReset { .. } => depth = 0,
+            Op::ClipStencil { .. } => {
+                depth += 1;
+                continue;
+            }
+            Op::ClipCover { .. } => {}
+            _ => continue,
+        }
+        states.push((scissor, depth));
+    }
+    states
+}
+
+#[test]
+fn nested_clips() {
+    let rect = |x, y, w, h| Clip::Rect(FRect::new(x, y, w, h));
+    let data = build(vec![
+        Cmd::PushClip(rect(10f32, 10f32, 100f32, 50f32)),
+        // axis-aligned clips intersect the scissor, rounded to pixels
+        Cmd::PushTransform(Transform::translation(0f32, 5f32)),
+        Cmd::PushClip(rect(20.4, 0f32, 200f32, 20.6)),
+        Cmd::PushTransform(Transform::scale(2f32, 2f32)),
+        Cmd::PushClip(rect(0f32, 0f32, 30f32, 30f32)),
+        Cmd::PopClip,
+        Cmd::PopTransform,
+        Cmd::PopClip,
+        Cmd::PopTransform,
+        // the other clips are written in the stencil buffer, within the scissor
+        Cmd::PushTransform(Transform::rotation(0.5)),
+        Cmd::PushClip(rect(0f32, 0f32, 50f32, 50f32)),
+        Cmd::PushClip(Clip::RoundedRect {
+            rect: FRect::new(0f32, 0f32, 40f32, 40f32),
+            radii: FCorners::new(5f32, 5f32, 5f32, 5f32),
+        }),
+        Cmd::PopTransform,
+        Cmd::PushClip(rect(50f32, 0f32, 100f32, 100f32)),
+        Cmd::PopClip,
+        // popping a stencil clip rebuilds the clip bit from the remaining ones
+        Cmd::PopClip,
+        Cmd::PopClip,
+        Cmd::PopClip,
+    ]);
+    assert_eq!(1, data.passes.len());
+    let outer = [10, 10, 110, 60];
+    assert_eq!(
+        vec![
+            (outer, 0),
+            ([20, 10, 110, 26], 0),
+            ([20, 10, 60, 26], 0),
+            ([20, 10, 110, 26], 0),
+            (outer, 0),
+            (outer, 1),
+            (outer, 2),
+            ([50, 10, 110, 60], 2),
+            (outer, 2),
+            (outer, 0),
+            (outer, 1),
+            (outer, 0),
+            ([0, 0, 200, 100], 0),
+        ],
+        clip_states(&data.passes[0])
+    );
+}
+
+#[test]
+fn sibling_layers() {
+    let rect = |x, y| Cmd::RoundedRect {
+        rect: FRect::new(x, y, 20f32, 10f32),
+        radii: FCorners::new(2f32, 2f32, 2f32, 2f32),
+        paint: Paint::Solid([1f32, 0f32, 0f32, 1f32]),
+    };
+    let data = build(vec![
+        Cmd::PushLayer(Layer::new(0.5)),
+        rect(10f32, 10f32),
+        Cmd::PopLayer,
+        Cmd::PushLayer(Layer::new(0.5)),
+        rect(100.5, 50f32),
+        rect(150f32, 80f32),
+        Cmd::PopLayer,
+        Cmd::PushLayer(Layer::new(1f32).with_blur(4f32)),
+        rect(0f32, 0f32),
+        Cmd::PopLayer,
+    ]);
+    // each layer has its own target, sized to its content and its antialiasing margin,
+    // and the blur spreads the content by its 6 taps on each side
+    let passes = 0..data.passes.len();
+    let rects: Vec<_> = passes.map(|p| data.target_rect(p)).collect();
+    assert_eq!(
+        vec![
+            Some(([9, 9], (22, 12))),
+            Some(([99, 49], (72, 42))),
+            Some(([0, 0], (21, 11))),
+            Some(([0, 0], (28, 18))),
+            Some(([0, 0], (35, 25))),
+            None,
+        ],
+        rects
+    );
+}
+
+#[test]
+fn blend_groups() {
+    let rect = |x, y| Cmd::RoundedRect {
+        rect: FRect::new(x, y, 8f32, 8f32),
+        radii: FCorners::new(0f32, 0f32, 0f32, 0f32),
+        paint: Paint::Solid([1f32, 0f32, 0f32, 1f32]),
+    };
+    // draws that do not overlap are blended through one layer
+    let mut cmds = vec![Cmd::BlendMode(BlendMode::Multiply)];
+    cmds.extend((0..100).map(|i| rect((i % 20 * 10) as f32, (i / 20 * 10) as f32)));
+    let data = build(cmds);
+    // the root is rendered in a layer too, since the group reads it as backdrop
+    assert_eq!(3, data.passes.len());
+    assert_eq!(2, data.passes[1].ops.len());
+    assert_eq!(Some(([0, 0], (199, 49))), data.target_rect(0));
+
+    // another mode, or a draw over the previous ones, needs another layer
+    let data = build(vec![
+        Cmd::BlendMode(BlendMode::Multiply),
+        rect(0f32, 0f32),
+        rect(20f32, 0f32),
+        rect(24f32, 4f32),
+        Cmd::BlendMode(BlendMode::Screen),
+        rect(50f32, 0f32),
+        Cmd::BlendMode(BlendMode::Overlay),
+        rect(60f32, 0f32),
+        Cmd::BlendMode(BlendMode::Darken),
+        rect(70f32, 0f32),
+    ]);
+    let layers = 0..data.passes.len() - 1;
+    let rects: Vec<_> = layers.map(|p| data.target_rect(p)).collect();
+    // screen is blended by the fixed-function pipeline
+    assert_eq!(
+        vec![
+            Some(([0, 0], (29, 9))),
+            Some(([23, 3], (10, 10))),
+            Some(([59, 0], (10, 9))),
+            Some(([69, 0], (10, 9))),
+            Some(([0, 0], (79, 13))),
+        ],
+        rects
+    );
+}
+
+#[test]
+fn nine_patches() {
+    let (rect, src) = (
+        FRect::new(0f32, 0f32, 100f32, 10f32),
+        FRect::new(0f32, 0f32, 8f32, 8f32),
+    );
+    let patches = nine_patch(rect, src, FMargins::new(2f32, 2f32, 2f32, 2f32));
+    // the corners keep their size, the edges and the center stretch
+    assert_eq!(
+        ([0f32, 0f32, 2f32, 2f32], [0f32, 0f32, 2f32, 2f32]),
+        patches[0]
+    );
+    assert_eq!(
+        ([2f32, 2f32, 98f32, 8f32], [2f32, 2f32, 6f32, 6f32]),
+        patches[4]
+    );
+    assert_eq!(
+        ([98f32, 8f32, 100f32, 10f32], [6f32, 6f32, 8f32, 8f32]),
+        patches[8]
+    );
+    // the insets are limited to the source, even when it is empty or not a number
+    let patches = nine_patch(rect, src, FMargins::new(6f32, 6f32, -1f32, f32::NAN));
+    assert_eq!(
+        ([0f32, 0f32, 6f32, 0f32], [0f32, 0f32, 6f32, 0f32]),
+        patches[0]
+    );
+    assert_eq!(
+        ([98f32, 10f32, 100f32, 10f32], [6f32, 8f32, 8f32, 8f32]),
+        patches[8]
+    );
+    for src in &[
+        FRect::new(0f32, 0f32, -8f32, 8f32),
+        FRect::new(0f32, 0f32, 8f32, f32::NAN),
+    ] {
+        assert_eq!(
+            9,
+            nine_patch(rect, *src, FMargins::new(2f32, 2f32, 2f32, 2f32)).len()
+        );
+    }
+}
+
+#[test]
+fn blurs() {
+    // the resolution is reduced while the kernel keeps 1.5 texels of standard deviation
+    assert_eq!((1, 1f32), blur_scale(2f32));
+    assert_eq!((4, 1.5), blur_scale(12f32));
+    assert_eq!((8, 1.5), blur_scale(24f32));
+    assert_eq!((8, 10f32), blur_scale(400f32));
+    for &radius in &[0.5, 2f32, 5f32, 12f32, 40f32, 100f32, 400f32, 1000f32] {
+        let (_, sigma) = blur_scale(radius);
+        // the kernel is not cut before 3 standard deviations
+        assert!(3f32 * sigma <= MAX_BLUR_TAPS as f32, "{}", radius);
+        let weights = blur_weights(sigma);
+        let taps = blur_taps(sigma) as usize;
+        assert_eq!(2 * taps + 1, weights.len());
+        assert!((weights.iter().sum::<f32>() - 1f32).abs() < 1e-5);
+        for i in 0..=taps {
+            assert_eq!(weights[taps - i], weights[taps + i]);
+            let gaussian = (-0.5 * (i * i) as f32 / (sigma * sigma)).exp();
+            assert!((weights[taps + i] / weights[taps] - gaussian).abs() < 1e-5);
+        }
+    }
+    // close to the continuous Gaussian
+    let weights = blur_weights(1.5);
+    let peak = 1f32 / (2f32 * std::f32::consts::PI).sqrt() / 1.5;
+    assert!((weights[5] - peak).abs() < 1e-3);
+
+    // the passes of a backdrop blur cover the blurred rectangle and the reach of the kernel,
+    // at a reduced resolution
+    let data = build(vec![
+        Cmd::RoundedRect {
+            rect: FRect::new(0f32, 0f32, 200f32, 100f32),
+            radii: FCorners::new(0f32, 0f32, 0f32, 0f32),
+            paint: Paint::Solid([1f32, 0f32, 0f32, 1f32]),
+        },
+        Cmd::BackdropBlur {
+            rect: FRect::new(100f32, 40f32, 40f32, 20f32),
+            radius: 24f32,
+        },
+    ]);
+    let scales: Vec<_> = data.passes.iter().map(|p| p.scale).collect();
+    assert_eq!(vec![1, 8, 8, 1], scales);
+    // 5 taps of 8 pixels and the filtering around [100, 40, 140, 60], in texels of 8 pixels
+    let blurred = Some(([48, 0], (18, 13)));
+    assert_eq!(blurred, data.target_rect(1));
+    assert_eq!(blurred, data.target_rect(2));
+}
+
+#[test]
+fn custom_passes() {
+    let rect = |x, y| Cmd::RoundedRect {
+        rect: FRect::new(x, y, 8f32, 8f32),
+        radii: FCorners::new(0f32, 0f32, 0f32, 0f32),
+        paint: Paint::Solid([1f32, 0f32, 0f32, 1f32]),
+    };
+    let callback: RenderCallback = std::sync::Arc::new(|_| {});
+    let data = build(vec![
+        rect(0f32, 0f32),
+        Cmd::PushTransform(Transform::translation(10.5, 20f32)),
+        Cmd::Custom {
+            rect: FRect::new(0f32, 0f32, 30f32, 40f32),
+            callback,
+        },
+        Cmd::PopTransform,
+        rect(20f32, 20f32),
+    ]);
+    // the callback renders its own pass, composited between the rectangles
+    assert_eq!(2, data.passes.len());
+    let custom = data.passes[0].custom.as_ref().unwrap();
+    assert_eq!([10, 20, 41, 60], custom.rect);
+    assert_eq!(Some(([10, 20], (31, 40))), data.target_rect(0));
+    let ops = &data.passes[1].ops;
+    assert_eq!(3, ops.len());
+    assert!(matches!(ops[0], Op::Shapes { .. }));
+    assert!(matches!(ops[1], Op::Composite { layer: 0, .. }));
+    assert!(matches!(ops[2], Op::Shapes { .. }));
+}
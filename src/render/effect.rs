@@ -0,0 +1,188 @@
+use super::pipeline::Pipelines;
+use super::resource::{Handle, ResourceId};
+use crate::gfx;
+use crate::paint::BlendMode;
+use gfx_hal::Device;
+use std::collections::HashMap;
+
+/// Maximum number of textures sampled by an effect.
+pub const MAX_EFFECT_INPUTS: usize = 4;
+
+/// Number of floats of the uniform block of an effect.
+pub const EFFECT_UNIFORMS: usize = 16;
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+/// A user-supplied SPIR-V fragment shader that fills a rectangle, or transforms a layer.
+///
+/// The shader has the following interface, with `main` as entry point:
+///
+/// ```glsl
+/// layout(push_constant) uniform EffectConsts {
+///     vec4 viewport;
+///     vec4 mat0;
+///     vec4 mat1;
+///     vec4 rect;
+///     // the uniforms of the draw
+///     vec4 uniforms[4];
+/// } pc;
+///
+/// // the input i, for i from 0 to the number of inputs
+/// layout(set = i, binding = 0) uniform texture2D u_input_i;
+/// layout(set = i, binding = 1) uniform sampler u_input_i_sampler;
+///
+/// // from (0, 0) to (1, 1) across the rectangle
+/// layout(location = 0) in vec2 v_uv;
+/// // in user space
+/// layout(location = 1) in vec2 v_pos;
+///
+/// // premultiplied
+/// layout(location = 0) out vec4 o_color;
+/// ```
+///
+/// The images are sampled with normalized coordinates. Applied to a layer, the effect covers
+/// the viewport, and its first input is the content of the layer, which has the size
+/// of the window: it is sampled at `gl_FragCoord.xy / textureSize(...)`.
+pub struct Effect {
+    spirv: Vec<u8>,
+    inputs: usize,
+}
+
+/// An effect shared by the frames, compiled by each window that draws it.
+pub type EffectHandle = Handle<Effect>;
+
+impl Effect {
+    /// Panics if `spirv` is not a SPIR-V module or if there are too many inputs.
+    pub(crate) fn new(spirv: &[u8], inputs: usize) -> Effect {
+        if let Err(msg) = Effect::check(spirv, inputs) {
+            panic!("{}", msg);
+        }
+        Effect {
+            spirv: spirv.to_vec(),
+            inputs,
+        }
+    }
+
+    /// Whether `Effect::new` accepts `spirv` and `inputs`.
+    pub(crate) fn check(spirv: &[u8], inputs: usize) -> Result<(), String> {
+        if !spirv.len().is_multiple_of(4) || spirv.len() < 20 {
+            return Err("an effect needs a SPIR-V module".into());
+        }
+        let magic = u32::from_le_bytes([spirv[0], spirv[1], spirv[2], spirv[3]]);
+        if magic != SPIRV_MAGIC {
+            return Err("an effect needs a little-endian SPIR-V module".into());
+        }
+        if inputs > MAX_EFFECT_INPUTS {
+            return Err(format!(
+                "an effect samples at most {} inputs",
+                MAX_EFFECT_INPUTS
+            ));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn spirv(&self) -> &[u8] {
+        &self.spirv
+    }
+
+    /// Number of textures sampled by the shader.
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+}
+
+/// The pipelines of the effects drawn in a window, by blend mode.
+/// They are compiled when a frame first draws them.
+pub(crate) struct EffectPipelines {
+    /// None if the effect failed to compile.
+    pipelines: HashMap<(ResourceId, BlendMode), Option<gfx::GraphicsPipeline>>,
+}
+
+impl EffectPipelines {
+    pub fn new() -> EffectPipelines {
+        EffectPipelines {
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Compiles the effects drawn by a frame.
+    /// The errors are reported once, and the effects that failed are not drawn.
+    pub fn prepare(
+        &mut self,
+        dev: &gfx::Device,
+        cache: &gfx::PipelineCache,
+        pipes: &Pipelines,
+        effects: &[(EffectHandle, BlendMode)],
+    ) {
+        for (effect, mode) in effects {
+            let key = (effect.id(), *mode);
+            if self.pipelines.contains_key(&key) {
+                continue;
+            }
+            let pipe = match unsafe { pipes.effect(dev, cache, &effect.spirv, *mode) } {
+                Ok(pipe) => Some(pipe),
+                Err(err) => {
+                    println!("could not compile an effect: {}", err);
+                    None
+                }
+            };
+            self.pipelines.insert(key, pipe);
+        }
+    }
+
+    pub fn pipeline(&self, id: ResourceId, mode: BlendMode) -> Option<&gfx::GraphicsPipeline> {
+        self.pipelines.get(&(id, mode)).and_then(Option::as_ref)
+    }
+
+    /// Destroys the pipelines of a released effect.
+    /// The device must not use them anymore.
+    pub fn remove(&mut self, dev: &gfx::Device, id: ResourceId) {
+        let keys: Vec<_> = self
+            .pipelines
+            .keys()
+            .filter(|k| k.0 == id)
+            .cloned()
+            .collect();
+        for key in keys {
+            if let Some(Some(pipe)) = self.pipelines.remove(&key) {
+                unsafe {
+                    dev.destroy_graphics_pipeline(pipe);
+                }
+            }
+        }
+    }
+
+    /// Destroys all the pipelines, which are compiled again when drawn.
+    /// The device must not use them anymore.
+    pub fn clear(&mut self, dev: &gfx::Device) {
+        for pipe in self.pipelines.drain().filter_map(|(_, pipe)| pipe) {
+            unsafe {
+                dev.destroy_graphics_pipeline(pipe);
+            }
+        }
+    }
+}
+
+#[test]
+fn check_effects() {
+    // the header of a module: magic number, version, generator, bound and schema
+    let header = [SPIRV_MAGIC, 0x0001_0000, 0, 1, 0];
+    let mut spirv: Vec<u8> = header
+        .iter()
+        .flat_map(|w| w.to_le_bytes().to_vec())
+        .collect();
+    assert_eq!(Ok(()), Effect::check(&spirv, MAX_EFFECT_INPUTS));
+    assert_eq!(
+        Err("an effect samples at most 4 inputs".to_string()),
+        Effect::check(&spirv, MAX_EFFECT_INPUTS + 1)
+    );
+    // truncated, or not made of words
+    assert!(Effect::check(&spirv[..16], 0).is_err());
+    spirv.push(0);
+    assert!(Effect::check(&spirv, 0).is_err());
+    spirv.pop();
+    // big-endian, or not SPIR-V at all
+    spirv[..4].reverse();
+    assert!(Effect::check(&spirv, 0).is_err());
+    assert!(Effect::check(b"#version 450\nvoid main() {}\n", 0).is_err());
+}
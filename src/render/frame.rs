@@ -1,14 +1,353 @@
-use crate::geom::IRect;
+use super::animation::Animation;
+use super::custom::RenderCallback;
+use super::effect::{EffectHandle, EFFECT_UNIFORMS};
+use super::images::{Filter, ImageHandle};
+use super::resource::PathHandle;
+use crate::geom::{FCorners, FMargins, FPoint, FRect, IRect, Margins, Transform};
+use crate::paint::{BlendMode, Color, Paint};
+use crate::path::{FillRule, Path};
+use crate::text::{Font, GlyphId};
 use winit::WindowId;
 
 pub struct Frame {
     pub window: WindowId,
     pub viewport: IRect,
     pub clear_color: Option<[f32; 4]>,
+    pub cmds: Vec<Cmd>,
+}
+
+/// A display list command.
+/// Coordinates are in the user space set by the transform stack,
+/// which starts as pixels relative to the frame viewport.
+pub enum Cmd {
+    /// Pushes a transform applied to the following commands before the current one.
+    PushTransform(Transform),
+    PopTransform,
+    /// Restricts the following commands to the intersection of `Clip` and of the current clip.
+    /// The clip is captured with the current transform.
+    PushClip(Clip),
+    PopClip,
+    /// Renders the commands up to the matching `PopLayer` in an intermediate target,
+    /// and composites the result.
+    PushLayer(Layer),
+    PopLayer,
+    /// Sets how the following commands blend with what is below them,
+    /// until the end of the current layer.
+    BlendMode(BlendMode),
+    Fill {
+        path: Path,
+        fill_rule: FillRule,
+        paint: Paint,
+    },
+    /// Fills a path shared by many frames, whose flattening is cached.
+    FillShared {
+        path: PathHandle,
+        fill_rule: FillRule,
+        paint: Paint,
+    },
+    RoundedRect {
+        rect: FRect,
+        radii: FCorners,
+        paint: Paint,
+    },
+    Border(Border),
+    BoxShadow(BoxShadow),
+    GlyphRun(GlyphRun),
+    Image(ImageDraw),
+    /// Fills a rectangle with a custom effect.
+    Effect { rect: FRect, effect: EffectDraw },
+    /// Blurs what is already rendered below a rectangle, inside the current clip,
+    /// as the frosted glass of a panel drawn afterwards.
+    /// `radius` is twice the standard deviation of the Gaussian, like `BoxShadow::blur`.
+    /// Inside a layer, only what the layer rendered so far is blurred,
+    /// not what the layer is composited on.
+    BackdropBlur { rect: FRect, radius: f32 },
+    /// Fills a rectangle with what a callback renders on the render thread.
+    /// See `CustomRender` for the rules the callback follows.
+    Custom {
+        rect: FRect,
+        callback: RenderCallback,
+    },
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Clip {
+    Rect(FRect),
+    RoundedRect { rect: FRect, radii: FCorners },
+    Path { path: Path, fill_rule: FillRule },
+}
+
+/// A group of commands composited at once.
+pub struct Layer {
+    pub opacity: f32,
+    pub blend_mode: BlendMode,
+    /// Display list whose alpha masks the layer, in the same space as the layer.
+    pub mask: Option<Vec<Cmd>>,
+    /// Transforms the content of the layer, which is the first input of the effect,
+    /// before it is masked and composited.
+    pub effect: Option<EffectDraw>,
+    /// Blur radius of the content, after the effect if any.
+    /// Twice the standard deviation of the Gaussian, like `BoxShadow::blur`.
+    pub blur: f32,
+    /// Properties interpolated by the render thread each time it renders the frame.
+    pub animations: Vec<Animation>,
+}
+
+impl Layer {
+    pub fn new(opacity: f32) -> Layer {
+        Layer {
+            opacity,
+            blend_mode: BlendMode::SrcOver,
+            mask: None,
+            effect: None,
+            blur: 0f32,
+            animations: Vec::new(),
+        }
+    }
+
+    pub fn with_blend_mode(mut self, blend_mode: BlendMode) -> Layer {
+        self.blend_mode = blend_mode;
+        self
+    }
+
+    pub fn with_mask(mut self, mask: Vec<Cmd>) -> Layer {
+        self.mask = Some(mask);
+        self
+    }
+
+    pub fn with_effect(mut self, effect: EffectDraw) -> Layer {
+        self.effect = Some(effect);
+        self
+    }
+
+    pub fn with_blur(mut self, radius: f32) -> Layer {
+        self.blur = radius;
+        self
+    }
+
+    pub fn with_animation(mut self, animation: Animation) -> Layer {
+        self.animations.push(animation);
+        self
+    }
+}
+
+/// A custom effect with the values of its uniform block and its input images.
+/// The images that are not loaded yet prevent the effect from being drawn.
+#[derive(Clone, PartialEq, Debug)]
+pub struct EffectDraw {
+    pub effect: EffectHandle,
+    pub uniforms: [f32; EFFECT_UNIFORMS],
+    pub inputs: Vec<ImageHandle>,
+}
+
+impl EffectDraw {
+    pub fn new(effect: EffectHandle) -> EffectDraw {
+        EffectDraw {
+            effect,
+            uniforms: [0f32; EFFECT_UNIFORMS],
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn with_uniforms(mut self, uniforms: [f32; EFFECT_UNIFORMS]) -> EffectDraw {
+        self.uniforms = uniforms;
+        self
+    }
+
+    pub fn with_input(mut self, image: ImageHandle) -> EffectDraw {
+        self.inputs.push(image);
+        self
+    }
+}
+
+/// A border drawn inside `rect`, with one width and color per side.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Border {
+    pub rect: FRect,
+    pub radii: FCorners,
+    pub widths: FMargins,
+    pub colors: Margins<Color>,
+}
+
+/// A Gaussian shadow cast by a rounded rectangle, with CSS semantics.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BoxShadow {
+    /// Rectangle of the element casting the shadow.
+    pub rect: FRect,
+    pub radii: FCorners,
+    pub offset: FPoint,
+    /// Blur radius, twice the standard deviation of the Gaussian.
+    pub blur: f32,
+    pub spread: f32,
+    pub color: Color,
+    /// Inset shadows are drawn inside the element, outset ones outside of it.
+    pub inset: bool,
+}
+
+/// Glyphs of a font filled with a paint.
+/// The glyphs stay upright, at the size given by the scale of the transform.
+#[derive(Clone, PartialEq, Debug)]
+pub struct GlyphRun {
+    pub font: Font,
+    /// Font size, in pixels of the user space.
+    pub size: f32,
+    pub glyphs: Vec<PositionedGlyph>,
+    pub paint: Paint,
+}
+
+/// An image, or a part of it, stretched over a rectangle.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ImageDraw {
+    pub image: ImageHandle,
+    pub rect: FRect,
+    /// Part of the image that is drawn, in texels. The whole image if None.
+    /// Nothing is drawn if it is empty.
+    pub src: Option<FRect>,
+    pub filter: Filter,
+    /// Insets of the source, in texels, splitting it in nine patches.
+    /// The corners keep their size, the edges stretch along them and the center both ways.
+    pub nine_patch: Option<FMargins>,
+    pub opacity: f32,
+}
+
+impl ImageDraw {
+    pub fn new(image: ImageHandle, rect: FRect) -> ImageDraw {
+        ImageDraw {
+            image,
+            rect,
+            src: None,
+            filter: Filter::Linear,
+            nine_patch: None,
+            opacity: 1f32,
+        }
+    }
+
+    pub fn with_src(mut self, src: FRect) -> ImageDraw {
+        self.src = Some(src);
+        self
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> ImageDraw {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_nine_patch(mut self, insets: FMargins) -> ImageDraw {
+        self.nine_patch = Some(insets);
+        self
+    }
+
+    pub fn with_opacity(mut self, opacity: f32) -> ImageDraw {
+        self.opacity = opacity;
+        self
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PositionedGlyph {
+    pub id: GlyphId,
+    /// Origin of the glyph, on the baseline.
+    pub pos: FPoint,
 }
 
 impl Frame {
     pub fn new (window: WindowId, viewport: IRect, clear_color: Option<[f32; 4]>) -> Frame {
-        Frame { window, viewport, clear_color }
+        Frame { window, viewport, clear_color, cmds: Vec::new() }
+    }
+
+    pub fn push_transform(&mut self, transform: Transform) {
+        self.cmds.push(Cmd::PushTransform(transform));
+    }
+
+    pub fn pop_transform(&mut self) {
+        self.cmds.push(Cmd::PopTransform);
+    }
+
+    pub fn push_clip(&mut self, clip: Clip) {
+        self.cmds.push(Cmd::PushClip(clip));
+    }
+
+    pub fn push_clip_rect(&mut self, rect: FRect) {
+        self.push_clip(Clip::Rect(rect));
+    }
+
+    pub fn pop_clip(&mut self) {
+        self.cmds.push(Cmd::PopClip);
+    }
+
+    pub fn push_layer(&mut self, layer: Layer) {
+        self.cmds.push(Cmd::PushLayer(layer));
+    }
+
+    pub fn pop_layer(&mut self) {
+        self.cmds.push(Cmd::PopLayer);
+    }
+
+    pub fn set_blend_mode(&mut self, mode: BlendMode) {
+        self.cmds.push(Cmd::BlendMode(mode));
+    }
+
+    pub fn fill_path<P: Into<Paint>>(&mut self, path: Path, fill_rule: FillRule, paint: P) {
+        self.cmds.push(Cmd::Fill {
+            path,
+            fill_rule,
+            paint: paint.into(),
+        });
+    }
+
+    pub fn fill_shared<P: Into<Paint>>(
+        &mut self,
+        path: &PathHandle,
+        fill_rule: FillRule,
+        paint: P,
+    ) {
+        self.cmds.push(Cmd::FillShared {
+            path: path.clone(),
+            fill_rule,
+            paint: paint.into(),
+        });
+    }
+
+    pub fn fill_rect<P: Into<Paint>>(&mut self, rect: FRect, paint: P) {
+        self.fill_path(Path::rect(rect), FillRule::NonZero, paint);
+    }
+
+    pub fn rounded_rect<P: Into<Paint>>(&mut self, rect: FRect, radii: FCorners, paint: P) {
+        self.cmds.push(Cmd::RoundedRect {
+            rect,
+            radii,
+            paint: paint.into(),
+        });
+    }
+
+    pub fn border(&mut self, border: Border) {
+        self.cmds.push(Cmd::Border(border));
+    }
+
+    pub fn box_shadow(&mut self, shadow: BoxShadow) {
+        self.cmds.push(Cmd::BoxShadow(shadow));
     }
-}
\ No newline at end of file
+
+    pub fn glyph_run(&mut self, run: GlyphRun) {
+        self.cmds.push(Cmd::GlyphRun(run));
+    }
+
+    pub fn image(&mut self, draw: ImageDraw) {
+        self.cmds.push(Cmd::Image(draw));
+    }
+
+    pub fn effect(&mut self, rect: FRect, effect: EffectDraw) {
+        self.cmds.push(Cmd::Effect { rect, effect });
+    }
+
+    pub fn backdrop_blur(&mut self, rect: FRect, radius: f32) {
+        self.cmds.push(Cmd::BackdropBlur { rect, radius });
+    }
+
+    pub fn custom(&mut self, rect: FRect, callback: &RenderCallback) {
+        self.cmds.push(Cmd::Custom {
+            rect,
+            callback: callback.clone(),
+        });
+    }
+}
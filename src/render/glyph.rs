@@ -0,0 +1,238 @@
+use super::atlas::{Atlas, AtlasRect};
+use super::buffer::Buffer;
+use super::memory::Allocator;
+use super::texture::{Texture, COLOR_RANGE};
+use crate::geom::Transform;
+use crate::gfx;
+use crate::text::{self, Font, GlyphId};
+use gfx_hal::{buffer, command, format, image, memory, pso};
+
+/// Size of the glyph atlas, in texels.
+const ATLAS_SIZE: u32 = 1024;
+
+/// Horizontal positions at which a glyph is rasterized within a pixel.
+pub(crate) const SUBPIXEL_STEPS: u32 = 4;
+
+/// Font sizes are rounded to this fraction of a pixel.
+const SIZE_STEPS: f32 = 4f32;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+struct GlyphKey {
+    font: u64,
+    glyph: GlyphId,
+    size: u32,
+    subpixel: u32,
+}
+
+/// A rasterized glyph in the atlas.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct GlyphImage {
+    pub rect: AtlasRect,
+    /// Position of the top-left texel relative to the pixel of the glyph origin.
+    pub left: i32,
+    pub top: i32,
+    /// Whether the texels are premultiplied colors rather than coverage.
+    pub color: bool,
+}
+
+/// The glyphs rendered recently, in a texture shared by the windows.
+/// The coverage of the monochrome glyphs is stored in the four channels,
+/// next to the premultiplied colors of the color glyphs.
+pub(crate) struct GlyphCache {
+    atlas: Atlas<GlyphKey, (i32, i32, bool)>,
+    pub texture: Texture,
+    /// Glyphs rasterized since the last upload.
+    pending: Vec<(AtlasRect, Vec<u8>)>,
+    initialized: bool,
+    /// Whether a glyph did not fit in the atlas since the beginning of the frame.
+    overflowed: bool,
+}
+
+impl GlyphCache {
+    pub fn new(dev: &gfx::Device, alloc: &Allocator) -> GlyphCache {
+        let texture = Texture::new(
+            dev,
+            alloc,
+            (ATLAS_SIZE, ATLAS_SIZE),
+            format::Format::Rgba8Unorm,
+            image::Usage::TRANSFER_DST | image::Usage::SAMPLED,
+            format::Aspects::COLOR,
+        );
+        GlyphCache {
+            atlas: Atlas::new((ATLAS_SIZE, ATLAS_SIZE)),
+            texture,
+            pending: Vec::new(),
+            initialized: false,
+            overflowed: false,
+        }
+    }
+
+    /// The glyphs looked up from now on are kept in the atlas until the next frame.
+    pub fn begin_frame(&mut self) {
+        self.atlas.begin_frame();
+        self.overflowed = false;
+    }
+
+    /// Whether some glyphs looked up since the beginning of the frame did not fit in the atlas.
+    pub fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+
+    /// Looks a glyph up, rasterizing it if needed.
+    /// `subpixel` is the horizontal position of the origin within its pixel, in steps.
+    /// The color glyphs are rendered in their colors when the font has them.
+    /// None if the glyph has no image or does not fit in the atlas.
+    pub fn glyph(
+        &mut self,
+        font: &Font,
+        glyph: GlyphId,
+        size: f32,
+        subpixel: u32,
+    ) -> Option<GlyphImage> {
+        let key = GlyphKey {
+            font: font.id(),
+            glyph,
+            size: (size * SIZE_STEPS).round() as u32,
+            subpixel,
+        };
+        if let Some((rect, &(left, top, color))) = self.atlas.get(&key) {
+            return Some(GlyphImage {
+                rect,
+                left,
+                top,
+                color,
+            });
+        }
+        let size = key.size as f32 / SIZE_STEPS;
+        let dx = subpixel as f32 / SUBPIXEL_STEPS as f32;
+        let (width, height, left, top, texels, color) =
+            match text::rasterize_color(font, glyph, size, dx) {
+                Some(b) => (b.width, b.height, b.left, b.top, b.rgba, true),
+                None => {
+                    let offset = Transform::translation(dx, 0f32);
+                    let outline = font.outline(glyph, size)?.transformed(&offset);
+                    let b = text::rasterize(&outline)?;
+                    let texels = b.coverage.iter().flat_map(|&c| [c; 4]).collect();
+                    (b.width, b.height, b.left, b.top, texels, false)
+                }
+            };
+        let rect = self.atlas.insert(key, width, height, (left, top, color));
+        let rect = match rect {
+            Some(rect) => rect,
+            None => {
+                self.overflowed = true;
+                return None;
+            }
+        };
+        self.pending.push((rect, texels));
+        Some(GlyphImage {
+            rect,
+            left,
+            top,
+            color,
+        })
+    }
+
+    /// Records the copy of the pending glyphs to the atlas, through `staging`.
+    /// `staging` must not be in use by the device anymore.
+    /// The atlas is left in the `ShaderReadOnlyOptimal` layout.
+    pub unsafe fn upload(
+        &mut self,
+        dev: &gfx::Device,
+        alloc: &Allocator,
+        cmd: &mut gfx::CommandBuffer,
+        staging: &mut Option<Buffer>,
+    ) {
+        if self.pending.is_empty() && self.initialized {
+            return;
+        }
+        // the texels are 4 bytes long, as the buffer offsets of the copies must be
+        let mut texels = Vec::new();
+        let mut regions = Vec::with_capacity(self.pending.len());
+        for (rect, rgba) in self.pending.drain(..) {
+            regions.push(command::BufferImageCopy {
+                buffer_offset: texels.len() as u64,
+                buffer_width: rect.w,
+                buffer_height: rect.h,
+                image_layers: image::SubresourceLayers {
+                    aspects: format::Aspects::COLOR,
+                    level: 0,
+                    layers: 0..1,
+                },
+                image_offset: image::Offset {
+                    x: rect.x as i32,
+                    y: rect.y as i32,
+                    z: 0,
+                },
+                image_extent: image::Extent {
+                    width: rect.w,
+                    height: rect.h,
+                    depth: 1,
+                },
+            });
+            texels.extend(rgba);
+        }
+
+        let old_layout = if self.initialized {
+            image::Layout::ShaderReadOnlyOptimal
+        } else {
+            image::Layout::Undefined
+        };
+        let tex = &self.texture;
+        let to_transfer = memory::Barrier::Image {
+            states: (image::Access::SHADER_READ, old_layout)
+                ..(
+                    image::Access::TRANSFER_WRITE,
+                    image::Layout::TransferDstOptimal,
+                ),
+            target: &tex.image,
+            families: None,
+            range: COLOR_RANGE,
+        };
+        cmd.pipeline_barrier(
+            pso::PipelineStage::FRAGMENT_SHADER..pso::PipelineStage::TRANSFER,
+            memory::Dependencies::empty(),
+            &[to_transfer],
+        );
+        if !regions.is_empty() {
+            Buffer::ensure(
+                staging,
+                dev,
+                alloc,
+                texels.len() as u64,
+                buffer::Usage::TRANSFER_SRC,
+            );
+            let staging = staging.as_ref().unwrap();
+            staging.write(0, &texels);
+            cmd.copy_buffer_to_image(
+                &staging.buffer,
+                &tex.image,
+                image::Layout::TransferDstOptimal,
+                &regions,
+            );
+        }
+        let to_shader = memory::Barrier::Image {
+            states: (
+                image::Access::TRANSFER_WRITE,
+                image::Layout::TransferDstOptimal,
+            )
+                ..(
+                    image::Access::SHADER_READ,
+                    image::Layout::ShaderReadOnlyOptimal,
+                ),
+            target: &tex.image,
+            families: None,
+            range: COLOR_RANGE,
+        };
+        cmd.pipeline_barrier(
+            pso::PipelineStage::TRANSFER..pso::PipelineStage::FRAGMENT_SHADER,
+            memory::Dependencies::empty(),
+            &[to_shader],
+        );
+        self.initialized = true;
+    }
+
+    pub fn destroy(self, dev: &gfx::Device) {
+        self.texture.destroy(dev);
+    }
+}
@@ -0,0 +1,237 @@
+use super::buffer::Buffer;
+use super::layer::sampling_set;
+use super::memory::Allocator;
+use super::pipeline;
+use super::resource::{Handle, Lazy, ResourceId};
+use super::texture::Texture;
+use crate::gfx;
+use crate::image::Image;
+use gfx_hal::{buffer, command, format, image, memory, pso, Device};
+use std::collections::HashMap;
+
+/// An image shared by the frames.
+/// The pixels are kept by the handles, the render thread uploads them when first drawn.
+/// Until they are loaded, the frames draw a placeholder instead.
+pub type ImageHandle = Handle<Lazy<Image>>;
+
+/// How an image is sampled when it is scaled.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Filter {
+    Nearest,
+    /// Also blends the mipmap levels when the image is minified.
+    Linear,
+}
+
+struct DeviceImage {
+    texture: Texture,
+    /// Sets sampling the texture, by `Filter`.
+    sets: Vec<(gfx::DescriptorPool, gfx::DescriptorSet)>,
+}
+
+impl DeviceImage {
+    fn destroy(self, dev: &gfx::Device) {
+        unsafe {
+            for (pool, _) in self.sets {
+                dev.destroy_descriptor_pool(pool);
+            }
+        }
+        self.texture.destroy(dev);
+    }
+}
+
+/// The images drawn by the frames, shared by the windows.
+/// They are uploaded with all their mipmap levels when first drawn.
+pub(crate) struct ImageCache {
+    /// Compatible with `Pipelines::layer_set_layout`.
+    set_layout: gfx::DescriptorSetLayout,
+    /// By `Filter`.
+    samplers: Vec<gfx::Sampler>,
+    images: HashMap<ResourceId, DeviceImage>,
+    /// Images drawn since the last upload that are not on the device.
+    pending: Vec<ImageHandle>,
+}
+
+impl ImageCache {
+    pub fn new(dev: &gfx::Device) -> ImageCache {
+        unsafe {
+            let set_layout = pipeline::sampling_set_layout(dev);
+            let samplers = [image::Filter::Nearest, image::Filter::Linear]
+                .iter()
+                .map(|&filter| {
+                    dev.create_sampler(image::SamplerInfo::new(filter, image::WrapMode::Clamp))
+                        .expect("could not create a sampler")
+                })
+                .collect();
+            ImageCache {
+                set_layout,
+                samplers,
+                images: HashMap::new(),
+                pending: Vec::new(),
+            }
+        }
+    }
+
+    /// Destroys the device objects of a released image.
+    /// The device must not use them anymore.
+    pub fn remove(&mut self, dev: &gfx::Device, id: ResourceId) {
+        if let Some(img) = self.images.remove(&id) {
+            img.destroy(dev);
+        }
+    }
+
+    /// Schedules the upload of a loaded image drawn by a frame, if needed.
+    pub fn request(&mut self, image: &ImageHandle) {
+        if !self.images.contains_key(&image.id()) && !self.pending.contains(image) {
+            debug_assert!(image.get().is_some(), "requested image is not loaded");
+            self.pending.push(image.clone());
+        }
+    }
+
+    /// A set sampling an uploaded image, with the layout of `Pipelines::layer_set_layout`.
+    pub fn desc_set(&self, id: ResourceId, filter: Filter) -> Option<&gfx::DescriptorSet> {
+        self.images.get(&id).map(|img| &img.sets[filter as usize].1)
+    }
+
+    /// Records the upload of the pending images through `staging`.
+    /// `staging` must not be in use by the device anymore.
+    /// The images are left in the `ShaderReadOnlyOptimal` layout.
+    pub unsafe fn upload(
+        &mut self,
+        dev: &gfx::Device,
+        alloc: &Allocator,
+        cmd: &mut gfx::CommandBuffer,
+        staging: &mut Option<Buffer>,
+    ) {
+        if self.pending.is_empty() {
+            return;
+        }
+        // the texels are 4 bytes long, as the buffer offsets of the copies must be
+        let mut texels = Vec::new();
+        let mut copies = Vec::with_capacity(self.pending.len());
+        for handle in self.pending.drain(..) {
+            let image = handle.get().expect("pending image is not loaded");
+            let levels = image.mip_levels() as image::Level;
+            let texture = Texture::mipmapped(
+                dev,
+                alloc,
+                (image.width(), image.height()),
+                format::Format::Rgba8Unorm,
+                levels,
+                image::Usage::TRANSFER_DST | image::Usage::SAMPLED,
+            );
+            let mut regions = Vec::with_capacity(levels as usize);
+            // the first level is not copied
+            let mut level: Option<Image> = None;
+            for l in 0..levels {
+                let level_image = level.as_ref().unwrap_or(&*image);
+                regions.push(command::BufferImageCopy {
+                    buffer_offset: texels.len() as u64,
+                    buffer_width: level_image.width(),
+                    buffer_height: level_image.height(),
+                    image_layers: image::SubresourceLayers {
+                        aspects: format::Aspects::COLOR,
+                        level: l,
+                        layers: 0..1,
+                    },
+                    image_offset: image::Offset { x: 0, y: 0, z: 0 },
+                    image_extent: image::Extent {
+                        width: level_image.width(),
+                        height: level_image.height(),
+                        depth: 1,
+                    },
+                });
+                texels.extend_from_slice(level_image.rgba());
+                if l + 1 < levels {
+                    level = Some(level_image.downsampled());
+                }
+            }
+            let sets = self
+                .samplers
+                .iter()
+                .map(|sampler| sampling_set(dev, &self.set_layout, sampler, &texture.view))
+                .collect();
+            let id = handle.id();
+            copies.push((id, regions));
+            self.images.insert(id, DeviceImage { texture, sets });
+        }
+
+        Buffer::ensure(
+            staging,
+            dev,
+            alloc,
+            texels.len() as u64,
+            buffer::Usage::TRANSFER_SRC,
+        );
+        let staging = staging.as_ref().unwrap();
+        staging.write(0, &texels);
+
+        let textures: Vec<&Texture> = copies
+            .iter()
+            .map(|(id, _)| &self.images[id].texture)
+            .collect();
+        let range = |tex: &Texture| image::SubresourceRange {
+            aspects: format::Aspects::COLOR,
+            levels: 0..tex.levels,
+            layers: 0..1,
+        };
+        let to_transfer: Vec<_> = textures
+            .iter()
+            .map(|tex| memory::Barrier::Image {
+                states: (image::Access::empty(), image::Layout::Undefined)
+                    ..(
+                        image::Access::TRANSFER_WRITE,
+                        image::Layout::TransferDstOptimal,
+                    ),
+                target: &tex.image,
+                families: None,
+                range: range(tex),
+            })
+            .collect();
+        cmd.pipeline_barrier(
+            pso::PipelineStage::TOP_OF_PIPE..pso::PipelineStage::TRANSFER,
+            memory::Dependencies::empty(),
+            to_transfer,
+        );
+        for (tex, (_, regions)) in textures.iter().zip(&copies) {
+            cmd.copy_buffer_to_image(
+                &staging.buffer,
+                &tex.image,
+                image::Layout::TransferDstOptimal,
+                regions,
+            );
+        }
+        let to_shader: Vec<_> = textures
+            .iter()
+            .map(|tex| memory::Barrier::Image {
+                states: (
+                    image::Access::TRANSFER_WRITE,
+                    image::Layout::TransferDstOptimal,
+                )
+                    ..(
+                        image::Access::SHADER_READ,
+                        image::Layout::ShaderReadOnlyOptimal,
+                    ),
+                target: &tex.image,
+                families: None,
+                range: range(tex),
+            })
+            .collect();
+        cmd.pipeline_barrier(
+            pso::PipelineStage::TRANSFER..pso::PipelineStage::FRAGMENT_SHADER,
+            memory::Dependencies::empty(),
+            to_shader,
+        );
+    }
+
+    pub fn destroy(self, dev: &gfx::Device) {
+        for (_, img) in self.images {
+            img.destroy(dev);
+        }
+        unsafe {
+            for sampler in self.samplers {
+                dev.destroy_sampler(sampler);
+            }
+            dev.destroy_descriptor_set_layout(self.set_layout);
+        }
+    }
+}
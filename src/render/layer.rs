@@ -0,0 +1,381 @@
+use super::memory::Allocator;
+use super::pipeline::Pipelines;
+use super::texture::{Texture, COLOR_RANGE};
+use crate::gfx;
+use gfx_hal::pso::DescriptorPool;
+use gfx_hal::{command, format, image, memory, pso, Device};
+use std::mem;
+
+/// The sizes of the layer targets are rounded up to a multiple of this many pixels,
+/// so that the layers of close sizes share them.
+const LAYER_SIZE_STEP: u32 = 128;
+/// Layer targets unused for this many frames are destroyed.
+const LAYER_IDLE_FRAMES: u32 = 8;
+
+/// An intermediate target, at most as large as the window.
+pub(crate) struct LayerTarget {
+    /// Single sampled, the multisampled color is resolved into it.
+    pub color: Texture,
+    multisampled: Option<Texture>,
+    stencil: Texture,
+    pub framebuffer: gfx::Framebuffer,
+    desc_pool: gfx::DescriptorPool,
+    /// Samples `color`, with the layout of `Pipelines::layer_set_layout`.
+    pub desc_set: gfx::DescriptorSet,
+}
+
+impl LayerTarget {
+    pub fn new(
+        dev: &gfx::Device,
+        alloc: &Allocator,
+        size: (u32, u32),
+        (color_format, stencil_format): (format::Format, format::Format),
+        pipes: &Pipelines,
+    ) -> LayerTarget {
+        let color = Texture::new(
+            dev,
+            alloc,
+            size,
+            color_format,
+            image::Usage::COLOR_ATTACHMENT | image::Usage::SAMPLED | image::Usage::TRANSFER_SRC,
+            format::Aspects::COLOR,
+        );
+        let multisampled = if pipes.samples > 1 {
+            Some(Texture::multisampled(
+                dev,
+                alloc,
+                size,
+                color_format,
+                pipes.samples,
+                image::Usage::COLOR_ATTACHMENT,
+                format::Aspects::COLOR,
+            ))
+        } else {
+            None
+        };
+        let stencil = Texture::multisampled(
+            dev,
+            alloc,
+            size,
+            stencil_format,
+            pipes.samples,
+            image::Usage::DEPTH_STENCIL_ATTACHMENT,
+            format::Aspects::DEPTH | format::Aspects::STENCIL,
+        );
+        unsafe {
+            let attachments = match &multisampled {
+                Some(ms) => vec![&ms.view, &stencil.view, &color.view],
+                None => vec![&color.view, &stencil.view],
+            };
+            let framebuffer = dev
+                .create_framebuffer(
+                    &pipes.layer_pass,
+                    attachments,
+                    image::Extent {
+                        width: size.0,
+                        height: size.1,
+                        depth: 1,
+                    },
+                )
+                .expect("could not create a framebuffer");
+            let (desc_pool, desc_set) =
+                sampling_set(dev, &pipes.layer_set_layout, &pipes.sampler, &color.view);
+            LayerTarget {
+                color,
+                multisampled,
+                stencil,
+                framebuffer,
+                desc_pool,
+                desc_set,
+            }
+        }
+    }
+
+    /// The whole target.
+    pub fn area(&self) -> pso::Rect {
+        pso::Rect {
+            x: 0,
+            y: 0,
+            w: self.color.width as i16,
+            h: self.color.height as i16,
+        }
+    }
+
+    pub fn destroy(self, dev: &gfx::Device) {
+        unsafe {
+            dev.destroy_descriptor_pool(self.desc_pool);
+            dev.destroy_framebuffer(self.framebuffer);
+        }
+        self.stencil.destroy(dev);
+        if let Some(ms) = self.multisampled {
+            ms.destroy(dev);
+        }
+        self.color.destroy(dev);
+    }
+}
+
+/// The layer targets of the frames rendered to a swapchain image.
+/// A frame takes the targets of the sizes it needs, and the ones no frame took
+/// for `LAYER_IDLE_FRAMES` frames are destroyed.
+pub(crate) struct LayerPool<T> {
+    /// With their size, and the number of frames since one took them.
+    targets: Vec<(T, (u32, u32), u32)>,
+}
+
+impl<T> LayerPool<T> {
+    pub fn new() -> LayerPool<T> {
+        LayerPool {
+            targets: Vec::new(),
+        }
+    }
+
+    /// Indices of the targets for a frame whose layers have `sizes`,
+    /// creating the missing ones with `create`.
+    /// The sizes are rounded up, but not beyond `max`.
+    pub fn assign<F>(&mut self, sizes: &[(u32, u32)], max: (u32, u32), mut create: F) -> Vec<usize>
+    where
+        F: FnMut((u32, u32)) -> T,
+    {
+        for target in &mut self.targets {
+            target.2 += 1;
+        }
+        let round = |v: u32, max: u32| (v.div_ceil(LAYER_SIZE_STEP) * LAYER_SIZE_STEP).min(max);
+        sizes
+            .iter()
+            .map(|&(w, h)| {
+                let size = (round(w, max.0).max(1), round(h, max.1).max(1));
+                // the targets taken by the frame are not idle anymore
+                let free = self.targets.iter().position(|t| t.1 == size && t.2 > 0);
+                match free {
+                    Some(idx) => {
+                        self.targets[idx].2 = 0;
+                        idx
+                    }
+                    None => {
+                        self.targets.push((create(size), size, 0));
+                        self.targets.len() - 1
+                    }
+                }
+            })
+            .collect()
+    }
+
+    pub fn get(&self, idx: usize) -> &T {
+        &self.targets[idx].0
+    }
+
+    /// Destroys the idle targets.
+    /// The indices given by `assign` are not valid anymore.
+    pub fn trim<F: FnMut(T)>(&mut self, destroy: F) {
+        let (kept, idle) = mem::take(&mut self.targets)
+            .into_iter()
+            .partition(|t| t.2 < LAYER_IDLE_FRAMES);
+        self.targets = kept;
+        idle.into_iter().map(|t: (T, _, _)| t.0).for_each(destroy);
+    }
+
+    pub fn destroy<F: FnMut(T)>(self, destroy: F) {
+        self.targets.into_iter().map(|t| t.0).for_each(destroy);
+    }
+}
+
+/// A copy of a layer, read by the blend modes that are done in the shader.
+pub(crate) struct Backdrop {
+    texture: Texture,
+    desc_pool: gfx::DescriptorPool,
+    /// Samples the copy, with the layout of `Pipelines::layer_set_layout`.
+    pub desc_set: gfx::DescriptorSet,
+}
+
+impl Backdrop {
+    pub fn new(
+        dev: &gfx::Device,
+        alloc: &Allocator,
+        size: (u32, u32),
+        color_format: format::Format,
+        pipes: &Pipelines,
+    ) -> Backdrop {
+        let texture = Texture::new(
+            dev,
+            alloc,
+            size,
+            color_format,
+            image::Usage::TRANSFER_DST | image::Usage::SAMPLED,
+            format::Aspects::COLOR,
+        );
+        let (desc_pool, desc_set) = unsafe {
+            sampling_set(dev, &pipes.layer_set_layout, &pipes.sampler, &texture.view)
+        };
+        Backdrop {
+            texture,
+            desc_pool,
+            desc_set,
+        }
+    }
+
+    /// Records the copy of the color of `layer`, outside of a render pass.
+    /// `layer` goes from `ShaderReadOnlyOptimal` to `TransferSrcOptimal`,
+    /// which `Pipelines::layer_resume_pass` starts from.
+    pub unsafe fn copy(&self, cmd: &mut gfx::CommandBuffer, layer: &LayerTarget) {
+        let (src, dst) = (&layer.color, &self.texture);
+        let barriers = [
+            memory::Barrier::Image {
+                states: (
+                    image::Access::COLOR_ATTACHMENT_WRITE,
+                    image::Layout::ShaderReadOnlyOptimal,
+                )
+                    ..(image::Access::TRANSFER_READ, image::Layout::TransferSrcOptimal),
+                target: &src.image,
+                families: None,
+                range: COLOR_RANGE,
+            },
+            // the previous copy may still be read by a composite
+            memory::Barrier::Image {
+                states: (image::Access::SHADER_READ, image::Layout::Undefined)
+                    ..(image::Access::TRANSFER_WRITE, image::Layout::TransferDstOptimal),
+                target: &dst.image,
+                families: None,
+                range: COLOR_RANGE,
+            },
+        ];
+        cmd.pipeline_barrier(
+            (pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT | pso::PipelineStage::FRAGMENT_SHADER)
+                ..pso::PipelineStage::TRANSFER,
+            memory::Dependencies::empty(),
+            &barriers,
+        );
+        let layers = image::SubresourceLayers {
+            aspects: format::Aspects::COLOR,
+            level: 0,
+            layers: 0..1,
+        };
+        cmd.copy_image(
+            &src.image,
+            image::Layout::TransferSrcOptimal,
+            &dst.image,
+            image::Layout::TransferDstOptimal,
+            &[command::ImageCopy {
+                src_subresource: layers.clone(),
+                src_offset: image::Offset { x: 0, y: 0, z: 0 },
+                dst_subresource: layers,
+                dst_offset: image::Offset { x: 0, y: 0, z: 0 },
+                extent: image::Extent {
+                    width: dst.width.min(src.width),
+                    height: dst.height.min(src.height),
+                    depth: 1,
+                },
+            }],
+        );
+        let to_shader = memory::Barrier::Image {
+            states: (image::Access::TRANSFER_WRITE, image::Layout::TransferDstOptimal)
+                ..(image::Access::SHADER_READ, image::Layout::ShaderReadOnlyOptimal),
+            target: &dst.image,
+            families: None,
+            range: COLOR_RANGE,
+        };
+        cmd.pipeline_barrier(
+            pso::PipelineStage::TRANSFER..pso::PipelineStage::FRAGMENT_SHADER,
+            memory::Dependencies::empty(),
+            &[to_shader],
+        );
+    }
+
+    pub fn destroy(self, dev: &gfx::Device) {
+        unsafe {
+            dev.destroy_descriptor_pool(self.desc_pool);
+        }
+        self.texture.destroy(dev);
+    }
+}
+
+/// A descriptor set sampling `view` with `sampler`, in its own pool.
+/// `layout` is the one of `Pipelines::layer_set_layout`.
+pub(crate) unsafe fn sampling_set(
+    dev: &gfx::Device,
+    layout: &gfx::DescriptorSetLayout,
+    sampler: &gfx::Sampler,
+    view: &gfx::ImageView,
+) -> (gfx::DescriptorPool, gfx::DescriptorSet) {
+    let mut desc_pool = dev
+        .create_descriptor_pool(
+            1,
+            &[
+                pso::DescriptorRangeDesc {
+                    ty: pso::DescriptorType::SampledImage,
+                    count: 1,
+                },
+                pso::DescriptorRangeDesc {
+                    ty: pso::DescriptorType::Sampler,
+                    count: 1,
+                },
+            ],
+        )
+        .expect("could not create a descriptor pool");
+    let desc_set = desc_pool
+        .allocate_set(layout)
+        .expect("could not allocate a descriptor set");
+    dev.write_descriptor_sets(vec![
+        pso::DescriptorSetWrite {
+            set: &desc_set,
+            binding: 0,
+            array_offset: 0,
+            descriptors: Some(pso::Descriptor::Image(
+                view,
+                image::Layout::ShaderReadOnlyOptimal,
+            )),
+        },
+        pso::DescriptorSetWrite {
+            set: &desc_set,
+            binding: 1,
+            array_offset: 0,
+            descriptors: Some(pso::Descriptor::Sampler(sampler)),
+        },
+    ]);
+    (desc_pool, desc_set)
+}
+
+#[test]
+fn layer_pool() {
+    // the targets are their sizes
+    fn frame(
+        pool: &mut LayerPool<(u32, u32)>,
+        created: &mut usize,
+        sizes: &[(u32, u32)],
+    ) -> Vec<(u32, u32)> {
+        let targets = pool.assign(sizes, (1000, 600), |size| {
+            *created += 1;
+            size
+        });
+        targets.iter().map(|&t| *pool.get(t)).collect()
+    }
+    let (mut pool, mut created) = (LayerPool::new(), 0);
+    // sibling layers need their own targets, rounded up but not beyond the window
+    let siblings = [(20, 30), (100, 100), (990, 590)];
+    let sizes = vec![(128, 128), (128, 128), (1000, 600)];
+    assert_eq!(sizes, frame(&mut pool, &mut created, &siblings));
+    assert_eq!(3, created);
+    // the next frames take the targets of the same size
+    assert_eq!(
+        vec![(128, 128)],
+        frame(&mut pool, &mut created, &[(120, 1)])
+    );
+    assert_eq!(3, created);
+    assert_eq!(
+        vec![(256, 128)],
+        frame(&mut pool, &mut created, &[(129, 1)])
+    );
+    assert_eq!(4, created);
+
+    // the targets unused for a while are destroyed, not the one in use
+    let mut destroyed = Vec::new();
+    for _ in 0..LAYER_IDLE_FRAMES {
+        pool.trim(|target| destroyed.push(target));
+        frame(&mut pool, &mut created, &[(129, 1)]);
+    }
+    destroyed.sort();
+    assert_eq!(sizes, destroyed);
+    assert_eq!(4, created);
+    let mut remaining = Vec::new();
+    pool.destroy(|target| remaining.push(target));
+    assert_eq!(vec![(256, 128)], remaining);
+}
@@ -0,0 +1,175 @@
+use super::images::ImageHandle;
+use super::resource::{Handle, Lazy, LoadState, ResourceId};
+use crate::image::Image;
+use crate::text::Font;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+/// A font shared by the widgets, that may still be loading.
+pub type FontHandle = Handle<Lazy<Font>>;
+
+/// Where a resource is loaded from.
+#[derive(Clone, Debug)]
+pub enum Source {
+    Path(PathBuf),
+    Bytes(Arc<[u8]>),
+}
+
+impl Source {
+    fn bytes(&self) -> io::Result<Vec<u8>> {
+        match self {
+            Source::Path(path) => std::fs::read(path),
+            Source::Bytes(bytes) => Ok(bytes.to_vec()),
+        }
+    }
+}
+
+impl From<PathBuf> for Source {
+    fn from(path: PathBuf) -> Source {
+        Source::Path(path)
+    }
+}
+
+impl<'a> From<&'a std::path::Path> for Source {
+    fn from(path: &'a std::path::Path) -> Source {
+        Source::Path(path.to_owned())
+    }
+}
+
+impl From<Vec<u8>> for Source {
+    fn from(bytes: Vec<u8>) -> Source {
+        Source::Bytes(bytes.into())
+    }
+}
+
+/// Notifies that a resource finished loading.
+#[derive(Clone, Debug)]
+pub struct Loaded {
+    pub source: Source,
+    /// The error message if the load failed.
+    pub error: Option<String>,
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+type Notify = Arc<Mutex<Box<dyn FnMut(Loaded) + Send>>>;
+
+/// Decodes images and fonts on worker threads.
+/// The handles are returned right away, and are drawn as placeholders until they are loaded.
+/// `notify` is called from the workers after each load, typically to wake the UI thread up
+/// so that it schedules a redraw.
+pub struct Loader {
+    jobs: Option<mpsc::Sender<Job>>,
+    releases: mpsc::Sender<ResourceId>,
+    notify: Notify,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl Loader {
+    pub(crate) fn new<F>(workers: usize, releases: mpsc::Sender<ResourceId>, notify: F) -> Loader
+    where
+        F: FnMut(Loaded) + Send + 'static,
+    {
+        assert!(workers > 0, "a loader needs at least one worker");
+        let (jobs, rx) = mpsc::channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+        let workers = (0..workers)
+            .map(|i| {
+                let rx = rx.clone();
+                thread::Builder::new()
+                    .name(format!("ows-loader-{}", i))
+                    .spawn(move || loop {
+                        let job = rx.lock().unwrap().recv();
+                        match job {
+                            Ok(job) => job(),
+                            Err(_) => break,
+                        }
+                    })
+                    .expect("Could not spawn a loader thread")
+            })
+            .collect();
+        Loader {
+            jobs: Some(jobs),
+            releases,
+            notify: Arc::new(Mutex::new(Box::new(notify))),
+            workers,
+        }
+    }
+
+    /// Loads a PNG or a JPEG image.
+    pub fn load_image<S: Into<Source>>(&self, source: S) -> ImageHandle {
+        self.load(source.into(), |src| Image::decode(&src.bytes()?))
+    }
+
+    /// Loads the font at `index` in a font file or collection.
+    pub fn load_font<S: Into<Source>>(&self, source: S, index: u32) -> FontHandle {
+        self.load(source.into(), move |src| {
+            Font::from_bytes(src.bytes()?, index)
+        })
+    }
+
+    fn load<T, F>(&self, source: Source, load: F) -> Handle<Lazy<T>>
+    where
+        T: Send + Sync + 'static,
+        F: FnOnce(&Source) -> io::Result<T> + Send + 'static,
+    {
+        let handle = Handle::new(Lazy::loading(), self.releases.clone());
+        let (target, notify) = (handle.clone(), self.notify.clone());
+        let job = Box::new(move || {
+            let (state, error) = match load(&source) {
+                Ok(value) => (LoadState::Ready(Arc::new(value)), None),
+                Err(err) => (LoadState::Failed(err.to_string()), Some(err.to_string())),
+            };
+            target.set(state);
+            // the loader keeps no reference once loaded
+            drop(target);
+            let mut notify = notify.lock().unwrap();
+            (*notify)(Loaded { source, error });
+        });
+        self.jobs
+            .as_ref()
+            .unwrap()
+            .send(job)
+            .expect("Could not send a job to the loader threads");
+        handle
+    }
+}
+
+impl Drop for Loader {
+    fn drop(&mut self) {
+        // the workers finish the pending loads and stop
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            worker.join().expect("Could not join a loader thread");
+        }
+    }
+}
+
+#[test]
+fn load_in_background() {
+    let (releases, _released) = mpsc::channel();
+    let (tx, rx) = mpsc::channel();
+    let loader = Loader::new(2, releases, move |loaded| tx.send(loaded).unwrap());
+
+    let mut png = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut png, 2, 1);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[255; 8]).unwrap();
+    }
+    let image = loader.load_image(png);
+    let garbage = loader.load_image(vec![0u8; 16]);
+    let missing = loader.load_font(PathBuf::from("/no/such/font.ttf"), 0);
+
+    let errors: Vec<_> = rx.iter().take(3).map(|loaded| loaded.error).collect();
+    assert_eq!(2, errors.iter().filter(|e| e.is_some()).count());
+    assert_eq!(
+        (2, 1),
+        image.get().map(|i| (i.width(), i.height())).unwrap()
+    );
+    assert!(matches!(garbage.state(), LoadState::Failed(_)));
+    assert!(missing.get().is_none());
+}
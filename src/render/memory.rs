@@ -0,0 +1,394 @@
+use crate::gfx;
+use gfx_hal::{self as hal, memory, Device};
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+use std::{mem, ptr};
+
+/// Size of the blocks of device memory that are sub-allocated.
+const BLOCK_SIZE: u64 = 16 << 20;
+
+/// Resources larger than this get a block of their own.
+const DEDICATED_SIZE: u64 = BLOCK_SIZE / 2;
+
+/// Buffers and optimal images are kept in separate blocks,
+/// so that the buffer-image granularity never applies between neighbours.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum Tiling {
+    Linear,
+    Optimal,
+}
+
+/// Device memory usage of the render thread.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct MemoryStats {
+    /// Blocks of device memory.
+    pub blocks: usize,
+    /// Total size of the blocks.
+    pub allocated: u64,
+    /// Resources in the blocks.
+    pub allocations: usize,
+    /// Total size of the resources, with their alignment.
+    pub used: u64,
+    /// Size of the largest free range of a block.
+    pub largest_free: u64,
+}
+
+impl MemoryStats {
+    /// Part of the free memory that is not in the largest free range, from 0 to 1.
+    /// A high fragmentation means that the free memory is mostly in small holes.
+    pub fn fragmentation(&self) -> f32 {
+        let free = self.allocated - self.used;
+        if free == 0 {
+            0f32
+        } else {
+            1f32 - self.largest_free as f32 / free as f32
+        }
+    }
+}
+
+/// Sub-allocates device memory for the buffers and textures of the renderer.
+/// Each memory type has its own blocks, allocated on demand and released by `trim`.
+#[derive(Clone)]
+pub(crate) struct Allocator {
+    heaps: Rc<RefCell<Heaps>>,
+}
+
+struct Heaps {
+    props: hal::MemoryProperties,
+    /// Bytes allocated from each memory heap.
+    heap_usage: Vec<u64>,
+    /// Freed blocks leave a hole so that the indices held by `Memory` stay valid.
+    blocks: Vec<Option<Block>>,
+}
+
+struct Block {
+    memory: gfx::Memory,
+    ty: hal::MemoryTypeId,
+    tiling: Tiling,
+    size: u64,
+    dedicated: bool,
+    /// Mapped for the lifetime of the block if the memory is host visible.
+    ptr: Option<*mut u8>,
+    free: FreeList,
+    allocations: usize,
+}
+
+/// Free ranges of a block, sorted and coalesced.
+struct FreeList(Vec<Range<u64>>);
+
+/// A range of a block, given back to the allocator with `free`.
+pub(crate) struct Memory {
+    heaps: Rc<RefCell<Heaps>>,
+    block: usize,
+    range: Range<u64>,
+    /// Start of the range, if host visible.
+    ptr: Option<*mut u8>,
+}
+
+impl Allocator {
+    pub fn new(props: hal::MemoryProperties) -> Allocator {
+        let heap_usage = vec![0; props.memory_heaps.len()];
+        Allocator {
+            heaps: Rc::new(RefCell::new(Heaps {
+                props,
+                heap_usage,
+                blocks: Vec::new(),
+            })),
+        }
+    }
+
+    /// Allocates memory meeting `req` from a type with the `required` properties.
+    /// The types that also have the `preferred` ones are tried first.
+    pub fn allocate(
+        &self,
+        dev: &gfx::Device,
+        req: &memory::Requirements,
+        required: memory::Properties,
+        preferred: memory::Properties,
+        tiling: Tiling,
+    ) -> Memory {
+        let mut heaps = self.heaps.borrow_mut();
+        let mut types: Vec<_> = heaps
+            .props
+            .memory_types
+            .iter()
+            .enumerate()
+            .filter(|(id, ty)| {
+                req.type_mask & (1u64 << id) != 0 && ty.properties.contains(required)
+            })
+            .map(|(id, ty)| (id, ty.properties.contains(preferred)))
+            .collect();
+        // stable, the types are ordered by performance
+        types.sort_by_key(|&(_, pref)| !pref);
+        for (id, _) in types {
+            let ty = hal::MemoryTypeId(id);
+            let found = heaps
+                .sub_allocate(ty, tiling, req)
+                .or_else(|| heaps.new_block(dev, ty, tiling, req));
+            if let Some((block, range, ptr)) = found {
+                return Memory {
+                    heaps: self.heaps.clone(),
+                    block,
+                    range,
+                    ptr,
+                };
+            }
+        }
+        panic!("could not allocate {} bytes of device memory", req.size);
+    }
+
+    /// Releases the empty blocks, but one per memory type to avoid reallocating
+    /// at every frame.
+    pub fn trim(&self, dev: &gfx::Device) {
+        let mut heaps = self.heaps.borrow_mut();
+        let mut spares = Vec::new();
+        for i in 0..heaps.blocks.len() {
+            let keep = match &heaps.blocks[i] {
+                Some(b) if b.allocations == 0 => {
+                    let key = (b.ty, b.tiling);
+                    if b.dedicated || spares.contains(&key) {
+                        false
+                    } else {
+                        spares.push(key);
+                        true
+                    }
+                }
+                _ => true,
+            };
+            if !keep {
+                let block = heaps.blocks[i].take().unwrap();
+                heaps.free_block(dev, block);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> MemoryStats {
+        let heaps = self.heaps.borrow();
+        let mut stats = MemoryStats::default();
+        for block in heaps.blocks.iter().flatten() {
+            stats.blocks += 1;
+            stats.allocated += block.size;
+            stats.allocations += block.allocations;
+            stats.used += block.size - block.free.total();
+            stats.largest_free = stats.largest_free.max(block.free.largest());
+        }
+        stats
+    }
+
+    /// Every `Memory` must have been freed.
+    pub fn destroy(&self, dev: &gfx::Device) {
+        let mut heaps = self.heaps.borrow_mut();
+        let blocks = mem::take(&mut heaps.blocks);
+        for block in blocks.into_iter().flatten() {
+            if block.allocations > 0 {
+                println!("{} allocations leaked in a memory block", block.allocations);
+            }
+            heaps.free_block(dev, block);
+        }
+    }
+}
+
+impl Heaps {
+    fn sub_allocate(
+        &mut self,
+        ty: hal::MemoryTypeId,
+        tiling: Tiling,
+        req: &memory::Requirements,
+    ) -> Option<(usize, Range<u64>, Option<*mut u8>)> {
+        if req.size > DEDICATED_SIZE {
+            return None;
+        }
+        for (i, block) in self.blocks.iter_mut().enumerate() {
+            let block = match block {
+                Some(b) if b.ty == ty && b.tiling == tiling && !b.dedicated => b,
+                _ => continue,
+            };
+            if let Some(range) = block.free.take(req.size, req.alignment.max(1)) {
+                block.allocations += 1;
+                let ptr = block.ptr.map(|p| unsafe { p.add(range.start as usize) });
+                return Some((i, range, ptr));
+            }
+        }
+        None
+    }
+
+    /// None if the heap of the type is full.
+    fn new_block(
+        &mut self,
+        dev: &gfx::Device,
+        ty: hal::MemoryTypeId,
+        tiling: Tiling,
+        req: &memory::Requirements,
+    ) -> Option<(usize, Range<u64>, Option<*mut u8>)> {
+        let mem_type = &self.props.memory_types[ty.0];
+        let heap = mem_type.heap_index;
+        let dedicated = req.size > DEDICATED_SIZE;
+        let size = if dedicated { req.size } else { BLOCK_SIZE };
+        if self.heap_usage[heap] + size > self.props.memory_heaps[heap] {
+            return None;
+        }
+        let memory = unsafe { dev.allocate_memory(ty, size) }.ok()?;
+        let ptr = if mem_type
+            .properties
+            .contains(memory::Properties::CPU_VISIBLE)
+        {
+            let ptr = unsafe { dev.map_memory(&memory, 0..size) };
+            Some(ptr.expect("could not map device memory"))
+        } else {
+            None
+        };
+        self.heap_usage[heap] += size;
+        let mut block = Block {
+            memory,
+            ty,
+            tiling,
+            size,
+            dedicated,
+            ptr,
+            free: FreeList::new(size),
+            allocations: 1,
+        };
+        let range = block.free.take(req.size, req.alignment.max(1))?;
+        let idx = match self.blocks.iter().position(Option::is_none) {
+            Some(idx) => idx,
+            None => {
+                self.blocks.push(None);
+                self.blocks.len() - 1
+            }
+        };
+        self.blocks[idx] = Some(block);
+        Some((idx, range, ptr))
+    }
+
+    fn free_block(&mut self, dev: &gfx::Device, block: Block) {
+        let heap = self.props.memory_types[block.ty.0].heap_index;
+        self.heap_usage[heap] -= block.size;
+        unsafe {
+            if block.ptr.is_some() {
+                dev.unmap_memory(&block.memory);
+            }
+            dev.free_memory(block.memory);
+        }
+    }
+}
+
+impl FreeList {
+    fn new(size: u64) -> FreeList {
+        FreeList(vec![Range {
+            start: 0,
+            end: size,
+        }])
+    }
+
+    /// First fit.
+    fn take(&mut self, size: u64, align: u64) -> Option<Range<u64>> {
+        let (i, start) = self.0.iter().enumerate().find_map(|(i, r)| {
+            let start = r.start.div_ceil(align) * align;
+            if start + size <= r.end {
+                Some((i, start))
+            } else {
+                None
+            }
+        })?;
+        let r = self.0.remove(i);
+        if start + size < r.end {
+            self.0.insert(i, start + size..r.end);
+        }
+        if r.start < start {
+            self.0.insert(i, r.start..start);
+        }
+        Some(start..start + size)
+    }
+
+    fn give_back(&mut self, range: Range<u64>) {
+        let free = &mut self.0;
+        let i = free.iter().position(|r| r.start > range.start);
+        let i = i.unwrap_or(free.len());
+        free.insert(i, range);
+        if i + 1 < free.len() && free[i].end == free[i + 1].start {
+            let next = free.remove(i + 1);
+            free[i].end = next.end;
+        }
+        if i > 0 && free[i - 1].end == free[i].start {
+            let cur = free.remove(i);
+            free[i - 1].end = cur.end;
+        }
+    }
+
+    fn total(&self) -> u64 {
+        self.0.iter().map(|r| r.end - r.start).sum()
+    }
+
+    fn largest(&self) -> u64 {
+        self.0.iter().map(|r| r.end - r.start).max().unwrap_or(0)
+    }
+}
+
+impl Memory {
+    pub fn size(&self) -> u64 {
+        self.range.end - self.range.start
+    }
+
+    pub unsafe fn bind_buffer(&self, dev: &gfx::Device, buffer: &mut gfx::Buffer) {
+        let heaps = self.heaps.borrow();
+        let block = heaps.blocks[self.block].as_ref().unwrap();
+        dev.bind_buffer_memory(&block.memory, self.range.start, buffer)
+            .expect("could not bind buffer memory");
+    }
+
+    pub unsafe fn bind_image(&self, dev: &gfx::Device, image: &mut gfx::Image) {
+        let heaps = self.heaps.borrow();
+        let block = heaps.blocks[self.block].as_ref().unwrap();
+        dev.bind_image_memory(&block.memory, self.range.start, image)
+            .expect("could not bind image memory");
+    }
+
+    /// Copies `data` at `offset`, the memory must be host visible and coherent.
+    pub fn write<T: Copy>(&self, offset: u64, data: &[T]) {
+        let len = (data.len() * mem::size_of::<T>()) as u64;
+        assert!(offset + len <= self.size());
+        let dst = self.ptr.expect("memory is not host visible");
+        unsafe {
+            ptr::copy_nonoverlapping(
+                data.as_ptr() as *const u8,
+                dst.add(offset as usize),
+                len as usize,
+            );
+        }
+    }
+
+    /// Copies the memory at `offset` to `data`, the memory must be host visible and coherent.
+    pub fn read(&self, offset: u64, data: &mut [u8]) {
+        assert!(offset + data.len() as u64 <= self.size());
+        let src = self.ptr.expect("memory is not host visible");
+        unsafe {
+            ptr::copy_nonoverlapping(src.add(offset as usize), data.as_mut_ptr(), data.len());
+        }
+    }
+
+    /// The resource bound to the memory must be destroyed.
+    pub fn free(self) {
+        let mut heaps = self.heaps.borrow_mut();
+        let block = heaps.blocks[self.block].as_mut().unwrap();
+        block.free.give_back(self.range);
+        block.allocations -= 1;
+    }
+}
+
+#[test]
+fn free_list() {
+    let mut free = FreeList::new(256);
+    assert_eq!(Some(0..10), free.take(10, 4));
+    assert_eq!(Some(16..32), free.take(16, 16));
+    assert_eq!(Some(12..16), free.take(4, 4));
+    assert_eq!(vec![10..12, 32..256], free.0);
+    assert_eq!(None, free.take(256, 1));
+
+    free.give_back(12..16);
+    free.give_back(0..10);
+    assert_eq!(vec![0..16, 32..256], free.0);
+    free.give_back(16..32);
+    assert_eq!(vec![0..256], free.0);
+    assert_eq!((256, 256), (free.total(), free.largest()));
+}
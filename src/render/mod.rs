@@ -1,23 +1,109 @@
 use crate::gfx;
-use gfx_hal::{self as hal, Device, Instance, PhysicalDevice, QueueFamily, Surface, Swapchain};
+use crate::image::Image;
+use crate::path::Path;
+use crate::paint::{Gradient, RAMP_SIZE};
+use gfx_hal::pso::DescriptorPool;
+use gfx_hal::{self as hal, format, image, pso};
+use gfx_hal::{Device, Instance, PhysicalDevice, QueueFamily, Surface, Swapchain};
 use hal::format::Format;
 use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::path::PathBuf;
 use std::sync::{mpsc, Arc};
-use std::thread;
+use std::time::{Duration, Instant};
+use std::{mem, thread};
 use winit::{self, dpi::PhysicalSize, WindowId};
 
+mod animation;
+mod antialiasing;
+mod atlas;
+mod batch;
+mod buffer;
+mod capture;
+mod custom;
+mod draw;
+mod effect;
 mod frame;
+mod glyph;
+mod images;
+mod layer;
+mod loader;
+mod memory;
+mod pipeline;
+mod pipeline_cache;
+mod record;
+mod resource;
+mod texture;
 
-pub use frame::Frame;
+pub use animation::{Animation, Easing, Keyframe, Property, TransformValue};
+pub use antialiasing::Antialiasing;
+pub use batch::BatchStats;
+pub use capture::Capture;
+pub use custom::{CustomRender, RenderCallback};
+pub use effect::{Effect, EffectHandle, EFFECT_UNIFORMS, MAX_EFFECT_INPUTS};
+pub use frame::{
+    Border, BoxShadow, Clip, Cmd, EffectDraw, Frame, GlyphRun, ImageDraw, Layer, PositionedGlyph,
+};
+pub use images::{Filter, ImageHandle};
+pub use loader::{FontHandle, Loaded, Loader, Source};
+pub use memory::MemoryStats;
+pub use record::{Recorded, Recorder, Replay};
+pub use resource::{
+    GradientHandle, Handle, Lazy, LoadState, PathHandle, SharedGradient, SharedPath,
+};
+
+use buffer::{Buffer, LinearBuffer};
+use capture::Readback;
+use draw::{
+    DrawData, GlyphInstance, ImageInstance, Resources, ShapeInstance, Vertex, Warning, RAMP_ROWS,
+};
+use effect::EffectPipelines;
+use glyph::GlyphCache;
+use images::ImageCache;
+use layer::{Backdrop, LayerPool, LayerTarget};
+use memory::Allocator;
+use pipeline::Pipelines;
+use pipeline_cache::PipelineCache;
+use resource::{Registry, ResourceId};
+use texture::{Texture, UploadTexture};
+
+/// How many times the device is opened again after it was lost, before giving up.
+const REOPEN_ATTEMPTS: u32 = 10;
+/// Between the attempts, while the GPU resets.
+const REOPEN_DELAY: Duration = Duration::from_millis(500);
+/// Between the checks of the captured frames, while no message arrives.
+const CAPTURE_POLL: Duration = Duration::from_millis(2);
 
 pub struct Thread {
     instance: Arc<gfx::Instance>,
     tx: mpsc::SyncSender<Msg>,
+    /// Given to the handles, which send their id when the last one is dropped.
+    releases: mpsc::Sender<ResourceId>,
     join_handle: thread::JoinHandle<()>,
 }
 
 impl Thread {
     pub fn new<Ws>(windows: Ws) -> Thread
+    where
+        Ws: IntoIterator,
+        Ws::Item: Borrow<winit::Window>,
+    {
+        Thread::spawn(windows, None)
+    }
+
+    /// Like `new`, but the compiled pipelines are kept in `cache_dir` across runs,
+    /// which speeds up the startup. The directory is created if needed, and should be
+    /// dedicated to the application in the cache directory of the platform.
+    pub fn with_cache_dir<Ws, P>(windows: Ws, cache_dir: P) -> Thread
+    where
+        Ws: IntoIterator,
+        Ws::Item: Borrow<winit::Window>,
+        P: Into<PathBuf>,
+    {
+        Thread::spawn(windows, Some(cache_dir.into()))
+    }
+
+    fn spawn<Ws>(windows: Ws, cache_dir: Option<PathBuf>) -> Thread
     where
         Ws: IntoIterator,
         Ws::Item: Borrow<winit::Window>,
@@ -41,16 +127,20 @@ impl Thread {
 
         let instance2 = instance.clone();
         let (tx, rx) = mpsc::sync_channel::<Msg>(1);
+        // not bounded, the handles are also dropped on the render thread
+        let (releases, released) = mpsc::channel();
         let join_handle = thread::spawn(move || {
-            render_loop(instance2, windows, rx);
+            render_loop(instance2, windows, cache_dir, rx, Registry::new(released));
         });
         Thread {
             instance,
             tx,
+            releases,
             join_handle,
         }
     }
 
+    /// Also restores a window whose surface was lost.
     pub fn add_window(&self, window: &winit::Window) {
         let size = window
             .get_inner_size()
@@ -72,6 +162,89 @@ impl Thread {
             .expect("Could not remove window from render thread");
     }
 
+    /// `notify` is called on the render thread once it recovered from a loss.
+    /// The frames rendered before it are not presented again: the application should send
+    /// new ones.
+    pub fn on_lost<F>(&self, notify: F)
+    where
+        F: FnMut(Lost) + Send + 'static,
+    {
+        self.tx
+            .send(Msg::OnLost(Box::new(notify)))
+            .expect("Could not send lost handler to render thread");
+    }
+
+    /// Unsupported settings fall back to the closest supported one.
+    pub fn set_antialiasing(&self, id: WindowId, aa: Antialiasing) {
+        self.tx
+            .send(Msg::Antialiasing(id, aa))
+            .expect("Could not send anti-aliasing to render thread");
+    }
+
+    /// The image is uploaded when a frame first draws it,
+    /// and destroyed once its handles are dropped and no frame in flight uses it.
+    pub fn add_image(&self, image: Image) -> ImageHandle {
+        Handle::new(Lazy::ready(image), self.releases.clone())
+    }
+
+    /// A loader whose handles are resolved by this render thread.
+    /// `notify` is called on a worker thread each time a resource is loaded or failed to.
+    pub fn loader<F>(&self, workers: usize, notify: F) -> Loader
+    where
+        F: FnMut(Loaded) + Send + 'static,
+    {
+        Loader::new(workers, self.releases.clone(), notify)
+    }
+
+    /// An effect whose fragment shader is the SPIR-V module `spirv`, sampling `inputs` textures.
+    /// It is compiled by the render thread when first drawn, and the compilation errors are
+    /// reported there.
+    pub fn add_effect(&self, spirv: &[u8], inputs: usize) -> EffectHandle {
+        Handle::new(Effect::new(spirv, inputs), self.releases.clone())
+    }
+
+    pub fn add_path(&self, path: Path) -> PathHandle {
+        Handle::new(SharedPath::new(path), self.releases.clone())
+    }
+
+    /// The ramp of the gradient is computed once, instead of at each frame that draws it.
+    pub fn add_gradient(&self, gradient: Gradient) -> GradientHandle {
+        Handle::new(SharedGradient::new(gradient), self.releases.clone())
+    }
+
+    /// The device memory used by the render thread, once the previous messages are processed.
+    pub fn memory_stats(&self) -> MemoryStats {
+        let (tx, rx) = mpsc::channel();
+        self.tx
+            .send(Msg::MemoryStats(tx))
+            .expect("Could not query memory stats from render thread");
+        rx.recv()
+            .expect("Could not receive memory stats from render thread")
+    }
+
+    /// How the last frame rendered was batched, once the previous messages are processed.
+    pub fn batch_stats(&self) -> BatchStats {
+        let (tx, rx) = mpsc::channel();
+        self.tx
+            .send(Msg::BatchStats(tx))
+            .expect("Could not query batch stats from render thread");
+        rx.recv()
+            .expect("Could not receive batch stats from render thread")
+    }
+
+    /// The content of the window, once the previous messages are processed.
+    /// The last frame sent to the window is rendered again and captured.
+    /// None if no frame was sent to the window, if the frame could not be presented,
+    /// or if the swapchain of the window does not allow the capture.
+    pub fn capture(&self, id: WindowId) -> Option<Capture> {
+        let (tx, rx) = mpsc::channel();
+        self.tx
+            .send(Msg::Capture(id, tx))
+            .expect("Could not send capture to render thread");
+        // a window lost while it rendered drops the request
+        rx.recv().unwrap_or(None)
+    }
+
     pub fn frame(&self, frame: Frame) {
         self.tx
             .send(Msg::Frame(frame))
@@ -94,9 +267,59 @@ impl Thread {
     }
 }
 
+/// What the render thread lost, reported by `Thread::on_lost`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Lost {
+    /// The device was lost, after a GPU reset or a driver upgrade for instance.
+    /// It was recreated with the swapchains, and the resources are uploaded again
+    /// from their handles when drawn. The `RenderCallback`s create their objects again,
+    /// see `CustomRender::generation`.
+    Device,
+    /// The surface of a window was lost, after a remote session reconnects for instance.
+    /// The frames of the window are dropped until it is added again by `Thread::add_window`.
+    Surface(WindowId),
+}
+
+/// What a failed acquisition of a swapchain image means for its window.
+/// Ok when the swapchain is only out of date, and rebuilt at the next frame.
+fn acquire_failure(err: hal::AcquireError, id: WindowId) -> Result<(), Lost> {
+    match err {
+        hal::AcquireError::OutOfDate => Ok(()),
+        hal::AcquireError::SurfaceLost(_) => Err(Lost::Surface(id)),
+        // out of memory or lost, or not ready despite the infinite timeout
+        _ => Err(Lost::Device),
+    }
+}
+
+/// Windows whose surface was lost, with their anti-aliasing, until they are added again.
+#[derive(Default)]
+struct LostWindows(Vec<(WindowId, Antialiasing)>);
+
+impl LostWindows {
+    fn lose(&mut self, id: WindowId, aa: Antialiasing) {
+        self.0.retain(|&(lost, _)| lost != id);
+        self.0.push((id, aa));
+    }
+
+    fn contains(&self, id: WindowId) -> bool {
+        self.0.iter().any(|&(lost, _)| lost == id)
+    }
+
+    /// Forgets a window added again, returns the anti-aliasing it had.
+    fn take(&mut self, id: WindowId) -> Option<Antialiasing> {
+        let idx = self.0.iter().position(|&(lost, _)| lost == id)?;
+        Some(self.0.remove(idx).1)
+    }
+}
+
 enum Msg {
     WindowAdd(WindowInfo),
     WindowRemove(WindowId),
+    OnLost(Box<dyn FnMut(Lost) + Send>),
+    Antialiasing(WindowId, Antialiasing),
+    MemoryStats(mpsc::Sender<MemoryStats>),
+    BatchStats(mpsc::Sender<BatchStats>),
+    Capture(WindowId, mpsc::Sender<Option<Capture>>),
     Frame(frame::Frame),
     Frames(Vec<frame::Frame>),
     Exit,
@@ -108,9 +331,41 @@ struct WindowInfo {
     surf: gfx::Surface,
 }
 
-fn render_loop(instance: Arc<gfx::Instance>, windows: Vec<WindowInfo>, rx: mpsc::Receiver<Msg>) {
-    let mut renderer = Renderer::new(instance, windows);
-    for msg in rx {
+fn render_loop(
+    instance: Arc<gfx::Instance>,
+    windows: Vec<WindowInfo>,
+    cache_dir: Option<PathBuf>,
+    rx: mpsc::Receiver<Msg>,
+    registry: Registry,
+) {
+    let mut renderer = Renderer::new(instance, windows, cache_dir, registry);
+    loop {
+        // the animated frames are rendered again at each vsync while no message arrives
+        let msg = if renderer.is_animating() {
+            match rx.try_recv() {
+                Ok(msg) => msg,
+                Err(mpsc::TryRecvError::Empty) => {
+                    renderer.animate();
+                    continue;
+                }
+                Err(mpsc::TryRecvError::Disconnected) => break,
+            }
+        } else if renderer.is_capturing() {
+            // the UI thread waits for the captures, they are answered once their frame completes
+            match rx.recv_timeout(CAPTURE_POLL) {
+                Ok(msg) => msg,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    renderer.answer_captures();
+                    continue;
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+        } else {
+            match rx.recv() {
+                Ok(msg) => msg,
+                Err(_) => break,
+            }
+        };
         match msg {
             Msg::WindowAdd(info) => {
                 renderer.window_add(info);
@@ -118,6 +373,21 @@ fn render_loop(instance: Arc<gfx::Instance>, windows: Vec<WindowInfo>, rx: mpsc:
             Msg::WindowRemove(id) => {
                 renderer.window_remove(id);
             }
+            Msg::OnLost(notify) => {
+                renderer.on_lost = Some(notify);
+            }
+            Msg::Antialiasing(id, aa) => {
+                renderer.set_antialiasing(id, aa);
+            }
+            Msg::MemoryStats(tx) => {
+                let _ = tx.send(renderer.memory.stats());
+            }
+            Msg::BatchStats(tx) => {
+                let _ = tx.send(renderer.batch_stats);
+            }
+            Msg::Capture(id, tx) => {
+                renderer.capture(id, tx);
+            }
             Msg::Frame(frame) => {
                 renderer.frame(frame);
             }
@@ -131,47 +401,69 @@ fn render_loop(instance: Arc<gfx::Instance>, windows: Vec<WindowInfo>, rx: mpsc:
 }
 
 struct Renderer {
+    /// Opens the device again when it is lost.
+    instance: Arc<gfx::Instance>,
+    cache_dir: Option<PathBuf>,
     physical_device: gfx::PhysicalDevice,
     device: gfx::Device,
+    /// Incremented each time the device is recreated.
+    generation: u64,
     queues: gfx::QueueGroup,
-    _memory_props: hal::MemoryProperties,
+    memory: Allocator,
+    pipeline_cache: PipelineCache,
+    stencil_format: Format,
+    /// Shared by the windows.
+    glyphs: GlyphCache,
+    images: ImageCache,
+    registry: Registry,
+    /// Serial of the last submitted frame.
+    serial: u64,
+    /// Of the last submitted frame.
+    batch_stats: BatchStats,
+    /// Printed once, when a frame first reports them.
+    warnings: HashSet<Warning>,
     windows: Vec<Window>,
+    lost_windows: LostWindows,
+    on_lost: Option<Box<dyn FnMut(Lost) + Send>>,
 }
 
 impl Renderer {
-    fn new(instance: Arc<gfx::Instance>, windows: Vec<WindowInfo>) -> Renderer {
-        use gfx_hal::Graphics;
+    fn new(
+        instance: Arc<gfx::Instance>,
+        windows: Vec<WindowInfo>,
+        cache_dir: Option<PathBuf>,
+        registry: Registry,
+    ) -> Renderer {
         for (idx, adapter) in instance.enumerate_adapters().iter().enumerate() {
             println!("Adapter {}: {:?}", idx, adapter.info);
         }
-        let (adapter, device, queues) = instance
-            .enumerate_adapters()
-            .into_iter()
-            .map(|a| {
-                let dq = a.open_with::<_, Graphics>(1, |qf| {
-                    qf.supports_graphics()
-                        && qf.supports_transfer()
-                        && windows.iter().all(|w| w.surf.supports_queue_family(qf))
-                });
-                (a, dq)
-            })
-            // filter out devices that can't open
-            .filter_map(|adq| {
-                let (a, dq) = (adq.0, adq.1);
-                dq.ok().map(|dq| (a, dq.0, dq.1))
-            })
-            // take the first one that can open
-            .nth(0)
-            .expect("could not open a graphics adapter");
-
+        let (adapter, device, queues) =
+            open_device(&instance, &windows).expect("could not open a graphics adapter");
         let physical_device = adapter.physical_device;
-        let memory_props = physical_device.memory_properties();
+        let memory = Allocator::new(physical_device.memory_properties());
+        let pipeline_cache = PipelineCache::new(&device, &adapter.info, cache_dir.as_deref());
+        let stencil_format = pipeline::find_stencil_format(&physical_device);
+        let glyphs = GlyphCache::new(&device, &memory);
+        let images = ImageCache::new(&device);
         let mut renderer = Renderer {
+            instance,
+            cache_dir,
             physical_device,
             device,
+            generation: 0,
             queues,
-            _memory_props: memory_props,
+            memory,
+            pipeline_cache,
+            stencil_format,
+            glyphs,
+            images,
+            registry,
+            serial: 0,
+            batch_stats: BatchStats::default(),
+            warnings: HashSet::new(),
             windows: Vec::with_capacity(windows.len()),
+            lost_windows: LostWindows::default(),
+            on_lost: None,
         };
         renderer.windows = windows
             .into_iter()
@@ -180,74 +472,358 @@ impl Renderer {
         renderer
     }
 
-    fn destroy(self) {
+    fn destroy(mut self) {
         self.device.wait_idle().unwrap();
+        self.pipeline_cache.save(&self.device);
+        for id in self.registry.collect_all() {
+            self.images.remove(&self.device, id);
+        }
         for w in self.windows.into_iter() {
             w.destroy(&self.device);
         }
+        self.glyphs.destroy(&self.device);
+        self.images.destroy(&self.device);
+        self.memory.destroy(&self.device);
+        self.pipeline_cache.destroy(&self.device);
         std::mem::drop(self.queues);
         std::mem::drop(self.device);
     }
 
     fn window_add(&mut self, info: WindowInfo) {
-        self.windows.push(Window::new(info, self));
+        let aa = self.lost_windows.take(info.id);
+        let mut w = Window::new(info, self);
+        let rebuilt = aa.map_or(Ok(()), |aa| w.rebuild(aa, self));
+        self.windows.push(w);
+        if let Err(lost) = rebuilt {
+            self.recover(lost);
+        }
     }
 
     fn window_remove(&mut self, _id: WindowId) {}
 
+    /// Serial of the last frame that the device is done with.
+    fn completed_serial(&self) -> u64 {
+        self.windows
+            .iter()
+            .flat_map(|w| w.images.iter())
+            .filter(|img| !unsafe { self.device.get_fence_status(&img.fence) }.unwrap_or(false))
+            .map(|img| img.serial.saturating_sub(1))
+            .min()
+            .unwrap_or(self.serial)
+    }
+
+    /// Destroys the device objects of the released resources that no frame uses anymore.
+    fn collect_resources(&mut self) {
+        self.registry.poll(self.serial);
+        let completed = self.completed_serial();
+        for id in self.registry.collect(completed) {
+            self.images.remove(&self.device, id);
+            for w in &mut self.windows {
+                w.effects.remove(&self.device, id);
+            }
+        }
+        self.memory.trim(&self.device);
+    }
+
+    fn set_antialiasing(&mut self, id: WindowId, aa: Antialiasing) {
+        let limits = self.physical_device.limits();
+        let supported = aa.supported(
+            limits.framebuffer_color_samples_count,
+            limits.framebuffer_stencil_samples_count,
+        );
+        if supported != aa {
+            println!("{:?} is not supported, using {:?}", aa, supported);
+        }
+        let idx = self
+            .windows
+            .iter()
+            .position(|w| w.id == id)
+            .expect("Anti-aliasing sent to render thread with an unknown window token");
+        let mut w = self.windows.remove(idx);
+        let rebuilt = w.rebuild(supported, self);
+        self.windows.insert(idx, w);
+        if let Err(lost) = rebuilt {
+            self.recover(lost);
+        }
+    }
+
+    /// Renders a frame, and keeps it to render it again while it is animated or captured.
     fn frame(&mut self, frame: frame::Frame) {
-        let w = self
+        self.answer_captures();
+        let now = Instant::now();
+        if let Err(lost) = self.render(&frame, now) {
+            self.recover(lost);
+        }
+        let animated = animation::running(&frame.cmds, now);
+        if let Some(w) = self.windows.iter_mut().find(|w| w.id == frame.window) {
+            // the captures still waiting were not presented
+            for tx in w.captures.drain(..) {
+                let _ = tx.send(None);
+            }
+            w.last = Some(frame);
+            w.animated = animated;
+        }
+    }
+
+    fn is_animating(&self) -> bool {
+        self.windows.iter().any(Window::is_animating)
+    }
+
+    /// Whether captured frames are still rendering.
+    fn is_capturing(&self) -> bool {
+        let mut images = self.windows.iter().flat_map(|w| w.images.iter());
+        images.any(|img| img.capture.is_some())
+    }
+
+    /// Answers the captures whose frame completed.
+    fn answer_captures(&mut self) {
+        let dev = &self.device;
+        for img in self.windows.iter_mut().flat_map(|w| w.images.iter_mut()) {
+            img.answer_capture(dev);
+        }
+    }
+
+    /// Renders the animated frames again, until their animations end.
+    /// The presentation waits for the vsync.
+    fn animate(&mut self) {
+        let frames: Vec<_> = self
             .windows
             .iter_mut()
-            .find(|w| w.id == frame.window)
-            .expect("Frame sent to render thread with an unknown window token");
+            .filter(|w| w.is_animating())
+            .filter_map(|w| w.last.take())
+            .collect();
+        for frame in frames {
+            self.frame(frame);
+        }
+    }
+
+    /// Captures the last frame of a window, rendered again.
+    /// The UI thread waits for the reply, which is sent once the device completed the frame.
+    fn capture(&mut self, id: WindowId, tx: mpsc::Sender<Option<Capture>>) {
+        let w = match self.windows.iter_mut().find(|w| w.id == id) {
+            Some(w) if w.capturable => w,
+            Some(_) => {
+                println!("the swapchain of window {:?} cannot be captured", id);
+                let _ = tx.send(None);
+                return;
+            }
+            None => {
+                let _ = tx.send(None);
+                return;
+            }
+        };
+        // without a frame to render again, nothing would be presented
+        let frame = match w.last.take() {
+            Some(frame) => frame,
+            None => {
+                let _ = tx.send(None);
+                return;
+            }
+        };
+        w.captures.push(tx);
+        self.frame(frame);
+    }
+
+    /// Recovers from a loss, and notifies the application.
+    fn recover(&mut self, lost: Lost) {
+        match lost {
+            Lost::Device => self.recreate_device(),
+            Lost::Surface(id) => self.lose_surface(id),
+        }
+        if let Some(notify) = &mut self.on_lost {
+            notify(lost);
+        }
+    }
+
+    /// Destroys a window whose surface was lost, until it is added again.
+    fn lose_surface(&mut self, id: WindowId) {
+        println!("the surface of window {:?} was lost", id);
+        if let Some(idx) = self.windows.iter().position(|w| w.id == id) {
+            let _ = self.device.wait_idle();
+            let w = self.windows.remove(idx);
+            self.lost_windows.lose(id, w.aa);
+            w.destroy(&self.device);
+        }
+    }
+
+    /// Opens the device again, and recreates the windows and the caches.
+    /// The objects of the lost device can still be destroyed.
+    fn recreate_device(&mut self) {
+        println!("the device was lost, recreating it");
+        let _ = self.device.wait_idle();
+        let (mut infos, mut states) = (Vec::new(), Vec::new());
+        for mut w in mem::take(&mut self.windows) {
+            states.push((w.aa, w.last.take(), w.animated));
+            infos.push(w.destroy(&self.device));
+        }
+        // the resources are uploaded or compiled again from their handles when drawn
+        self.registry.collect_all();
+
+        let mut attempts = 1;
+        let (adapter, device, queues) = loop {
+            if let Some(opened) = open_device(&self.instance, &infos) {
+                break opened;
+            }
+            assert!(attempts < REOPEN_ATTEMPTS, "could not open a graphics adapter");
+            attempts += 1;
+            thread::sleep(REOPEN_DELAY);
+        };
+        let physical_device = adapter.physical_device;
+        let memory = Allocator::new(physical_device.memory_properties());
+        let cache_dir = self.cache_dir.as_deref();
+        let pipeline_cache = PipelineCache::new(&device, &adapter.info, cache_dir);
+        let old = &self.device;
+        mem::replace(&mut self.glyphs, GlyphCache::new(&device, &memory)).destroy(old);
+        mem::replace(&mut self.images, ImageCache::new(&device)).destroy(old);
+        mem::replace(&mut self.memory, memory).destroy(old);
+        mem::replace(&mut self.pipeline_cache, pipeline_cache).destroy(old);
+        self.stencil_format = pipeline::find_stencil_format(&physical_device);
+        self.physical_device = physical_device;
+        mem::drop(mem::replace(&mut self.queues, queues));
+        mem::drop(mem::replace(&mut self.device, device));
+        self.generation += 1;
+
+        // a window lost again keeps its anti-aliasing, the next frame reports the loss
+        for (info, (aa, last, animated)) in infos.into_iter().zip(states) {
+            let mut w = Window::new(info, self);
+            let _ = w.rebuild(aa, self);
+            w.last = last;
+            w.animated = animated;
+            self.windows.push(w);
+        }
+    }
+
+    /// Renders a frame with its animations interpolated at `now`.
+    /// The frames of the windows whose surface was lost are dropped.
+    fn render(&mut self, frame: &frame::Frame, now: Instant) -> Result<(), Lost> {
+        self.collect_resources();
+        let dev = &self.device;
+        let alloc = &self.memory;
+        let w = match self.windows.iter_mut().find(|w| w.id == frame.window) {
+            Some(w) => w,
+            None if self.lost_windows.contains(frame.window) => return Ok(()),
+            None => panic!("Frame sent to render thread with an unknown window token"),
+        };
+        let data = DrawData::new(frame, now, w.aa, &mut self.glyphs, &mut self.images);
+        for &warning in &data.warnings {
+            if self.warnings.insert(warning) {
+                println!("{}", warning);
+            }
+        }
+        w.effects.prepare(dev, &self.pipeline_cache.cache, &w.pipes, &data.effects);
 
         let idx = unsafe {
             w.swapchain
                 .acquire_image(u64::max_value(), hal::FrameSync::Semaphore(&w.image_avail))
         };
         match idx {
-            Err(err) => match err {
-                hal::AcquireError::OutOfDate => {
-                    w.must_rebuild = true;
-                }
-                _ => panic!("{:?}", err),
-            },
+            Err(err) => {
+                acquire_failure(err, w.id)?;
+                w.must_rebuild = true;
+            }
             Ok(idx) => unsafe {
-                let image = &mut w.images[idx as usize];
-                let cmd = &mut image.cmd;
+                w.must_rebuild = false;
+                let image_count = w.images.len();
+                let img = &mut w.images[idx as usize];
+
+                match dev.wait_for_fence(&img.fence, u64::max_value()) {
+                    Err(hal::device::OomOrDeviceLost::DeviceLost(_)) => return Err(Lost::Device),
+                    res => res.unwrap(),
+                };
+                img.answer_capture(dev);
+                // it only fails out of memory, the device is opened again
+                if dev.reset_fence(&img.fence).is_err() {
+                    return Err(Lost::Device);
+                }
 
-                self.device
-                    .wait_for_fence(&image.fence, u64::max_value())
-                    .unwrap();
-                self.device.reset_fence(&image.fence).unwrap();
+                let vertex_data = &mut img.vertex_data;
+                vertex_data.reset(
+                    dev,
+                    alloc,
+                    &[
+                        (data.vertices.len() * mem::size_of::<Vertex>()) as u64,
+                        (data.instances.len() * mem::size_of::<ShapeInstance>()) as u64,
+                        (data.glyphs.len() * mem::size_of::<GlyphInstance>()) as u64,
+                        (data.images.len() * mem::size_of::<ImageInstance>()) as u64,
+                    ],
+                );
+                let vertices = vertex_data.push(&data.vertices);
+                let instances = vertex_data.push(&data.instances);
+                let glyphs = vertex_data.push(&data.glyphs);
+                let images = vertex_data.push(&data.images);
+                let vertex_buffer = vertex_data.buffer();
+                let slice = |offset: Option<u64>| Some((vertex_buffer?, offset?));
+
+                // every pass but the last renders to a layer
+                let root = data.passes.len() - 1;
+                let (size, pipes) = (w.size, &w.pipes);
+                let sizes: Vec<_> = (0..root)
+                    .map(|pass| data.target_rect(pass).map_or(size, |(_, size)| size))
+                    .collect();
+                img.layers.trim(|layer| layer.destroy(dev));
+                let formats = (w.format, self.stencil_format);
+                let layers = img.layers.assign(&sizes, size, |size| {
+                    LayerTarget::new(dev, alloc, size, formats, pipes)
+                });
+                if data.needs_backdrop() && img.backdrop.is_none() {
+                    img.backdrop = Some(Backdrop::new(dev, alloc, w.size, w.format, &w.pipes));
+                }
+
+                let cmd = &mut img.cmd;
                 cmd.begin();
 
-                let subrange = hal::image::SubresourceRange {
-                    aspects: hal::format::Aspects::COLOR,
-                    levels: 0..1,
-                    layers: 0..1,
+                if data.ramp_rows() > 0 || img.ramps.needs_init() {
+                    img.ramps.upload(cmd, &data.ramps, data.ramp_rows());
+                }
+                self.glyphs.upload(dev, alloc, cmd, &mut img.glyph_staging);
+                self.images.upload(dev, alloc, cmd, &mut img.image_staging);
+
+                let res = Resources {
+                    device: (dev, self.generation),
+                    pipes: &w.pipes,
+                    area: pso::Rect {
+                        x: 0,
+                        y: 0,
+                        w: w.size.0 as i16,
+                        h: w.size.1 as i16,
+                    },
+                    framebuffer: &img.framebuffer,
+                    desc_set: &img.desc_set,
+                    vertices: slice(vertices),
+                    instances: slice(instances),
+                    glyphs: slice(glyphs),
+                    images: slice(images),
+                    image_cache: &self.images,
+                    effects: &w.effects,
+                    layers: layers.iter().map(|&idx| img.layers.get(idx)).collect(),
+                    backdrop: img.backdrop.as_ref(),
+                    image_index: (idx as usize, image_count),
                 };
+                let mut stats = data.stats;
+                for pass in 0..data.passes.len() {
+                    stats.pipeline_binds += data.record(pass, cmd, &res);
+                }
+                self.batch_stats = stats;
 
-                if let Some(cc) = frame.clear_color {
-                    cmd.clear_image(
-                        &image.image,
-                        hal::image::Layout::TransferDstOptimal,
-                        hal::command::ClearColor::Float(cc),
-                        hal::command::ClearDepthStencil(0f32, 0),
-                        &[subrange],
-                    );
+                if !w.captures.is_empty() {
+                    let readback = Readback::record(dev, alloc, cmd, &img.image, w.format, w.size);
+                    img.capture = Some((readback, w.captures.drain(..).collect()));
                 }
 
                 cmd.finish();
 
                 let submission = hal::Submission {
                     command_buffers: Some(&*cmd),
-                    wait_semaphores: Some((&w.image_avail, hal::pso::PipelineStage::TRANSFER)),
+                    wait_semaphores: Some((
+                        &w.image_avail,
+                        pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+                    )),
                     signal_semaphores: Some(&w.render_done),
                 };
 
-                self.queues.queues[0].submit(submission, Some(&image.fence));
+                self.queues.queues[0].submit(submission, Some(&img.fence));
+                self.serial += 1;
+                img.serial = self.serial;
 
                 if let Err(_) =
                     w.swapchain
@@ -257,34 +833,221 @@ impl Renderer {
                 }
             },
         }
+        Ok(())
     }
 }
 
 struct Window {
     id: WindowId,
-    _size: (u32, u32),
-    _surf: gfx::Surface,
+    size: (u32, u32),
+    format: Format,
+    aa: Antialiasing,
+    surf: gfx::Surface,
     swapchain: gfx::Swapchain,
     image_avail: gfx::Semaphore,
     render_done: gfx::Semaphore,
     pool: gfx::CommandPool,
+    desc_pool: gfx::DescriptorPool,
+    pipes: Pipelines,
+    /// Compiled for `pipes`.
+    effects: EffectPipelines,
     images: Vec<ImageData>,
     must_rebuild: bool,
+    /// The last frame, rendered again while it is animated or captured.
+    last: Option<frame::Frame>,
+    /// Whether the last frame has running animations.
+    animated: bool,
+    /// Whether the swapchain images can be copied.
+    capturable: bool,
+    /// Waiting for the last frame to be rendered again, recorded by `Renderer::render`.
+    captures: Vec<mpsc::Sender<Option<Capture>>>,
 }
 
+/// Resources attached to a swapchain image.
 struct ImageData {
     image: gfx::Image,
+    view: gfx::ImageView,
+    /// Resolved into the image with multisampling.
+    multisampled: Option<Texture>,
+    stencil: Texture,
+    framebuffer: gfx::Framebuffer,
+    /// The vertices and instances of the frame.
+    vertex_data: LinearBuffer,
+    /// Holds the glyphs uploaded to the atlas with the frame.
+    glyph_staging: Option<Buffer>,
+    /// Holds the images uploaded with the frame.
+    image_staging: Option<Buffer>,
+    ramps: UploadTexture,
+    desc_set: gfx::DescriptorSet,
+    /// Targets of the layers, created on demand.
+    layers: LayerPool<LayerTarget>,
+    backdrop: Option<Backdrop>,
     cmd: gfx::CommandBuffer,
     fence: gfx::Fence,
+    /// Serial of the last frame submitted with the fence.
+    serial: u64,
+    /// Copy of the frame, answering the captures once the fence is signaled.
+    capture: Option<(Readback, Vec<mpsc::Sender<Option<Capture>>>)>,
 }
 
 impl ImageData {
-    fn new(image: gfx::Image, pool: &mut gfx::CommandPool, dev: &gfx::Device) -> ImageData {
-        ImageData {
-            image,
-            cmd: pool.acquire_command_buffer(),
-            fence: dev.create_fence(true).unwrap(),
+    fn new(
+        image: gfx::Image,
+        format: Format,
+        size: (u32, u32),
+        pool: &mut gfx::CommandPool,
+        desc_pool: &mut gfx::DescriptorPool,
+        pipes: &Pipelines,
+        renderer: &Renderer,
+    ) -> ImageData {
+        let dev = &renderer.device;
+        let alloc = &renderer.memory;
+        unsafe {
+            let view = dev
+                .create_image_view(
+                    &image,
+                    image::ViewKind::D2,
+                    format,
+                    format::Swizzle::NO,
+                    texture::COLOR_RANGE,
+                )
+                .expect("could not create a swapchain image view");
+            let multisampled = if pipes.samples > 1 {
+                Some(Texture::multisampled(
+                    dev,
+                    alloc,
+                    size,
+                    format,
+                    pipes.samples,
+                    image::Usage::COLOR_ATTACHMENT | image::Usage::TRANSIENT_ATTACHMENT,
+                    format::Aspects::COLOR,
+                ))
+            } else {
+                None
+            };
+            let stencil = Texture::multisampled(
+                dev,
+                alloc,
+                size,
+                renderer.stencil_format,
+                pipes.samples,
+                image::Usage::DEPTH_STENCIL_ATTACHMENT,
+                format::Aspects::DEPTH | format::Aspects::STENCIL,
+            );
+            let attachments = match &multisampled {
+                Some(ms) => vec![&ms.view, &stencil.view, &view],
+                None => vec![&view, &stencil.view],
+            };
+            let framebuffer = dev
+                .create_framebuffer(
+                    &pipes.render_pass,
+                    attachments,
+                    image::Extent {
+                        width: size.0,
+                        height: size.1,
+                        depth: 1,
+                    },
+                )
+                .expect("could not create a framebuffer");
+            let ramps = UploadTexture::new(
+                dev,
+                alloc,
+                (RAMP_SIZE as u32, RAMP_ROWS as u32),
+                Format::Rgba8Unorm,
+            );
+            let desc_set = desc_pool
+                .allocate_set(&pipes.set_layout)
+                .expect("could not allocate a descriptor set");
+            dev.write_descriptor_sets(vec![
+                pso::DescriptorSetWrite {
+                    set: &desc_set,
+                    binding: 0,
+                    array_offset: 0,
+                    descriptors: Some(pso::Descriptor::Image(
+                        &ramps.texture.view,
+                        image::Layout::ShaderReadOnlyOptimal,
+                    )),
+                },
+                pso::DescriptorSetWrite {
+                    set: &desc_set,
+                    binding: 1,
+                    array_offset: 0,
+                    descriptors: Some(pso::Descriptor::Sampler(&pipes.sampler)),
+                },
+                pso::DescriptorSetWrite {
+                    set: &desc_set,
+                    binding: 2,
+                    array_offset: 0,
+                    descriptors: Some(pso::Descriptor::Image(
+                        &renderer.glyphs.texture.view,
+                        image::Layout::ShaderReadOnlyOptimal,
+                    )),
+                },
+            ]);
+            ImageData {
+                image,
+                view,
+                multisampled,
+                stencil,
+                framebuffer,
+                vertex_data: LinearBuffer::new(hal::buffer::Usage::VERTEX),
+                glyph_staging: None,
+                image_staging: None,
+                ramps,
+                desc_set,
+                layers: LayerPool::new(),
+                backdrop: None,
+                cmd: pool.acquire_command_buffer(),
+                fence: dev.create_fence(true).unwrap(),
+                serial: 0,
+                capture: None,
+            }
+        }
+    }
+
+    /// Answers the captures of the last frame, once the device is done with it.
+    fn answer_capture(&mut self, dev: &gfx::Device) {
+        match &self.capture {
+            Some((readback, _)) if readback.is_done(dev, &self.fence) => {}
+            _ => return,
+        }
+        if let Some((readback, requests)) = self.capture.take() {
+            let capture = readback.finish(dev, &self.fence);
+            for tx in requests {
+                let _ = tx.send(capture.clone());
+            }
+        }
+    }
+
+    /// Returns the swapchain image.
+    fn destroy(mut self, dev: &gfx::Device, pool: &mut gfx::CommandPool) -> gfx::Image {
+        self.answer_capture(dev);
+        // the frame never completed, the dropped requests are answered with None
+        if let Some((readback, _)) = self.capture {
+            readback.finish(dev, &self.fence);
+        }
+        unsafe {
+            dev.destroy_framebuffer(self.framebuffer);
+            dev.destroy_image_view(self.view);
+        }
+        if let Some(ms) = self.multisampled {
+            ms.destroy(dev);
+        }
+        self.stencil.destroy(dev);
+        self.ramps.destroy(dev);
+        self.layers.destroy(|layer| layer.destroy(dev));
+        if let Some(backdrop) = self.backdrop {
+            backdrop.destroy(dev);
         }
+        self.vertex_data.destroy(dev);
+        for buf in self.glyph_staging.into_iter().chain(self.image_staging) {
+            buf.destroy(dev);
+        }
+        unsafe {
+            pool.free(Some(self.cmd));
+            dev.destroy_fence(self.fence);
+        }
+        self.image
     }
 }
 
@@ -294,7 +1057,18 @@ impl Window {
         let pd = &renderer.physical_device;
         let queues = &renderer.queues;
 
-        let (swapchain, images) = build_swapchain(&mut info, pd, dev, None);
+        let (swapchain, images, format, capturable) = build_swapchain(&mut info, pd, dev, None);
+        let size: (u32, u32) = info.size.into();
+        let aa = Antialiasing::default();
+        let cache = &renderer.pipeline_cache;
+        let pipes = Pipelines::new(
+            dev,
+            &cache.cache,
+            format,
+            renderer.stencil_format,
+            aa.samples(),
+        );
+        cache.save(dev);
         let mut pool = unsafe {
             dev.create_command_pool_typed(
                 &queues,
@@ -302,35 +1076,116 @@ impl Window {
             )
         }
         .expect("could not create a command pool");
+        let mut desc_pool = unsafe {
+            dev.create_descriptor_pool(
+                images.len(),
+                &[
+                    // the ramps and the glyph atlas
+                    pso::DescriptorRangeDesc {
+                        ty: pso::DescriptorType::SampledImage,
+                        count: 2 * images.len(),
+                    },
+                    pso::DescriptorRangeDesc {
+                        ty: pso::DescriptorType::Sampler,
+                        count: images.len(),
+                    },
+                ],
+            )
+        }
+        .expect("could not create a descriptor pool");
         let images = images
             .into_iter()
-            .map(|i| ImageData::new(i, &mut pool, dev))
+            .map(|i| ImageData::new(i, format, size, &mut pool, &mut desc_pool, &pipes, renderer))
             .collect();
 
         Window {
             id: info.id,
-            _size: info.size.into(),
-            _surf: info.surf,
+            size,
+            format,
+            aa,
+            surf: info.surf,
             swapchain,
             image_avail: dev.create_semaphore().unwrap(),
             render_done: dev.create_semaphore().unwrap(),
             pool,
+            desc_pool,
+            pipes,
+            effects: EffectPipelines::new(),
             images,
             must_rebuild: false,
+            last: None,
+            animated: false,
+            capturable,
+            captures: Vec::new(),
         }
     }
 
-    fn destroy(mut self, dev: &gfx::Device) {
+    /// Whether the last frame is rendered again at the next vsync.
+    /// Nothing is presented while the swapchain is out of date, so the animation waits
+    /// for the next frame sent to the window instead of spinning.
+    fn is_animating(&self) -> bool {
+        self.animated && !self.must_rebuild
+    }
+
+    /// Recreates the pipelines and the targets for another anti-aliasing.
+    /// When the device is lost, they are created for it with the next device.
+    fn rebuild(&mut self, aa: Antialiasing, renderer: &Renderer) -> Result<(), Lost> {
+        if aa == self.aa {
+            return Ok(());
+        }
+        let dev = &renderer.device;
+        if dev.wait_idle().is_err() {
+            self.aa = aa;
+            return Err(Lost::Device);
+        }
+        let mut images = Vec::with_capacity(self.images.len());
+        for data in self.images.drain(..) {
+            images.push(data.destroy(dev, &mut self.pool));
+        }
+        unsafe {
+            self.desc_pool.reset();
+        }
+        let cache = &renderer.pipeline_cache;
+        let pipes = Pipelines::new(
+            dev,
+            &cache.cache,
+            self.format,
+            renderer.stencil_format,
+            aa.samples(),
+        );
+        cache.save(dev);
+        mem::replace(&mut self.pipes, pipes).destroy(dev);
+        self.effects.clear(dev);
+
+        let (format, size) = (self.format, self.size);
+        let (pool, desc_pool, pipes) = (&mut self.pool, &mut self.desc_pool, &self.pipes);
+        self.images = images
+            .into_iter()
+            .map(|i| ImageData::new(i, format, size, pool, desc_pool, pipes, renderer))
+            .collect();
+        self.aa = aa;
+        Ok(())
+    }
+
+    /// Returns the surface, to create the window again.
+    fn destroy(mut self, dev: &gfx::Device) -> WindowInfo {
         unsafe {
             dev.destroy_semaphore(self.image_avail);
             dev.destroy_semaphore(self.render_done);
-            dev.destroy_swapchain(self.swapchain);
-            for ImageData { cmd, fence, .. } in self.images.into_iter() {
-                self.pool.free(Some(cmd));
-                dev.destroy_fence(fence);
+            for data in self.images.into_iter() {
+                data.destroy(dev, &mut self.pool);
             }
+            dev.destroy_swapchain(self.swapchain);
+            dev.destroy_descriptor_pool(self.desc_pool);
+            self.effects.clear(dev);
+            self.pipes.destroy(dev);
             dev.destroy_command_pool(self.pool.into_raw());
         }
+        WindowInfo {
+            id: self.id,
+            size: PhysicalSize::new(f64::from(self.size.0), f64::from(self.size.1)),
+            surf: self.surf,
+        }
     }
 }
 
@@ -366,18 +1221,50 @@ fn find_surf_format(compat: Option<Vec<Format>>) -> Format {
     })
 }
 
+/// Opens the first adapter whose queues support the surfaces of the windows.
+fn open_device(
+    instance: &gfx::Instance,
+    windows: &[WindowInfo],
+) -> Option<(hal::Adapter<gfx::Backend>, gfx::Device, gfx::QueueGroup)> {
+    use gfx_hal::Graphics;
+    instance
+        .enumerate_adapters()
+        .into_iter()
+        .map(|a| {
+            let dq = a.open_with::<_, Graphics>(1, |qf| {
+                qf.supports_graphics()
+                    && qf.supports_transfer()
+                    && windows.iter().all(|w| w.surf.supports_queue_family(qf))
+            });
+            (a, dq)
+        })
+        // filter out devices that can't open
+        .filter_map(|adq| {
+            let (a, dq) = (adq.0, adq.1);
+            dq.ok().map(|dq| (a, dq.0, dq.1))
+        })
+        // take the first one that can open
+        .next()
+}
+
+/// Also returns whether the images can be captured.
 fn build_swapchain(
     info: &mut WindowInfo,
     pd: &gfx::PhysicalDevice,
     dev: &gfx::Device,
     old: Option<gfx::Swapchain>,
-) -> (gfx::Swapchain, Vec<gfx::Image>) {
+) -> (gfx::Swapchain, Vec<gfx::Image>, Format, bool) {
     use hal::image;
     let (caps, formats, present_modes, comp_alpha) = info.surf.compatibility(&pd);
-    let usage = image::Usage::TRANSFER_DST | image::Usage::COLOR_ATTACHMENT;
+    let mut usage = image::Usage::TRANSFER_DST | image::Usage::COLOR_ATTACHMENT;
     assert!(caps.usage.contains(usage));
     let image_count = std::cmp::max(2, caps.image_count.start);
     let format = find_surf_format(formats);
+    let capturable =
+        caps.usage.contains(image::Usage::TRANSFER_SRC) && capture::is_supported(format);
+    if capturable {
+        usage |= image::Usage::TRANSFER_SRC;
+    }
     assert!(present_modes
         .iter()
         .find(|&&pm| pm == hal::PresentMode::Fifo)
@@ -397,5 +1284,34 @@ fn build_swapchain(
             _ => panic!("Framebuffer Backbuffer unsupported"),
         }
     };
-    (swapchain, images)
+    (swapchain, images, format, capturable)
+}
+
+#[test]
+fn lost_triage() {
+    use hal::{device, AcquireError};
+
+    let id = unsafe { WindowId::dummy() };
+    let surface = AcquireError::SurfaceLost(device::SurfaceLost);
+    let lost = AcquireError::DeviceLost(device::DeviceLost);
+    assert_eq!(acquire_failure(AcquireError::OutOfDate, id), Ok(()));
+    assert_eq!(acquire_failure(surface, id), Err(Lost::Surface(id)));
+    assert_eq!(acquire_failure(lost, id), Err(Lost::Device));
+    // not ready despite the infinite timeout
+    let not_ready = AcquireError::NotReady;
+    assert_eq!(acquire_failure(not_ready, id), Err(Lost::Device));
+}
+
+#[test]
+fn lost_windows() {
+    let id = unsafe { WindowId::dummy() };
+    let mut lost = LostWindows::default();
+    assert!(!lost.contains(id));
+    lost.lose(id, Antialiasing::Msaa(4));
+    lost.lose(id, Antialiasing::None);
+    assert!(lost.contains(id));
+    // added again with the anti-aliasing it had when last lost
+    assert_eq!(lost.take(id), Some(Antialiasing::None));
+    assert!(!lost.contains(id));
+    assert_eq!(lost.take(id), None);
 }
@@ -0,0 +1,885 @@
+use super::draw::{EffectConsts, GlyphInstance, ImageInstance, PaintConsts, ShapeInstance, Vertex};
+use super::effect::MAX_EFFECT_INPUTS;
+use crate::gfx;
+use crate::paint::BlendMode;
+use crate::path::FillRule;
+use gfx_hal::{self as hal, format::Format, image, pass, pso, Device, PhysicalDevice};
+use std::mem;
+
+const FILL_VERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/fill.vert.spv"));
+const PAINT_FRAG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/paint.frag.spv"));
+const SHAPE_VERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/shape.vert.spv"));
+const SHAPE_FRAG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/shape.frag.spv"));
+const COMPOSITE_FRAG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/composite.frag.spv"));
+const BLUR_FRAG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/blur.frag.spv"));
+const GLYPH_VERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/glyph.vert.spv"));
+const GLYPH_FRAG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/glyph.frag.spv"));
+const IMAGE_VERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/image.vert.spv"));
+const IMAGE_FRAG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/image.frag.spv"));
+const EFFECT_VERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/effect.vert.spv"));
+
+const STENCIL_FORMATS: [Format; 3] = [
+    Format::D24UnormS8Uint,
+    Format::D32FloatS8Uint,
+    Format::D16UnormS8Uint,
+];
+
+pub(crate) fn find_stencil_format(pd: &gfx::PhysicalDevice) -> Format {
+    STENCIL_FORMATS
+        .iter()
+        .cloned()
+        .find(|&f| {
+            pd.format_properties(Some(f))
+                .optimal_tiling
+                .contains(hal::format::ImageFeature::DEPTH_STENCIL_ATTACHMENT)
+        })
+        .expect("could not find a supported stencil format")
+}
+
+/// Pre-multiplied alpha blending.
+const PREMULTIPLIED: pso::BlendState = pso::BlendState::On {
+    color: pso::BlendOp::Add {
+        src: pso::Factor::One,
+        dst: pso::Factor::OneMinusSrcAlpha,
+    },
+    alpha: pso::BlendOp::Add {
+        src: pso::Factor::One,
+        dst: pso::Factor::OneMinusSrcAlpha,
+    },
+};
+
+/// Stencil bit set where the current clip lets pixels through.
+pub(crate) const CLIP_BIT: u32 = 0x80;
+/// Stencil bits holding the winding of the path being filled.
+const WINDING_MASK: u32 = 0x7f;
+
+pub(crate) const PAINT_STAGES: pso::ShaderStageFlags = pso::ShaderStageFlags::GRAPHICS;
+
+/// The render passes and the graphics pipelines used to render the frames of a window.
+pub(crate) struct Pipelines {
+    /// Samples per pixel of the targets.
+    pub samples: u8,
+    pub render_pass: gfx::RenderPass,
+    /// Compatible with `render_pass`, renders to a layer that is sampled afterwards.
+    pub layer_pass: gfx::RenderPass,
+    /// Like `layer_pass`, but resumes rendering after the layer was copied.
+    pub layer_resume_pass: gfx::RenderPass,
+    pub set_layout: gfx::DescriptorSetLayout,
+    /// Layout of the sets sampling a layer.
+    pub layer_set_layout: gfx::DescriptorSetLayout,
+    pub layout: gfx::PipelineLayout,
+    pub composite_layout: gfx::PipelineLayout,
+    /// Layout of the effects, with a sampling set per input.
+    pub effect_layout: gfx::PipelineLayout,
+    pub sampler: gfx::Sampler,
+    pub stencil_nonzero: gfx::GraphicsPipeline,
+    pub stencil_evenodd: gfx::GraphicsPipeline,
+    /// Cover pipelines, by fixed-function `BlendMode`.
+    cover: Vec<Option<gfx::GraphicsPipeline>>,
+    /// Draws the anti-aliasing fringe of the paths, by fixed-function `BlendMode`.
+    fringe: Vec<Option<gfx::GraphicsPipeline>>,
+    pub clip_nonzero: gfx::GraphicsPipeline,
+    pub clip_evenodd: gfx::GraphicsPipeline,
+    pub clip_cover: gfx::GraphicsPipeline,
+    pub clip_reset: gfx::GraphicsPipeline,
+    shape: Vec<Option<gfx::GraphicsPipeline>>,
+    glyph: Vec<Option<gfx::GraphicsPipeline>>,
+    /// Draws images, with the layout of the composites.
+    image: Vec<Option<gfx::GraphicsPipeline>>,
+    composite: Vec<Option<gfx::GraphicsPipeline>>,
+    /// Composites with the blend mode done in the shader, replacing the destination.
+    pub composite_backdrop: gfx::GraphicsPipeline,
+    /// Blurs a layer in one direction, with the layout of the composites.
+    pub blur: gfx::GraphicsPipeline,
+}
+
+impl Pipelines {
+    /// Panics if the blend mode is not fixed-function.
+    pub fn cover(&self, mode: BlendMode) -> &gfx::GraphicsPipeline {
+        self.cover[mode as usize].as_ref().expect("blend mode not fixed-function")
+    }
+
+    /// Panics if the blend mode is not fixed-function.
+    pub fn fringe(&self, mode: BlendMode) -> &gfx::GraphicsPipeline {
+        self.fringe[mode as usize].as_ref().expect("blend mode not fixed-function")
+    }
+
+    /// Panics if the blend mode is not fixed-function.
+    pub fn shape(&self, mode: BlendMode) -> &gfx::GraphicsPipeline {
+        self.shape[mode as usize].as_ref().expect("blend mode not fixed-function")
+    }
+
+    /// Panics if the blend mode is not fixed-function.
+    pub fn glyph(&self, mode: BlendMode) -> &gfx::GraphicsPipeline {
+        self.glyph[mode as usize].as_ref().expect("blend mode not fixed-function")
+    }
+
+    /// Panics if the blend mode is not fixed-function.
+    pub fn image(&self, mode: BlendMode) -> &gfx::GraphicsPipeline {
+        self.image[mode as usize].as_ref().expect("blend mode not fixed-function")
+    }
+
+    /// Panics if the blend mode is not fixed-function.
+    pub fn composite(&self, mode: BlendMode) -> &gfx::GraphicsPipeline {
+        self.composite[mode as usize].as_ref().expect("blend mode not fixed-function")
+    }
+
+    /// Compiles the pipeline of an effect, whose fragment shader is user supplied.
+    /// Panics if the blend mode is not fixed-function.
+    pub unsafe fn effect(
+        &self,
+        dev: &gfx::Device,
+        cache: &gfx::PipelineCache,
+        spirv: &[u8],
+        mode: BlendMode,
+    ) -> Result<gfx::GraphicsPipeline, String> {
+        let blend = blend_state(mode).expect("blend mode not fixed-function");
+        let fs = dev
+            .create_shader_module(spirv)
+            .map_err(|err| format!("invalid shader: {:?}", err))?;
+        let vs = dev.create_shader_module(EFFECT_VERT).unwrap();
+        let pipe = try_create_pipeline(
+            dev,
+            cache,
+            &self.effect_layout,
+            (&self.render_pass, self.samples),
+            (&vs, Some(&fs)),
+            blend,
+            clip_test(),
+            None,
+        );
+        dev.destroy_shader_module(vs);
+        dev.destroy_shader_module(fs);
+        pipe.map_err(|err| format!("could not create the pipeline: {:?}", err))
+    }
+
+    pub fn new(
+        dev: &gfx::Device,
+        cache: &gfx::PipelineCache,
+        color_format: Format,
+        stencil_format: Format,
+        samples: u8,
+    ) -> Pipelines {
+        unsafe {
+            let formats = (color_format, stencil_format);
+            let render_pass = create_render_pass(dev, formats, samples, PassKind::Window);
+            let layer_pass = create_render_pass(dev, formats, samples, PassKind::Layer);
+            let layer_resume_pass =
+                create_render_pass(dev, formats, samples, PassKind::LayerResume);
+            let target = (&render_pass, samples);
+            let set_layout = dev
+                .create_descriptor_set_layout(
+                    &[
+                        pso::DescriptorSetLayoutBinding {
+                            binding: 0,
+                            ty: pso::DescriptorType::SampledImage,
+                            count: 1,
+                            stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                            immutable_samplers: false,
+                        },
+                        pso::DescriptorSetLayoutBinding {
+                            binding: 1,
+                            ty: pso::DescriptorType::Sampler,
+                            count: 1,
+                            stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                            immutable_samplers: false,
+                        },
+                        // the glyph atlas
+                        pso::DescriptorSetLayoutBinding {
+                            binding: 2,
+                            ty: pso::DescriptorType::SampledImage,
+                            count: 1,
+                            stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                            immutable_samplers: false,
+                        },
+                    ],
+                    &[],
+                )
+                .expect("could not create a descriptor set layout");
+            let layer_set_layout = sampling_set_layout(dev);
+            // push constant ranges are expressed in 32 bits words
+            let consts_len = (mem::size_of::<PaintConsts>() / 4) as u32;
+            let layout = dev
+                .create_pipeline_layout(Some(&set_layout), &[(PAINT_STAGES, 0..consts_len)])
+                .expect("could not create a pipeline layout");
+            // the layer, mask and backdrop sets come after the paint set
+            let composite_layout = dev
+                .create_pipeline_layout(
+                    vec![&set_layout, &layer_set_layout, &layer_set_layout, &layer_set_layout],
+                    &[(PAINT_STAGES, 0..consts_len)],
+                )
+                .expect("could not create a pipeline layout");
+            let effect_consts_len = (mem::size_of::<EffectConsts>() / 4) as u32;
+            let effect_layout = dev
+                .create_pipeline_layout(
+                    vec![&layer_set_layout; MAX_EFFECT_INPUTS],
+                    &[(PAINT_STAGES, 0..effect_consts_len)],
+                )
+                .expect("could not create a pipeline layout");
+            let sampler = dev
+                .create_sampler(image::SamplerInfo::new(
+                    image::Filter::Linear,
+                    image::WrapMode::Clamp,
+                ))
+                .expect("could not create a sampler");
+
+            let vs = dev.create_shader_module(FILL_VERT).unwrap();
+            let fs = dev.create_shader_module(PAINT_FRAG).unwrap();
+
+            let stencil = |fun, reference, op_front, op_back| {
+                let face = |op_pass| StencilFace {
+                    fun,
+                    reference,
+                    read_mask: CLIP_BIT,
+                    write_mask: WINDING_MASK,
+                    op_pass,
+                    op_fail: pso::StencilOp::Keep,
+                };
+                stencil_test(face(op_front), face(op_back))
+            };
+            let winding = |fun, reference, fill_rule| {
+                let (front, back) = match fill_rule {
+                    FillRule::NonZero => (pso::StencilOp::IncrementWrap, pso::StencilOp::DecrementWrap),
+                    FillRule::EvenOdd => (pso::StencilOp::Invert, pso::StencilOp::Invert),
+                };
+                let test = stencil(fun, reference, front, back);
+                fill_pipeline(dev, cache, &layout, target, &vs, None, test)
+            };
+            let stencil_nonzero = winding(pso::Comparison::Always, 0, FillRule::NonZero);
+            let stencil_evenodd = winding(pso::Comparison::Always, 0, FillRule::EvenOdd);
+            // clip paths only add winding where the current clip is set
+            let clip_nonzero = winding(pso::Comparison::Equal, CLIP_BIT, FillRule::NonZero);
+            let clip_evenodd = winding(pso::Comparison::Equal, CLIP_BIT, FillRule::EvenOdd);
+
+            // draws where the clip bit and some winding are set, and resets the winding
+            let cover_face = StencilFace {
+                fun: pso::Comparison::Less,
+                reference: CLIP_BIT,
+                read_mask: !0,
+                write_mask: WINDING_MASK,
+                op_pass: pso::StencilOp::Zero,
+                op_fail: pso::StencilOp::Zero,
+            };
+            let cover = fixed_function_variants(|blend| {
+                create_pipeline(
+                    dev,
+                    cache,
+                    &layout,
+                    target,
+                    (&vs, Some(&fs)),
+                    blend,
+                    stencil_test(cover_face, cover_face),
+                    Some(vertex_input()),
+                )
+            });
+            // draws where the clip bit is set and the winding is not
+            let fringe_face = StencilFace {
+                fun: pso::Comparison::Equal,
+                reference: CLIP_BIT,
+                read_mask: !0,
+                write_mask: 0,
+                op_pass: pso::StencilOp::Keep,
+                op_fail: pso::StencilOp::Keep,
+            };
+            let fringe = fixed_function_variants(|blend| {
+                create_pipeline(
+                    dev,
+                    cache,
+                    &layout,
+                    target,
+                    (&vs, Some(&fs)),
+                    blend,
+                    stencil_test(fringe_face, fringe_face),
+                    Some(vertex_input()),
+                )
+            });
+            // keeps the clip bit where some winding is set, and resets the winding
+            let clip_cover_face = StencilFace {
+                fun: pso::Comparison::NotEqual,
+                reference: CLIP_BIT,
+                read_mask: WINDING_MASK,
+                write_mask: !0,
+                op_pass: pso::StencilOp::Replace,
+                op_fail: pso::StencilOp::Zero,
+            };
+            let clip_cover = fill_pipeline(
+                dev,
+                cache,
+                &layout,
+                target,
+                &vs,
+                None,
+                stencil_test(clip_cover_face, clip_cover_face),
+            );
+            let clip_reset_face = StencilFace {
+                fun: pso::Comparison::Always,
+                reference: CLIP_BIT,
+                read_mask: !0,
+                write_mask: !0,
+                op_pass: pso::StencilOp::Replace,
+                op_fail: pso::StencilOp::Keep,
+            };
+            let clip_reset = fill_pipeline(
+                dev,
+                cache,
+                &layout,
+                target,
+                &vs,
+                None,
+                stencil_test(clip_reset_face, clip_reset_face),
+            );
+
+            dev.destroy_shader_module(vs);
+            dev.destroy_shader_module(fs);
+
+            let vs = dev.create_shader_module(SHAPE_VERT).unwrap();
+            let fs = dev.create_shader_module(SHAPE_FRAG).unwrap();
+            let stride = mem::size_of::<ShapeInstance>() as u32;
+            let shape = fixed_function_variants(|blend| {
+                let shaders = (&vs, &fs);
+                instance_pipeline(dev, cache, &layout, target, shaders, blend, clip_test(), stride)
+            });
+            dev.destroy_shader_module(vs);
+            dev.destroy_shader_module(fs);
+
+            let vs = dev.create_shader_module(GLYPH_VERT).unwrap();
+            let fs = dev.create_shader_module(GLYPH_FRAG).unwrap();
+            let stride = mem::size_of::<GlyphInstance>() as u32;
+            let glyph = fixed_function_variants(|blend| {
+                let shaders = (&vs, &fs);
+                instance_pipeline(dev, cache, &layout, target, shaders, blend, clip_test(), stride)
+            });
+            dev.destroy_shader_module(vs);
+            dev.destroy_shader_module(fs);
+
+            let vs = dev.create_shader_module(IMAGE_VERT).unwrap();
+            let fs = dev.create_shader_module(IMAGE_FRAG).unwrap();
+            let stride = mem::size_of::<ImageInstance>() as u32;
+            let image = fixed_function_variants(|blend| {
+                instance_pipeline(
+                    dev,
+                    cache,
+                    &composite_layout,
+                    target,
+                    (&vs, &fs),
+                    blend,
+                    clip_test(),
+                    stride,
+                )
+            });
+            dev.destroy_shader_module(vs);
+            dev.destroy_shader_module(fs);
+
+            let vs = dev.create_shader_module(FILL_VERT).unwrap();
+            let fs = dev.create_shader_module(COMPOSITE_FRAG).unwrap();
+            let composite_pipeline = |blend| {
+                create_pipeline(
+                    dev,
+                    cache,
+                    &composite_layout,
+                    target,
+                    (&vs, Some(&fs)),
+                    blend,
+                    clip_test(),
+                    Some(vertex_input()),
+                )
+            };
+            let composite = fixed_function_variants(&composite_pipeline);
+            let src = blend_state(BlendMode::Src).expect("source copy is fixed-function");
+            let composite_backdrop = composite_pipeline(src);
+            dev.destroy_shader_module(fs);
+
+            let fs = dev.create_shader_module(BLUR_FRAG).unwrap();
+            let blur = create_pipeline(
+                dev,
+                cache,
+                &composite_layout,
+                target,
+                (&vs, Some(&fs)),
+                src,
+                clip_test(),
+                Some(vertex_input()),
+            );
+            dev.destroy_shader_module(vs);
+            dev.destroy_shader_module(fs);
+
+            Pipelines {
+                samples,
+                render_pass,
+                layer_pass,
+                layer_resume_pass,
+                set_layout,
+                layer_set_layout,
+                layout,
+                composite_layout,
+                effect_layout,
+                sampler,
+                stencil_nonzero,
+                stencil_evenodd,
+                cover,
+                fringe,
+                clip_nonzero,
+                clip_evenodd,
+                clip_cover,
+                clip_reset,
+                shape,
+                glyph,
+                image,
+                composite,
+                composite_backdrop,
+                blur,
+            }
+        }
+    }
+
+    pub fn destroy(self, dev: &gfx::Device) {
+        unsafe {
+            dev.destroy_graphics_pipeline(self.stencil_nonzero);
+            dev.destroy_graphics_pipeline(self.stencil_evenodd);
+            dev.destroy_graphics_pipeline(self.clip_nonzero);
+            dev.destroy_graphics_pipeline(self.clip_evenodd);
+            dev.destroy_graphics_pipeline(self.clip_cover);
+            dev.destroy_graphics_pipeline(self.clip_reset);
+            let variants = self
+                .cover
+                .into_iter()
+                .chain(self.fringe)
+                .chain(self.shape)
+                .chain(self.glyph)
+                .chain(self.image)
+                .chain(self.composite);
+            for pipe in variants.flatten() {
+                dev.destroy_graphics_pipeline(pipe);
+            }
+            dev.destroy_graphics_pipeline(self.composite_backdrop);
+            dev.destroy_graphics_pipeline(self.blur);
+            dev.destroy_sampler(self.sampler);
+            dev.destroy_pipeline_layout(self.layout);
+            dev.destroy_pipeline_layout(self.composite_layout);
+            dev.destroy_pipeline_layout(self.effect_layout);
+            dev.destroy_descriptor_set_layout(self.set_layout);
+            dev.destroy_descriptor_set_layout(self.layer_set_layout);
+            dev.destroy_render_pass(self.render_pass);
+            dev.destroy_render_pass(self.layer_pass);
+            dev.destroy_render_pass(self.layer_resume_pass);
+        }
+    }
+}
+
+/// Layout of the sets sampling a single texture: the image, then the sampler.
+pub(crate) unsafe fn sampling_set_layout(dev: &gfx::Device) -> gfx::DescriptorSetLayout {
+    dev.create_descriptor_set_layout(
+        &[
+            pso::DescriptorSetLayoutBinding {
+                binding: 0,
+                ty: pso::DescriptorType::SampledImage,
+                count: 1,
+                stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                immutable_samplers: false,
+            },
+            pso::DescriptorSetLayoutBinding {
+                binding: 1,
+                ty: pso::DescriptorType::Sampler,
+                count: 1,
+                stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                immutable_samplers: false,
+            },
+        ],
+        &[],
+    )
+    .expect("could not create a descriptor set layout")
+}
+
+/// The render passes are compatible with each other.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum PassKind {
+    /// Renders to a swapchain image.
+    Window,
+    /// Renders to a layer that is sampled afterwards.
+    Layer,
+    /// Resumes `Layer` after its color was copied to a backdrop.
+    LayerResume,
+}
+
+/// With multisampling, the color is resolved into a third attachment,
+/// which receives the layouts of the single sampled color.
+unsafe fn create_render_pass(
+    dev: &gfx::Device,
+    (color_format, stencil_format): (Format, Format),
+    samples: u8,
+    kind: PassKind,
+) -> gfx::RenderPass {
+    let (load, color_layouts) = match kind {
+        PassKind::Window => (
+            pass::AttachmentLoadOp::Clear,
+            image::Layout::Undefined..image::Layout::Present,
+        ),
+        PassKind::Layer => (
+            pass::AttachmentLoadOp::Clear,
+            image::Layout::Undefined..image::Layout::ShaderReadOnlyOptimal,
+        ),
+        PassKind::LayerResume => (
+            pass::AttachmentLoadOp::Load,
+            image::Layout::TransferSrcOptimal..image::Layout::ShaderReadOnlyOptimal,
+        ),
+    };
+    // the clip state of a layer survives the copies
+    let (stencil_store, stencil_initial) = match kind {
+        PassKind::Window => (pass::AttachmentStoreOp::DontCare, image::Layout::Undefined),
+        PassKind::Layer => (pass::AttachmentStoreOp::Store, image::Layout::Undefined),
+        PassKind::LayerResume => (
+            pass::AttachmentStoreOp::Store,
+            image::Layout::DepthStencilAttachmentOptimal,
+        ),
+    };
+    let color = pass::Attachment {
+        format: Some(color_format),
+        samples: 1,
+        ops: pass::AttachmentOps::new(load, pass::AttachmentStoreOp::Store),
+        stencil_ops: pass::AttachmentOps::DONT_CARE,
+        layouts: color_layouts,
+    };
+    let (color, resolve) = if samples > 1 {
+        // the samples of a layer are kept for resuming it
+        let (ms_store, ms_initial) = match kind {
+            PassKind::Window => (pass::AttachmentStoreOp::DontCare, image::Layout::Undefined),
+            PassKind::Layer => (pass::AttachmentStoreOp::Store, image::Layout::Undefined),
+            PassKind::LayerResume => (
+                pass::AttachmentStoreOp::Store,
+                image::Layout::ColorAttachmentOptimal,
+            ),
+        };
+        let ms_color = pass::Attachment {
+            samples,
+            ops: pass::AttachmentOps::new(load, ms_store),
+            layouts: ms_initial..image::Layout::ColorAttachmentOptimal,
+            ..color.clone()
+        };
+        let resolve = pass::Attachment {
+            ops: pass::AttachmentOps::new(
+                pass::AttachmentLoadOp::DontCare,
+                pass::AttachmentStoreOp::Store,
+            ),
+            ..color
+        };
+        (ms_color, Some(resolve))
+    } else {
+        (color, None)
+    };
+    let stencil = pass::Attachment {
+        format: Some(stencil_format),
+        samples,
+        ops: pass::AttachmentOps::DONT_CARE,
+        stencil_ops: pass::AttachmentOps::new(load, stencil_store),
+        layouts: stencil_initial..image::Layout::DepthStencilAttachmentOptimal,
+    };
+    let subpass = pass::SubpassDesc {
+        colors: &[(0, image::Layout::ColorAttachmentOptimal)],
+        depth_stencil: Some(&(1, image::Layout::DepthStencilAttachmentOptimal)),
+        inputs: &[],
+        resolves: if resolve.is_some() {
+            &[(2, image::Layout::ColorAttachmentOptimal)]
+        } else {
+            &[]
+        },
+        preserves: &[],
+    };
+    // resuming overwrites the color that was just copied
+    let incoming = match kind {
+        PassKind::LayerResume => {
+            pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT | pso::PipelineStage::TRANSFER
+        }
+        _ => pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+    };
+    let mut dependencies = vec![pass::SubpassDependency {
+        passes: pass::SubpassRef::External..pass::SubpassRef::Pass(0),
+        stages: incoming..pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+        accesses: image::Access::empty()
+            ..(image::Access::COLOR_ATTACHMENT_READ | image::Access::COLOR_ATTACHMENT_WRITE),
+    }];
+    if kind != PassKind::Window {
+        // the target is sampled by the passes that follow
+        dependencies.push(pass::SubpassDependency {
+            passes: pass::SubpassRef::Pass(0)..pass::SubpassRef::External,
+            stages: pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT
+                ..pso::PipelineStage::FRAGMENT_SHADER,
+            accesses: image::Access::COLOR_ATTACHMENT_WRITE..image::Access::SHADER_READ,
+        });
+    }
+    let attachments: Vec<_> = vec![color, stencil].into_iter().chain(resolve).collect();
+    dev.create_render_pass(&attachments, &[subpass], &dependencies)
+        .expect("could not create a render pass")
+}
+
+/// The stencil state of a face, with static masks and reference.
+#[derive(Copy, Clone)]
+struct StencilFace {
+    fun: pso::Comparison,
+    reference: u32,
+    read_mask: u32,
+    write_mask: u32,
+    op_pass: pso::StencilOp,
+    op_fail: pso::StencilOp,
+}
+
+impl From<StencilFace> for pso::StencilFace {
+    fn from(f: StencilFace) -> pso::StencilFace {
+        pso::StencilFace {
+            fun: f.fun,
+            mask_read: pso::State::Static(f.read_mask),
+            mask_write: pso::State::Static(f.write_mask),
+            op_fail: f.op_fail,
+            op_depth_fail: f.op_fail,
+            op_pass: f.op_pass,
+            reference: pso::State::Static(f.reference),
+        }
+    }
+}
+
+/// Passes where the clip bit is set, without writing the stencil.
+pub(crate) fn clip_test() -> pso::DepthStencilDesc {
+    let face = StencilFace {
+        fun: pso::Comparison::Equal,
+        reference: CLIP_BIT,
+        read_mask: CLIP_BIT,
+        write_mask: 0,
+        op_pass: pso::StencilOp::Keep,
+        op_fail: pso::StencilOp::Keep,
+    };
+    stencil_test(face, face)
+}
+
+fn stencil_test(front: StencilFace, back: StencilFace) -> pso::DepthStencilDesc {
+    pso::DepthStencilDesc {
+        depth: pso::DepthTest::Off,
+        depth_bounds: false,
+        stencil: pso::StencilTest::On {
+            front: front.into(),
+            back: back.into(),
+        },
+    }
+}
+
+/// Fixed-function blending of pre-multiplied colors, if the mode allows it.
+fn blend_state(mode: BlendMode) -> Option<pso::BlendState> {
+    use crate::paint::BlendMode::*;
+    use gfx_hal::pso::Factor::*;
+    let (src, dst) = match mode {
+        Clear => (Zero, Zero),
+        Src => (One, Zero),
+        Dst => (Zero, One),
+        SrcOver => (One, OneMinusSrcAlpha),
+        DstOver => (OneMinusDstAlpha, One),
+        SrcIn => (DstAlpha, Zero),
+        DstIn => (Zero, SrcAlpha),
+        SrcOut => (OneMinusDstAlpha, Zero),
+        DstOut => (Zero, OneMinusSrcAlpha),
+        SrcAtop => (DstAlpha, OneMinusSrcAlpha),
+        DstAtop => (OneMinusDstAlpha, SrcAlpha),
+        Xor => (OneMinusDstAlpha, OneMinusSrcAlpha),
+        Plus => (One, One),
+        Screen => {
+            return Some(pso::BlendState::On {
+                color: pso::BlendOp::Add {
+                    src: One,
+                    dst: OneMinusSrcColor,
+                },
+                alpha: pso::BlendOp::Add {
+                    src: One,
+                    dst: OneMinusSrcAlpha,
+                },
+            });
+        }
+        _ => return None,
+    };
+    Some(pso::BlendState::On {
+        color: pso::BlendOp::Add { src, dst },
+        alpha: pso::BlendOp::Add { src, dst },
+    })
+}
+
+/// Creates a pipeline per fixed-function blend mode, indexed by `BlendMode`.
+fn fixed_function_variants<F>(mut create: F) -> Vec<Option<gfx::GraphicsPipeline>>
+where
+    F: FnMut(pso::BlendState) -> gfx::GraphicsPipeline,
+{
+    BlendMode::ALL
+        .iter()
+        .enumerate()
+        .map(|(i, &mode)| {
+            debug_assert_eq!(i, mode as usize);
+            blend_state(mode).map(&mut create)
+        })
+        .collect()
+}
+
+/// The vertex input of `Vertex` triangles.
+fn vertex_input() -> (pso::VertexBufferDesc, Vec<pso::AttributeDesc>) {
+    let buffer = pso::VertexBufferDesc {
+        binding: 0,
+        stride: mem::size_of::<Vertex>() as u32,
+        rate: 0,
+    };
+    let attributes = vec![
+        pso::AttributeDesc {
+            location: 0,
+            binding: 0,
+            element: pso::Element {
+                format: Format::Rg32Float,
+                offset: 0,
+            },
+        },
+        pso::AttributeDesc {
+            location: 1,
+            binding: 0,
+            element: pso::Element {
+                format: Format::R32Float,
+                offset: 8,
+            },
+        },
+    ];
+    (buffer, attributes)
+}
+
+/// A pipeline drawing `Vertex` triangles.
+/// Without fragment shader, only the stencil buffer is written.
+unsafe fn fill_pipeline(
+    dev: &gfx::Device,
+    cache: &gfx::PipelineCache,
+    layout: &gfx::PipelineLayout,
+    target: (&gfx::RenderPass, u8),
+    vs: &gfx::ShaderModule,
+    fs: Option<&gfx::ShaderModule>,
+    depth_stencil: pso::DepthStencilDesc,
+) -> gfx::GraphicsPipeline {
+    create_pipeline(
+        dev,
+        cache,
+        layout,
+        target,
+        (vs, fs),
+        PREMULTIPLIED,
+        depth_stencil,
+        Some(vertex_input()),
+    )
+}
+
+/// A pipeline drawing one quad per instance of `stride` bytes.
+unsafe fn instance_pipeline(
+    dev: &gfx::Device,
+    cache: &gfx::PipelineCache,
+    layout: &gfx::PipelineLayout,
+    target: (&gfx::RenderPass, u8),
+    (vs, fs): (&gfx::ShaderModule, &gfx::ShaderModule),
+    blend: pso::BlendState,
+    depth_stencil: pso::DepthStencilDesc,
+    stride: u32,
+) -> gfx::GraphicsPipeline {
+    // the instance is a sequence of vec4
+    let attributes = (0..stride / 16)
+        .map(|i| pso::AttributeDesc {
+            location: i,
+            binding: 0,
+            element: pso::Element {
+                format: Format::Rgba32Float,
+                offset: i * 16,
+            },
+        })
+        .collect();
+    let buffer = pso::VertexBufferDesc {
+        binding: 0,
+        stride,
+        rate: 1,
+    };
+    create_pipeline(
+        dev,
+        cache,
+        layout,
+        target,
+        (vs, Some(fs)),
+        blend,
+        depth_stencil,
+        Some((buffer, attributes)),
+    )
+}
+
+/// The target is a render pass and its number of samples per pixel.
+/// Without fragment shader, the color attachment is not written and `blend` is ignored.
+unsafe fn create_pipeline(
+    dev: &gfx::Device,
+    cache: &gfx::PipelineCache,
+    layout: &gfx::PipelineLayout,
+    target: (&gfx::RenderPass, u8),
+    shaders: (&gfx::ShaderModule, Option<&gfx::ShaderModule>),
+    blend: pso::BlendState,
+    depth_stencil: pso::DepthStencilDesc,
+    vertex_input: Option<(pso::VertexBufferDesc, Vec<pso::AttributeDesc>)>,
+) -> gfx::GraphicsPipeline {
+    try_create_pipeline(
+        dev,
+        cache,
+        layout,
+        target,
+        shaders,
+        blend,
+        depth_stencil,
+        vertex_input,
+    )
+    .expect("could not create a graphics pipeline")
+}
+
+/// Without vertex input, the vertex shader generates the vertices.
+unsafe fn try_create_pipeline(
+    dev: &gfx::Device,
+    cache: &gfx::PipelineCache,
+    layout: &gfx::PipelineLayout,
+    (render_pass, samples): (&gfx::RenderPass, u8),
+    (vs, fs): (&gfx::ShaderModule, Option<&gfx::ShaderModule>),
+    blend: pso::BlendState,
+    depth_stencil: pso::DepthStencilDesc,
+    vertex_input: Option<(pso::VertexBufferDesc, Vec<pso::AttributeDesc>)>,
+) -> Result<gfx::GraphicsPipeline, pso::CreationError> {
+    let entry = |module| pso::EntryPoint {
+        entry: "main",
+        module,
+        specialization: pso::Specialization::default(),
+    };
+    let shaders = pso::GraphicsShaderSet {
+        vertex: entry(vs),
+        hull: None,
+        domain: None,
+        geometry: None,
+        fragment: fs.map(entry),
+    };
+    let subpass = pass::Subpass {
+        index: 0,
+        main_pass: render_pass,
+    };
+    let mut desc = pso::GraphicsPipelineDesc::new(
+        shaders,
+        pso::Primitive::TriangleList,
+        pso::Rasterizer::FILL,
+        layout,
+        subpass,
+    );
+    desc.blender.targets.push(if fs.is_some() {
+        pso::ColorBlendDesc(pso::ColorMask::ALL, blend)
+    } else {
+        pso::ColorBlendDesc(pso::ColorMask::empty(), pso::BlendState::Off)
+    });
+    desc.depth_stencil = depth_stencil;
+    if samples > 1 {
+        desc.multisampling = Some(pso::Multisampling {
+            rasterization_samples: samples,
+            sample_shading: None,
+            sample_mask: !0,
+            alpha_coverage: false,
+            alpha_to_one: false,
+        });
+    }
+    if let Some((vertex_buffer, attributes)) = vertex_input {
+        desc.vertex_buffers.push(vertex_buffer);
+        desc.attributes = attributes;
+    }
+    dev.create_graphics_pipeline(&desc, Some(cache))
+}
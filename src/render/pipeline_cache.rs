@@ -0,0 +1,213 @@
+use crate::gfx;
+use gfx_hal::{AdapterInfo, Device};
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+/// Identifies the files written by `PipelineCache`.
+const MAGIC: &[u8; 8] = b"OWSPSO\r\n";
+
+/// Version of the file layout.
+const VERSION: u32 = 1;
+
+/// Size of the header that Vulkan puts in front of the cache data.
+/// It holds the vendor and device ids, and a UUID that changes with the driver.
+const DRIVER_HEADER_LEN: usize = 32;
+
+/// The pipeline cache of the device, loaded from and saved to a file of the cache directory.
+/// The file is ignored if it was written for another adapter, driver or version of the crate,
+/// or if it is corrupt.
+pub(crate) struct PipelineCache {
+    pub cache: gfx::PipelineCache,
+    path: Option<PathBuf>,
+    identity: Vec<u8>,
+    /// Checksum of the data last loaded or saved.
+    saved: Cell<u64>,
+}
+
+impl PipelineCache {
+    /// Without `dir`, the cache only lives as long as the device.
+    pub fn new(dev: &gfx::Device, info: &AdapterInfo, dir: Option<&Path>) -> PipelineCache {
+        let identity = format!(
+            "{}\n{:x}:{:x}\n{}",
+            info.name,
+            info.vendor,
+            info.device,
+            env!("CARGO_PKG_VERSION")
+        )
+        .into_bytes();
+        let path = dir.map(|dir| {
+            dir.join(format!("pipelines-{:04x}-{:04x}.bin", info.vendor, info.device))
+        });
+        // the header of an empty cache identifies the running driver
+        let driver = unsafe {
+            let empty = dev
+                .create_pipeline_cache(None)
+                .expect("could not create a pipeline cache");
+            let data = dev.get_pipeline_cache_data(&empty).unwrap_or_default();
+            dev.destroy_pipeline_cache(empty);
+            data
+        };
+        let data = path
+            .as_ref()
+            .and_then(|path| load(path, &identity, driver_header(&driver)));
+        let cache = data
+            .as_ref()
+            .and_then(|data| unsafe { dev.create_pipeline_cache(Some(data)) }.ok());
+        let cache = match cache {
+            Some(cache) => cache,
+            None => unsafe { dev.create_pipeline_cache(None) }
+                .expect("could not create a pipeline cache"),
+        };
+        PipelineCache {
+            cache,
+            path,
+            identity,
+            saved: Cell::new(data.map_or(0, |data| checksum(&data))),
+        }
+    }
+
+    /// Writes the cache to its file if pipelines were added since it was loaded or saved.
+    pub fn save(&self, dev: &gfx::Device) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let data = match unsafe { dev.get_pipeline_cache_data(&self.cache) } {
+            Ok(data) => data,
+            Err(err) => {
+                println!("could not get the pipeline cache data: {:?}", err);
+                return;
+            }
+        };
+        let sum = checksum(&data);
+        if sum == self.saved.get() {
+            return;
+        }
+        match write(path, &encode(&self.identity, &data)) {
+            Ok(()) => self.saved.set(sum),
+            Err(err) => println!("could not save the pipeline cache {}: {}", path.display(), err),
+        }
+    }
+
+    pub fn destroy(self, dev: &gfx::Device) {
+        unsafe {
+            dev.destroy_pipeline_cache(self.cache);
+        }
+    }
+}
+
+/// The cache data of the file at `path`, if it can be used with the `driver` header.
+fn load(path: &Path, identity: &[u8], driver: &[u8]) -> Option<Vec<u8>> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return None,
+        Err(err) => {
+            println!("could not read the pipeline cache {}: {}", path.display(), err);
+            return None;
+        }
+    };
+    match decode(&bytes, identity) {
+        Ok(data) if driver_header(data) == driver => Some(data.to_vec()),
+        Ok(_) => {
+            println!("pipeline cache {} was written by another driver", path.display());
+            None
+        }
+        Err(err) => {
+            println!("ignoring the pipeline cache {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+/// Replaces the file at once, so that a crash does not leave it half written.
+fn write(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, bytes)?;
+    fs::rename(&tmp, path)
+}
+
+fn driver_header(data: &[u8]) -> &[u8] {
+    &data[..data.len().min(DRIVER_HEADER_LEN)]
+}
+
+fn encode(identity: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(MAGIC.len() + 24 + identity.len() + data.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(identity.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(identity);
+    bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&checksum(data).to_le_bytes());
+    bytes.extend_from_slice(data);
+    bytes
+}
+
+/// The cache data of a file written for `identity`.
+fn decode<'a>(bytes: &'a [u8], identity: &[u8]) -> Result<&'a [u8], String> {
+    let mut rest = bytes;
+    if split(&mut rest, MAGIC.len())? != MAGIC {
+        return Err("not a pipeline cache".into());
+    }
+    if read_u32(&mut rest)? != VERSION {
+        return Err("unknown version".into());
+    }
+    let len = read_u32(&mut rest)? as usize;
+    if split(&mut rest, len)? != identity {
+        return Err("written for another adapter or version".into());
+    }
+    let len = read_u64(&mut rest)?;
+    let sum = read_u64(&mut rest)?;
+    if rest.len() as u64 != len {
+        return Err("unexpected size".into());
+    }
+    if checksum(rest) != sum {
+        return Err("checksum mismatch".into());
+    }
+    Ok(rest)
+}
+
+fn split<'a>(rest: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+    if rest.len() < len {
+        return Err("truncated".into());
+    }
+    let (head, tail) = rest.split_at(len);
+    *rest = tail;
+    Ok(head)
+}
+
+fn read_u32(rest: &mut &[u8]) -> Result<u32, String> {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(split(rest, 4)?);
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(rest: &mut &[u8]) -> Result<u64, String> {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(split(rest, 8)?);
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// FNV-1a.
+fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[test]
+fn cache_file() {
+    let data: Vec<u8> = (0..100).collect();
+    let bytes = encode(b"adapter", &data);
+    assert_eq!(Ok(&data[..]), decode(&bytes, b"adapter"));
+    assert!(decode(&bytes, b"other adapter").is_err());
+    assert!(decode(&bytes[..bytes.len() - 1], b"adapter").is_err());
+    assert!(decode(&bytes[..4], b"adapter").is_err());
+
+    let mut corrupt = bytes.clone();
+    *corrupt.last_mut().unwrap() ^= 1;
+    assert_eq!(Err("checksum mismatch".into()), decode(&corrupt, b"adapter"));
+}
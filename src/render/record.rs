@@ -0,0 +1,1268 @@
+use super::animation::{Animation, Easing, Keyframe, Property, TransformValue};
+use super::effect::{Effect, EffectHandle, EFFECT_UNIFORMS};
+use super::frame::{
+    Border, BoxShadow, Clip, Cmd, EffectDraw, Frame, GlyphRun, ImageDraw, Layer, PositionedGlyph,
+};
+use super::images::{Filter, ImageHandle};
+use super::resource::{
+    GradientHandle, Handle, Lazy, LoadState, PathHandle, ResourceId, SharedGradient, SharedPath,
+};
+use super::Thread;
+use crate::geom::{FCorners, FMargins, FPoint, FRect, IRect, Margins, Transform};
+use crate::image::Image;
+use crate::paint::{BlendMode, Color, ExtendMode, Gradient, GradientKind, GradientStop, Paint};
+use crate::path::{FillRule, Path, Segment};
+use crate::text::Font;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Write};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+use winit::WindowId;
+
+const MAGIC: &[u8] = b"OWSREC";
+/// Bumped at each change of the encoding, older recordings are rejected.
+const VERSION: u32 = 1;
+
+// kinds of the records following the header, each prefixed by its kind and its size
+const IMAGE: u8 = 0;
+const PATH: u8 = 1;
+const EFFECT: u8 = 2;
+const FONT: u8 = 3;
+const FRAME: u8 = 4;
+const GRADIENT: u8 = 5;
+
+/// Writes frames, and the resources they draw, to a binary recording that `Replay` reads.
+///
+/// The resources are written before the first frame that draws them: the images with their
+/// pixels, the shared paths and gradients, the effects with their SPIR-V and the fonts with
+/// their file.
+/// An image that was still loading is written again once loaded.
+/// The custom callbacks cannot be recorded, only their rectangle is, and they are not replayed.
+pub struct Recorder<W: Write> {
+    out: W,
+    windows: Vec<WindowId>,
+    start: Instant,
+    written: Written,
+}
+
+/// The resources of the recording, with the load state of the images.
+#[derive(Default)]
+struct Written {
+    images: HashMap<ResourceId, u8>,
+    paths: HashSet<ResourceId>,
+    effects: HashSet<ResourceId>,
+    gradients: HashSet<ResourceId>,
+    fonts: HashSet<u64>,
+}
+
+impl<W: Write> Recorder<W> {
+    /// Starts a recording of the frames of `windows`, which are replayed in as many windows.
+    pub fn new(mut out: W, windows: &[WindowId]) -> io::Result<Recorder<W>> {
+        let mut header = MAGIC.to_vec();
+        header.u32(VERSION);
+        header.u32(windows.len() as u32);
+        out.write_all(&header)?;
+        Ok(Recorder {
+            out,
+            windows: windows.to_vec(),
+            start: Instant::now(),
+            written: Written::default(),
+        })
+    }
+
+    /// Writes `frame`, typically right before it is sent to the render thread.
+    /// The animations are recorded relative to now.
+    pub fn record(&mut self, frame: &Frame) -> io::Result<()> {
+        let window = self
+            .windows
+            .iter()
+            .position(|w| *w == frame.window)
+            .ok_or_else(|| invalid_input("the window of the frame is not recorded"))?;
+        let records = self.encode(window, frame.viewport, frame.clear_color, &frame.cmds);
+        self.out.write_all(&records)
+    }
+
+    /// The record of a frame, preceded by the ones of the resources it draws first.
+    fn encode(
+        &mut self,
+        window: usize,
+        viewport: IRect,
+        clear_color: Option<Color>,
+        cmds: &[Cmd],
+    ) -> Vec<u8> {
+        let now = Instant::now();
+        let mut enc = Encoder {
+            buf: Vec::new(),
+            defs: Vec::new(),
+            now,
+            written: &mut self.written,
+        };
+        enc.buf.u64(duration_nanos(now - self.start));
+        enc.buf.u32(window as u32);
+        enc.buf.irect(viewport);
+        enc.buf.u8(clear_color.is_some() as u8);
+        enc.buf.color(clear_color.unwrap_or_default());
+        enc.cmds(cmds);
+        let Encoder { buf, mut defs, .. } = enc;
+        put_record(&mut defs, FRAME, &buf);
+        defs
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// A frame read from a recording.
+pub struct Recorded {
+    /// Since the start of the recording.
+    pub time: Duration,
+    /// Index of the window in the ones given to the `Recorder`.
+    pub window: usize,
+    pub viewport: IRect,
+    pub clear_color: Option<[f32; 4]>,
+    pub cmds: Vec<Cmd>,
+}
+
+impl Recorded {
+    pub fn into_frame(self, window: WindowId) -> Frame {
+        Frame {
+            window,
+            viewport: self.viewport,
+            clear_color: self.clear_color,
+            cmds: self.cmds,
+        }
+    }
+}
+
+/// Reads the frames of a recording, and recreates the resources they draw.
+pub struct Replay<R: Read> {
+    input: R,
+    windows: usize,
+    images: HashMap<u64, ImageHandle>,
+    paths: HashMap<u64, PathHandle>,
+    effects: HashMap<u64, EffectHandle>,
+    gradients: HashMap<u64, GradientHandle>,
+    fonts: HashMap<u64, Font>,
+}
+
+impl<R: Read> Replay<R> {
+    /// Fails if `input` is not a recording, or was written by another version.
+    pub fn new(mut input: R) -> io::Result<Replay<R>> {
+        let mut header = [0u8; 14];
+        input.read_exact(&mut header)?;
+        let mut rest = &header[..];
+        if rest.next_bytes(MAGIC.len())? != MAGIC {
+            return Err(invalid_data("not a recording"));
+        }
+        if rest.u32()? != VERSION {
+            return Err(invalid_data("recorded by another version"));
+        }
+        let windows = rest.u32()? as usize;
+        Ok(Replay {
+            input,
+            windows,
+            images: HashMap::new(),
+            paths: HashMap::new(),
+            effects: HashMap::new(),
+            gradients: HashMap::new(),
+            fonts: HashMap::new(),
+        })
+    }
+
+    /// Number of windows of the recording.
+    pub fn windows(&self) -> usize {
+        self.windows
+    }
+
+    /// The next frame, None at the end of the recording.
+    /// Its resources are resolved by `thread`, and its animations start relative to now.
+    pub fn next(&mut self, thread: &Thread) -> io::Result<Option<Recorded>> {
+        self.next_frame(&thread.releases)
+    }
+
+    fn next_frame(&mut self, releases: &mpsc::Sender<ResourceId>) -> io::Result<Option<Recorded>> {
+        loop {
+            let mut kind = [0u8];
+            match self.input.read_exact(&mut kind) {
+                Ok(()) => {}
+                Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(err) => return Err(err),
+            }
+            let mut len = [0u8; 4];
+            self.input.read_exact(&mut len)?;
+            let len = u64::from(u32::from_le_bytes(len));
+            let mut payload = Vec::new();
+            if (&mut self.input).take(len).read_to_end(&mut payload)? as u64 != len {
+                return Err(invalid_data("truncated"));
+            }
+            let mut rest = &payload[..];
+            match kind[0] {
+                IMAGE => self.read_image(&mut rest, releases)?,
+                PATH => {
+                    let id = rest.u64()?;
+                    let path = rest.path()?;
+                    let handle = Handle::new(SharedPath::new(path), releases.clone());
+                    self.paths.insert(id, handle);
+                }
+                EFFECT => {
+                    let id = rest.u64()?;
+                    let inputs = rest.u32()? as usize;
+                    let spirv = rest.blob()?;
+                    Effect::check(spirv, inputs).map_err(|msg| invalid_data(&msg))?;
+                    let effect = Effect::new(spirv, inputs);
+                    self.effects
+                        .insert(id, Handle::new(effect, releases.clone()));
+                }
+                GRADIENT => {
+                    let id = rest.u64()?;
+                    let gradient = SharedGradient::new(rest.gradient()?);
+                    self.gradients
+                        .insert(id, Handle::new(gradient, releases.clone()));
+                }
+                FONT => {
+                    let id = rest.u64()?;
+                    let index = rest.u32()?;
+                    let font = Font::from_bytes(rest.blob()?.to_vec(), index)?;
+                    self.fonts.insert(id, font);
+                }
+                FRAME => {
+                    let now = Instant::now();
+                    let time = Duration::from_nanos(rest.u64()?);
+                    let window = rest.u32()? as usize;
+                    if window >= self.windows {
+                        return Err(invalid_data("frame of an unknown window"));
+                    }
+                    let viewport = rest.irect()?;
+                    let clear = rest.u8()? != 0;
+                    let color = rest.color()?;
+                    let cmds = Decoder { replay: self, now }.cmds(&mut rest)?;
+                    return Ok(Some(Recorded {
+                        time,
+                        window,
+                        viewport,
+                        clear_color: if clear { Some(color) } else { None },
+                        cmds,
+                    }));
+                }
+                _ => return Err(invalid_data("unknown record")),
+            }
+        }
+    }
+
+    /// Defines an image, or updates the load state of a defined one.
+    fn read_image(
+        &mut self,
+        rest: &mut &[u8],
+        releases: &mpsc::Sender<ResourceId>,
+    ) -> io::Result<()> {
+        let id = rest.u64()?;
+        let state = match rest.u8()? {
+            LOADING => LoadState::Loading,
+            READY => {
+                let (width, height) = (rest.u32()?, rest.u32()?);
+                let rgba = rest.blob()?;
+                if u64::from(width) * u64::from(height) * 4 != rgba.len() as u64 {
+                    return Err(invalid_data("image data does not match its size"));
+                }
+                let image = Image::from_premultiplied(width, height, rgba.to_vec());
+                LoadState::Ready(Arc::new(image))
+            }
+            FAILED => LoadState::Failed(String::from_utf8_lossy(rest.blob()?).into_owned()),
+            _ => return Err(invalid_data("unknown image state")),
+        };
+        match self.images.get(&id) {
+            Some(handle) => handle.set(state),
+            None => {
+                let lazy = Lazy::loading();
+                lazy.set(state);
+                self.images.insert(id, Handle::new(lazy, releases.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+// load states of the images
+const LOADING: u8 = 0;
+const READY: u8 = 1;
+const FAILED: u8 = 2;
+
+struct Encoder<'a> {
+    buf: Vec<u8>,
+    /// Records of the resources not written yet, which precede the frame.
+    defs: Vec<u8>,
+    now: Instant,
+    written: &'a mut Written,
+}
+
+impl<'a> Encoder<'a> {
+    fn cmds(&mut self, cmds: &[Cmd]) {
+        self.buf.u32(cmds.len() as u32);
+        for cmd in cmds {
+            self.cmd(cmd);
+        }
+    }
+
+    fn cmd(&mut self, cmd: &Cmd) {
+        match cmd {
+            Cmd::PushTransform(transform) => {
+                self.buf.u8(0);
+                self.buf.transform(transform);
+            }
+            Cmd::PopTransform => self.buf.u8(1),
+            Cmd::PushClip(clip) => {
+                self.buf.u8(2);
+                self.clip(clip);
+            }
+            Cmd::PopClip => self.buf.u8(3),
+            Cmd::PushLayer(layer) => {
+                self.buf.u8(4);
+                self.layer(layer);
+            }
+            Cmd::PopLayer => self.buf.u8(5),
+            Cmd::BlendMode(mode) => {
+                self.buf.u8(6);
+                self.buf.blend_mode(*mode);
+            }
+            Cmd::Fill {
+                path,
+                fill_rule,
+                paint,
+            } => {
+                self.buf.u8(7);
+                self.buf.path(path);
+                self.buf.fill_rule(*fill_rule);
+                self.paint(paint);
+            }
+            Cmd::FillShared {
+                path,
+                fill_rule,
+                paint,
+            } => {
+                self.buf.u8(8);
+                self.shared_path(path);
+                self.buf.fill_rule(*fill_rule);
+                self.paint(paint);
+            }
+            Cmd::RoundedRect { rect, radii, paint } => {
+                self.buf.u8(9);
+                self.buf.rect(*rect);
+                self.buf.corners(*radii);
+                self.paint(paint);
+            }
+            Cmd::Border(border) => {
+                self.buf.u8(10);
+                self.buf.rect(border.rect);
+                self.buf.corners(border.radii);
+                self.buf.margins(border.widths);
+                let c = &border.colors;
+                for color in &[c.l, c.r, c.t, c.b] {
+                    self.buf.color(*color);
+                }
+            }
+            Cmd::BoxShadow(shadow) => {
+                self.buf.u8(11);
+                self.buf.rect(shadow.rect);
+                self.buf.corners(shadow.radii);
+                self.buf.point(shadow.offset);
+                self.buf.f32(shadow.blur);
+                self.buf.f32(shadow.spread);
+                self.buf.color(shadow.color);
+                self.buf.u8(shadow.inset as u8);
+            }
+            Cmd::GlyphRun(run) => {
+                self.buf.u8(12);
+                self.font(&run.font);
+                self.buf.f32(run.size);
+                self.buf.u32(run.glyphs.len() as u32);
+                for glyph in &run.glyphs {
+                    self.buf.u32(u32::from(glyph.id));
+                    self.buf.point(glyph.pos);
+                }
+                self.paint(&run.paint);
+            }
+            Cmd::Image(draw) => {
+                self.buf.u8(13);
+                self.image(&draw.image);
+                self.buf.rect(draw.rect);
+                self.buf.u8(draw.src.is_some() as u8);
+                self.buf.rect(
+                    draw.src
+                        .unwrap_or_else(|| FRect::new(0f32, 0f32, 0f32, 0f32)),
+                );
+                self.buf.u8(match draw.filter {
+                    Filter::Nearest => 0,
+                    Filter::Linear => 1,
+                });
+                self.buf.u8(draw.nine_patch.is_some() as u8);
+                let none = FMargins::new(0f32, 0f32, 0f32, 0f32);
+                self.buf.margins(draw.nine_patch.unwrap_or(none));
+                self.buf.f32(draw.opacity);
+            }
+            Cmd::Effect { rect, effect } => {
+                self.buf.u8(14);
+                self.buf.rect(*rect);
+                self.effect(effect);
+            }
+            Cmd::BackdropBlur { rect, radius } => {
+                self.buf.u8(15);
+                self.buf.rect(*rect);
+                self.buf.f32(*radius);
+            }
+            Cmd::Custom { rect, .. } => {
+                self.buf.u8(16);
+                self.buf.rect(*rect);
+            }
+        }
+    }
+
+    fn paint(&mut self, paint: &Paint) {
+        match paint {
+            Paint::Solid(color) => {
+                self.buf.u8(0);
+                self.buf.color(*color);
+            }
+            Paint::Gradient(g) => {
+                self.buf.u8(1);
+                self.buf.gradient(g);
+            }
+            Paint::SharedGradient(g) => {
+                if self.written.gradients.insert(g.id()) {
+                    let mut def = Vec::new();
+                    def.u64(g.id().0);
+                    def.gradient(g.gradient());
+                    put_record(&mut self.defs, GRADIENT, &def);
+                }
+                self.buf.u8(2);
+                self.buf.u64(g.id().0);
+            }
+        }
+    }
+
+    fn clip(&mut self, clip: &Clip) {
+        match clip {
+            Clip::Rect(rect) => {
+                self.buf.u8(0);
+                self.buf.rect(*rect);
+            }
+            Clip::RoundedRect { rect, radii } => {
+                self.buf.u8(1);
+                self.buf.rect(*rect);
+                self.buf.corners(*radii);
+            }
+            Clip::Path { path, fill_rule } => {
+                self.buf.u8(2);
+                self.buf.path(path);
+                self.buf.fill_rule(*fill_rule);
+            }
+        }
+    }
+
+    fn layer(&mut self, layer: &Layer) {
+        self.buf.f32(layer.opacity);
+        self.buf.blend_mode(layer.blend_mode);
+        self.buf.u8(layer.mask.is_some() as u8);
+        if let Some(mask) = &layer.mask {
+            self.cmds(mask);
+        }
+        self.buf.u8(layer.effect.is_some() as u8);
+        if let Some(effect) = &layer.effect {
+            self.effect(effect);
+        }
+        self.buf.f32(layer.blur);
+        self.buf.u32(layer.animations.len() as u32);
+        for anim in &layer.animations {
+            self.animation(anim);
+        }
+    }
+
+    fn animation(&mut self, anim: &Animation) {
+        match &anim.property {
+            Property::Opacity(keyframes) => {
+                self.buf.u8(0);
+                self.buf.keyframes(keyframes, |buf, v| buf.f32(*v));
+            }
+            Property::Transform { origin, keyframes } => {
+                self.buf.u8(1);
+                self.buf.point(*origin);
+                self.buf.keyframes(keyframes, |buf, v| {
+                    buf.point(v.translate);
+                    buf.f32(v.rotate);
+                    buf.point(v.scale);
+                });
+            }
+            Property::ScrollOffset(keyframes) => {
+                self.buf.u8(2);
+                self.buf.keyframes(keyframes, |buf, v| buf.point(*v));
+            }
+        }
+        // relative to the frame, the start may be in the past or in the future
+        let start = match anim.start.checked_duration_since(self.now) {
+            Some(ahead) => duration_nanos(ahead) as i64,
+            None => -(duration_nanos(self.now - anim.start) as i64),
+        };
+        self.buf.u64(start as u64);
+        self.buf.u8(anim.iterations.is_some() as u8);
+        self.buf.u32(anim.iterations.unwrap_or(0));
+    }
+
+    fn effect(&mut self, draw: &EffectDraw) {
+        let effect = &draw.effect;
+        if self.written.effects.insert(effect.id()) {
+            let mut def = Vec::new();
+            def.u64(effect.id().0);
+            def.u32(effect.inputs() as u32);
+            def.blob(effect.spirv());
+            put_record(&mut self.defs, EFFECT, &def);
+        }
+        self.buf.u64(effect.id().0);
+        for u in &draw.uniforms {
+            self.buf.f32(*u);
+        }
+        self.buf.u32(draw.inputs.len() as u32);
+        for input in &draw.inputs {
+            self.image(input);
+        }
+    }
+
+    fn image(&mut self, image: &ImageHandle) {
+        let state = image.state();
+        let kind = match state {
+            LoadState::Loading => LOADING,
+            LoadState::Ready(_) => READY,
+            LoadState::Failed(_) => FAILED,
+        };
+        if self.written.images.insert(image.id(), kind) != Some(kind) {
+            let mut def = Vec::new();
+            def.u64(image.id().0);
+            def.u8(kind);
+            match state {
+                LoadState::Loading => {}
+                LoadState::Ready(image) => {
+                    def.u32(image.width());
+                    def.u32(image.height());
+                    def.blob(image.rgba());
+                }
+                LoadState::Failed(msg) => def.blob(msg.as_bytes()),
+            }
+            put_record(&mut self.defs, IMAGE, &def);
+        }
+        self.buf.u64(image.id().0);
+    }
+
+    fn shared_path(&mut self, path: &PathHandle) {
+        if self.written.paths.insert(path.id()) {
+            let mut def = Vec::new();
+            def.u64(path.id().0);
+            def.path(path.path());
+            put_record(&mut self.defs, PATH, &def);
+        }
+        self.buf.u64(path.id().0);
+    }
+
+    fn font(&mut self, font: &Font) {
+        if self.written.fonts.insert(font.id()) {
+            let mut def = Vec::new();
+            def.u64(font.id());
+            def.u32(font.index());
+            def.blob(font.bytes());
+            put_record(&mut self.defs, FONT, &def);
+        }
+        self.buf.u64(font.id());
+    }
+}
+
+struct Decoder<'a, R: Read> {
+    replay: &'a Replay<R>,
+    now: Instant,
+}
+
+impl<'a, R: Read> Decoder<'a, R> {
+    fn cmds(&self, rest: &mut &[u8]) -> io::Result<Vec<Cmd>> {
+        let mut cmds = Vec::new();
+        for _ in 0..rest.u32()? {
+            if let Some(cmd) = self.cmd(rest)? {
+                cmds.push(cmd);
+            }
+        }
+        Ok(cmds)
+    }
+
+    /// None for the custom callbacks, which are skipped.
+    fn cmd(&self, rest: &mut &[u8]) -> io::Result<Option<Cmd>> {
+        let cmd = match rest.u8()? {
+            0 => Cmd::PushTransform(rest.transform()?),
+            1 => Cmd::PopTransform,
+            2 => Cmd::PushClip(match rest.u8()? {
+                0 => Clip::Rect(rest.rect()?),
+                1 => Clip::RoundedRect {
+                    rect: rest.rect()?,
+                    radii: rest.corners()?,
+                },
+                2 => Clip::Path {
+                    path: rest.path()?,
+                    fill_rule: rest.fill_rule()?,
+                },
+                _ => return Err(invalid_data("unknown clip")),
+            }),
+            3 => Cmd::PopClip,
+            4 => Cmd::PushLayer(self.layer(rest)?),
+            5 => Cmd::PopLayer,
+            6 => Cmd::BlendMode(rest.blend_mode()?),
+            7 => Cmd::Fill {
+                path: rest.path()?,
+                fill_rule: rest.fill_rule()?,
+                paint: self.paint(rest)?,
+            },
+            8 => Cmd::FillShared {
+                path: resolve(&self.replay.paths, rest.u64()?)?.clone(),
+                fill_rule: rest.fill_rule()?,
+                paint: self.paint(rest)?,
+            },
+            9 => Cmd::RoundedRect {
+                rect: rest.rect()?,
+                radii: rest.corners()?,
+                paint: self.paint(rest)?,
+            },
+            10 => Cmd::Border(Border {
+                rect: rest.rect()?,
+                radii: rest.corners()?,
+                widths: rest.margins()?,
+                colors: Margins::new(rest.color()?, rest.color()?, rest.color()?, rest.color()?),
+            }),
+            11 => Cmd::BoxShadow(BoxShadow {
+                rect: rest.rect()?,
+                radii: rest.corners()?,
+                offset: rest.point()?,
+                blur: rest.f32()?,
+                spread: rest.f32()?,
+                color: rest.color()?,
+                inset: rest.u8()? != 0,
+            }),
+            12 => {
+                let font = resolve(&self.replay.fonts, rest.u64()?)?.clone();
+                let size = rest.f32()?;
+                let mut glyphs = Vec::new();
+                for _ in 0..rest.u32()? {
+                    let id = rest.u32()? as u16;
+                    glyphs.push(PositionedGlyph {
+                        id,
+                        pos: rest.point()?,
+                    });
+                }
+                Cmd::GlyphRun(GlyphRun {
+                    font,
+                    size,
+                    glyphs,
+                    paint: self.paint(rest)?,
+                })
+            }
+            13 => {
+                let image = self.image(rest)?;
+                let rect = rest.rect()?;
+                let has_src = rest.u8()? != 0;
+                let src = rest.rect()?;
+                let filter = match rest.u8()? {
+                    0 => Filter::Nearest,
+                    1 => Filter::Linear,
+                    _ => return Err(invalid_data("unknown filter")),
+                };
+                let has_nine_patch = rest.u8()? != 0;
+                let nine_patch = rest.margins()?;
+                Cmd::Image(ImageDraw {
+                    image,
+                    rect,
+                    src: if has_src { Some(src) } else { None },
+                    filter,
+                    nine_patch: if has_nine_patch {
+                        Some(nine_patch)
+                    } else {
+                        None
+                    },
+                    opacity: rest.f32()?,
+                })
+            }
+            14 => Cmd::Effect {
+                rect: rest.rect()?,
+                effect: self.effect(rest)?,
+            },
+            15 => Cmd::BackdropBlur {
+                rect: rest.rect()?,
+                radius: rest.f32()?,
+            },
+            16 => {
+                rest.rect()?;
+                return Ok(None);
+            }
+            _ => return Err(invalid_data("unknown command")),
+        };
+        Ok(Some(cmd))
+    }
+
+    fn paint(&self, rest: &mut &[u8]) -> io::Result<Paint> {
+        match rest.u8()? {
+            0 => Ok(Paint::Solid(rest.color()?)),
+            1 => Ok(Paint::Gradient(rest.gradient()?)),
+            2 => {
+                let gradient = resolve(&self.replay.gradients, rest.u64()?)?;
+                Ok(Paint::SharedGradient(gradient.clone()))
+            }
+            _ => Err(invalid_data("unknown paint")),
+        }
+    }
+
+    fn layer(&self, rest: &mut &[u8]) -> io::Result<Layer> {
+        let mut layer = Layer::new(rest.f32()?).with_blend_mode(rest.blend_mode()?);
+        if rest.u8()? != 0 {
+            layer.mask = Some(self.cmds(rest)?);
+        }
+        if rest.u8()? != 0 {
+            layer.effect = Some(self.effect(rest)?);
+        }
+        layer.blur = rest.f32()?;
+        for _ in 0..rest.u32()? {
+            layer.animations.push(self.animation(rest)?);
+        }
+        Ok(layer)
+    }
+
+    fn animation(&self, rest: &mut &[u8]) -> io::Result<Animation> {
+        let property = match rest.u8()? {
+            0 => Property::Opacity(rest.keyframes(|rest| rest.f32())?),
+            1 => Property::Transform {
+                origin: rest.point()?,
+                keyframes: rest.keyframes(|rest| {
+                    Ok(TransformValue {
+                        translate: rest.point()?,
+                        rotate: rest.f32()?,
+                        scale: rest.point()?,
+                    })
+                })?,
+            },
+            2 => Property::ScrollOffset(rest.keyframes(|rest| rest.point())?),
+            _ => return Err(invalid_data("unknown animated property")),
+        };
+        let start = rest.u64()? as i64;
+        let offset = Duration::from_nanos(start.unsigned_abs());
+        let start = if start < 0 {
+            self.now.checked_sub(offset)
+        } else {
+            self.now.checked_add(offset)
+        };
+        let has_iterations = rest.u8()? != 0;
+        let iterations = rest.u32()?;
+        Ok(Animation {
+            property,
+            start: start.unwrap_or(self.now),
+            iterations: if has_iterations {
+                Some(iterations)
+            } else {
+                None
+            },
+        })
+    }
+
+    fn effect(&self, rest: &mut &[u8]) -> io::Result<EffectDraw> {
+        let effect = resolve(&self.replay.effects, rest.u64()?)?.clone();
+        let mut uniforms = [0f32; EFFECT_UNIFORMS];
+        for u in &mut uniforms {
+            *u = rest.f32()?;
+        }
+        let mut inputs = Vec::new();
+        for _ in 0..rest.u32()? {
+            inputs.push(self.image(rest)?);
+        }
+        Ok(EffectDraw {
+            effect,
+            uniforms,
+            inputs,
+        })
+    }
+
+    fn image(&self, rest: &mut &[u8]) -> io::Result<ImageHandle> {
+        resolve(&self.replay.images, rest.u64()?).cloned()
+    }
+}
+
+fn resolve<T>(resources: &HashMap<u64, T>, id: u64) -> io::Result<&T> {
+    resources
+        .get(&id)
+        .ok_or_else(|| invalid_data("undefined resource"))
+}
+
+fn put_record(out: &mut Vec<u8>, kind: u8, payload: &[u8]) {
+    out.u8(kind);
+    out.u32(payload.len() as u32);
+    out.extend_from_slice(payload);
+}
+
+fn duration_nanos(d: Duration) -> u64 {
+    d.as_nanos().min(u128::from(u64::MAX)) as u64
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+/// Little-endian encoding of the values of the display list.
+trait Put {
+    fn u8(&mut self, v: u8);
+    fn u32(&mut self, v: u32);
+    fn u64(&mut self, v: u64);
+    fn blob(&mut self, v: &[u8]);
+
+    fn f32(&mut self, v: f32) {
+        self.u32(v.to_bits());
+    }
+
+    fn point(&mut self, p: FPoint) {
+        self.f32(p.x);
+        self.f32(p.y);
+    }
+
+    fn rect(&mut self, r: FRect) {
+        for v in &[r.x, r.y, r.w, r.h] {
+            self.f32(*v);
+        }
+    }
+
+    fn irect(&mut self, r: IRect) {
+        for v in &[r.x, r.y, r.w, r.h] {
+            self.u32(*v as u32);
+        }
+    }
+
+    fn corners(&mut self, c: FCorners) {
+        for v in &[c.tl, c.tr, c.br, c.bl] {
+            self.f32(*v);
+        }
+    }
+
+    fn margins(&mut self, m: FMargins) {
+        for v in &[m.l, m.r, m.t, m.b] {
+            self.f32(*v);
+        }
+    }
+
+    fn color(&mut self, c: Color) {
+        for v in &c {
+            self.f32(*v);
+        }
+    }
+
+    fn transform(&mut self, t: &Transform) {
+        for v in &[t.m11, t.m12, t.m21, t.m22, t.m31, t.m32] {
+            self.f32(*v);
+        }
+    }
+
+    fn blend_mode(&mut self, mode: BlendMode) {
+        let index = BlendMode::ALL.iter().position(|m| *m == mode);
+        self.u8(index.expect("blend mode listed in ALL") as u8);
+    }
+
+    fn fill_rule(&mut self, rule: FillRule) {
+        self.u8(match rule {
+            FillRule::NonZero => 0,
+            FillRule::EvenOdd => 1,
+        });
+    }
+
+    fn path(&mut self, path: &Path) {
+        self.u32(path.segments().len() as u32);
+        for seg in path.segments() {
+            match *seg {
+                Segment::MoveTo(p) => {
+                    self.u8(0);
+                    self.point(p);
+                }
+                Segment::LineTo(p) => {
+                    self.u8(1);
+                    self.point(p);
+                }
+                Segment::QuadTo(c, p) => {
+                    self.u8(2);
+                    self.point(c);
+                    self.point(p);
+                }
+                Segment::CubicTo(c1, c2, p) => {
+                    self.u8(3);
+                    self.point(c1);
+                    self.point(c2);
+                    self.point(p);
+                }
+                Segment::Close => self.u8(4),
+            }
+        }
+    }
+
+    fn gradient(&mut self, g: &Gradient) {
+        match g.kind {
+            GradientKind::Linear { start, end } => {
+                self.u8(0);
+                self.point(start);
+                self.point(end);
+            }
+            GradientKind::Radial { center, radius } => {
+                self.u8(1);
+                self.point(center);
+                self.f32(radius);
+            }
+            GradientKind::Sweep {
+                center,
+                start_angle,
+                end_angle,
+            } => {
+                self.u8(2);
+                self.point(center);
+                self.f32(start_angle);
+                self.f32(end_angle);
+            }
+        }
+        self.u32(g.stops.len() as u32);
+        for stop in &g.stops {
+            self.f32(stop.offset);
+            self.color(stop.color);
+        }
+        self.u8(match g.extend {
+            ExtendMode::Pad => 0,
+            ExtendMode::Repeat => 1,
+            ExtendMode::Reflect => 2,
+        });
+        self.transform(&g.transform);
+    }
+
+    fn keyframes<T, F>(&mut self, keyframes: &[Keyframe<T>], mut value: F)
+    where
+        F: FnMut(&mut Self, &T),
+    {
+        self.u32(keyframes.len() as u32);
+        for k in keyframes {
+            self.u64(duration_nanos(k.time));
+            value(self, &k.value);
+            match k.easing {
+                Easing::Linear => self.u8(0),
+                Easing::CubicBezier(x1, y1, x2, y2) => {
+                    self.u8(1);
+                    for v in &[x1, y1, x2, y2] {
+                        self.f32(*v);
+                    }
+                }
+                Easing::Steps(steps) => {
+                    self.u8(2);
+                    self.u32(steps);
+                }
+            }
+        }
+    }
+}
+
+impl Put for Vec<u8> {
+    fn u8(&mut self, v: u8) {
+        self.push(v);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn blob(&mut self, v: &[u8]) {
+        self.u32(v.len() as u32);
+        self.extend_from_slice(v);
+    }
+}
+
+/// Decoding of what `Put` encodes, from the rest of a record.
+trait Take<'a> {
+    fn next_bytes(&mut self, len: usize) -> io::Result<&'a [u8]>;
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.next_bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.next_bytes(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.next_bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn blob(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.next_bytes(len)
+    }
+
+    fn f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    fn point(&mut self) -> io::Result<FPoint> {
+        Ok(FPoint::new(self.f32()?, self.f32()?))
+    }
+
+    fn rect(&mut self) -> io::Result<FRect> {
+        Ok(FRect::new(
+            self.f32()?,
+            self.f32()?,
+            self.f32()?,
+            self.f32()?,
+        ))
+    }
+
+    fn irect(&mut self) -> io::Result<IRect> {
+        let mut v = [0i32; 4];
+        for v in &mut v {
+            *v = self.u32()? as i32;
+        }
+        Ok(IRect::new(v[0], v[1], v[2], v[3]))
+    }
+
+    fn corners(&mut self) -> io::Result<FCorners> {
+        Ok(FCorners::new(
+            self.f32()?,
+            self.f32()?,
+            self.f32()?,
+            self.f32()?,
+        ))
+    }
+
+    fn margins(&mut self) -> io::Result<FMargins> {
+        Ok(FMargins::new(
+            self.f32()?,
+            self.f32()?,
+            self.f32()?,
+            self.f32()?,
+        ))
+    }
+
+    fn color(&mut self) -> io::Result<Color> {
+        Ok([self.f32()?, self.f32()?, self.f32()?, self.f32()?])
+    }
+
+    fn transform(&mut self) -> io::Result<Transform> {
+        let mut m = [0f32; 6];
+        for v in &mut m {
+            *v = self.f32()?;
+        }
+        Ok(Transform::new(m[0], m[1], m[2], m[3], m[4], m[5]))
+    }
+
+    fn blend_mode(&mut self) -> io::Result<BlendMode> {
+        let index = self.u8()? as usize;
+        BlendMode::ALL
+            .get(index)
+            .cloned()
+            .ok_or_else(|| invalid_data("unknown blend mode"))
+    }
+
+    fn fill_rule(&mut self) -> io::Result<FillRule> {
+        match self.u8()? {
+            0 => Ok(FillRule::NonZero),
+            1 => Ok(FillRule::EvenOdd),
+            _ => Err(invalid_data("unknown fill rule")),
+        }
+    }
+
+    fn path(&mut self) -> io::Result<Path> {
+        let mut segs = Vec::new();
+        for _ in 0..self.u32()? {
+            segs.push(match self.u8()? {
+                0 => Segment::MoveTo(self.point()?),
+                1 => Segment::LineTo(self.point()?),
+                2 => Segment::QuadTo(self.point()?, self.point()?),
+                3 => Segment::CubicTo(self.point()?, self.point()?, self.point()?),
+                4 => Segment::Close,
+                _ => return Err(invalid_data("unknown path segment")),
+            });
+        }
+        Ok(Path::from_segments(segs))
+    }
+
+    fn gradient(&mut self) -> io::Result<Gradient> {
+        let kind = match self.u8()? {
+            0 => GradientKind::Linear {
+                start: self.point()?,
+                end: self.point()?,
+            },
+            1 => GradientKind::Radial {
+                center: self.point()?,
+                radius: self.f32()?,
+            },
+            2 => GradientKind::Sweep {
+                center: self.point()?,
+                start_angle: self.f32()?,
+                end_angle: self.f32()?,
+            },
+            _ => return Err(invalid_data("unknown gradient")),
+        };
+        let mut stops = Vec::new();
+        for _ in 0..self.u32()? {
+            stops.push(GradientStop::new(self.f32()?, self.color()?));
+        }
+        let extend = match self.u8()? {
+            0 => ExtendMode::Pad,
+            1 => ExtendMode::Repeat,
+            2 => ExtendMode::Reflect,
+            _ => return Err(invalid_data("unknown extend mode")),
+        };
+        Ok(Gradient {
+            kind,
+            stops,
+            extend,
+            transform: self.transform()?,
+        })
+    }
+
+    fn keyframes<T, F>(&mut self, mut value: F) -> io::Result<Vec<Keyframe<T>>>
+    where
+        F: FnMut(&mut Self) -> io::Result<T>,
+    {
+        let mut keyframes = Vec::new();
+        for _ in 0..self.u32()? {
+            let time = Duration::from_nanos(self.u64()?);
+            let value = value(self)?;
+            let easing = match self.u8()? {
+                0 => Easing::Linear,
+                1 => Easing::CubicBezier(self.f32()?, self.f32()?, self.f32()?, self.f32()?),
+                2 => Easing::Steps(self.u32()?),
+                _ => return Err(invalid_data("unknown easing")),
+            };
+            keyframes.push(Keyframe::new(time, value).with_easing(easing));
+        }
+        Ok(keyframes)
+    }
+}
+
+impl<'a> Take<'a> for &'a [u8] {
+    fn next_bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.len() < len {
+            return Err(invalid_data("truncated"));
+        }
+        let (head, tail) = self.split_at(len);
+        *self = tail;
+        Ok(head)
+    }
+}
+
+#[test]
+fn replay_cmds() {
+    let (releases, _released) = mpsc::channel();
+    let pixels = Image::from_rgba(1, 1, vec![255, 0, 0, 255]);
+    let image = Handle::new(Lazy::ready(pixels.clone()), releases.clone());
+    let square = Path::rect(FRect::new(0f32, 0f32, 4f32, 4f32));
+    let path = Handle::new(SharedPath::new(square.clone()), releases.clone());
+    let gradient = Gradient::linear(
+        FPoint::new(0f32, 0f32),
+        FPoint::new(10f32, 0f32),
+        vec![
+            GradientStop::new(0f32, [1f32, 0f32, 0f32, 1f32]),
+            GradientStop::new(1f32, [0f32, 0f32, 1f32, 1f32]),
+        ],
+    );
+    let fade = Property::Opacity(vec![
+        Keyframe::new(Duration::from_millis(0), 0f32),
+        Keyframe::new(Duration::from_millis(300), 1f32).with_easing(Easing::EASE),
+    ]);
+    let shared = Handle::new(SharedGradient::new(gradient.clone()), releases.clone());
+    let mask = vec![Cmd::FillShared {
+        path: path.clone(),
+        fill_rule: FillRule::EvenOdd,
+        paint: Paint::SharedGradient(shared),
+    }];
+    let cmds = vec![
+        Cmd::PushTransform(Transform::translation(2f32, 3f32)),
+        Cmd::PushLayer(
+            Layer::new(0.5f32)
+                .with_blend_mode(BlendMode::Multiply)
+                .with_mask(mask)
+                .with_animation(Animation::new(fade.clone(), Instant::now()).with_iterations(3)),
+        ),
+        Cmd::Fill {
+            path: square.clone(),
+            fill_rule: FillRule::NonZero,
+            paint: Paint::Gradient(gradient.clone()),
+        },
+        Cmd::Image(
+            ImageDraw::new(image, FRect::new(0f32, 0f32, 8f32, 8f32))
+                .with_nine_patch(FMargins::new(1f32, 1f32, 1f32, 1f32)),
+        ),
+        Cmd::PopLayer,
+        Cmd::PopTransform,
+    ];
+
+    let mut recorder = Recorder::new(Vec::new(), &[]).unwrap();
+    let first = recorder.encode(0, IRect::new(0, 0, 20, 20), None, &cmds);
+    // the path, the gradient and the image are written before the first frame only
+    let second = recorder.encode(0, IRect::new(0, 0, 20, 20), Some([1f32; 4]), &cmds);
+    assert_eq!(PATH, first[0]);
+    assert_eq!(FRAME, second[0]);
+
+    let mut input = MAGIC.to_vec();
+    input.u32(VERSION);
+    input.u32(1);
+    input.extend_from_slice(&first);
+    input.extend_from_slice(&second);
+    let mut replay = Replay::new(&input[..]).unwrap();
+    assert_eq!(1, replay.windows());
+    let replayed = replay.next_frame(&releases).unwrap().unwrap();
+    assert_eq!(None, replayed.clear_color);
+    let replayed = replay.next_frame(&releases).unwrap().unwrap();
+    assert_eq!(Some([1f32; 4]), replayed.clear_color);
+    assert!(replay.next_frame(&releases).unwrap().is_none());
+
+    let cmds = replayed.cmds;
+    assert_eq!(6, cmds.len());
+    match &cmds[1] {
+        Cmd::PushLayer(layer) => {
+            assert_eq!(
+                (0.5f32, BlendMode::Multiply),
+                (layer.opacity, layer.blend_mode)
+            );
+            match &layer.mask.as_ref().unwrap()[..] {
+                [Cmd::FillShared { path, paint, .. }] => {
+                    assert_eq!(&square, path.path());
+                    match paint {
+                        Paint::SharedGradient(g) => assert_eq!(&gradient, g.gradient()),
+                        _ => panic!("shared gradient not replayed"),
+                    }
+                }
+                _ => panic!("mask not replayed"),
+            }
+            assert_eq!(fade, layer.animations[0].property);
+            assert_eq!(Some(3), layer.animations[0].iterations);
+        }
+        _ => panic!("layer not replayed"),
+    }
+    match &cmds[2] {
+        Cmd::Fill { path, paint, .. } => {
+            assert_eq!(&square, path);
+            assert_eq!(&Paint::Gradient(gradient), paint);
+        }
+        _ => panic!("fill not replayed"),
+    }
+    match &cmds[3] {
+        Cmd::Image(draw) => {
+            assert_eq!(Some(FMargins::new(1f32, 1f32, 1f32, 1f32)), draw.nine_patch);
+            assert_eq!(Some(pixels), draw.image.get().map(|i| (*i).clone()));
+        }
+        _ => panic!("image not replayed"),
+    }
+}
@@ -0,0 +1,262 @@
+use crate::geom::Transform;
+use crate::paint::Gradient;
+use crate::path::{Path, Polyline, DEFAULT_TOLERANCE};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::{fmt, ops};
+
+/// Identifies a resource, whatever its kind.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub(crate) struct ResourceId(pub(crate) u64);
+
+/// A reference counted handle to a resource created on the UI thread.
+/// Frames hold clones of the handles they draw, the render thread resolves them
+/// to the device objects it derives from the resource.
+/// When the last handle is dropped, these objects are destroyed once the frames
+/// in flight are done with them.
+pub struct Handle<T> {
+    shared: Arc<Shared<T>>,
+}
+
+struct Shared<T> {
+    id: ResourceId,
+    value: T,
+    releases: mpsc::Sender<ResourceId>,
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        // the render thread may be gone already
+        let _ = self.releases.send(self.id);
+    }
+}
+
+impl<T> Handle<T> {
+    pub(crate) fn new(value: T, releases: mpsc::Sender<ResourceId>) -> Handle<T> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Handle {
+            shared: Arc::new(Shared {
+                id: ResourceId(NEXT_ID.fetch_add(1, Ordering::Relaxed)),
+                value,
+                releases,
+            }),
+        }
+    }
+
+    pub(crate) fn id(&self) -> ResourceId {
+        self.shared.id
+    }
+}
+
+impl<T> ops::Deref for Handle<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.shared.value
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Handle<T> {
+        Handle {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Handle<T>) -> bool {
+        self.shared.id == other.shared.id
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.shared.id.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Handle").field(&self.shared.id.0).finish()
+    }
+}
+
+/// A resource that may still be loading in the background.
+pub struct Lazy<T> {
+    state: RwLock<LoadState<T>>,
+}
+
+pub enum LoadState<T> {
+    Loading,
+    Ready(Arc<T>),
+    /// The load failed, with the error message.
+    Failed(String),
+}
+
+impl<T> Clone for LoadState<T> {
+    fn clone(&self) -> LoadState<T> {
+        match self {
+            LoadState::Loading => LoadState::Loading,
+            LoadState::Ready(value) => LoadState::Ready(value.clone()),
+            LoadState::Failed(msg) => LoadState::Failed(msg.clone()),
+        }
+    }
+}
+
+impl<T> Lazy<T> {
+    pub fn ready(value: T) -> Lazy<T> {
+        Lazy {
+            state: RwLock::new(LoadState::Ready(Arc::new(value))),
+        }
+    }
+
+    pub(crate) fn loading() -> Lazy<T> {
+        Lazy {
+            state: RwLock::new(LoadState::Loading),
+        }
+    }
+
+    pub(crate) fn set(&self, state: LoadState<T>) {
+        *self.state.write().unwrap() = state;
+    }
+
+    pub fn state(&self) -> LoadState<T> {
+        self.state.read().unwrap().clone()
+    }
+
+    /// The value, if it is loaded.
+    pub fn get(&self) -> Option<Arc<T>> {
+        match &*self.state.read().unwrap() {
+            LoadState::Ready(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// A path shared by the frames.
+pub type PathHandle = Handle<SharedPath>;
+
+/// A path drawn by many frames.
+/// Its flattening is kept for the last transform it was drawn with.
+pub struct SharedPath {
+    path: Path,
+    flattened: Mutex<Option<(Transform, Vec<Polyline>)>>,
+}
+
+impl SharedPath {
+    pub fn new(path: Path) -> SharedPath {
+        SharedPath {
+            path,
+            flattened: Mutex::new(None),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The polylines of the path transformed by `transform`.
+    pub(crate) fn flatten(&self, transform: &Transform) -> Vec<Polyline> {
+        let mut flattened = self.flattened.lock().unwrap();
+        match &*flattened {
+            Some((t, lines)) if t == transform => lines.clone(),
+            _ => {
+                let lines = self.path.transformed(transform).flatten(DEFAULT_TOLERANCE);
+                *flattened = Some((*transform, lines.clone()));
+                lines
+            }
+        }
+    }
+}
+
+/// A gradient shared by the frames.
+pub type GradientHandle = Handle<SharedGradient>;
+
+/// A gradient drawn by many frames, whose ramp is computed when it is created.
+/// Each frame drawing it copies the ramp to its ramp texture.
+pub struct SharedGradient {
+    gradient: Gradient,
+    ramp: Vec<[u8; 4]>,
+}
+
+impl SharedGradient {
+    pub fn new(gradient: Gradient) -> SharedGradient {
+        let ramp = gradient.ramp();
+        SharedGradient { gradient, ramp }
+    }
+
+    pub fn gradient(&self) -> &Gradient {
+        &self.gradient
+    }
+
+    pub(crate) fn ramp(&self) -> &[[u8; 4]] {
+        &self.ramp
+    }
+}
+
+/// Collects the resources whose last handle was dropped,
+/// until the device is done with the frames that could use them.
+pub(crate) struct Registry {
+    releases: mpsc::Receiver<ResourceId>,
+    /// Released resources, with the serial of the last frame submitted before the release.
+    released: Vec<(ResourceId, u64)>,
+}
+
+impl Registry {
+    pub fn new(releases: mpsc::Receiver<ResourceId>) -> Registry {
+        Registry {
+            releases,
+            released: Vec::new(),
+        }
+    }
+
+    /// Takes the resources released since the last call.
+    /// `serial` is the serial of the last submitted frame.
+    pub fn poll(&mut self, serial: u64) {
+        self.released
+            .extend(self.releases.try_iter().map(|id| (id, serial)));
+    }
+
+    /// The released resources that no frame up to `completed` uses anymore.
+    pub fn collect(&mut self, completed: u64) -> Vec<ResourceId> {
+        let (done, pending): (Vec<_>, Vec<_>) = self
+            .released
+            .drain(..)
+            .partition(|&(_, serial)| serial <= completed);
+        self.released = pending;
+        done.into_iter().map(|(id, _)| id).collect()
+    }
+
+    /// Every resource released so far, the device must be idle.
+    pub fn collect_all(&mut self) -> Vec<ResourceId> {
+        self.poll(0);
+        self.released.drain(..).map(|(id, _)| id).collect()
+    }
+}
+
+#[test]
+fn deferred_release() {
+    let (tx, rx) = mpsc::channel();
+    let mut registry = Registry::new(rx);
+    let a = Handle::new(1u32, tx.clone());
+    let b = Handle::new(2u32, tx);
+    let (ida, idb) = (a.id(), b.id());
+    assert_eq!(2, *b.clone());
+
+    drop(a);
+    registry.poll(3);
+    let b2 = b.clone();
+    drop(b);
+    registry.poll(4);
+    assert!(registry.collect(2).is_empty());
+    assert_eq!(vec![ida], registry.collect(3));
+
+    // the clones keep the resource alive
+    drop(b2);
+    registry.poll(5);
+    assert!(registry.collect(4).is_empty());
+    assert_eq!(vec![idb], registry.collect(5));
+}
@@ -0,0 +1,48 @@
+#version 450
+
+// Shares the layout of `PaintConsts`.
+layout(push_constant) uniform BlurConsts {
+    vec4 viewport;
+    // offset from the target to the source, in pixels of the window
+    vec4 color;
+    vec4 mat0;
+    vec4 mat1;
+    // direction, standard deviation in texels of the target
+    vec4 params;
+    // scales of the target and of the source
+    ivec4 info;
+} pc;
+
+layout(set = 1, binding = 0) uniform texture2D u_src;
+layout(set = 1, binding = 1) uniform sampler u_src_sampler;
+
+layout(location = 0) out vec4 o_color;
+
+// Must be kept in sync with `draw::MAX_BLUR_TAPS`.
+const int MAX_TAPS = 32;
+
+void main() {
+    vec2 size = vec2(textureSize(sampler2D(u_src, u_src_sampler), 0));
+    float scale = float(pc.info.x);
+    float src_scale = float(pc.info.y);
+    float sigma = pc.params.z;
+    // the targets are scaled from their origin
+    vec2 p = gl_FragCoord.xy * scale;
+    // the edges of the viewport are extended
+    vec2 lo = pc.viewport.xy + 0.5 * src_scale;
+    vec2 hi = pc.viewport.xy + pc.viewport.zw - 0.5 * src_scale;
+    int taps = min(int(ceil(3.0 * sigma)), MAX_TAPS);
+    vec4 sum = vec4(0.0);
+    float total = 0.0;
+    for (int i = -taps; i <= taps; i++) {
+        float w = exp(-0.5 * float(i * i) / (sigma * sigma));
+        vec2 q = clamp(p + pc.params.xy * float(i) * scale, lo, hi);
+        vec2 t = (q + pc.color.xy) / src_scale;
+        // the source is transparent outside of its target
+        if (all(greaterThanEqual(t, vec2(0.0))) && all(lessThanEqual(t, size))) {
+            sum += w * texture(sampler2D(u_src, u_src_sampler), t / size);
+        }
+        total += w;
+    }
+    o_color = sum / total;
+}
@@ -0,0 +1,175 @@
+#version 450
+
+// Shares the layout of `PaintConsts`.
+layout(push_constant) uniform CompositeConsts {
+    vec4 viewport;
+    // offsets from the target to the layer and to the mask, in pixels
+    vec4 color;
+    vec4 mat0;
+    vec4 mat1;
+    // opacity, has mask
+    vec4 params;
+    // blend mode done in the shader with the backdrop, or -1, scale of the layer
+    ivec4 info;
+} pc;
+
+layout(set = 1, binding = 0) uniform texture2D u_layer;
+layout(set = 1, binding = 1) uniform sampler u_layer_sampler;
+layout(set = 2, binding = 0) uniform texture2D u_mask;
+layout(set = 2, binding = 1) uniform sampler u_mask_sampler;
+layout(set = 3, binding = 0) uniform texture2D u_backdrop;
+layout(set = 3, binding = 1) uniform sampler u_backdrop_sampler;
+
+layout(location = 0) out vec4 o_color;
+
+// Must be kept in sync with `paint::BlendMode`.
+const int MULTIPLY = 13;
+const int SCREEN = 14;
+const int OVERLAY = 15;
+const int DARKEN = 16;
+const int LIGHTEN = 17;
+const int COLOR_DODGE = 18;
+const int COLOR_BURN = 19;
+const int HARD_LIGHT = 20;
+const int SOFT_LIGHT = 21;
+const int DIFFERENCE = 22;
+const int EXCLUSION = 23;
+const int HUE = 24;
+const int SATURATION = 25;
+const int COLOR = 26;
+const int LUMINOSITY = 27;
+
+float color_dodge(float cs, float cb) {
+    if (cb == 0.0) {
+        return 0.0;
+    } else if (cs >= 1.0) {
+        return 1.0;
+    }
+    return min(1.0, cb / (1.0 - cs));
+}
+
+float color_burn(float cs, float cb) {
+    if (cb >= 1.0) {
+        return 1.0;
+    } else if (cs == 0.0) {
+        return 0.0;
+    }
+    return 1.0 - min(1.0, (1.0 - cb) / cs);
+}
+
+vec3 hard_light(vec3 cs, vec3 cb) {
+    vec3 multiply = cb * 2.0 * cs;
+    vec3 s = 2.0 * cs - 1.0;
+    vec3 screen = cb + s - cb * s;
+    return mix(screen, multiply, lessThanEqual(cs, vec3(0.5)));
+}
+
+vec3 soft_light(vec3 cs, vec3 cb) {
+    vec3 d = mix(sqrt(cb), ((16.0 * cb - 12.0) * cb + 4.0) * cb, lessThanEqual(cb, vec3(0.25)));
+    vec3 low = cb - (1.0 - 2.0 * cs) * cb * (1.0 - cb);
+    vec3 high = cb + (2.0 * cs - 1.0) * (d - cb);
+    return mix(high, low, lessThanEqual(cs, vec3(0.5)));
+}
+
+float lum(vec3 c) {
+    return dot(c, vec3(0.3, 0.59, 0.11));
+}
+
+vec3 set_lum(vec3 c, float l) {
+    c += l - lum(c);
+    // clip the color in gamut, keeping its luminosity
+    l = lum(c);
+    float n = min(min(c.r, c.g), c.b);
+    float x = max(max(c.r, c.g), c.b);
+    if (n < 0.0) {
+        c = l + (c - l) * l / (l - n);
+    }
+    if (x > 1.0) {
+        c = l + (c - l) * (1.0 - l) / (x - l);
+    }
+    return c;
+}
+
+float sat(vec3 c) {
+    return max(max(c.r, c.g), c.b) - min(min(c.r, c.g), c.b);
+}
+
+vec3 set_sat(vec3 c, float s) {
+    float n = min(min(c.r, c.g), c.b);
+    float x = max(max(c.r, c.g), c.b);
+    return x > n ? (c - n) * s / (x - n) : vec3(0.0);
+}
+
+vec3 blend(int mode, vec3 cs, vec3 cb) {
+    if (mode == MULTIPLY) {
+        return cs * cb;
+    } else if (mode == SCREEN) {
+        return cs + cb - cs * cb;
+    } else if (mode == OVERLAY) {
+        return hard_light(cb, cs);
+    } else if (mode == DARKEN) {
+        return min(cs, cb);
+    } else if (mode == LIGHTEN) {
+        return max(cs, cb);
+    } else if (mode == COLOR_DODGE) {
+        return vec3(color_dodge(cs.r, cb.r), color_dodge(cs.g, cb.g), color_dodge(cs.b, cb.b));
+    } else if (mode == COLOR_BURN) {
+        return vec3(color_burn(cs.r, cb.r), color_burn(cs.g, cb.g), color_burn(cs.b, cb.b));
+    } else if (mode == HARD_LIGHT) {
+        return hard_light(cs, cb);
+    } else if (mode == SOFT_LIGHT) {
+        return soft_light(cs, cb);
+    } else if (mode == DIFFERENCE) {
+        return abs(cs - cb);
+    } else if (mode == EXCLUSION) {
+        return cs + cb - 2.0 * cs * cb;
+    } else if (mode == HUE) {
+        return set_lum(set_sat(cs, sat(cb)), lum(cb));
+    } else if (mode == SATURATION) {
+        return set_lum(set_sat(cb, sat(cs)), lum(cb));
+    } else if (mode == COLOR) {
+        return set_lum(cs, lum(cb));
+    } else {
+        return set_lum(cb, lum(cs));
+    }
+}
+
+vec3 unpremultiply(vec4 c) {
+    return c.a > 0.0 ? c.rgb / c.a : vec3(0.0);
+}
+
+bool inside(vec2 q, ivec2 size) {
+    return all(greaterThanEqual(q, vec2(0.0))) && all(lessThan(q, vec2(size)));
+}
+
+void main() {
+    // layers are rendered at the same place as they are composited,
+    // in targets that start at their own origin
+    ivec2 p = ivec2(gl_FragCoord.xy);
+    ivec2 size = textureSize(sampler2D(u_layer, u_layer_sampler), 0);
+    // a downsampled layer is scaled from its origin
+    vec2 q = (gl_FragCoord.xy + pc.color.xy) / float(pc.info.y);
+    // transparent outside of its target
+    vec4 c = vec4(0.0);
+    if (inside(q, size) && pc.info.y > 1) {
+        c = textureLod(sampler2D(u_layer, u_layer_sampler), q / vec2(size), 0.0);
+    } else if (inside(q, size)) {
+        c = texelFetch(sampler2D(u_layer, u_layer_sampler), ivec2(q), 0);
+    }
+    c *= pc.params.x;
+    if (pc.params.y > 0.5) {
+        vec2 m = gl_FragCoord.xy + pc.color.zw;
+        bool masked = inside(m, textureSize(sampler2D(u_mask, u_mask_sampler), 0));
+        c *= masked ? texelFetch(sampler2D(u_mask, u_mask_sampler), ivec2(m), 0).a : 0.0;
+    }
+    if (pc.info.x >= 0) {
+        // the result replaces the destination
+        vec4 d = texelFetch(sampler2D(u_backdrop, u_backdrop_sampler), p, 0);
+        vec3 b = blend(pc.info.x, unpremultiply(c), unpremultiply(d));
+        c = vec4(
+            c.rgb * (1.0 - d.a) + d.rgb * (1.0 - c.a) + c.a * d.a * b,
+            c.a + d.a - c.a * d.a
+        );
+    }
+    o_color = c;
+}
@@ -0,0 +1,31 @@
+#version 450
+
+// Must be kept in sync with `draw::EffectConsts`.
+layout(push_constant) uniform EffectConsts {
+    vec4 viewport;
+    // rows of the transform from the user space to viewport coordinates
+    vec4 mat0;
+    vec4 mat1;
+    // x0, y0, x1, y1 in user space
+    vec4 rect;
+    vec4 uniforms[4];
+} pc;
+
+// from (0, 0) to (1, 1) across the rectangle
+layout(location = 0) out vec2 v_uv;
+// in user space
+layout(location = 1) out vec2 v_pos;
+
+const vec2 CORNERS[6] = vec2[6](
+    vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0),
+    vec2(0.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 1.0)
+);
+
+void main() {
+    vec2 corner = CORNERS[gl_VertexIndex];
+    vec3 p = vec3(mix(pc.rect.xy, pc.rect.zw, corner), 1.0);
+    vec2 device = vec2(dot(pc.mat0.xyz, p), dot(pc.mat1.xyz, p));
+    gl_Position = vec4(device / pc.viewport.zw * 2.0 - 1.0, 0.0, 1.0);
+    v_uv = corner;
+    v_pos = p.xy;
+}
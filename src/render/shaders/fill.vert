@@ -0,0 +1,13 @@
+#version 450
+
+#include "paint.glsl"
+
+layout(location = 0) in vec2 a_pos;
+layout(location = 1) in float a_coverage;
+
+layout(location = 0) out float v_coverage;
+
+void main() {
+    v_coverage = a_coverage;
+    gl_Position = vec4(a_pos / pc.viewport.zw * 2.0 - 1.0, 0.0, 1.0);
+}
@@ -0,0 +1,18 @@
+#version 450
+
+#include "paint.glsl"
+
+layout(set = 0, binding = 2) uniform texture2D u_glyphs;
+
+layout(location = 0) in vec2 v_uv;
+layout(location = 1) flat in float v_color;
+
+layout(location = 0) out vec4 o_color;
+
+void main() {
+    // glyphs are drawn texel to pixel
+    vec4 texel = texelFetch(sampler2D(u_glyphs, u_ramp_sampler), ivec2(v_uv), 0);
+    vec4 paint = paint_color(gl_FragCoord.xy - pc.viewport.xy);
+    // color glyphs keep their colors, with the opacity of the paint
+    o_color = v_color > 0.5 ? texel * paint.a : paint * texel.a;
+}
@@ -0,0 +1,27 @@
+#version 450
+
+#include "paint.glsl"
+
+// Must be kept in sync with `draw::GlyphInstance`.
+// x0, y0, x1, y1 in viewport coordinates
+layout(location = 0) in vec4 a_rect;
+// x0, y0, x1, y1 in atlas texels
+layout(location = 1) in vec4 a_uv;
+// x: 1 for the color glyphs
+layout(location = 2) in vec4 a_params;
+
+layout(location = 0) out vec2 v_uv;
+layout(location = 1) flat out float v_color;
+
+const vec2 CORNERS[6] = vec2[6](
+    vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0),
+    vec2(0.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 1.0)
+);
+
+void main() {
+    vec2 corner = CORNERS[gl_VertexIndex];
+    vec2 pos = mix(a_rect.xy, a_rect.zw, corner);
+    gl_Position = vec4(pos / pc.viewport.zw * 2.0 - 1.0, 0.0, 1.0);
+    v_uv = mix(a_uv.xy, a_uv.zw, corner);
+    v_color = a_params.x;
+}
@@ -0,0 +1,25 @@
+#version 450
+
+// Shares the layout of `PaintConsts`.
+layout(push_constant) uniform ImageConsts {
+    vec4 viewport;
+    vec4 color;
+    vec4 mat0;
+    vec4 mat1;
+    // opacity
+    vec4 params;
+    ivec4 info;
+} pc;
+
+layout(set = 1, binding = 0) uniform texture2D u_image;
+layout(set = 1, binding = 1) uniform sampler u_image_sampler;
+
+layout(location = 0) in vec2 v_uv;
+
+layout(location = 0) out vec4 o_color;
+
+void main() {
+    vec2 size = vec2(textureSize(sampler2D(u_image, u_image_sampler), 0));
+    // the texels are premultiplied
+    o_color = texture(sampler2D(u_image, u_image_sampler), v_uv / size) * pc.params.x;
+}
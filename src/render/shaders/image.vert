@@ -0,0 +1,36 @@
+#version 450
+
+// Shares the layout of `PaintConsts`.
+layout(push_constant) uniform ImageConsts {
+    vec4 viewport;
+    vec4 color;
+    vec4 mat0;
+    vec4 mat1;
+    // opacity
+    vec4 params;
+    ivec4 info;
+} pc;
+
+// Must be kept in sync with `draw::ImageInstance`.
+// rows of the transform from the user space to viewport coordinates
+layout(location = 0) in vec4 a_mat0;
+layout(location = 1) in vec4 a_mat1;
+// x0, y0, x1, y1 in user space
+layout(location = 2) in vec4 a_rect;
+// x0, y0, x1, y1 in image texels
+layout(location = 3) in vec4 a_uv;
+
+layout(location = 0) out vec2 v_uv;
+
+const vec2 CORNERS[6] = vec2[6](
+    vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0),
+    vec2(0.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 1.0)
+);
+
+void main() {
+    vec2 corner = CORNERS[gl_VertexIndex];
+    vec3 p = vec3(mix(a_rect.xy, a_rect.zw, corner), 1.0);
+    vec2 device = vec2(dot(a_mat0.xyz, p), dot(a_mat1.xyz, p));
+    gl_Position = vec4(device / pc.viewport.zw * 2.0 - 1.0, 0.0, 1.0);
+    v_uv = mix(a_uv.xy, a_uv.zw, corner);
+}
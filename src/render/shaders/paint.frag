@@ -0,0 +1,11 @@
+#version 450
+
+#include "paint.glsl"
+
+layout(location = 0) in float v_coverage;
+
+layout(location = 0) out vec4 o_color;
+
+void main() {
+    o_color = paint_color(gl_FragCoord.xy - pc.viewport.xy) * v_coverage;
+}
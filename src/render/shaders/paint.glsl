@@ -0,0 +1,69 @@
+// Paint evaluation shared by the shaders that fill with a `Paint`.
+// Must be kept in sync with `draw::PaintConsts`.
+
+layout(push_constant) uniform PaintConsts {
+    // x, y, width, height of the frame viewport
+    vec4 viewport;
+    // pre-multiplied color of solid paints
+    vec4 color;
+    // rows of the transform from viewport coordinates to gradient space
+    vec4 mat0;
+    vec4 mat1;
+    // linear: start.xy, end.xy
+    // radial: center.xy, radius
+    // sweep: center.xy, start angle, end angle
+    vec4 params;
+    // kind, extend mode, ramp row
+    ivec4 info;
+} pc;
+
+layout(set = 0, binding = 0) uniform texture2D u_ramps;
+layout(set = 0, binding = 1) uniform sampler u_ramp_sampler;
+
+const int PAINT_SOLID = 0;
+const int PAINT_LINEAR = 1;
+const int PAINT_RADIAL = 2;
+const int PAINT_SWEEP = 3;
+
+const int EXTEND_PAD = 0;
+const int EXTEND_REPEAT = 1;
+const int EXTEND_REFLECT = 2;
+
+const float RAMP_SIZE = 256.0;
+const float RAMP_ROWS = 64.0;
+
+const float TWO_PI = 6.28318530718;
+
+vec4 paint_color(vec2 frag) {
+    if (pc.info.x == PAINT_SOLID) {
+        return pc.color;
+    }
+    vec3 fp = vec3(frag, 1.0);
+    vec2 p = vec2(dot(pc.mat0.xyz, fp), dot(pc.mat1.xyz, fp));
+
+    float t;
+    if (pc.info.x == PAINT_LINEAR) {
+        vec2 d = pc.params.zw - pc.params.xy;
+        t = dot(p - pc.params.xy, d) / dot(d, d);
+    } else if (pc.info.x == PAINT_RADIAL) {
+        t = length(p - pc.params.xy) / pc.params.z;
+    } else {
+        vec2 d = p - pc.params.xy;
+        float a = mod(atan(d.y, d.x) - pc.params.z, TWO_PI);
+        t = a / (pc.params.w - pc.params.z);
+    }
+
+    if (pc.info.y == EXTEND_PAD) {
+        t = clamp(t, 0.0, 1.0);
+    } else if (pc.info.y == EXTEND_REPEAT) {
+        t = fract(t);
+    } else {
+        t = 1.0 - abs(mod(t, 2.0) - 1.0);
+    }
+
+    vec2 uv = vec2(
+        (t * (RAMP_SIZE - 1.0) + 0.5) / RAMP_SIZE,
+        (float(pc.info.z) + 0.5) / RAMP_ROWS
+    );
+    return texture(sampler2D(u_ramps, u_ramp_sampler), uv);
+}
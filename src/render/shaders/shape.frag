@@ -0,0 +1,153 @@
+#version 450
+
+#include "paint.glsl"
+
+// position in the shape space
+layout(location = 0) in vec2 v_pos;
+// x0, y0, x1, y1
+layout(location = 1) flat in vec4 v_rect;
+// top-left, top-right, bottom-right, bottom-left
+layout(location = 2) flat in vec4 v_radii;
+// border widths: left, top, right, bottom
+layout(location = 3) flat in vec4 v_widths;
+// shape of the element casting a shadow
+layout(location = 4) flat in vec4 v_clip_rect;
+layout(location = 5) flat in vec4 v_clip_radii;
+// kind, shadow sigma, aliased
+layout(location = 6) flat in vec4 v_params;
+// pre-multiplied colors: fill, or left, top, right, bottom borders
+layout(location = 7) flat in vec4 v_color0;
+layout(location = 8) flat in vec4 v_color1;
+layout(location = 9) flat in vec4 v_color2;
+layout(location = 10) flat in vec4 v_color3;
+
+layout(location = 0) out vec4 o_color;
+
+const int SHAPE_FILL = 0;
+const int SHAPE_BORDER = 1;
+const int SHAPE_SHADOW = 2;
+const int SHAPE_INSET_SHADOW = 3;
+
+const float SQRT_2PI = 2.50662827463;
+
+float corner_radius(vec2 q, vec4 radii) {
+    if (q.x < 0.0) {
+        return q.y < 0.0 ? radii.x : radii.w;
+    } else {
+        return q.y < 0.0 ? radii.y : radii.z;
+    }
+}
+
+// Signed distance to a rounded rectangle, negative inside.
+float sd_rrect(vec2 p, vec4 rect, vec4 radii) {
+    vec2 half_size = (rect.zw - rect.xy) * 0.5;
+    vec2 q = p - (rect.xy + rect.zw) * 0.5;
+    float r = corner_radius(q, radii);
+    vec2 d = abs(q) - half_size + r;
+    return min(max(d.x, d.y), 0.0) + length(max(d, 0.0)) - r;
+}
+
+float coverage(float sd, float aa) {
+    return clamp(0.5 - sd / aa, 0.0, 1.0);
+}
+
+vec2 erf(vec2 x) {
+    vec2 s = sign(x);
+    vec2 a = abs(x);
+    x = 1.0 + (0.278393 + (0.230389 + 0.078108 * (a * a)) * a) * a;
+    x *= x;
+    return s - s / (x * x);
+}
+
+float gaussian(float x, float sigma) {
+    return exp(-(x * x) / (2.0 * sigma * sigma)) / (SQRT_2PI * sigma);
+}
+
+// Blur of a rounded box along x, for the row at `y` (relative to the box center).
+float shadow_x(float x, float y, float sigma, float r, vec2 half_size) {
+    float delta = min(half_size.y - r - abs(y), 0.0);
+    float curved = half_size.x - r + sqrt(max(0.0, r * r - delta * delta));
+    vec2 integral = 0.5 + 0.5 * erf((x + vec2(-curved, curved)) * (sqrt(0.5) / sigma));
+    return integral.y - integral.x;
+}
+
+// Gaussian blurred rounded rectangle: exact along x, sampled along y.
+float shadow(vec2 p, vec4 rect, vec4 radii, float sigma, float aa) {
+    if (sigma < 0.1) {
+        return coverage(sd_rrect(p, rect, radii), aa);
+    }
+    vec2 half_size = (rect.zw - rect.xy) * 0.5;
+    vec2 q = p - (rect.xy + rect.zw) * 0.5;
+    float r = min(corner_radius(q, radii), min(half_size.x, half_size.y));
+
+    float low = q.y - half_size.y;
+    float high = q.y + half_size.y;
+    float start = clamp(-3.0 * sigma, low, high);
+    float end = clamp(3.0 * sigma, low, high);
+    float step = (end - start) / 4.0;
+    float y = start + step * 0.5;
+    float value = 0.0;
+    for (int i = 0; i < 4; i++) {
+        value += shadow_x(q.x, q.y - y, sigma, r, half_size) * gaussian(y, sigma) * step;
+        y += step;
+    }
+    return value;
+}
+
+vec4 border_color(vec4 inner) {
+    vec4 d = vec4(inner.xy - v_pos, v_pos - inner.zw) / max(v_widths, 1e-4);
+    // sides without width never own a pixel
+    d = mix(vec4(-1e6), d, greaterThan(v_widths, vec4(0.0)));
+    vec4 c = v_color0;
+    float m = d.x;
+    if (d.y > m) {
+        m = d.y;
+        c = v_color1;
+    }
+    if (d.z > m) {
+        m = d.z;
+        c = v_color2;
+    }
+    if (d.w > m) {
+        c = v_color3;
+    }
+    return c;
+}
+
+void main() {
+    // anti-aliasing width, computed in uniform control flow
+    float aa = max(length(fwidth(v_pos)) * 0.70710678, 1e-4);
+    if (v_params.z != 0.0) {
+        // hard edges
+        aa = 1e-4;
+    }
+    int kind = int(v_params.x);
+
+    if (kind == SHAPE_FILL) {
+        vec4 c = pc.info.x == PAINT_SOLID ? v_color0 : paint_color(gl_FragCoord.xy - pc.viewport.xy);
+        o_color = c * coverage(sd_rrect(v_pos, v_rect, v_radii), aa);
+    } else if (kind == SHAPE_BORDER) {
+        vec4 w = v_widths;
+        vec4 inner = v_rect + vec4(w.xy, -w.zw);
+        vec4 inner_radii = max(
+            v_radii - vec4(max(w.x, w.y), max(w.z, w.y), max(w.z, w.w), max(w.x, w.w)),
+            0.0
+        );
+        float outer = coverage(sd_rrect(v_pos, v_rect, v_radii), aa);
+        float hole = 0.0;
+        if (inner.x < inner.z && inner.y < inner.w) {
+            hole = coverage(sd_rrect(v_pos, inner, inner_radii), aa);
+        }
+        o_color = border_color(inner) * outer * (1.0 - hole);
+    } else {
+        float elem = coverage(sd_rrect(v_pos, v_clip_rect, v_clip_radii), aa);
+        float a = shadow(v_pos, v_rect, v_radii, v_params.y, aa);
+        if (kind == SHAPE_SHADOW) {
+            // outset shadows are not visible under the element
+            o_color = v_color0 * a * (1.0 - elem);
+        } else {
+            // inset shadows are cast by the outside of `v_rect`, within the element
+            o_color = v_color0 * (1.0 - a) * elem;
+        }
+    }
+}
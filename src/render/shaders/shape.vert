@@ -0,0 +1,54 @@
+#version 450
+
+#include "paint.glsl"
+
+// Must be kept in sync with `draw::ShapeInstance`.
+// rows of the transform from the shape space to viewport coordinates
+layout(location = 0) in vec4 a_mat0;
+layout(location = 1) in vec4 a_mat1;
+layout(location = 2) in vec4 a_bounds;
+layout(location = 3) in vec4 a_rect;
+layout(location = 4) in vec4 a_radii;
+layout(location = 5) in vec4 a_widths;
+layout(location = 6) in vec4 a_clip_rect;
+layout(location = 7) in vec4 a_clip_radii;
+layout(location = 8) in vec4 a_params;
+layout(location = 9) in vec4 a_color0;
+layout(location = 10) in vec4 a_color1;
+layout(location = 11) in vec4 a_color2;
+layout(location = 12) in vec4 a_color3;
+
+layout(location = 0) out vec2 v_pos;
+layout(location = 1) flat out vec4 v_rect;
+layout(location = 2) flat out vec4 v_radii;
+layout(location = 3) flat out vec4 v_widths;
+layout(location = 4) flat out vec4 v_clip_rect;
+layout(location = 5) flat out vec4 v_clip_radii;
+layout(location = 6) flat out vec4 v_params;
+layout(location = 7) flat out vec4 v_color0;
+layout(location = 8) flat out vec4 v_color1;
+layout(location = 9) flat out vec4 v_color2;
+layout(location = 10) flat out vec4 v_color3;
+
+const vec2 CORNERS[6] = vec2[6](
+    vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0),
+    vec2(0.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 1.0)
+);
+
+void main() {
+    vec2 pos = mix(a_bounds.xy, a_bounds.zw, CORNERS[gl_VertexIndex]);
+    vec3 p = vec3(pos, 1.0);
+    vec2 device = vec2(dot(a_mat0.xyz, p), dot(a_mat1.xyz, p));
+    gl_Position = vec4(device / pc.viewport.zw * 2.0 - 1.0, 0.0, 1.0);
+    v_pos = pos;
+    v_rect = a_rect;
+    v_radii = a_radii;
+    v_widths = a_widths;
+    v_clip_rect = a_clip_rect;
+    v_clip_radii = a_clip_radii;
+    v_params = a_params;
+    v_color0 = a_color0;
+    v_color1 = a_color1;
+    v_color2 = a_color2;
+    v_color3 = a_color3;
+}
@@ -0,0 +1,236 @@
+use super::buffer::Buffer;
+use super::memory::{Allocator, Memory, Tiling};
+use crate::gfx;
+use gfx_hal::{buffer, command, format, image, memory, pso, Device};
+
+pub(crate) const COLOR_RANGE: image::SubresourceRange = image::SubresourceRange {
+    aspects: format::Aspects::COLOR,
+    levels: 0..1,
+    layers: 0..1,
+};
+
+/// A 2D image in device local memory, with a view on it.
+pub(crate) struct Texture {
+    pub image: gfx::Image,
+    memory: Memory,
+    pub view: gfx::ImageView,
+    pub width: u32,
+    pub height: u32,
+    pub levels: image::Level,
+    pub format: format::Format,
+}
+
+impl Texture {
+    pub fn new(
+        dev: &gfx::Device,
+        alloc: &Allocator,
+        size: (u32, u32),
+        format: format::Format,
+        usage: image::Usage,
+        aspects: format::Aspects,
+    ) -> Texture {
+        Texture::multisampled(dev, alloc, size, format, 1, usage, aspects)
+    }
+
+    pub fn multisampled(
+        dev: &gfx::Device,
+        alloc: &Allocator,
+        (width, height): (u32, u32),
+        format: format::Format,
+        samples: u8,
+        usage: image::Usage,
+        aspects: format::Aspects,
+    ) -> Texture {
+        let kind = image::Kind::D2(width, height, 1, samples);
+        Texture::create(dev, alloc, kind, 1, format, usage, aspects)
+    }
+
+    /// A color texture with `levels` mipmap levels, the first one being `size`.
+    pub fn mipmapped(
+        dev: &gfx::Device,
+        alloc: &Allocator,
+        (width, height): (u32, u32),
+        format: format::Format,
+        levels: image::Level,
+        usage: image::Usage,
+    ) -> Texture {
+        let kind = image::Kind::D2(width, height, 1, 1);
+        Texture::create(dev, alloc, kind, levels, format, usage, format::Aspects::COLOR)
+    }
+
+    fn create(
+        dev: &gfx::Device,
+        alloc: &Allocator,
+        kind: image::Kind,
+        levels: image::Level,
+        format: format::Format,
+        usage: image::Usage,
+        aspects: format::Aspects,
+    ) -> Texture {
+        let extent = kind.extent();
+        unsafe {
+            let mut image = dev
+                .create_image(
+                    kind,
+                    levels,
+                    format,
+                    image::Tiling::Optimal,
+                    usage,
+                    image::ViewCapabilities::empty(),
+                )
+                .expect("could not create an image");
+            let req = dev.get_image_requirements(&image);
+            let memory = alloc.allocate(
+                dev,
+                &req,
+                memory::Properties::empty(),
+                memory::Properties::DEVICE_LOCAL,
+                Tiling::Optimal,
+            );
+            memory.bind_image(dev, &mut image);
+            let view = dev
+                .create_image_view(
+                    &image,
+                    image::ViewKind::D2,
+                    format,
+                    format::Swizzle::NO,
+                    image::SubresourceRange {
+                        aspects,
+                        levels: 0..levels,
+                        layers: 0..1,
+                    },
+                )
+                .expect("could not create an image view");
+            Texture {
+                image,
+                memory,
+                view,
+                width: extent.width,
+                height: extent.height,
+                levels,
+                format,
+            }
+        }
+    }
+
+    pub fn destroy(self, dev: &gfx::Device) {
+        unsafe {
+            dev.destroy_image_view(self.view);
+            dev.destroy_image(self.image);
+        }
+        self.memory.free();
+    }
+}
+
+/// A sampled texture that is updated from the host through a staging buffer.
+pub(crate) struct UploadTexture {
+    pub texture: Texture,
+    staging: Buffer,
+    initialized: bool,
+}
+
+impl UploadTexture {
+    pub fn new(
+        dev: &gfx::Device,
+        alloc: &Allocator,
+        size: (u32, u32),
+        format: format::Format,
+    ) -> UploadTexture {
+        let texel_size = (format.surface_desc().bits / 8) as u64;
+        let staging = Buffer::new(
+            dev,
+            alloc,
+            size.0 as u64 * size.1 as u64 * texel_size,
+            buffer::Usage::TRANSFER_SRC,
+        );
+        let texture = Texture::new(
+            dev,
+            alloc,
+            size,
+            format,
+            image::Usage::TRANSFER_DST | image::Usage::SAMPLED,
+            format::Aspects::COLOR,
+        );
+        UploadTexture {
+            texture,
+            staging,
+            initialized: false,
+        }
+    }
+
+    pub fn needs_init(&self) -> bool {
+        !self.initialized
+    }
+
+    /// Writes the `rows` first rows of the texture from `texels`
+    /// and records the copy into `cmd`.
+    /// The texture is left in the `ShaderReadOnlyOptimal` layout.
+    pub unsafe fn upload<T: Copy>(
+        &mut self,
+        cmd: &mut gfx::CommandBuffer,
+        texels: &[T],
+        rows: u32,
+    ) {
+        let tex = &self.texture;
+        self.staging.write(0, texels);
+
+        let old_layout = if self.initialized {
+            image::Layout::ShaderReadOnlyOptimal
+        } else {
+            image::Layout::Undefined
+        };
+        let to_transfer = memory::Barrier::Image {
+            states: (image::Access::SHADER_READ, old_layout)
+                ..(image::Access::TRANSFER_WRITE, image::Layout::TransferDstOptimal),
+            target: &tex.image,
+            families: None,
+            range: COLOR_RANGE,
+        };
+        cmd.pipeline_barrier(
+            pso::PipelineStage::FRAGMENT_SHADER..pso::PipelineStage::TRANSFER,
+            memory::Dependencies::empty(),
+            &[to_transfer],
+        );
+        if rows > 0 {
+            cmd.copy_buffer_to_image(
+                &self.staging.buffer,
+                &tex.image,
+                image::Layout::TransferDstOptimal,
+                &[command::BufferImageCopy {
+                    buffer_offset: 0,
+                    buffer_width: tex.width,
+                    buffer_height: rows,
+                    image_layers: image::SubresourceLayers {
+                        aspects: format::Aspects::COLOR,
+                        level: 0,
+                        layers: 0..1,
+                    },
+                    image_offset: image::Offset { x: 0, y: 0, z: 0 },
+                    image_extent: image::Extent {
+                        width: tex.width,
+                        height: rows,
+                        depth: 1,
+                    },
+                }],
+            );
+        }
+        let to_shader = memory::Barrier::Image {
+            states: (image::Access::TRANSFER_WRITE, image::Layout::TransferDstOptimal)
+                ..(image::Access::SHADER_READ, image::Layout::ShaderReadOnlyOptimal),
+            target: &tex.image,
+            families: None,
+            range: COLOR_RANGE,
+        };
+        cmd.pipeline_barrier(
+            pso::PipelineStage::TRANSFER..pso::PipelineStage::FRAGMENT_SHADER,
+            memory::Dependencies::empty(),
+            &[to_shader],
+        );
+        self.initialized = true;
+    }
+
+    pub fn destroy(self, dev: &gfx::Device) {
+        self.texture.destroy(dev);
+        self.staging.destroy(dev);
+    }
+}
@@ -0,0 +1,505 @@
+use super::Font;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+use ttf_parser::name_id;
+
+/// Weight of the regular faces.
+pub const NORMAL_WEIGHT: u16 = 400;
+/// Weight of the bold faces.
+pub const BOLD_WEIGHT: u16 = 700;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum FontStyle {
+    Normal,
+    Italic,
+    Oblique,
+}
+
+/// A font request, with CSS semantics.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct FontDescriptor {
+    /// Family names or generic families ("serif", "sans-serif", "monospace" or "emoji"),
+    /// in order of preference.
+    pub families: Vec<String>,
+    /// From 1 to 1000.
+    pub weight: u16,
+    pub style: FontStyle,
+}
+
+impl FontDescriptor {
+    pub fn new(family: &str) -> FontDescriptor {
+        FontDescriptor {
+            families: vec![family.to_string()],
+            weight: NORMAL_WEIGHT,
+            style: FontStyle::Normal,
+        }
+    }
+
+    pub fn with_weight(mut self, weight: u16) -> FontDescriptor {
+        self.weight = weight;
+        self
+    }
+
+    pub fn with_style(mut self, style: FontStyle) -> FontDescriptor {
+        self.style = style;
+        self
+    }
+}
+
+/// A face found in a font file, indexed without being kept in memory.
+#[derive(Clone, Debug)]
+pub struct FaceInfo {
+    pub path: PathBuf,
+    /// Index of the face in a font collection, 0 otherwise.
+    pub index: u32,
+    pub family: String,
+    pub weight: u16,
+    pub style: FontStyle,
+    /// Sorted ranges of the characters mapped by the font.
+    coverage: Vec<(u32, u32)>,
+}
+
+impl FaceInfo {
+    pub fn covers(&self, c: char) -> bool {
+        let c = c as u32;
+        match self.coverage.binary_search_by(|&(start, _)| start.cmp(&c)) {
+            Ok(_) => true,
+            Err(0) => false,
+            Err(i) => c <= self.coverage[i - 1].1,
+        }
+    }
+}
+
+/// An index of font faces, with font matching and fallback.
+/// The faces are read from the files when they are matched.
+pub struct FontCollection {
+    faces: Vec<FaceInfo>,
+    generics: HashMap<String, Vec<String>>,
+    loaded: HashMap<usize, Option<Font>>,
+    fallbacks: HashMap<(usize, char), Option<usize>>,
+    skipped: Vec<(PathBuf, String)>,
+}
+
+impl FontCollection {
+    /// An empty collection, with the usual families of Linux distributions as generic families.
+    pub fn new() -> FontCollection {
+        let generic = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+        let mut generics = HashMap::new();
+        generics.insert(
+            "serif".to_string(),
+            generic(&[
+                "DejaVu Serif",
+                "Noto Serif",
+                "Liberation Serif",
+                "FreeSerif",
+            ]),
+        );
+        generics.insert(
+            "sans-serif".to_string(),
+            generic(&[
+                "DejaVu Sans",
+                "Noto Sans",
+                "Liberation Sans",
+                "FreeSans",
+                "Cantarell",
+            ]),
+        );
+        generics.insert(
+            "monospace".to_string(),
+            generic(&[
+                "DejaVu Sans Mono",
+                "Noto Sans Mono",
+                "Liberation Mono",
+                "FreeMono",
+            ]),
+        );
+        generics.insert(
+            "emoji".to_string(),
+            generic(&[
+                "Noto Color Emoji",
+                "Twemoji",
+                "JoyPixels",
+                "Noto Emoji",
+                "Symbola",
+            ]),
+        );
+        FontCollection {
+            faces: Vec::new(),
+            generics,
+            loaded: HashMap::new(),
+            fallbacks: HashMap::new(),
+            skipped: Vec::new(),
+        }
+    }
+
+    /// A collection of the fonts of the standard Linux directories,
+    /// the system ones and the ones of the user.
+    pub fn system() -> FontCollection {
+        let mut collection = FontCollection::new();
+        for dir in system_dirs() {
+            collection.add_dir(dir);
+        }
+        collection
+    }
+
+    /// Indexes the fonts of a directory and of its subdirectories.
+    /// Missing directories are ignored, invalid files are skipped.
+    pub fn add_dir<P: AsRef<Path>>(&mut self, dir: P) {
+        let mut entries: Vec<PathBuf> = match fs::read_dir(dir) {
+            Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+            Err(_) => return,
+        };
+        entries.sort();
+        for path in entries {
+            if path.is_dir() {
+                self.add_dir(path);
+            } else if is_font_file(&path) {
+                if let Err(err) = self.add_file(&path) {
+                    self.skipped.push((path, err.to_string()));
+                }
+            }
+        }
+    }
+
+    /// Indexes the faces of a font file or collection.
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let data = fs::read(path)?;
+        let count = ttf_parser::fonts_in_collection(&data).unwrap_or(1);
+        for index in 0..count {
+            let face = ttf_parser::Face::parse(&data, index)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+            if let Some(info) = face_info(&face, path, index) {
+                self.faces.push(info);
+            }
+        }
+        self.fallbacks.clear();
+        Ok(())
+    }
+
+    pub fn faces(&self) -> &[FaceInfo] {
+        &self.faces
+    }
+
+    /// The font files that could not be indexed or loaded, with the reason.
+    pub fn skipped(&self) -> &[(PathBuf, String)] {
+        &self.skipped
+    }
+
+    /// Names of the families, sorted.
+    pub fn families(&self) -> Vec<&str> {
+        let mut families: Vec<&str> = self.faces.iter().map(|f| f.family.as_str()).collect();
+        families.sort_unstable();
+        families.dedup();
+        families
+    }
+
+    /// Sets the families a generic family stands for.
+    pub fn set_generic_family(&mut self, generic: &str, families: Vec<String>) {
+        self.generics.insert(generic.to_string(), families);
+        self.fallbacks.clear();
+    }
+
+    /// Index in `faces()` of the face matching `desc`.
+    /// The first family found is used, falling back to "sans-serif" and then to any family.
+    pub fn query(&self, desc: &FontDescriptor) -> Option<usize> {
+        self.families_of(desc)
+            .into_iter()
+            .chain(self.expand("sans-serif"))
+            .chain(self.families().into_iter().map(|f| f.to_string()))
+            .find_map(|family| self.query_family(&family, desc))
+    }
+
+    /// The font of the face matching `desc`.
+    pub fn select(&mut self, desc: &FontDescriptor) -> Option<Font> {
+        let idx = self.query(desc)?;
+        self.load(idx)
+    }
+
+    /// The font of a face, read once.
+    pub fn load(&mut self, idx: usize) -> Option<Font> {
+        let (face, skipped) = (&self.faces[idx], &mut self.skipped);
+        self.loaded
+            .entry(idx)
+            .or_insert_with(|| match Font::from_file(&face.path, face.index) {
+                Ok(font) => Some(font),
+                Err(err) => {
+                    skipped.push((face.path.clone(), err.to_string()));
+                    None
+                }
+            })
+            .clone()
+    }
+
+    /// A font covering `c`, preferably the one matching `desc`.
+    /// The families of `desc` come first, then the emoji families for the emoji,
+    /// then all the others, with the closest weight and style.
+    pub fn fallback(&mut self, desc: &FontDescriptor, c: char) -> Option<Font> {
+        let primary = self.query(desc)?;
+        let idx = match self.fallbacks.get(&(primary, c)) {
+            Some(&idx) => idx,
+            None => {
+                let idx = self.find_fallback(primary, desc, c);
+                self.fallbacks.insert((primary, c), idx);
+                idx
+            }
+        };
+        self.load(idx?)
+    }
+
+    /// The font matching `desc`, followed by the fonts needed for the characters
+    /// of `text` that it does not cover.
+    /// Suits `ParagraphStyle::font` and `ParagraphStyle::fallback`.
+    pub fn fallback_chain(&mut self, desc: &FontDescriptor, text: &str) -> Vec<Font> {
+        let mut chain: Vec<Font> = self.select(desc).into_iter().collect();
+        for c in text.chars() {
+            if chain.iter().any(|f| f.glyph_index(c).is_some()) || is_default_ignorable(c) {
+                continue;
+            }
+            if let Some(font) = self.fallback(desc, c) {
+                if !chain.contains(&font) {
+                    chain.push(font);
+                }
+            }
+        }
+        chain
+    }
+
+    fn find_fallback(&self, primary: usize, desc: &FontDescriptor, c: char) -> Option<usize> {
+        if self.faces[primary].covers(c) {
+            return Some(primary);
+        }
+        let emoji = if is_emoji(c) {
+            self.expand("emoji")
+        } else {
+            Vec::new()
+        };
+        self.families_of(desc)
+            .into_iter()
+            .chain(emoji)
+            .chain(self.families().into_iter().map(|f| f.to_string()))
+            .filter_map(|family| self.query_family(&family, desc))
+            .find(|&idx| self.faces[idx].covers(c))
+    }
+
+    /// The families of a descriptor, with the generic families expanded.
+    fn families_of(&self, desc: &FontDescriptor) -> Vec<String> {
+        desc.families.iter().flat_map(|f| self.expand(f)).collect()
+    }
+
+    fn expand(&self, family: &str) -> Vec<String> {
+        match self.generics.get(&family.to_lowercase()) {
+            Some(families) => families.clone(),
+            None => vec![family.to_string()],
+        }
+    }
+
+    /// The face of a family closest to `desc`: the style is matched first, then the weight.
+    fn query_family(&self, family: &str, desc: &FontDescriptor) -> Option<usize> {
+        let faces: Vec<usize> = (0..self.faces.len())
+            .filter(|&i| self.faces[i].family.eq_ignore_ascii_case(family))
+            .collect();
+        let styles = match desc.style {
+            FontStyle::Normal => [FontStyle::Normal, FontStyle::Oblique, FontStyle::Italic],
+            FontStyle::Italic => [FontStyle::Italic, FontStyle::Oblique, FontStyle::Normal],
+            FontStyle::Oblique => [FontStyle::Oblique, FontStyle::Italic, FontStyle::Normal],
+        };
+        let style = styles
+            .iter()
+            .find(|&&s| faces.iter().any(|&i| self.faces[i].style == s))?;
+        faces
+            .into_iter()
+            .filter(|&i| self.faces[i].style == *style)
+            .min_by_key(|&i| weight_distance(desc.weight, self.faces[i].weight))
+    }
+}
+
+impl Default for FontCollection {
+    fn default() -> FontCollection {
+        FontCollection::new()
+    }
+}
+
+/// Orders the weights as the CSS font matching algorithm does.
+/// Between 400 and 500, the weights up to 500 come first, then the lighter ones,
+/// then the heavier ones. Below 400 the lighter weights come first,
+/// above 500 the heavier ones.
+fn weight_distance(desired: u16, weight: u16) -> (u8, u16) {
+    let (d, w) = (desired as i32, weight as i32);
+    let (rank, dist) = if (400..=500).contains(&d) {
+        if w >= d && w <= 500 {
+            (0, w - d)
+        } else if w < d {
+            (1, d - w)
+        } else {
+            (2, w - d)
+        }
+    } else if d < 400 {
+        if w <= d {
+            (0, d - w)
+        } else {
+            (1, w - d)
+        }
+    } else if w >= d {
+        (0, w - d)
+    } else {
+        (1, d - w)
+    };
+    (rank, dist as u16)
+}
+
+fn face_info(face: &ttf_parser::Face, path: &Path, index: u32) -> Option<FaceInfo> {
+    let family = face_name(face, name_id::TYPOGRAPHIC_FAMILY)
+        .or_else(|| face_name(face, name_id::FAMILY))?;
+    let style = if face.is_italic() {
+        FontStyle::Italic
+    } else if face.is_oblique() {
+        FontStyle::Oblique
+    } else {
+        FontStyle::Normal
+    };
+    let mut codepoints = Vec::new();
+    if let Some(cmap) = face.tables().cmap {
+        for subtable in cmap.subtables {
+            if subtable.is_unicode() {
+                subtable.codepoints(|c| codepoints.push(c));
+            }
+        }
+    }
+    codepoints.sort_unstable();
+    codepoints.dedup();
+    let mut coverage: Vec<(u32, u32)> = Vec::new();
+    for c in codepoints {
+        match coverage.last_mut() {
+            Some(range) if range.1 + 1 == c => range.1 = c,
+            _ => coverage.push((c, c)),
+        }
+    }
+    Some(FaceInfo {
+        path: path.to_path_buf(),
+        index,
+        family,
+        weight: face.weight().to_number(),
+        style,
+        coverage,
+    })
+}
+
+/// A name of the face, in American English if there is one.
+fn face_name(face: &ttf_parser::Face, id: u16) -> Option<String> {
+    let names: Vec<_> = face
+        .names()
+        .into_iter()
+        .filter(|n| n.name_id == id && n.is_unicode())
+        .collect();
+    names
+        .iter()
+        .find(|n| n.language_id == 0x409)
+        .or_else(|| names.first())
+        .and_then(|n| n.to_string())
+}
+
+fn is_font_file(path: &Path) -> bool {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    ["ttf", "otf", "ttc", "otc"]
+        .iter()
+        .any(|e| ext.eq_ignore_ascii_case(e))
+}
+
+/// The font directories of the XDG base directories, and the legacy ones.
+fn system_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    let home = env::var_os("HOME").map(PathBuf::from);
+    match env::var_os("XDG_DATA_HOME") {
+        Some(data) => dirs.push(PathBuf::from(data).join("fonts")),
+        None => dirs.extend(home.iter().map(|h| h.join(".local/share/fonts"))),
+    }
+    dirs.extend(home.iter().map(|h| h.join(".fonts")));
+    let data_dirs =
+        env::var("XDG_DATA_DIRS").unwrap_or_else(|_| "/usr/local/share:/usr/share".to_string());
+    for dir in data_dirs.split(':').filter(|d| !d.is_empty()) {
+        dirs.push(Path::new(dir).join("fonts"));
+    }
+    for dir in &["/usr/local/share/fonts", "/usr/share/fonts"] {
+        if !dirs.iter().any(|d| d == Path::new(dir)) {
+            dirs.push(PathBuf::from(dir));
+        }
+    }
+    dirs
+}
+
+/// Whether a character is usually displayed as an emoji.
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x231a..=0x23ff | 0x2600..=0x27bf | 0x2b00..=0x2bff | 0x1f000..=0x1faff
+    )
+}
+
+/// Whether a character is invisible and takes the font of the character before it,
+/// such as the joiners and the variation selectors.
+pub(crate) fn is_default_ignorable(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x200b..=0x200f | 0x2060..=0x206f | 0xfe00..=0xfe0f | 0xe0000..=0xe0fff
+    )
+}
+
+#[test]
+fn matching_and_fallback() {
+    let mut fonts = FontCollection::new();
+    fonts.add_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/fonts"));
+    assert_eq!(
+        vec!["Fixture CJK", "Fixture Emoji", "Fixture Sans"],
+        fonts.families()
+    );
+    // invalid files are reported to the caller
+    let skipped: Vec<_> = fonts
+        .skipped()
+        .iter()
+        .map(|s| s.0.file_name().unwrap())
+        .collect();
+    assert_eq!(vec!["Broken.ttf"], skipped);
+
+    let file = |fonts: &FontCollection, desc: &FontDescriptor| {
+        let idx = fonts.query(desc).unwrap();
+        fonts.faces()[idx].path.file_name().unwrap().to_owned()
+    };
+    let sans = FontDescriptor::new("fixture sans");
+    assert_eq!("FixtureSans-Regular.ttf", file(&fonts, &sans));
+    // 600 prefers the heavier weights, 300 the lighter ones then the heavier ones
+    assert_eq!(
+        "FixtureSans-Bold.ttf",
+        file(&fonts, &sans.clone().with_weight(600))
+    );
+    assert_eq!(
+        "FixtureSans-Regular.ttf",
+        file(&fonts, &sans.clone().with_weight(300))
+    );
+    // the style is matched before the weight
+    let italic = sans
+        .clone()
+        .with_style(FontStyle::Italic)
+        .with_weight(BOLD_WEIGHT);
+    assert_eq!("FixtureSans-Italic.ttf", file(&fonts, &italic));
+
+    // unknown families fall back to sans-serif, then to the first family
+    fonts.set_generic_family("sans-serif", vec!["Fixture Sans".to_string()]);
+    assert_eq!(
+        "FixtureSans-Regular.ttf",
+        file(&fonts, &FontDescriptor::new("Nope"))
+    );
+
+    let regular = fonts.select(&sans).unwrap();
+    assert_eq!(Some(regular.clone()), fonts.fallback(&sans, 'a'));
+    let cjk = fonts.fallback(&sans, '一').unwrap();
+    assert!(cjk.glyph_index('一').is_some());
+    assert_eq!(None, fonts.fallback(&sans, '\u{1f600}'));
+    let emoji = fonts.fallback(&sans, '\u{263a}').unwrap();
+    assert!(emoji.glyph_index('\u{263a}').is_some());
+    assert_eq!(
+        vec![regular, cjk],
+        fonts.fallback_chain(&sans, "a 一\u{fe0f}")
+    );
+}
@@ -0,0 +1,199 @@
+use super::raster::{rasterize, GlyphBitmap};
+use super::{Font, GlyphId};
+use crate::geom::Transform;
+use crate::image::Image;
+use ttf_parser::{colr, RasterImageFormat, RgbaColor};
+
+/// The colors of a glyph, premultiplied RGBA with four bytes per pixel.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ColorBitmap {
+    pub width: u32,
+    pub height: u32,
+    /// Position of the top-left pixel relative to the origin of the glyph.
+    pub left: i32,
+    pub top: i32,
+    pub rgba: Vec<u8>,
+}
+
+/// Renders a glyph with colors, from its COLR layers or from its PNG bitmap strike.
+/// The layers are offset by `dx` pixels, the bitmaps are scaled to `size` and stay aligned.
+/// The layers in the foreground color are painted in black,
+/// as the color glyphs do not depend on the paint of the text.
+/// None if the glyph has no colors or covers no pixel.
+pub fn rasterize_color(font: &Font, glyph: GlyphId, size: f32, dx: f32) -> Option<ColorBitmap> {
+    let face = font.face();
+    let id = ttf_parser::GlyphId(glyph);
+    if face.is_color_glyph(id) {
+        let mut layers = Layers(Vec::new());
+        face.paint_color_glyph(id, 0, &mut layers)?;
+        let offset = Transform::translation(dx, 0f32);
+        let bitmaps: Vec<(GlyphBitmap, RgbaColor)> = layers
+            .0
+            .into_iter()
+            .filter_map(|(glyph, color)| {
+                let outline = font.outline(glyph.0, size)?.transformed(&offset);
+                Some((rasterize(&outline)?, color))
+            })
+            .collect();
+        return composite(&bitmaps);
+    }
+    let image = face.glyph_raster_image(id, size.round().max(1f32) as u16)?;
+    if image.format != RasterImageFormat::PNG {
+        return None;
+    }
+    let png = Image::decode(image.data).ok()?;
+    let (width, height) = (png.width(), png.height());
+    // the offsets are in pixels of the strike, up from the bottom-left corner of the image
+    let scale = size / image.pixels_per_em.max(1) as f32;
+    let bitmap = ColorBitmap {
+        width,
+        height,
+        left: image.x as i32,
+        top: -(image.y as i32 + height as i32),
+        rgba: png.into_rgba(),
+    };
+    Some(scaled(&bitmap, scale))
+}
+
+/// Collects the layers of a COLR glyph.
+struct Layers(Vec<(ttf_parser::GlyphId, RgbaColor)>);
+
+impl colr::Painter for Layers {
+    fn outline(&mut self, glyph: ttf_parser::GlyphId) {
+        self.0.push((glyph, RgbaColor::new(0, 0, 0, 255)));
+    }
+
+    fn paint_foreground(&mut self) {}
+
+    fn paint_color(&mut self, color: RgbaColor) {
+        if let Some(layer) = self.0.last_mut() {
+            layer.1 = color;
+        }
+    }
+}
+
+/// Paints the layers in order, each over the ones before it.
+fn composite(layers: &[(GlyphBitmap, RgbaColor)]) -> Option<ColorBitmap> {
+    let left = layers.iter().map(|(b, _)| b.left).min()?;
+    let top = layers.iter().map(|(b, _)| b.top).min()?;
+    let right = layers.iter().map(|(b, _)| b.left + b.width as i32).max()?;
+    let bottom = layers.iter().map(|(b, _)| b.top + b.height as i32).max()?;
+    let (width, height) = ((right - left) as usize, (bottom - top) as usize);
+    let mut pixels = vec![[0f32; 4]; width * height];
+    for (bitmap, color) in layers {
+        let alpha = color.alpha as f32 / 255f32;
+        let src = [
+            color.red as f32 / 255f32 * alpha,
+            color.green as f32 / 255f32 * alpha,
+            color.blue as f32 / 255f32 * alpha,
+            alpha,
+        ];
+        for y in 0..bitmap.height as usize {
+            for x in 0..bitmap.width as usize {
+                let coverage = bitmap.coverage[y * bitmap.width as usize + x] as f32 / 255f32;
+                let px = x + (bitmap.left - left) as usize;
+                let py = y + (bitmap.top - top) as usize;
+                let dst = &mut pixels[py * width + px];
+                let a = src[3] * coverage;
+                for c in 0..4 {
+                    dst[c] = src[c] * coverage + dst[c] * (1f32 - a);
+                }
+            }
+        }
+    }
+    Some(ColorBitmap {
+        width: width as u32,
+        height: height as u32,
+        left,
+        top,
+        rgba: pixels
+            .iter()
+            .flat_map(|p| p.iter().map(|c| (c * 255f32 + 0.5f32) as u8))
+            .collect(),
+    })
+}
+
+/// Resizes a bitmap by averaging the pixels it covers.
+fn scaled(bitmap: &ColorBitmap, scale: f32) -> ColorBitmap {
+    if (scale - 1f32).abs() < 1e-3 {
+        return bitmap.clone();
+    }
+    let left = (bitmap.left as f32 * scale).floor() as i32;
+    let top = (bitmap.top as f32 * scale).floor() as i32;
+    let width = ((bitmap.width as f32 * scale).ceil() as u32).max(1);
+    let height = ((bitmap.height as f32 * scale).ceil() as u32).max(1);
+    // footprint of a pixel of the result in the source, and its weights along an axis
+    let footprint = |i: u32, offset: f32, len: u32| {
+        let start = ((i as f32 + offset) / scale).max(0f32);
+        let end = ((i as f32 + 1f32 + offset) / scale).min(len as f32);
+        (start.floor() as u32..end.ceil() as u32).map(move |s| {
+            (
+                s,
+                (end.min(s as f32 + 1f32) - start.max(s as f32)).max(0f32),
+            )
+        })
+    };
+    let (dx, dy) = (
+        left as f32 - bitmap.left as f32 * scale,
+        top as f32 - bitmap.top as f32 * scale,
+    );
+    // the pixels outside of the source are transparent
+    let area = 1f32 / (scale * scale);
+    let mut rgba = Vec::with_capacity((width * height * 4) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0f32; 4];
+            for (sy, wy) in footprint(y, dy, bitmap.height) {
+                for (sx, wx) in footprint(x, dx, bitmap.width) {
+                    let i = ((sy * bitmap.width + sx) * 4) as usize;
+                    for (s, &t) in sum.iter_mut().zip(&bitmap.rgba[i..i + 4]) {
+                        *s += t as f32 * wx * wy;
+                    }
+                }
+            }
+            rgba.extend(sum.iter().map(|c| (c / area + 0.5f32).min(255f32) as u8));
+        }
+    }
+    ColorBitmap {
+        width,
+        height,
+        left,
+        top,
+        rgba,
+    }
+}
+
+#[test]
+fn color_glyphs() {
+    let path = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/fixtures/fonts/FixtureEmoji-Regular.ttf"
+    );
+    let font = Font::from_file(path, 0).unwrap();
+    let pixel = |b: &ColorBitmap, x: i32, y: i32| {
+        let i = (((y - b.top) * b.width as i32 + x - b.left) * 4) as usize;
+        [b.rgba[i], b.rgba[i + 1], b.rgba[i + 2], b.rgba[i + 3]]
+    };
+
+    // a red square under a half transparent blue one
+    let smiley = font.glyph_index('\u{263a}').unwrap();
+    let b = rasterize_color(&font, smiley, 10f32, 0f32).unwrap();
+    assert_eq!([255, 0, 0, 255], pixel(&b, 1, -1));
+    assert_eq!([127, 0, 128, 255], pixel(&b, 5, -4));
+
+    // a 20 pixels strike, red on its top half
+    let star = font.glyph_index('\u{2b50}').unwrap();
+    let b = rasterize_color(&font, star, 20f32, 0f32).unwrap();
+    assert_eq!((20, 20, 0, -20), (b.width, b.height, b.left, b.top));
+    assert_eq!([255, 0, 0, 255], pixel(&b, 3, -20));
+    assert_eq!([0, 0, 0, 0], pixel(&b, 3, -1));
+    let b = rasterize_color(&font, star, 10f32, 0f32).unwrap();
+    assert_eq!((10, 10, 0, -10), (b.width, b.height, b.left, b.top));
+    assert_eq!([255, 0, 0, 255], pixel(&b, 3, -6));
+    assert_eq!([0, 0, 0, 0], pixel(&b, 3, -5));
+
+    assert_eq!(
+        None,
+        rasterize_color(&font, font.glyph_index(' ').unwrap(), 10f32, 0f32)
+    );
+}
@@ -0,0 +1,693 @@
+use super::collection::is_default_ignorable;
+use super::shape::shape;
+use super::{Font, GlyphId};
+use crate::geom::{FPoint, FRect};
+use crate::paint::Paint;
+use crate::render::{GlyphRun, PositionedGlyph};
+use std::ops::Range;
+use unicode_bidi::{BidiInfo, Level};
+use unicode_linebreak::{linebreaks, BreakOpportunity};
+use unicode_script::{Script, UnicodeScript};
+
+/// Width of the caret rectangles, in pixels.
+const CARET_WIDTH: f32 = 1f32;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Ltr,
+    Rtl,
+}
+
+/// Horizontal alignment of the lines.
+/// `Start` and `End` depend on the direction of the paragraph.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Align {
+    Start,
+    End,
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ParagraphStyle {
+    pub font: Font,
+    /// Fonts for the characters that `font` does not cover, in order of preference.
+    pub fallback: Vec<Font>,
+    /// Font size, in pixels.
+    pub size: f32,
+    /// Distance between the baselines, the one of the font if None.
+    pub line_height: Option<f32>,
+    pub align: Align,
+    /// Guessed from the first strong character of the text if None.
+    pub direction: Option<Direction>,
+    /// Width at which the lines are wrapped. The lines are only broken
+    /// at the mandatory breaks if None.
+    pub max_width: Option<f32>,
+    pub max_lines: Option<usize>,
+    /// Ends the last line with an ellipsis when `max_lines` cuts the text.
+    pub ellipsis: bool,
+}
+
+impl ParagraphStyle {
+    pub fn new(font: Font, size: f32) -> ParagraphStyle {
+        ParagraphStyle {
+            font,
+            fallback: Vec::new(),
+            size,
+            line_height: None,
+            align: Align::Start,
+            direction: None,
+            max_width: None,
+            max_lines: None,
+            ellipsis: false,
+        }
+    }
+
+    /// The font at an index of `LayoutGlyph::font`.
+    pub fn font(&self, idx: usize) -> &Font {
+        if idx == 0 {
+            &self.font
+        } else {
+            &self.fallback[idx - 1]
+        }
+    }
+}
+
+/// A glyph of a laid out paragraph.
+#[derive(Clone, PartialEq, Debug)]
+pub struct LayoutGlyph {
+    /// 0 for the font of the style, then the index in the fallback fonts plus one.
+    pub font: usize,
+    pub id: GlyphId,
+    /// Origin of the glyph on the baseline, relative to the paragraph.
+    pub pos: FPoint,
+    pub advance: f32,
+    /// Bytes of the text in the cluster of the glyph, empty for the ellipsis.
+    pub range: Range<usize>,
+    pub rtl: bool,
+    /// False for the line separators, which are not drawn.
+    pub visible: bool,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Line {
+    /// Bytes of the text in the line, including its trailing whitespace.
+    pub range: Range<usize>,
+    pub top: f32,
+    pub height: f32,
+    pub baseline: f32,
+    /// Left of the content of the line, without the trailing whitespace.
+    pub left: f32,
+    pub width: f32,
+    pub rtl: bool,
+    /// Glyphs in visual order.
+    pub glyphs: Vec<LayoutGlyph>,
+}
+
+/// A text shaped and broken into lines, with its glyphs positioned.
+pub struct Paragraph {
+    text: String,
+    style: ParagraphStyle,
+    lines: Vec<Line>,
+    width: f32,
+}
+
+/// Text of a single bidi level, script and font, shaped at once.
+struct Run {
+    range: Range<usize>,
+    glyphs: Vec<LayoutGlyph>,
+}
+
+impl Paragraph {
+    pub fn new(text: &str, style: ParagraphStyle) -> Paragraph {
+        let default_level = style.direction.map(|d| match d {
+            Direction::Ltr => Level::ltr(),
+            Direction::Rtl => Level::rtl(),
+        });
+        let bidi = BidiInfo::new(text, default_level);
+        let runs = shape_runs(text, &bidi.levels, &style);
+
+        // advance of the text before each byte, in logical order
+        let mut advances = vec![0f32; text.len() + 1];
+        for g in runs.iter().flat_map(|r| r.glyphs.iter()) {
+            advances[g.range.start + 1] += g.advance;
+        }
+        for i in 1..advances.len() {
+            advances[i] += advances[i - 1];
+        }
+        let mut boundaries = vec![false; text.len() + 1];
+        for g in runs.iter().flat_map(|r| r.glyphs.iter()) {
+            boundaries[g.range.start] = true;
+        }
+        boundaries[text.len()] = true;
+
+        let measure = Measure {
+            text,
+            advances,
+            boundaries,
+        };
+        let mut ranges = break_lines(&measure, style.max_width);
+        let mut ellipsis = None;
+        if let Some(max) = style.max_lines {
+            if ranges.len() > max.max(1) {
+                ranges.truncate(max.max(1));
+                if style.ellipsis {
+                    let last = ranges.last_mut().unwrap();
+                    let e = ellipsis_glyph(&style);
+                    let end = measure.fit(last.clone(), style.max_width, e.advance);
+                    last.end = measure.trim_end(last.start..end);
+                    ellipsis = Some(e);
+                }
+            }
+        }
+
+        let metrics = style.font.metrics(style.size);
+        let text_height = metrics.ascent + metrics.descent;
+        let height = style.line_height.unwrap_or(text_height + metrics.line_gap);
+        let n = ranges.len();
+        let mut lines: Vec<Line> = ranges
+            .into_iter()
+            .enumerate()
+            .map(|(i, range)| {
+                let top = i as f32 * height;
+                let baseline = top + (height - text_height) / 2f32 + metrics.ascent;
+                let ellipsis = if i + 1 == n { ellipsis.clone() } else { None };
+                build_line(
+                    &measure,
+                    &bidi,
+                    &runs,
+                    range,
+                    ellipsis,
+                    (top, height, baseline),
+                )
+            })
+            .collect();
+
+        let width = style
+            .max_width
+            .unwrap_or_else(|| lines.iter().map(|l| l.width).fold(0f32, f32::max));
+        for line in &mut lines {
+            let align = match (style.align, line.rtl) {
+                (Align::Start, false) | (Align::End, true) | (Align::Left, _) => Align::Left,
+                (Align::Start, true) | (Align::End, false) | (Align::Right, _) => Align::Right,
+                (Align::Center, _) => Align::Center,
+            };
+            let left = match align {
+                Align::Left => 0f32,
+                Align::Right => width - line.width,
+                _ => (width - line.width) / 2f32,
+            };
+            // the trailing whitespace of right-to-left lines hangs on the left
+            let shift = if line.rtl {
+                left - (line_advance(line) - line.width)
+            } else {
+                left
+            };
+            line.left = left;
+            for g in &mut line.glyphs {
+                g.pos.x += shift;
+            }
+        }
+
+        Paragraph {
+            text: text.to_string(),
+            style,
+            lines,
+            width,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn style(&self) -> &ParagraphStyle {
+        &self.style
+    }
+
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    /// The maximum width if set, the width of the longest line otherwise.
+    pub fn width(&self) -> f32 {
+        self.width
+    }
+
+    pub fn height(&self) -> f32 {
+        self.lines.last().map_or(0f32, |l| l.top + l.height)
+    }
+
+    /// The visible glyphs, with the paragraph at `origin`, in one run per font.
+    pub fn glyph_runs<P: Into<Paint>>(&self, origin: FPoint, paint: P) -> Vec<GlyphRun> {
+        let paint = paint.into();
+        let mut runs: Vec<GlyphRun> = Vec::new();
+        let glyphs = self.lines.iter().flat_map(|l| l.glyphs.iter());
+        for g in glyphs.filter(|g| g.visible) {
+            let font = self.style.font(g.font);
+            let glyph = PositionedGlyph {
+                id: g.id,
+                pos: origin + g.pos,
+            };
+            match runs.iter_mut().find(|r| r.font == *font) {
+                Some(run) => run.glyphs.push(glyph),
+                None => runs.push(GlyphRun {
+                    font: font.clone(),
+                    size: self.style.size,
+                    glyphs: vec![glyph],
+                    paint: paint.clone(),
+                }),
+            }
+        }
+        runs
+    }
+
+    /// Byte offset of the caret position closest to `point`.
+    pub fn hit_test(&self, point: FPoint) -> usize {
+        let idx = self
+            .lines
+            .iter()
+            .position(|l| point.y < l.top + l.height)
+            .unwrap_or(self.lines.len() - 1);
+        let line = &self.lines[idx];
+        let offset = self
+            .carets(line)
+            .into_iter()
+            .min_by(|a, b| {
+                let (da, db) = ((a.0 - point.x).abs(), (b.0 - point.x).abs());
+                da.partial_cmp(&db).unwrap()
+            })
+            .map_or(line.range.start, |(_, offset)| offset);
+        if offset == line.range.end && idx + 1 < self.lines.len() {
+            // the end of a line is displayed at the start of the next one
+            self.text[..offset]
+                .char_indices()
+                .next_back()
+                .map_or(offset, |(i, _)| i.max(line.range.start))
+        } else {
+            offset
+        }
+    }
+
+    /// Rectangle of the caret before the character at byte `offset`.
+    pub fn caret_rect(&self, offset: usize) -> FRect {
+        let line = self
+            .lines
+            .iter()
+            .rev()
+            .find(|l| l.range.start <= offset)
+            .unwrap_or(&self.lines[0]);
+        let x = self
+            .carets(line)
+            .into_iter()
+            .filter(|&(_, o)| o <= offset)
+            .max_by_key(|&(_, o)| o)
+            .map_or(line.left, |(x, _)| x);
+        FRect::new(x, line.top, CARET_WIDTH, line.height)
+    }
+
+    /// Rectangles covering the text in the `range` of bytes, in visual order.
+    pub fn selection_rects(&self, range: Range<usize>) -> Vec<FRect> {
+        let mut rects: Vec<FRect> = Vec::new();
+        for line in &self.lines {
+            let mut line_rects: Vec<FRect> = Vec::new();
+            for g in &line.glyphs {
+                let start = range.start.max(g.range.start);
+                let end = range.end.min(g.range.end);
+                if start >= end {
+                    continue;
+                }
+                let xs: Vec<f32> = self
+                    .glyph_carets(g)
+                    .filter(|&(_, o)| o >= start && o <= end)
+                    .map(|(x, _)| x)
+                    .collect();
+                let x0 = xs.iter().cloned().fold(f32::INFINITY, f32::min);
+                let x1 = xs.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+                if x1 <= x0 {
+                    continue;
+                }
+                match line_rects.last_mut() {
+                    Some(r) if (r.x + r.w - x0).abs() < 1e-3 => r.w = x1 - r.x,
+                    _ => line_rects.push(FRect::new(x0, line.top, x1 - x0, line.height)),
+                }
+            }
+            rects.extend(line_rects);
+        }
+        rects
+    }
+
+    /// Caret positions of a line: x and byte offset, in visual order.
+    fn carets(&self, line: &Line) -> Vec<(f32, usize)> {
+        let carets: Vec<_> = line
+            .glyphs
+            .iter()
+            .flat_map(|g| self.glyph_carets(g))
+            .collect();
+        if carets.is_empty() {
+            vec![(line.left, line.range.start)]
+        } else {
+            carets
+        }
+    }
+
+    /// Caret positions at the character boundaries of the cluster of a glyph.
+    /// Ligatures are divided evenly between their characters.
+    fn glyph_carets<'a>(&'a self, g: &'a LayoutGlyph) -> impl Iterator<Item = (f32, usize)> + 'a {
+        let text = &self.text[g.range.clone()];
+        let n = text.chars().count().max(1) as f32;
+        let offsets = text
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(Some(text.len()))
+            .map(move |i| g.range.start + i);
+        offsets.enumerate().map(move |(k, offset)| {
+            let d = g.advance * k as f32 / n;
+            let x = if g.rtl {
+                g.pos.x + g.advance - d
+            } else {
+                g.pos.x + d
+            };
+            (x, offset)
+        })
+    }
+}
+
+/// Splits the text in runs of a single bidi level, script and font, and shapes them.
+fn shape_runs(text: &str, levels: &[Level], style: &ParagraphStyle) -> Vec<Run> {
+    let mut runs: Vec<(Range<usize>, Level, Script, usize)> = Vec::new();
+    for (i, c) in text.char_indices() {
+        let level = levels[i];
+        let script = c.script();
+        let end = i + c.len_utf8();
+        let mut font = font_of(style, c);
+        if let Some((range, l, s, f)) = runs.last_mut() {
+            // common characters, such as spaces and punctuation, join the script before them,
+            // and keep its font if it covers them, as marks and joiners do
+            let common = script == Script::Common || script == Script::Inherited;
+            if (common && style.font(*f).glyph_index(c).is_some())
+                || script == Script::Inherited
+                || is_default_ignorable(c)
+            {
+                font = *f;
+            }
+            if *l == level && *f == font && (common || *s == script || *s == Script::Common) {
+                range.end = end;
+                if !common {
+                    *s = script;
+                }
+                continue;
+            }
+        }
+        runs.push((i..end, level, script, font));
+    }
+    runs.into_iter()
+        .map(|(range, level, _, font)| {
+            let run_text = &text[range.clone()];
+            let shaped = shape(style.font(font), style.size, run_text, level.is_rtl());
+            let mut clusters: Vec<usize> = shaped.iter().map(|g| g.cluster).collect();
+            clusters.sort_unstable();
+            clusters.dedup();
+            let glyphs = shaped
+                .iter()
+                .map(|g| {
+                    let next = clusters.iter().find(|&&c| c > g.cluster);
+                    let end = next.map_or(run_text.len(), |&c| c);
+                    let separator = run_text[g.cluster..end].chars().all(is_separator);
+                    LayoutGlyph {
+                        font,
+                        id: g.id,
+                        pos: g.offset,
+                        advance: if separator { 0f32 } else { g.advance },
+                        range: range.start + g.cluster..range.start + end,
+                        rtl: level.is_rtl(),
+                        visible: !separator,
+                    }
+                })
+                .collect();
+            Run { range, glyphs }
+        })
+        .collect()
+}
+
+/// Index of the first font covering a character, the one of the style if none does.
+fn font_of(style: &ParagraphStyle, c: char) -> usize {
+    if style.font.glyph_index(c).is_some() {
+        return 0;
+    }
+    style
+        .fallback
+        .iter()
+        .position(|f| f.glyph_index(c).is_some())
+        .map_or(0, |i| i + 1)
+}
+
+fn ellipsis_glyph(style: &ParagraphStyle) -> LayoutGlyph {
+    let font = &style.font;
+    let (id, count) = match font.glyph_index('\u{2026}') {
+        Some(id) => (id, 1),
+        None => (font.glyph_index('.').unwrap_or(0), 3),
+    };
+    LayoutGlyph {
+        font: 0,
+        id,
+        pos: FPoint::new(0f32, 0f32),
+        advance: font.advance(id, style.size) * count as f32,
+        range: 0..0,
+        rtl: false,
+        visible: true,
+    }
+}
+
+/// Widths of the text in logical order.
+struct Measure<'a> {
+    text: &'a str,
+    /// Advance of the text before each byte.
+    advances: Vec<f32>,
+    /// Whether each byte starts a cluster.
+    boundaries: Vec<bool>,
+}
+
+impl<'a> Measure<'a> {
+    /// Width of a range of the text, without its trailing whitespace.
+    fn width(&self, range: Range<usize>) -> f32 {
+        let end = self.trim_end(range.clone());
+        self.advances[end] - self.advances[range.start]
+    }
+
+    fn trim_end(&self, range: Range<usize>) -> usize {
+        range.start + self.text[range].trim_end().len()
+    }
+
+    /// Largest end of the range for which its width and `extra` fit in `max`.
+    /// At least one cluster is kept, unless `extra` is not zero.
+    fn fit(&self, range: Range<usize>, max: Option<f32>, extra: f32) -> usize {
+        let max = match max {
+            Some(max) => max,
+            None => return range.end,
+        };
+        let mut end = range.end;
+        while end > range.start && self.width(range.start..end) + extra > max {
+            end -= 1;
+            while !self.boundaries[end] {
+                end -= 1;
+            }
+            if extra == 0f32 && end == range.start {
+                return (range.start + 1..=range.end)
+                    .find(|&e| self.boundaries[e])
+                    .unwrap_or(range.end);
+            }
+        }
+        end
+    }
+}
+
+/// Breaks the text at the opportunities of UAX #14, so that the lines fit in `max_width`.
+/// Words longer than a line are broken between clusters.
+fn break_lines(m: &Measure, max_width: Option<f32>) -> Vec<Range<usize>> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut candidate = None;
+    for (pos, opportunity) in linebreaks(m.text) {
+        if let Some(max) = max_width {
+            while start < pos && m.width(start..pos) > max {
+                let end = match candidate.take() {
+                    Some(c) if c > start => c,
+                    _ => m.fit(start..pos, Some(max), 0f32),
+                };
+                lines.push(start..end);
+                start = end;
+            }
+        }
+        if opportunity == BreakOpportunity::Mandatory {
+            lines.push(start..pos);
+            start = pos;
+            candidate = None;
+        } else {
+            candidate = Some(pos);
+        }
+    }
+    // a line separator at the end starts an empty line
+    let needs_empty = match lines.last() {
+        Some(last) => !last.is_empty() && m.text.chars().next_back().is_some_and(is_separator),
+        None => true,
+    };
+    if needs_empty {
+        lines.push(m.text.len()..m.text.len());
+    }
+    lines
+}
+
+fn build_line(
+    m: &Measure,
+    bidi: &BidiInfo,
+    runs: &[Run],
+    range: Range<usize>,
+    ellipsis: Option<LayoutGlyph>,
+    (top, height, baseline): (f32, f32, f32),
+) -> Line {
+    let para = bidi
+        .paragraphs
+        .iter()
+        .find(|p| p.range.start <= range.start && range.start < p.range.end)
+        .or_else(|| bidi.paragraphs.last());
+    let rtl = para.is_some_and(|p| p.level.is_rtl());
+    let mut glyphs = Vec::new();
+    if let (Some(para), false) = (para, range.is_empty()) {
+        let (levels, visual_runs) = bidi.visual_runs(para, range.clone());
+        for vr in visual_runs {
+            let mut logical: Vec<&Run> = runs
+                .iter()
+                .filter(|r| r.range.start < vr.end && vr.start < r.range.end)
+                .collect();
+            if levels[vr.start].is_rtl() {
+                logical.reverse();
+            }
+            for run in logical {
+                let inside = |g: &&LayoutGlyph| vr.contains(&g.range.start);
+                glyphs.extend(run.glyphs.iter().filter(inside).cloned());
+            }
+        }
+    }
+    if let Some(mut e) = ellipsis {
+        e.range = range.end..range.end;
+        e.rtl = rtl;
+        if rtl {
+            glyphs.insert(0, e);
+        } else {
+            glyphs.push(e);
+        }
+    }
+    let mut x = 0f32;
+    for g in &mut glyphs {
+        g.pos = FPoint::new(x + g.pos.x, baseline + g.pos.y);
+        x += g.advance;
+    }
+    let ellipsis_width: f32 = glyphs
+        .iter()
+        .filter(|g| g.range.is_empty())
+        .map(|g| g.advance)
+        .sum();
+    Line {
+        width: m.width(range.clone()) + ellipsis_width,
+        range,
+        top,
+        height,
+        baseline,
+        left: 0f32,
+        rtl,
+        glyphs,
+    }
+}
+
+fn is_separator(c: char) -> bool {
+    matches!(
+        c,
+        '\n' | '\r' | '\u{b}' | '\u{c}' | '\u{85}' | '\u{2028}' | '\u{2029}'
+    )
+}
+
+/// Advance of a line, with its trailing whitespace.
+fn line_advance(line: &Line) -> f32 {
+    line.glyphs.iter().map(|g| g.advance).sum()
+}
+
+#[cfg(test)]
+fn fixture(name: &str) -> Font {
+    let path = format!("{}/fixtures/fonts/{}", env!("CARGO_MANIFEST_DIR"), name);
+    Font::from_file(path, 0).unwrap()
+}
+
+#[test]
+fn line_breaking() {
+    // advances are 5 pixels, spaces 2.5
+    let mut style = ParagraphStyle::new(fixture("FixtureSans-Regular.ttf"), 10f32);
+    style.max_width = Some(20f32);
+    let p = Paragraph::new("aaa bbb ccc", style.clone());
+    let ranges: Vec<_> = p.lines().iter().map(|l| l.range.clone()).collect();
+    assert_eq!(vec![0..4, 4..8, 8..11], ranges);
+    assert_eq!(15f32, p.lines()[0].width);
+
+    // words longer than a line are broken
+    let p = Paragraph::new("aaaaaaaaa", style.clone());
+    let ranges: Vec<_> = p.lines().iter().map(|l| l.range.clone()).collect();
+    assert_eq!(vec![0..4, 4..8, 8..9], ranges);
+
+    style.max_lines = Some(1);
+    style.ellipsis = true;
+    let p = Paragraph::new("aaa bbb ccc", style.clone());
+    assert_eq!(1, p.lines().len());
+    assert_eq!(0..2, p.lines()[0].range);
+    assert_eq!(20f32, p.lines()[0].width);
+
+    // ideographs can be broken anywhere
+    let mut style = ParagraphStyle::new(fixture("FixtureCJK-Regular.ttf"), 10f32);
+    style.max_width = Some(25f32);
+    let p = Paragraph::new("一丁丂七", style);
+    let ranges: Vec<_> = p.lines().iter().map(|l| l.range.clone()).collect();
+    assert_eq!(vec![0..6, 6..12], ranges);
+}
+
+#[test]
+fn bidi_and_carets() {
+    let style = ParagraphStyle::new(fixture("FixtureSans-Regular.ttf"), 10f32);
+    // the Hebrew letters are 2 bytes long and displayed from right to left
+    let p = Paragraph::new("ab אב", style.clone());
+    let line = &p.lines()[0];
+    let clusters: Vec<_> = line.glyphs.iter().map(|g| g.range.start).collect();
+    assert_eq!(vec![0, 1, 2, 5, 3], clusters);
+    assert_eq!(12.5f32, line.glyphs[3].pos.x);
+
+    assert_eq!(0, p.hit_test(FPoint::new(-3f32, 5f32)));
+    assert_eq!(3, p.hit_test(FPoint::new(22f32, 5f32)));
+    assert_eq!(5, p.hit_test(FPoint::new(16f32, 5f32)));
+    assert_eq!(17.5f32, p.caret_rect(5).x);
+    assert_eq!(
+        vec![FRect::new(12.5f32, 0f32, 5f32, line.height)],
+        p.selection_rects(5..7)
+    );
+
+    // right-to-left paragraphs are aligned on the right
+    let mut style = style;
+    style.max_width = Some(50f32);
+    let p = Paragraph::new("אב", style);
+    assert!(p.lines()[0].rtl);
+    assert_eq!(40f32, p.lines()[0].glyphs[0].pos.x);
+    assert_eq!(2, p.lines()[0].glyphs[0].range.start);
+}
+
+#[test]
+fn fallback_fonts() {
+    let mut style = ParagraphStyle::new(fixture("FixtureSans-Regular.ttf"), 10f32);
+    style.fallback.push(fixture("FixtureCJK-Regular.ttf"));
+    let p = Paragraph::new("ab 一丁", style.clone());
+    let fonts: Vec<_> = p.lines()[0].glyphs.iter().map(|g| g.font).collect();
+    assert_eq!(vec![0, 0, 0, 1, 1], fonts);
+    assert_eq!(32.5f32, p.width());
+
+    let runs = p.glyph_runs(FPoint::new(0f32, 0f32), [0f32, 0f32, 0f32, 1f32]);
+    assert_eq!(2, runs.len());
+    assert_eq!(style.fallback[0], runs[1].font);
+    assert_eq!(2, runs[1].glyphs.len());
+}
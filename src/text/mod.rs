@@ -0,0 +1,174 @@
+use crate::geom::FPoint;
+use crate::path::{Path, PathBuilder};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::{fs, io};
+
+mod collection;
+mod color;
+mod layout;
+mod raster;
+mod shape;
+
+pub use self::collection::{
+    FaceInfo, FontCollection, FontDescriptor, FontStyle, BOLD_WEIGHT, NORMAL_WEIGHT,
+};
+pub use self::color::{rasterize_color, ColorBitmap};
+pub use self::layout::{Align, Direction, LayoutGlyph, Line, Paragraph, ParagraphStyle};
+pub use self::raster::{rasterize, GlyphBitmap};
+pub use self::shape::{shape, ShapedGlyph};
+
+pub type GlyphId = u16;
+
+/// A face of a TrueType or OpenType font.
+/// Clones share the font data.
+#[derive(Clone)]
+pub struct Font {
+    data: Arc<FontData>,
+}
+
+struct FontData {
+    id: u64,
+    bytes: Vec<u8>,
+    index: u32,
+}
+
+/// Vertical metrics of a font, in pixels.
+/// The ascent and the descent are both positive.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct FontMetrics {
+    pub ascent: f32,
+    pub descent: f32,
+    pub line_gap: f32,
+}
+
+impl Font {
+    /// Loads the face at `index` in a font file or collection.
+    pub fn from_bytes(bytes: Vec<u8>, index: u32) -> io::Result<Font> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        ttf_parser::Face::parse(&bytes, index)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        Ok(Font {
+            data: Arc::new(FontData {
+                id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+                bytes,
+                index,
+            }),
+        })
+    }
+
+    pub fn from_file<P: AsRef<std::path::Path>>(path: P, index: u32) -> io::Result<Font> {
+        Font::from_bytes(fs::read(path)?, index)
+    }
+
+    /// Identifies the loaded font, clones have the same id.
+    pub fn id(&self) -> u64 {
+        self.data.id
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.data.bytes
+    }
+
+    pub fn index(&self) -> u32 {
+        self.data.index
+    }
+
+    pub(crate) fn face(&self) -> ttf_parser::Face<'_> {
+        ttf_parser::Face::parse(&self.data.bytes, self.data.index)
+            .expect("font data checked at loading")
+    }
+
+    pub fn metrics(&self, size: f32) -> FontMetrics {
+        let face = self.face();
+        let scale = size / face.units_per_em() as f32;
+        FontMetrics {
+            ascent: face.ascender() as f32 * scale,
+            descent: -face.descender() as f32 * scale,
+            line_gap: face.line_gap() as f32 * scale,
+        }
+    }
+
+    pub fn glyph_index(&self, c: char) -> Option<GlyphId> {
+        self.face().glyph_index(c).map(|g| g.0)
+    }
+
+    /// Horizontal advance of a glyph, in pixels.
+    pub fn advance(&self, glyph: GlyphId, size: f32) -> f32 {
+        let face = self.face();
+        let advance = face
+            .glyph_hor_advance(ttf_parser::GlyphId(glyph))
+            .unwrap_or(0);
+        advance as f32 * size / face.units_per_em() as f32
+    }
+
+    /// Outline of a glyph in pixels, with the origin on the baseline and y going down.
+    /// None for the glyphs without outline, such as spaces.
+    pub fn outline(&self, glyph: GlyphId, size: f32) -> Option<Path> {
+        let face = self.face();
+        let mut outline = Outline {
+            builder: Path::builder(),
+            scale: size / face.units_per_em() as f32,
+        };
+        face.outline_glyph(ttf_parser::GlyphId(glyph), &mut outline)?;
+        Some(outline.builder.build())
+    }
+}
+
+impl std::fmt::Debug for Font {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Font")
+            .field("id", &self.data.id)
+            .field("index", &self.data.index)
+            .finish()
+    }
+}
+
+impl PartialEq for Font {
+    fn eq(&self, other: &Font) -> bool {
+        self.data.id == other.data.id
+    }
+}
+
+struct Outline {
+    builder: PathBuilder,
+    scale: f32,
+}
+
+impl Outline {
+    fn point(&self, x: f32, y: f32) -> FPoint {
+        FPoint::new(x * self.scale, -y * self.scale)
+    }
+}
+
+impl ttf_parser::OutlineBuilder for Outline {
+    fn move_to(&mut self, x: f32, y: f32) {
+        let p = self.point(x, y);
+        self.builder.move_to(p);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let p = self.point(x, y);
+        self.builder.line_to(p);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (c, p) = (self.point(x1, y1), self.point(x, y));
+        self.builder.quad_to(c, p);
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (c1, c2, p) = (self.point(x1, y1), self.point(x2, y2), self.point(x, y));
+        self.builder.cubic_to(c1, c2, p);
+    }
+
+    fn close(&mut self) {
+        self.builder.close();
+    }
+}
+
+#[test]
+fn invalid_font() {
+    let err = Font::from_bytes(vec![0u8; 16], 0).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+}
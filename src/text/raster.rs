@@ -0,0 +1,160 @@
+use crate::geom::FPoint;
+use crate::path::Path;
+
+/// Flattening tolerance of the outlines, in pixels.
+const TOLERANCE: f32 = 0.05;
+
+/// The coverage of a glyph, one byte per pixel.
+#[derive(Clone, PartialEq, Debug)]
+pub struct GlyphBitmap {
+    pub width: u32,
+    pub height: u32,
+    /// Position of the top-left pixel relative to the origin of the outline.
+    pub left: i32,
+    pub top: i32,
+    pub coverage: Vec<u8>,
+}
+
+/// Computes the exact area coverage of the pixels by a path filled with the non-zero rule.
+/// The path is in pixels. None if it covers no pixel.
+pub fn rasterize(path: &Path) -> Option<GlyphBitmap> {
+    let lines = path.flatten(TOLERANCE);
+    let mut min = FPoint::new(f32::INFINITY, f32::INFINITY);
+    let mut max = FPoint::new(f32::NEG_INFINITY, f32::NEG_INFINITY);
+    for p in lines.iter().flat_map(|l| l.points.iter()) {
+        min = FPoint::new(min.x.min(p.x), min.y.min(p.y));
+        max = FPoint::new(max.x.max(p.x), max.y.max(p.y));
+    }
+    if !(min.x < max.x && min.y < max.y) {
+        return None;
+    }
+    let (left, top) = (min.x.floor() as i32, min.y.floor() as i32);
+    let width = (max.x.ceil() as i32 - left) as usize;
+    let height = (max.y.ceil() as i32 - top) as usize;
+    let origin = FPoint::new(left as f32, top as f32);
+
+    let mut acc = Accumulator {
+        // one extra cell receives the right part of the last pixel
+        stride: width + 1,
+        height,
+        cells: vec![0f32; (width + 1) * height],
+    };
+    for line in &lines {
+        let n = line.points.len();
+        for i in 0..n {
+            // the polylines are closed for filling
+            acc.line(line.points[i] - origin, line.points[(i + 1) % n] - origin);
+        }
+    }
+
+    let mut coverage = Vec::with_capacity(width * height);
+    for row in acc.cells.chunks(acc.stride) {
+        let mut sum = 0f32;
+        for cell in &row[..width] {
+            sum += cell;
+            coverage.push((sum.abs().min(1f32) * 255f32 + 0.5f32) as u8);
+        }
+    }
+    Some(GlyphBitmap {
+        width: width as u32,
+        height: height as u32,
+        left,
+        top,
+        coverage,
+    })
+}
+
+/// Signed area of the edges, which summed along the rows gives the coverage.
+struct Accumulator {
+    stride: usize,
+    height: usize,
+    cells: Vec<f32>,
+}
+
+impl Accumulator {
+    fn line(&mut self, p0: FPoint, p1: FPoint) {
+        if p0.y == p1.y {
+            return;
+        }
+        let (dir, a, b) = if p0.y < p1.y {
+            (1f32, p0, p1)
+        } else {
+            (-1f32, p1, p0)
+        };
+        let dxdy = (b.x - a.x) / (b.y - a.y);
+        let first = a.y.max(0f32).floor() as usize;
+        let last = (b.y.ceil().max(0f32) as usize).min(self.height);
+        for y in first..last {
+            let ya = a.y.max(y as f32);
+            let yb = b.y.min(y as f32 + 1f32);
+            if yb <= ya {
+                continue;
+            }
+            let xa = a.x + (ya - a.y) * dxdy;
+            let xb = a.x + (yb - a.y) * dxdy;
+            self.row(y, xa.min(xb), xa.max(xb), (yb - ya) * dir);
+        }
+    }
+
+    /// Adds an edge crossing a row from `x0` to `x1` over a height `d`.
+    fn row(&mut self, y: usize, x0: f32, x1: f32, d: f32) {
+        if x1 - x0 < 1e-6 {
+            self.cell(y, x0, x0, d);
+            return;
+        }
+        let mut c = x0.floor();
+        while c < x1 {
+            let u0 = x0.max(c);
+            let u1 = x1.min(c + 1f32);
+            self.cell(y, u0, u1, d * (u1 - u0) / (x1 - x0));
+            c += 1f32;
+        }
+    }
+
+    /// Adds an edge within one pixel, from `u0` to `u1`.
+    fn cell(&mut self, y: usize, u0: f32, u1: f32, d: f32) {
+        let c = (u0.min(u1).floor().max(0f32) as usize).min(self.stride - 2);
+        // the part of the pixel on the right of the edge is covered
+        let f = (0.5f32 * (u0 + u1) - c as f32).clamp(0f32, 1f32);
+        let i = y * self.stride + c;
+        self.cells[i] += d * (1f32 - f);
+        self.cells[i + 1] += d * f;
+    }
+}
+
+#[test]
+fn coverage() {
+    use crate::geom::FRect;
+
+    let bitmap = rasterize(&Path::rect(FRect::new(0.5f32, 0.5f32, 2f32, 2f32))).unwrap();
+    assert_eq!(
+        (3, 3, 0, 0),
+        (bitmap.width, bitmap.height, bitmap.left, bitmap.top)
+    );
+    #[rustfmt::skip]
+    let expected = [
+        64, 128, 64,
+        128, 255, 128,
+        64, 128, 64,
+    ];
+    assert_eq!(&expected[..], &bitmap.coverage[..]);
+
+    // a diagonal halves the pixels it crosses
+    let mut b = Path::builder();
+    b.move_to(FPoint::new(-1f32, 0f32))
+        .line_to(FPoint::new(2f32, 0f32))
+        .line_to(FPoint::new(2f32, 3f32))
+        .close();
+    let bitmap = rasterize(&b.build()).unwrap();
+    assert_eq!(
+        (3, 3, -1, 0),
+        (bitmap.width, bitmap.height, bitmap.left, bitmap.top)
+    );
+    #[rustfmt::skip]
+    let expected = [
+        128, 255, 255,
+        0, 128, 255,
+        0, 0, 128,
+    ];
+    assert_eq!(&expected[..], &bitmap.coverage[..]);
+}
@@ -0,0 +1,42 @@
+use super::{Font, GlyphId};
+use crate::geom::FPoint;
+
+/// A glyph placed by the shaping, in pixels.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ShapedGlyph {
+    pub id: GlyphId,
+    /// Byte offset in the text of the first character of the cluster of the glyph.
+    pub cluster: usize,
+    pub advance: f32,
+    /// Offset of the glyph from the pen position, with y going down.
+    pub offset: FPoint,
+}
+
+/// Shapes a text of a single script and direction, applying the ligatures,
+/// the kerning and the contextual forms of the font.
+/// The glyphs are returned in visual order.
+pub fn shape(font: &Font, size: f32, text: &str, rtl: bool) -> Vec<ShapedGlyph> {
+    let face = rustybuzz::Face::from_slice(font.bytes(), font.index())
+        .expect("font data checked at loading");
+    let scale = size / face.units_per_em() as f32;
+    let mut buffer = rustybuzz::UnicodeBuffer::new();
+    buffer.push_str(text);
+    buffer.set_direction(if rtl {
+        rustybuzz::Direction::RightToLeft
+    } else {
+        rustybuzz::Direction::LeftToRight
+    });
+    buffer.guess_segment_properties();
+    let output = rustybuzz::shape(&face, &[], buffer);
+    output
+        .glyph_infos()
+        .iter()
+        .zip(output.glyph_positions())
+        .map(|(info, pos)| ShapedGlyph {
+            id: info.glyph_id as GlyphId,
+            cluster: info.cluster as usize,
+            advance: pos.x_advance as f32 * scale,
+            offset: FPoint::new(pos.x_offset as f32 * scale, -pos.y_offset as f32 * scale),
+        })
+        .collect()
+}